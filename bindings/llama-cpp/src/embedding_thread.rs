@@ -0,0 +1,309 @@
+use std::sync::Arc;
+
+use anyhow::Context as _;
+
+/// The maximum number of inputs embedded together in one batch.
+const MAX_EMBEDDING_SEQUENCES: u32 = 16;
+
+type EmbeddingSender = tokio::sync::oneshot::Sender<anyhow::Result<Vec<Vec<f32>>>>;
+
+struct EmbeddingRequest {
+    /// The tokenized inputs.
+    inputs: Vec<sauropod_inference_engine_api::TokenSequence>,
+    /// A sender to return one embedding per input.
+    sender: EmbeddingSender,
+}
+
+/// An embedding model and an inference thread.
+pub struct EmbeddingInferenceThread {
+    /// The model to use for inference.
+    pub model: Arc<crate::Model>,
+    /// The queue for inputs to be processed.
+    queue: tokio::sync::mpsc::Sender<EmbeddingRequest>,
+    /// The thread handle for the inference worker.
+    _thread_handle: std::thread::JoinHandle<()>,
+}
+
+impl EmbeddingInferenceThread {
+    /// Create a new embedding thread from a model file.
+    pub async fn from_file(name: String, model_path: &std::path::Path) -> anyhow::Result<Self> {
+        let model = crate::Model::embedding_from_file(model_path).await?;
+        Self::new(name, Arc::new(model))
+    }
+
+    /// Create a new embedding thread for a reranking model from a model file.
+    pub async fn reranker_from_file(
+        name: String,
+        model_path: &std::path::Path,
+    ) -> anyhow::Result<Self> {
+        let model = crate::Model::reranker_from_file(model_path).await?;
+        Self::new(name, Arc::new(model))
+    }
+
+    /// Create a new embedding thread.
+    pub fn new(name: String, model: Arc<crate::Model>) -> anyhow::Result<Self> {
+        let (input_tx, input_rx) = tokio::sync::mpsc::channel(32);
+        let model_clone = model.clone();
+        let thread_handle = std::thread::Builder::new().name(name).spawn(move || {
+            if let Err(error) = embedding_thread(model_clone, input_rx) {
+                tracing::error!("Embedding thread encountered an error: {:#?}", error);
+            }
+        })?;
+
+        Ok(Self {
+            model,
+            queue: input_tx,
+            _thread_handle: thread_handle,
+        })
+    }
+
+    /// Run tokenized inputs through the model on the inference thread.
+    async fn run(
+        &self,
+        inputs: Vec<sauropod_inference_engine_api::TokenSequence>,
+    ) -> anyhow::Result<Vec<Vec<f32>>> {
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        self.queue
+            .send(EmbeddingRequest { inputs, sender })
+            .await
+            .context("Failed to enqueue llama.cpp embedding request")?;
+        receiver
+            .await
+            .context("The llama.cpp embedding request was dropped")?
+    }
+}
+
+#[async_trait::async_trait]
+impl sauropod_inference_engine_api::EmbeddingModel for EmbeddingInferenceThread {
+    async fn embed(
+        self: Arc<Self>,
+        inputs: Vec<sauropod_inference_engine_api::EmbeddingInput>,
+    ) -> anyhow::Result<sauropod_inference_engine_api::EmbedResponse> {
+        let inputs = inputs
+            .into_iter()
+            .map(|input| match input {
+                sauropod_inference_engine_api::EmbeddingInput::Text(text) => {
+                    Ok(self.model.tokenize(&text)?)
+                }
+                sauropod_inference_engine_api::EmbeddingInput::Tokens(tokens) => Ok(tokens),
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let input_token_count = inputs.iter().map(|input| input.len() as i64).sum();
+        let embeddings = self.run(inputs).await?;
+
+        Ok(sauropod_inference_engine_api::EmbedResponse {
+            embeddings,
+            input_token_count,
+        })
+    }
+
+    fn embedding_dimensions(&self) -> usize {
+        self.model.embedding_dimensions()
+    }
+}
+
+#[async_trait::async_trait]
+impl sauropod_inference_engine_api::RerankModel for EmbeddingInferenceThread {
+    async fn rerank(
+        self: Arc<Self>,
+        query: String,
+        documents: Vec<String>,
+    ) -> anyhow::Result<sauropod_inference_engine_api::RerankResponse> {
+        anyhow::ensure!(
+            self.model.is_reranker(),
+            "The model doesn't use rank pooling"
+        );
+        let inputs = documents
+            .iter()
+            .map(|document| Ok(self.model.tokenize_rerank_input(&query, document)?))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let input_token_count = inputs.iter().map(|input| input.len() as i64).sum();
+        let outputs = self.run(inputs).await?;
+
+        let scores = outputs
+            .into_iter()
+            .enumerate()
+            .map(|(index, output)| {
+                output
+                    .first()
+                    .copied()
+                    .with_context(|| format!("The model produced no score for document {index}"))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(sauropod_inference_engine_api::RerankResponse {
+            scores,
+            input_token_count,
+        })
+    }
+}
+
+/// Scale an embedding to unit length.
+fn normalize(embedding: &mut [f32]) {
+    let norm = embedding.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        embedding.iter_mut().for_each(|x| *x /= norm);
+    }
+}
+
+/// Check that every input fits in one batch.
+fn validate_inputs(
+    inputs: &[sauropod_inference_engine_api::TokenSequence],
+    batch_size: usize,
+) -> anyhow::Result<()> {
+    for (index, input) in inputs.iter().enumerate() {
+        anyhow::ensure!(!input.is_empty(), "Input {index} is empty");
+        anyhow::ensure!(
+            input.len() <= batch_size,
+            "Input {index} has {} tokens which is more than the model's maximum of {batch_size}",
+            input.len()
+        );
+    }
+    Ok(())
+}
+
+/// Run the model on a batch of sequences.
+fn run_batch(
+    model: &crate::Model,
+    context: &crate::Context,
+    batch: &crate::OwnedBatch,
+) -> anyhow::Result<()> {
+    if model.has_encoder() {
+        match unsafe { llama_cpp_sys::llama_encode(context.0, batch.0) } {
+            0 => Ok(()),
+            error => Err(anyhow::anyhow!("llama_encode failed with error {error}")),
+        }
+    } else {
+        crate::inference_thread::decode_batch(context, batch.0)
+    }
+}
+
+/// Embed the inputs, packing as many of them as fit into each batch.
+fn embed_inputs(
+    model: &crate::Model,
+    context: &crate::Context,
+    batch: &mut crate::OwnedBatch,
+    inputs: &[&sauropod_inference_engine_api::TokenSequence],
+) -> anyhow::Result<Vec<Vec<f32>>> {
+    let batch_size = context.batch_size() as usize;
+    let dimensions = model.embedding_dimensions();
+    let mut embeddings = Vec::with_capacity(inputs.len());
+    let mut start = 0;
+    while start < inputs.len() {
+        batch.clear();
+        let mut end = start;
+        while end < inputs.len()
+            && end - start < MAX_EMBEDDING_SEQUENCES as usize
+            && batch.len() + inputs[end].len() <= batch_size
+        {
+            let seq_id = (end - start) as llama_cpp_sys::llama_seq_id;
+            for (position, token) in inputs[end].iter().enumerate() {
+                // SAFETY: the batch has room for `batch_size` tokens
+                unsafe { batch.push(*token as i32, position as i32, seq_id, true) };
+            }
+            end += 1;
+        }
+
+        let result = run_batch(model, context, batch).and_then(|()| {
+            for seq_id in 0..(end - start) {
+                let embedding = unsafe {
+                    llama_cpp_sys::llama_get_embeddings_seq(
+                        context.0,
+                        seq_id as llama_cpp_sys::llama_seq_id,
+                    )
+                };
+                anyhow::ensure!(
+                    !embedding.is_null(),
+                    "Failed to get the embedding of sequence {seq_id}"
+                );
+                let mut embedding =
+                    unsafe { std::slice::from_raw_parts(embedding, dimensions) }.to_vec();
+                // Rank pooling outputs relevance scores rather than an embedding
+                if !model.is_reranker() {
+                    normalize(&mut embedding);
+                }
+                embeddings.push(embedding);
+            }
+            Ok(())
+        });
+        unsafe { llama_cpp_sys::llama_memory_clear(context.get_memory(), true) };
+        result?;
+        start = end;
+    }
+    Ok(embeddings)
+}
+
+/// The embedding loop.
+fn embedding_thread(
+    model: Arc<crate::Model>,
+    mut input_rx: tokio::sync::mpsc::Receiver<EmbeddingRequest>,
+) -> anyhow::Result<()> {
+    let context = model.embedding_context(MAX_EMBEDDING_SEQUENCES)?;
+    let batch_size = context.batch_size() as usize;
+    let mut batch = crate::OwnedBatch::new(batch_size as i32);
+
+    while let Some(request) = input_rx.blocking_recv() {
+        // Embed the inputs of all the waiting requests together.
+        let mut requests = vec![request];
+        while let Ok(request) = input_rx.try_recv() {
+            requests.push(request);
+        }
+        let requests: Vec<_> = requests
+            .into_iter()
+            .filter_map(
+                |request| match validate_inputs(&request.inputs, batch_size) {
+                    Ok(()) => Some(request),
+                    Err(e) => {
+                        let _ = request.sender.send(Err(e));
+                        None
+                    }
+                },
+            )
+            .collect();
+
+        let inputs: Vec<_> = requests
+            .iter()
+            .flat_map(|request| request.inputs.iter())
+            .collect();
+        match embed_inputs(&model, &context, &mut batch, &inputs) {
+            Ok(embeddings) => {
+                let mut embeddings = embeddings.into_iter();
+                for request in requests {
+                    let request_embeddings = embeddings.by_ref().take(request.inputs.len());
+                    let _ = request.sender.send(Ok(request_embeddings.collect()));
+                }
+            }
+            Err(e) => {
+                tracing::error!("Error during embedding: {e:#?}");
+                for request in requests {
+                    let _ = request.sender.send(Err(anyhow::anyhow!("{e:#}")));
+                }
+            }
+        }
+    }
+
+    tracing::info!("Embedding thread exiting");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        let mut embedding = vec![3.0, 4.0];
+        normalize(&mut embedding);
+        assert_eq!(embedding, vec![0.6, 0.8]);
+
+        let mut zeros = vec![0.0, 0.0];
+        normalize(&mut zeros);
+        assert_eq!(zeros, vec![0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_validate_inputs() {
+        assert!(validate_inputs(&[vec![1, 2], vec![3]], 2).is_ok());
+        assert!(validate_inputs(&[vec![1, 2, 3]], 2).is_err());
+        assert!(validate_inputs(&[vec![]], 2).is_err());
+    }
+}
@@ -3,14 +3,137 @@ use std::sync::Arc;
 use crate::mtmd::MtmdBitmap;
 use anyhow::Context as _;
 
-type TokenReceiver =
-    tokio::sync::mpsc::Receiver<anyhow::Result<sauropod_inference_engine_api::Token>>;
-type TokenSender = tokio::sync::mpsc::Sender<anyhow::Result<sauropod_inference_engine_api::Token>>;
-type InputTokenCountOneshot = tokio::sync::oneshot::Sender<i64>;
+// The token channels are unbounded so that a slow receiver never stalls the other sequences of
+// a batch. A sequence sends at most `max_predict` tokens, which bounds the buffered output.
+type TokenReceiver = tokio::sync::mpsc::UnboundedReceiver<anyhow::Result<Generated>>;
+type TokenSender = tokio::sync::mpsc::UnboundedSender<anyhow::Result<Generated>>;
+type InputTokenCountOneshot = tokio::sync::oneshot::Sender<InputTokenCount>;
 
 /// The minimum batch size for llama.cpp.
 const MIN_BATCH_SIZE: i32 = llama_cpp_sys::ggml_kq_mask_pad as i32;
 
+/// The maximum number of requests decoded together in one llama.cpp context.
+const MAX_PARALLEL_SEQUENCES: usize = 8;
+
+/// The maximum number of finished sequences kept in the context for prefix reuse.
+const MAX_CACHED_SEQUENCES: usize = 8;
+
+/// The input token counts of a request.
+#[derive(Debug, Clone, Copy)]
+pub struct InputTokenCount {
+    /// The number of tokens in the prompt.
+    pub input_tokens: i64,
+    /// The number of prompt tokens reused from the KV cache.
+    pub cached_tokens: i64,
+}
+
+/// A token sampled by the inference thread.
+#[derive(Debug, Clone)]
+pub struct SampledToken {
+    pub token: sauropod_inference_engine_api::Token,
+    /// The log probabilities computed from the logits the token was sampled from, if requested.
+    pub logprobs: Option<TokenLogprobs>,
+}
+
+/// An output of the inference thread.
+#[derive(Debug, Clone)]
+pub enum Generated {
+    /// A sampled token.
+    Token(SampledToken),
+    /// The generation stopped and no tokens follow.
+    Stopped(sauropod_inference_engine_api::StopReason),
+}
+
+/// The log probability of a sampled token and of the most likely tokens.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TokenLogprobs {
+    /// The log probability of the sampled token.
+    pub logprob: f32,
+    /// The most likely tokens and their log probabilities, most likely first.
+    pub top: Vec<(sauropod_inference_engine_api::Token, f32)>,
+}
+
+/// Compute the log probability of `token` and the `top_count` most likely tokens from the logits.
+fn token_logprobs(logits: &[f32], token: usize, top_count: usize) -> TokenLogprobs {
+    let max_logit = logits.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let log_sum_exp = max_logit
+        + logits
+            .iter()
+            .map(|logit| (logit - max_logit).exp())
+            .sum::<f32>()
+            .ln();
+
+    let mut top: Vec<(sauropod_inference_engine_api::Token, f32)> =
+        Vec::with_capacity(top_count + 1);
+    if top_count > 0 {
+        for (index, &logit) in logits.iter().enumerate() {
+            if top.len() == top_count && top[top_count - 1].1 >= logit {
+                continue;
+            }
+            let position = top.partition_point(|(_, top_logit)| *top_logit >= logit);
+            top.insert(position, (index as u32, logit));
+            top.truncate(top_count);
+        }
+    }
+    for (_, logit) in top.iter_mut() {
+        *logit -= log_sum_exp;
+    }
+
+    TokenLogprobs {
+        logprob: logits[token] - log_sum_exp,
+        top,
+    }
+}
+
+/// Convert a token into the bytes of its text.
+fn token_to_piece<'a>(
+    vocab: &crate::Vocab,
+    token: sauropod_inference_engine_api::Token,
+    buffer: &'a mut [u8],
+) -> anyhow::Result<&'a [u8]> {
+    let n = unsafe {
+        llama_cpp_sys::llama_token_to_piece(
+            vocab.as_ptr(),
+            token as i32, // Convert u32 to i32 for llama_cpp_sys
+            buffer.as_mut_ptr() as *mut libc::c_char,
+            buffer.len() as i32,
+            0,    // flags
+            true, // add_special
+        )
+    };
+    if n < 0 {
+        anyhow::bail!("Failed to convert token to piece");
+    }
+    Ok(&buffer[..n as usize])
+}
+
+/// Convert the log probabilities of a token into the OpenAI format.
+fn token_logprob(
+    vocab: &crate::Vocab,
+    piece: &[u8],
+    logprobs: TokenLogprobs,
+    buffer: &mut [u8],
+) -> anyhow::Result<sauropod_openai_api::LogProb> {
+    let top_logprobs = logprobs
+        .top
+        .into_iter()
+        .map(|(token, logprob)| {
+            let piece = token_to_piece(vocab, token, buffer)?;
+            Ok(sauropod_openai_api::TopLogProb {
+                bytes: piece.iter().map(|&byte| byte as i64).collect(),
+                logprob: logprob as f64,
+                token: String::from_utf8_lossy(piece).to_string(),
+            })
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    Ok(sauropod_openai_api::LogProb {
+        bytes: piece.iter().map(|&byte| byte as i64).collect(),
+        logprob: logprobs.logprob as f64,
+        token: String::from_utf8_lossy(piece).to_string(),
+        top_logprobs,
+    })
+}
+
 #[derive(Debug)]
 pub enum GenerationRequestInput {
     Tokens(sauropod_inference_engine_api::TokenSequence),
@@ -29,8 +152,10 @@ pub struct GenerationRequest {
     ///
     /// The output is streamed back to the caller token by token.
     pub token_sender: TokenSender,
-    /// A sender to return the count of input tokens.
+    /// A sender to return the count of input tokens once the request starts decoding.
     pub input_token_count_oneshot: InputTokenCountOneshot,
+    /// Cancelled when the caller no longer wants the output.
+    pub cancellation_token: sauropod_inference_engine_api::CancellationToken,
     /// Parent span ID.
     pub parent_span_id: Option<tracing::Id>,
 }
@@ -47,23 +172,43 @@ pub struct ModelInferenceThread {
 
 impl ModelInferenceThread {
     /// Create a new inference thread from a model file.
+    ///
+    /// Tokens are drafted with the model at `draft_model_path` if one is given.
     pub async fn from_file(
         name: String,
         model_path: &std::path::Path,
         projector_model_path: Option<&std::path::Path>,
+        draft_model_path: Option<&std::path::Path>,
+        settings: &crate::ModelSettings,
     ) -> anyhow::Result<Self> {
-        let model = crate::Model::from_file(model_path, projector_model_path).await?;
-        Self::new(name, Arc::new(model))
+        let model = crate::Model::from_file(model_path, projector_model_path, settings).await?;
+        let draft_model = match draft_model_path {
+            Some(draft_model_path) => {
+                let draft_model = crate::Model::draft_from_file(draft_model_path, settings).await?;
+                if !model.shares_vocabulary_with(&draft_model)? {
+                    return Err(crate::Error::DraftVocabularyMismatch.into());
+                }
+                Some(Arc::new(draft_model))
+            }
+            None => None,
+        };
+        Self::new(name, Arc::new(model), draft_model)
     }
 
     /// Create a new inference thread.
-    pub fn new(name: String, model: Arc<crate::Model>) -> anyhow::Result<Self> {
+    ///
+    /// `draft_model` must share the vocabulary of `model`.
+    pub fn new(
+        name: String,
+        model: Arc<crate::Model>,
+        draft_model: Option<Arc<crate::Model>>,
+    ) -> anyhow::Result<Self> {
         let (input_tx, input_rx) = tokio::sync::mpsc::channel(32);
         let model_clone = model.clone();
         let thread_handle = std::thread::Builder::new()
             .name(name.clone())
             .spawn(move || {
-                if let Err(error) = inference_thread(name, model_clone, input_rx) {
+                if let Err(error) = inference_thread(name, model_clone, draft_model, input_rx) {
                     tracing::error!("Inference thread encountered an error: {:#?}", error);
                 }
             })?;
@@ -79,13 +224,15 @@ impl ModelInferenceThread {
         &self,
         sampler_properties: sauropod_inference_engine_api::SamplerProperties,
         input: GenerationRequestInput,
-    ) -> anyhow::Result<(i64, TokenReceiver)> {
-        let (tx, rx) = tokio::sync::mpsc::channel(5);
+        cancellation_token: sauropod_inference_engine_api::CancellationToken,
+    ) -> anyhow::Result<(InputTokenCount, TokenReceiver)> {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
         let (input_token_count_tx, input_token_count_rx) = tokio::sync::oneshot::channel();
         let request = GenerationRequest {
             sampler_properties,
             token_sender: tx,
             input_token_count_oneshot: input_token_count_tx,
+            cancellation_token,
             input,
             parent_span_id: tracing::Span::current().id(),
         };
@@ -93,8 +240,14 @@ impl ModelInferenceThread {
             .send(request)
             .await
             .context("Failed to enqueue llama.cpp request")?;
-        let input_tokens = input_token_count_rx.await?;
-        Ok((input_tokens, rx))
+        let Ok(input_token_count) = input_token_count_rx.await else {
+            // The request failed before decoding started and the error is in the token channel.
+            return Err(match rx.recv().await {
+                Some(Err(e)) => e,
+                _ => anyhow::anyhow!("The llama.cpp request was dropped"),
+            });
+        };
+        Ok((input_token_count, rx))
     }
 
     async fn generate_from_string_impl(
@@ -102,9 +255,10 @@ impl ModelInferenceThread {
         sampler_properties: sauropod_inference_engine_api::SamplerProperties,
         text: String,
         multimodal_data: Vec<sauropod_prompt_templates::MultimodalData>,
+        cancellation_token: sauropod_inference_engine_api::CancellationToken,
     ) -> anyhow::Result<(
-        i64,
-        impl tokio_stream::Stream<Item = anyhow::Result<String>>,
+        InputTokenCount,
+        impl tokio_stream::Stream<Item = anyhow::Result<sauropod_inference_engine_api::Part>>,
     )> {
         let vocab = self.model.get_vocab()?;
         let (input_token_count, mut receiver) = self
@@ -114,30 +268,45 @@ impl ModelInferenceThread {
                     content: text,
                     multimodal_data,
                 },
+                cancellation_token,
             )
             .await?;
 
         let mut part_buffer = [0u8; 256];
+        let mut top_logprob_buffer = [0u8; 256];
         let stream = async_stream::stream! {
             loop {
                 match receiver.recv().await {
-                    Some(Ok(token)) => {
-                        let n = unsafe {
-                            llama_cpp_sys::llama_token_to_piece(
-                                vocab.as_ptr(),
-                                token as i32, // Convert u32 to i32 for llama_cpp_sys
-                                part_buffer.as_mut_ptr() as *mut libc::c_char,
-                                part_buffer.len() as i32,
-                                0,    // flags
-                                true, // add_special
-                            )
+                    Some(Ok(Generated::Stopped(stop_reason))) => {
+                        yield Ok(sauropod_inference_engine_api::Part::stop(stop_reason));
+                        break;
+                    }
+                    Some(Ok(Generated::Token(sampled))) => {
+                        let piece = match token_to_piece(&vocab, sampled.token, &mut part_buffer) {
+                            Ok(piece) => piece,
+                            Err(e) => {
+                                yield Err(e);
+                                break;
+                            }
                         };
-                        if n < 0 {
-                            yield Err(anyhow::anyhow!(
-                                "Failed to convert token to piece"
-                            ));
-                        }
-                        yield Ok(String::from_utf8_lossy(&part_buffer[..n as usize]).to_string());
+                        let logprob = match sampled
+                            .logprobs
+                            .map(|logprobs| {
+                                token_logprob(&vocab, piece, logprobs, &mut top_logprob_buffer)
+                            })
+                            .transpose()
+                        {
+                            Ok(logprob) => logprob,
+                            Err(e) => {
+                                yield Err(e);
+                                break;
+                            }
+                        };
+                        yield Ok(sauropod_inference_engine_api::Part {
+                            text: String::from_utf8_lossy(piece).to_string(),
+                            logprob,
+                            stop_reason: None,
+                        });
                     }
                     Some(Err(e)) => {
                         yield Err(e);
@@ -159,14 +328,26 @@ impl sauropod_inference_engine_api::LlmModel for ModelInferenceThread {
         self: Arc<Self>,
         sampler_properties: sauropod_inference_engine_api::SamplerProperties,
         tokens: sauropod_inference_engine_api::TokenSequence,
+        cancellation_token: sauropod_inference_engine_api::CancellationToken,
     ) -> anyhow::Result<sauropod_inference_engine_api::TokenStream> {
         let (_, receiver) = self
-            .generate_impl(sampler_properties, GenerationRequestInput::Tokens(tokens))
+            .generate_impl(
+                sampler_properties,
+                GenerationRequestInput::Tokens(tokens),
+                cancellation_token,
+            )
             .await?;
-        Ok(
-            Box::pin(tokio_stream::wrappers::ReceiverStream::new(receiver))
-                as sauropod_inference_engine_api::TokenStream,
-        )
+        use tokio_stream::StreamExt as _;
+
+        Ok(Box::pin(
+            tokio_stream::wrappers::UnboundedReceiverStream::new(receiver).filter_map(
+                |generated| match generated {
+                    Ok(Generated::Token(sampled)) => Some(Ok(sampled.token)),
+                    Ok(Generated::Stopped(_)) => None,
+                    Err(e) => Some(Err(e)),
+                },
+            ),
+        ) as sauropod_inference_engine_api::TokenStream)
     }
 
     async fn generate_from_text(
@@ -174,22 +355,45 @@ impl sauropod_inference_engine_api::LlmModel for ModelInferenceThread {
         sampler_properties: sauropod_inference_engine_api::SamplerProperties,
         text: String,
         multimodal_data: Vec<sauropod_prompt_templates::MultimodalData>,
+        cancellation_token: sauropod_inference_engine_api::CancellationToken,
     ) -> anyhow::Result<sauropod_inference_engine_api::GenerateFromTextResponse> {
         let (input_token_count, stream) = self
-            .generate_from_string_impl(sampler_properties, text, multimodal_data)
+            .generate_from_string_impl(
+                sampler_properties,
+                text,
+                multimodal_data,
+                cancellation_token,
+            )
             .await?;
         Ok(sauropod_inference_engine_api::GenerateFromTextResponse {
             stream: Box::pin(stream) as sauropod_inference_engine_api::PartStream,
-            input_token_count,
+            input_token_count: input_token_count.input_tokens,
+            cached_token_count: input_token_count.cached_tokens,
         })
     }
 
+    fn count_tokens(&self, text: &str) -> anyhow::Result<usize> {
+        Ok(self.model.tokenize(text)?.len())
+    }
+
+    fn context_size(&self) -> usize {
+        self.model.max_context_size() as usize
+    }
+
+    fn load_lora_adapter(
+        &self,
+        adapter: &sauropod_config::ConfigModelSource,
+        path: &std::path::Path,
+    ) -> anyhow::Result<()> {
+        Ok(self.model.load_lora_adapter(adapter, path)?)
+    }
+
     fn get_model_chat_template(&self) -> &str {
-        self.model.chat_template.as_str()
+        self.model.chat_template()
     }
 
     fn get_model_type(&self) -> sauropod_output_parser::ModelType {
-        self.model.model_type
+        self.model.model_type.clone()
     }
 
     fn supports_vision(&self) -> bool {
@@ -202,7 +406,10 @@ impl sauropod_inference_engine_api::LlmModel for ModelInferenceThread {
 }
 
 /// Decode a batch of tokens in the llama.cpp context.
-fn decode_batch(context: &crate::Context, batch: llama_cpp_sys::llama_batch) -> anyhow::Result<()> {
+pub(crate) fn decode_batch(
+    context: &crate::Context,
+    batch: llama_cpp_sys::llama_batch,
+) -> anyhow::Result<()> {
     match unsafe { llama_cpp_sys::llama_decode(context.0, batch) } {
         0 => Ok(()),
         1 => Err(anyhow::anyhow!("Could not find a KV slot for the batch")),
@@ -214,258 +421,1310 @@ fn decode_batch(context: &crate::Context, batch: llama_cpp_sys::llama_batch) ->
     }
 }
 
-/// Run the decode loop for a single input.
-fn run_for_tokens(
-    model: &Arc<crate::Model>,
-    mut tokens: sauropod_inference_engine_api::TokenSequence,
-    sampler_properties: &sauropod_inference_engine_api::SamplerProperties,
-    token_count_sender: InputTokenCountOneshot,
-    sender: &TokenSender,
+/// Decode the next piece of a sequence's multimodal chunks: a batch of text tokens, the encoding
+/// of a media chunk or a batch of its embeddings.
+///
+/// The trailing text becomes the sequence's prompt so that it is decoded, and its logits
+/// sampled, together with the other sequences.
+fn prefill_multimodal_piece(
+    shared: &mut SharedContext,
+    mtmd_context: &crate::mtmd::MtmdContext,
+    sequence: &mut Sequence,
 ) -> anyhow::Result<()> {
-    if let Err(e) = token_count_sender.send(tokens.len() as i64) {
-        tracing::error!("Failed to send token count: {:#?}", e);
+    let prefill = sequence
+        .prefill
+        .as_mut()
+        .context("The sequence has no multimodal chunks")?;
+    if prefill.chunk_index >= prefill.chunks.len() {
+        return Err(anyhow::anyhow!("Multimodal prompts must end with text"));
     }
+    let chunk_ptr = prefill.chunks.get(prefill.chunk_index);
+    let is_last_chunk = prefill.chunk_index + 1 == prefill.chunks.len();
+    let number_of_tokens_in_chunk =
+        unsafe { llama_cpp_sys::mtmd_input_chunk_get_n_tokens(chunk_ptr) };
 
-    let vocab = model.get_vocab()?;
-    let sampler = crate::Sampler::new(sampler_properties)?;
-    let mut generated_token_count = 0usize;
-    let start_time = std::time::Instant::now();
+    match unsafe { llama_cpp_sys::mtmd_input_chunk_get_type(chunk_ptr) } {
+        llama_cpp_sys::mtmd_input_chunk_type::MTMD_INPUT_CHUNK_TYPE_TEXT => {
+            let mut number_of_tokens = 0usize;
+            let tokens_ptr = unsafe {
+                llama_cpp_sys::mtmd_input_chunk_get_tokens_text(chunk_ptr, &mut number_of_tokens)
+            };
+            let tokens_slice = unsafe { std::slice::from_raw_parts(tokens_ptr, number_of_tokens) };
 
-    let context =
-        match model.llama_context(tokens.len() as i64, sampler_properties.max_predict as i64) {
-            Ok(context) => context,
-            Err(e) => {
-                return Err(anyhow::anyhow!(e));
+            if is_last_chunk {
+                if tokens_slice.is_empty() {
+                    return Err(anyhow::anyhow!("Multimodal prompts must end with text"));
+                }
+                sequence.prompt = tokens_slice.iter().map(|&token| token as u32).collect();
+                sequence.prefill = None;
+                return Ok(());
             }
-        };
 
-    let mut batch = unsafe {
-        llama_cpp_sys::llama_batch_get_one(
-            tokens.as_mut_ptr() as *mut i32, // u32 -> i32
-            tokens.len() as i32,
-        )
-    };
+            let end = (prefill.chunk_offset + MIN_BATCH_SIZE as usize).min(number_of_tokens);
+            shared.batch.clear();
+            for &token in &tokens_slice[prefill.chunk_offset..end] {
+                unsafe {
+                    shared
+                        .batch
+                        .push(token, sequence.position, sequence.seq_id, false)
+                };
+                sequence.position += 1;
+            }
+            sequence.used_cells += end - prefill.chunk_offset;
+            prefill.chunk_offset = end;
+            decode_batch(&shared.context, shared.batch.0)?;
+        }
+        llama_cpp_sys::mtmd_input_chunk_type::MTMD_INPUT_CHUNK_TYPE_IMAGE
+        | llama_cpp_sys::mtmd_input_chunk_type::MTMD_INPUT_CHUNK_TYPE_AUDIO => {
+            if is_last_chunk {
+                return Err(anyhow::anyhow!("Multimodal prompts must end with text"));
+            }
 
-    loop {
-        let n_ctx_used =
-            unsafe { llama_cpp_sys::llama_memory_seq_pos_max(context.get_memory(), 0) };
-        let n_ctx_needed = n_ctx_used + batch.n_tokens;
-        if n_ctx_needed > context.context_size() as i32 {
-            return Err(anyhow::anyhow!(
-                "Context size exceeded: {n_ctx_needed} > {}",
-                context.context_size()
-            ));
+            let number_of_embeddings = unsafe {
+                llama_cpp_sys::llama_model_n_embd(llama_cpp_sys::llama_get_model(shared.context.0))
+            } as usize;
+
+            if prefill.embeddings.is_empty() {
+                let encode_result =
+                    unsafe { llama_cpp_sys::mtmd_encode_chunk(mtmd_context.0, chunk_ptr) };
+                if encode_result != 0 {
+                    return Err(anyhow::anyhow!(
+                        "Failed to encode multimodal chunk: {encode_result}",
+                    ));
+                }
+                let embedding_ptr = unsafe { llama_cpp_sys::mtmd_get_output_embd(mtmd_context.0) };
+                if embedding_ptr.is_null() {
+                    return Err(anyhow::anyhow!("Failed to get encoded embeddings"));
+                }
+                prefill.embeddings = unsafe {
+                    std::slice::from_raw_parts(
+                        embedding_ptr,
+                        number_of_tokens_in_chunk * number_of_embeddings,
+                    )
+                }
+                .to_vec();
+                // The embeddings are decoded in the following steps.
+                return Ok(());
+            }
+
+            let tokens_to_process = std::cmp::min(
+                MIN_BATCH_SIZE as usize,
+                number_of_tokens_in_chunk - prefill.chunk_offset,
+            );
+            let mut seq_id = sequence.seq_id;
+            let mut positions = (0..tokens_to_process)
+                .map(|offset| sequence.position + (prefill.chunk_offset + offset) as i32)
+                .collect::<Vec<_>>();
+            let mut n_seq_ids = vec![1; tokens_to_process];
+            let mut seq_ids = vec![&mut seq_id as *mut _; tokens_to_process];
+            let mut logits = vec![0i8; tokens_to_process];
+
+            let batch = llama_cpp_sys::llama_batch {
+                n_tokens: tokens_to_process as i32,
+                token: std::ptr::null_mut(),
+                embd: prefill.embeddings[prefill.chunk_offset * number_of_embeddings..]
+                    .as_mut_ptr(),
+                pos: positions.as_mut_ptr(),
+                n_seq_id: n_seq_ids.as_mut_ptr(),
+                seq_id: seq_ids.as_mut_ptr(),
+                logits: logits.as_mut_ptr(),
+            };
+            decode_batch(&shared.context, batch)?;
+
+            prefill.chunk_offset += tokens_to_process;
+            sequence.used_cells += tokens_to_process;
+            if prefill.chunk_offset == number_of_tokens_in_chunk {
+                sequence.position +=
+                    unsafe { llama_cpp_sys::mtmd_input_chunk_get_n_pos(chunk_ptr) };
+                prefill.embeddings = Vec::new();
+            }
+        }
+        #[allow(unreachable_patterns)]
+        x => {
+            return Err(anyhow::anyhow!("Unsupported chunk type: {}", x as i32));
         }
+    }
 
-        decode_batch(&context, batch)?;
+    if prefill.chunk_offset == number_of_tokens_in_chunk {
+        prefill.chunk_index += 1;
+        prefill.chunk_offset = 0;
+    }
+    Ok(())
+}
+
+/// Tell the receiver why the generation stopped.
+fn send_stop(sender: &TokenSender, stop_reason: sauropod_inference_engine_api::StopReason) {
+    if sender.send(Ok(Generated::Stopped(stop_reason))).is_err() {
+        tracing::debug!("The receiver was dropped before the generation stopped");
+    }
+}
 
-        generated_token_count += 1;
-        let mut new_token_id = sampler.sample(&context);
-        if vocab.is_end_of_generation(new_token_id as u32) {
-            let duration = std::time::Instant::now() - start_time;
-            let tokens_per_second = generated_token_count as f64 / duration.as_secs_f64();
-            tracing::debug!("Inference completed in {duration:.2?} with {tokens_per_second} tok/s",);
+/// Send an error back to the caller of a request.
+fn send_error(sender: &TokenSender, error: anyhow::Error) {
+    tracing::error!("Error during inference: {:#?}", error);
+    if let Err(send_error) = sender.send(Err(error)) {
+        tracing::error!("Failed to send error back to sender: {:#?}", send_error);
+    }
+}
+
+/// The tokenized input of a request.
+enum PendingInput {
+    Tokens(sauropod_inference_engine_api::TokenSequence),
+    Multimodal(MultimodalPrefill),
+}
+
+/// The chunks of a multimodal prompt, which are decoded a piece per step so that the other
+/// sequences keep generating while images and audio are processed.
+struct MultimodalPrefill {
+    chunks: crate::mtmd::MtmdInputChunks,
+    /// The bitmaps referenced by `chunks`.
+    _bitmaps: Vec<MtmdBitmap>,
+    /// The index of the chunk being decoded.
+    chunk_index: usize,
+    /// The number of tokens of the chunk which have been decoded.
+    chunk_offset: usize,
+    /// The encoded embeddings of the media chunk being decoded.
+    ///
+    /// They are copied out of the projector, whose output the next encode overwrites.
+    embeddings: Vec<f32>,
+}
+
+/// A tokenized request waiting for a sequence in the shared context.
+struct PendingRequest {
+    input: PendingInput,
+    /// The number of input tokens.
+    input_token_count: usize,
+    sampler_properties: sauropod_inference_engine_api::SamplerProperties,
+    token_sender: TokenSender,
+    input_token_count_oneshot: InputTokenCountOneshot,
+    cancellation_token: sauropod_inference_engine_api::CancellationToken,
+    span: tracing::Span,
+}
+
+impl PendingRequest {
+    /// The number of KV cells the request may occupy in a context of at most `max_context_size`.
+    fn required_cells(&self, max_context_size: usize) -> usize {
+        (self.input_token_count + self.sampler_properties.max_predict).min(max_context_size)
+    }
+}
 
-            return Ok(());
+/// A request being decoded as a sequence of the shared context.
+struct Sequence {
+    seq_id: llama_cpp_sys::llama_seq_id,
+    /// The prompt tokens to decode.
+    prompt: sauropod_inference_engine_api::TokenSequence,
+    /// The number of prompt tokens which have been added to a batch.
+    prompt_offset: usize,
+    /// The multimodal chunks preceding the prompt which are still being decoded.
+    prefill: Option<MultimodalPrefill>,
+    /// The token sampled in the previous step which still has to be decoded.
+    next_token: Option<llama_cpp_sys::llama_token>,
+    /// The tokens drafted to follow `next_token`, which are verified in the current batch.
+    drafted: Vec<llama_cpp_sys::llama_token>,
+    /// The generated tokens which have been added to a batch.
+    generated: sauropod_inference_engine_api::TokenSequence,
+    /// Whether the sequence can be kept for prefix reuse once it finishes.
+    cacheable: bool,
+    /// Whether tokens are drafted for the sequence.
+    speculative: bool,
+    /// The number of tokens of the sequence in the draft model's KV cache.
+    draft_cells: usize,
+    /// The number of drafted tokens which were verified.
+    drafted_token_count: usize,
+    /// The number of drafted tokens which matched the sampled tokens.
+    accepted_token_count: usize,
+    /// The LoRA adapters applied while decoding the sequence.
+    lora_adapters: Vec<sauropod_config::LoraAdapterConfig>,
+    /// The position of the next token in the sequence.
+    position: llama_cpp_sys::llama_pos,
+    /// The number of KV cells used by the sequence.
+    used_cells: usize,
+    /// The number of KV cells reserved for the sequence.
+    reserved_cells: usize,
+    /// The maximum number of tokens to generate.
+    max_predict: usize,
+    /// The index of the sequence's logits in the current batch.
+    logits_index: Option<i32>,
+    sampler: crate::Sampler,
+    /// The number of most likely tokens to return with the log probability of each token.
+    top_logprobs: Option<usize>,
+    token_sender: TokenSender,
+    cancellation_token: sauropod_inference_engine_api::CancellationToken,
+    generated_token_count: usize,
+    start_time: std::time::Instant,
+    span: tracing::Span,
+}
+
+impl Sequence {
+    /// The tokens of the sequence which are missing from the draft model's KV cache.
+    ///
+    /// Only the tokens of text prompts are known, so this is only valid for speculative
+    /// sequences, whose position is the number of tokens in the KV cache.
+    fn undrafted_tokens(
+        &self,
+        next_token: llama_cpp_sys::llama_token,
+    ) -> Vec<llama_cpp_sys::llama_token> {
+        self.prompt[..self.prompt_offset]
+            .iter()
+            .chain(&self.generated)
+            .skip(self.draft_cells)
+            .map(|&token| token as llama_cpp_sys::llama_token)
+            .chain(std::iter::once(next_token))
+            .collect()
+    }
+
+    /// The tokens stored in the KV cache for the sequence.
+    fn into_cached_tokens(mut self) -> sauropod_inference_engine_api::TokenSequence {
+        self.prompt.truncate(self.prompt_offset);
+        self.prompt.append(&mut self.generated);
+        self.prompt
+    }
+}
+
+/// A finished sequence kept in the context so that later prompts can reuse its prefix.
+struct CachedPrefix {
+    seq_id: llama_cpp_sys::llama_seq_id,
+    /// The tokens stored in the KV cache for the sequence.
+    tokens: sauropod_inference_engine_api::TokenSequence,
+    /// The LoRA adapters applied when the tokens were decoded.
+    lora_adapters: Vec<sauropod_config::LoraAdapterConfig>,
+}
+
+/// Finished sequences kept for prefix reuse, least recently used first.
+#[derive(Default)]
+struct PrefixCache(Vec<CachedPrefix>);
+
+impl PrefixCache {
+    /// The number of KV cells held by the cached prefixes.
+    fn cells(&self) -> usize {
+        self.0.iter().map(|cached| cached.tokens.len()).sum()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Find the prefix decoded with `lora_adapters` sharing the most tokens with `prompt`.
+    ///
+    /// At least the final prompt token is left to decode so that there are logits to sample.
+    fn find(
+        &self,
+        prompt: &[sauropod_inference_engine_api::Token],
+        lora_adapters: &[sauropod_config::LoraAdapterConfig],
+    ) -> Option<(llama_cpp_sys::llama_seq_id, usize)> {
+        self.0
+            .iter()
+            .filter(|cached| cached.lora_adapters == lora_adapters)
+            .map(|cached| {
+                let length = common_prefix_length(&cached.tokens, prompt);
+                (cached.seq_id, length.min(prompt.len().saturating_sub(1)))
+            })
+            .filter(|(_, length)| *length > 0)
+            .max_by_key(|(_, length)| *length)
+    }
+
+    /// The least recently used prefix, other than `keep` unless it is the only one.
+    fn eviction_victim(
+        &self,
+        keep: Option<llama_cpp_sys::llama_seq_id>,
+    ) -> Option<llama_cpp_sys::llama_seq_id> {
+        self.0
+            .iter()
+            .find(|cached| Some(cached.seq_id) != keep)
+            .or(self.0.first())
+            .map(|cached| cached.seq_id)
+    }
+
+    /// Add the most recently used prefix and return the sequence ID of the prefix it evicts.
+    fn insert(&mut self, prefix: CachedPrefix) -> Option<llama_cpp_sys::llama_seq_id> {
+        let evicted = if self.0.len() >= MAX_CACHED_SEQUENCES {
+            Some(self.0.remove(0).seq_id)
+        } else {
+            None
+        };
+        self.0.push(prefix);
+        evicted
+    }
+
+    fn remove(&mut self, seq_id: llama_cpp_sys::llama_seq_id) -> Option<CachedPrefix> {
+        let index = self.0.iter().position(|cached| cached.seq_id == seq_id)?;
+        Some(self.0.remove(index))
+    }
+}
+
+/// The length of the common prefix of two token sequences.
+fn common_prefix_length(
+    a: &[sauropod_inference_engine_api::Token],
+    b: &[sauropod_inference_engine_api::Token],
+) -> usize {
+    a.iter().zip(b).take_while(|(a, b)| a == b).count()
+}
+
+/// A context shared by all sequences and the batch used to decode them.
+struct SharedContext {
+    context: crate::Context,
+    batch: crate::OwnedBatch,
+    /// The context of the draft model, which holds the same sequences.
+    draft: Option<DraftContext>,
+}
+
+impl SharedContext {
+    fn new(
+        model: &crate::Model,
+        draft_model: Option<&crate::Model>,
+        context_size: u32,
+    ) -> anyhow::Result<Self> {
+        tracing::debug!("Creating a llama.cpp context with {context_size} cells");
+        let max_sequences = (MAX_PARALLEL_SEQUENCES + MAX_CACHED_SEQUENCES) as u32;
+        let context = model.llama_context(context_size, max_sequences)?;
+        let batch = crate::OwnedBatch::new(context.batch_size() as i32);
+        let draft = match draft_model {
+            Some(draft_model) => {
+                let context = draft_model.llama_context(context_size, max_sequences)?;
+                let batch = crate::OwnedBatch::new(context.batch_size() as i32);
+                Some(DraftContext { context, batch })
+            }
+            None => None,
+        };
+        Ok(Self {
+            context,
+            batch,
+            draft,
+        })
+    }
+}
+
+/// The context of a draft model and the batch used to draft tokens.
+struct DraftContext {
+    context: crate::Context,
+    batch: crate::OwnedBatch,
+}
+
+impl DraftContext {
+    /// Remove the tokens of a sequence from position `start` onwards and return how many are kept.
+    fn truncate(&self, seq_id: llama_cpp_sys::llama_seq_id, start: usize) -> usize {
+        truncate_sequence(&self.context, seq_id, start)
+    }
+}
+
+/// Remove the tokens of a sequence from position `start` onwards and return how many are kept.
+///
+/// Memories which can't drop part of a sequence, such as those of recurrent models, drop the
+/// whole sequence instead.
+fn truncate_sequence(
+    context: &crate::Context,
+    seq_id: llama_cpp_sys::llama_seq_id,
+    start: usize,
+) -> usize {
+    let memory = context.get_memory();
+    if unsafe { llama_cpp_sys::llama_memory_seq_rm(memory, seq_id, start as i32, -1) } {
+        return start;
+    }
+    tracing::debug!("Could not remove the tokens of sequence {seq_id} from position {start}");
+    unsafe { llama_cpp_sys::llama_memory_seq_rm(memory, seq_id, -1, -1) };
+    0
+}
+
+/// A model which drafts tokens for speculative decoding.
+///
+/// The drafted tokens are verified together in one batch of the model, which keeps the tokens
+/// the model samples itself. The output is therefore the same as without a draft model, but
+/// several tokens may be generated per decode of the model.
+struct Drafter {
+    model: Arc<crate::Model>,
+    vocab: crate::Vocab,
+    /// The maximum number of tokens drafted per step.
+    length: usize,
+}
+
+/// The most likely token according to `logits`.
+fn most_likely_token(logits: &[f32]) -> llama_cpp_sys::llama_token {
+    logits
+        .iter()
+        .enumerate()
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map_or(0, |(token, _)| token as llama_cpp_sys::llama_token)
+}
+
+/// Decodes concurrent requests together as separate sequences of one llama.cpp context.
+///
+/// Requests are admitted between decode steps while there are free sequences and enough
+/// unreserved KV cells, and retired as soon as they finish. Finished text sequences stay
+/// resident so that a later prompt sharing their prefix only decodes its suffix.
+struct BatchScheduler {
+    name: String,
+    model: Arc<crate::Model>,
+    vocab: crate::Vocab,
+    drafter: Option<Drafter>,
+    shared: Option<SharedContext>,
+    pending: std::collections::VecDeque<PendingRequest>,
+    active: Vec<Sequence>,
+    cache: PrefixCache,
+    /// The LoRA adapters applied by the shared context, which all active sequences use.
+    lora_adapters: Vec<sauropod_config::LoraAdapterConfig>,
+    free_seq_ids: Vec<llama_cpp_sys::llama_seq_id>,
+    /// The number of KV cells reserved by active sequences.
+    reserved_cells: usize,
+}
+
+impl BatchScheduler {
+    fn new(
+        name: String,
+        model: Arc<crate::Model>,
+        draft_model: Option<Arc<crate::Model>>,
+    ) -> anyhow::Result<Self> {
+        let vocab = model.get_vocab()?;
+        let drafter = match draft_model {
+            Some(draft_model) => Some(Drafter {
+                vocab: draft_model.get_vocab()?,
+                model: draft_model,
+                length: model.settings.draft_length(),
+            }),
+            None => None,
+        };
+        Ok(Self {
+            name,
+            model,
+            vocab,
+            drafter,
+            shared: None,
+            pending: std::collections::VecDeque::new(),
+            active: Vec::with_capacity(MAX_PARALLEL_SEQUENCES),
+            cache: PrefixCache::default(),
+            lora_adapters: Vec::new(),
+            free_seq_ids: (0..(MAX_PARALLEL_SEQUENCES + MAX_CACHED_SEQUENCES)
+                as llama_cpp_sys::llama_seq_id)
+                .rev()
+                .collect(),
+            reserved_cells: 0,
+        })
+    }
+
+    /// Whether there is no work to do.
+    fn is_idle(&self) -> bool {
+        self.active.is_empty() && self.pending.is_empty()
+    }
+
+    /// Tokenize a request and queue it for admission.
+    fn enqueue(&mut self, request: GenerationRequest) {
+        let span = tracing::info_span!(
+            parent: None,
+            "llama.cpp inference",
+            model = %self.name,
+            drafted_tokens = tracing::field::Empty,
+            accepted_tokens = tracing::field::Empty,
+            acceptance_rate = tracing::field::Empty,
+        );
+        span.follows_from(request.parent_span_id);
+        let guard = span.enter();
+
+        if let Some(lora_adapter) = request
+            .sampler_properties
+            .lora_adapters
+            .iter()
+            .find(|lora_adapter| !self.model.has_lora_adapter(&lora_adapter.adapter))
+        {
+            let error = crate::Error::LoraAdapterNotLoaded(lora_adapter.adapter.to_string());
+            send_error(&request.token_sender, error.into());
+            return;
         }
 
-        if let Err(send_error) = sender.blocking_send(Ok(new_token_id as u32)) {
-            tracing::error!(
-                "Failed to send token {new_token_id} back to sender: {:#?}",
-                send_error
+        let (input, input_token_count) = match self.prepare_input(request.input) {
+            Ok(prepared) => prepared,
+            Err(error) => {
+                send_error(&request.token_sender, error);
+                return;
+            }
+        };
+        drop(guard);
+
+        let max_context_size = self.model.max_context_size() as usize;
+        if input_token_count >= max_context_size {
+            let _guard = span.enter();
+            tracing::debug!(
+                "The prompt of {input_token_count} tokens leaves no room in the context of {max_context_size} tokens"
             );
-            return Ok(()); // We break with `Ok` since we already know the sender is erroring
+            if let Err(e) = request.input_token_count_oneshot.send(InputTokenCount {
+                input_tokens: input_token_count as i64,
+                cached_tokens: 0,
+            }) {
+                tracing::error!("Failed to send token count: {:#?}", e);
+            }
+            send_stop(
+                &request.token_sender,
+                sauropod_inference_engine_api::StopReason::ContextFull,
+            );
+            return;
         }
 
-        // The next batch is the previous sampled token
-        batch = unsafe { llama_cpp_sys::llama_batch_get_one(&mut new_token_id as *mut _, 1) };
+        self.pending.push_back(PendingRequest {
+            input,
+            input_token_count,
+            sampler_properties: request.sampler_properties,
+            token_sender: request.token_sender,
+            input_token_count_oneshot: request.input_token_count_oneshot,
+            cancellation_token: request.cancellation_token,
+            span,
+        });
     }
-}
 
-fn run_for_multimodal(
-    model: &Arc<crate::Model>,
-    content: String,
-    multimodal_data: Vec<sauropod_prompt_templates::MultimodalData>,
-    sampler_properties: &sauropod_inference_engine_api::SamplerProperties,
-    token_count_sender: InputTokenCountOneshot,
-    sender: &TokenSender,
-) -> anyhow::Result<()> {
-    let Some(mtmd_context) = model.mtmd_context.as_ref() else {
-        return Err(anyhow::anyhow!(
-            "This model does not support multimodal inputs"
-        ));
-    };
+    /// Tokenize the input of a request.
+    fn prepare_input(
+        &self,
+        input: GenerationRequestInput,
+    ) -> anyhow::Result<(PendingInput, usize)> {
+        let (input, input_token_count) = match input {
+            GenerationRequestInput::Tokens(tokens) => {
+                let count = tokens.len();
+                (PendingInput::Tokens(tokens), count)
+            }
+            GenerationRequestInput::Text {
+                content,
+                multimodal_data,
+            } if multimodal_data.is_empty() => {
+                let tokens = self.model.tokenize(&content)?;
+                let count = tokens.len();
+                (PendingInput::Tokens(tokens), count)
+            }
+            GenerationRequestInput::Text {
+                content,
+                multimodal_data,
+            } => {
+                let Some(mtmd_context) = self.model.mtmd_context.as_ref() else {
+                    return Err(anyhow::anyhow!(
+                        "This model does not support multimodal inputs"
+                    ));
+                };
 
-    let vocab = model.get_vocab()?;
-    let sampler = crate::Sampler::new(sampler_properties)?;
-    let mut generated_token_count = 0usize;
-    let start_time = std::time::Instant::now();
+                let mut bitmaps = multimodal_data
+                    .iter()
+                    .map(|data| match data {
+                        sauropod_prompt_templates::MultimodalData::Image(image) => {
+                            MtmdBitmap::new_rgb(image.width(), image.height(), image.as_raw())
+                                .map_err(|e| {
+                                    anyhow::anyhow!("Failed to create image bitmap: {:?}", e)
+                                })
+                        }
+                        sauropod_prompt_templates::MultimodalData::Audio(data) => {
+                            MtmdBitmap::new_audio(data).map_err(|e| {
+                                anyhow::anyhow!("Failed to create audio bitmap: {:?}", e)
+                            })
+                        }
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
 
-    let mut bitmaps = multimodal_data
-        .iter()
-        .map(|data| match data {
-            sauropod_prompt_templates::MultimodalData::Image(image) => {
-                MtmdBitmap::new_rgb(image.width(), image.height(), image.as_raw())
-                    .map_err(|e| anyhow::anyhow!("Failed to create image bitmap: {:?}", e))
+                let chunks = mtmd_context
+                    .tokenize(&content, bitmaps.as_mut_slice())
+                    .map_err(|e| anyhow::anyhow!("Failed to tokenize multimodal input: {:?}", e))?;
+
+                let count: usize = (0..chunks.len())
+                    .map(|i| unsafe { llama_cpp_sys::mtmd_input_chunk_get_n_tokens(chunks.get(i)) })
+                    .sum();
+                (
+                    PendingInput::Multimodal(MultimodalPrefill {
+                        chunks,
+                        _bitmaps: bitmaps,
+                        chunk_index: 0,
+                        chunk_offset: 0,
+                        embeddings: Vec::new(),
+                    }),
+                    count,
+                )
             }
-            sauropod_prompt_templates::MultimodalData::Audio(data) => MtmdBitmap::new_audio(data)
-                .map_err(|e| anyhow::anyhow!("Failed to create audio bitmap: {:?}", e)),
+        };
+
+        if input_token_count == 0 {
+            return Err(anyhow::anyhow!("The prompt is empty"));
+        }
+        Ok((input, input_token_count))
+    }
+
+    /// The size of the context to create for a request needing `required_cells` cells.
+    ///
+    /// A configured context size is used as is. Otherwise the context leaves room for other
+    /// sequences up to the model's training context size.
+    fn context_size_for(&self, required_cells: usize) -> u32 {
+        if let Some(context_size) = self.model.configured_context_size() {
+            return context_size;
+        }
+        let shared_cells =
+            (required_cells * MAX_PARALLEL_SEQUENCES).min(self.model.max_context_size() as usize);
+        shared_cells.max(required_cells) as u32
+    }
+
+    /// Whether a request needing `required_cells` fits next to the active and cached sequences.
+    fn fits(&self, required_cells: usize) -> bool {
+        self.shared.as_ref().is_some_and(|shared| {
+            self.reserved_cells + self.cache.cells() + required_cells
+                <= shared.context.context_size() as usize
         })
-        .collect::<Result<Vec<_>, _>>()?;
-
-    let chunks = mtmd_context
-        .tokenize(&content, bitmaps.as_mut_slice())
-        .map_err(|e| anyhow::anyhow!("Failed to tokenize multimodal input: {:?}", e))?;
-
-    let n_tokens: usize = (0..chunks.len())
-        .map(|i| unsafe { llama_cpp_sys::mtmd_input_chunk_get_n_tokens(chunks.get(i)) })
-        .sum();
-    if let Err(e) = token_count_sender.send(n_tokens as i64) {
-        tracing::error!("Failed to send token count: {:#?}", e);
-    }
-
-    let context = model.llama_context(n_tokens as i64, sampler_properties.max_predict as i64)?;
-
-    let mut number_of_processed_tokens: i32 = 0;
-    let seq_id = 0;
-
-    for i in 0..chunks.len() {
-        let chunk_ptr = chunks.get(i);
-        match unsafe { llama_cpp_sys::mtmd_input_chunk_get_type(chunk_ptr) } {
-            llama_cpp_sys::mtmd_input_chunk_type::MTMD_INPUT_CHUNK_TYPE_TEXT => {
-                let mut batch = crate::OwnedBatch(unsafe {
-                    llama_cpp_sys::llama_batch_init(MIN_BATCH_SIZE, 0, 1)
-                });
-                let mut number_of_tokens = 0usize;
-                let tokens_ptr = unsafe {
-                    llama_cpp_sys::mtmd_input_chunk_get_tokens_text(
-                        chunk_ptr,
-                        &mut number_of_tokens,
-                    )
-                };
-                let tokens_slice =
-                    unsafe { std::slice::from_raw_parts(tokens_ptr, number_of_tokens) };
-
-                let batch_token =
-                    unsafe { std::slice::from_raw_parts_mut(batch.0.token, number_of_tokens) };
-                let batch_pos =
-                    unsafe { std::slice::from_raw_parts_mut(batch.0.pos, number_of_tokens) };
-                let batch_n_seq_id =
-                    unsafe { std::slice::from_raw_parts_mut(batch.0.n_seq_id, number_of_tokens) };
-                let batch_seq_id =
-                    unsafe { std::slice::from_raw_parts_mut(batch.0.seq_id, number_of_tokens) };
-                let batch_logits =
-                    unsafe { std::slice::from_raw_parts_mut(batch.0.logits, number_of_tokens) };
-
-                for token_chunk in tokens_slice.chunks(MIN_BATCH_SIZE as usize) {
-                    batch.0.n_tokens = token_chunk.len() as i32;
-
-                    for (batch_token_index, &token) in token_chunk.iter().enumerate() {
-                        batch_token[batch_token_index] = token;
-                        batch_pos[batch_token_index] =
-                            number_of_processed_tokens + batch_token_index as i32;
-                        batch_n_seq_id[batch_token_index] = 1;
-                        unsafe { *batch_seq_id[batch_token_index] = 0 };
-                        batch_logits[batch_token_index] =
-                            if batch_token_index == token_chunk.len() - 1 {
-                                1
-                            } else {
-                                0
-                            };
-                    }
+    }
+
+    /// Remove a sequence from the context and make its ID available.
+    fn release_seq_id(&mut self, seq_id: llama_cpp_sys::llama_seq_id) {
+        if let Some(shared) = self.shared.as_ref() {
+            truncate_sequence(&shared.context, seq_id, 0);
+            if let Some(draft) = shared.draft.as_ref() {
+                draft.truncate(seq_id, 0);
+            }
+        }
+        self.free_seq_ids.push(seq_id);
+    }
+
+    /// Drop a cached prefix.
+    fn evict(&mut self, seq_id: llama_cpp_sys::llama_seq_id) {
+        self.cache.remove(seq_id);
+        self.release_seq_id(seq_id);
+    }
+
+    /// Keep the KV cache of a finished sequence for prefix reuse.
+    fn cache_prefix(
+        &mut self,
+        seq_id: llama_cpp_sys::llama_seq_id,
+        tokens: sauropod_inference_engine_api::TokenSequence,
+        lora_adapters: Vec<sauropod_config::LoraAdapterConfig>,
+    ) {
+        if tokens.is_empty() {
+            self.release_seq_id(seq_id);
+            return;
+        }
+        if let Some(evicted) = self.cache.insert(CachedPrefix {
+            seq_id,
+            tokens,
+            lora_adapters,
+        }) {
+            self.release_seq_id(evicted);
+        }
+    }
+
+    /// Get a sequence ID whose KV cache holds the first tokens of a cached prefix and the number
+    /// of those tokens, which is `length` unless the memory can't hold part of a sequence.
+    fn reuse_prefix(
+        &mut self,
+        cached_seq_id: llama_cpp_sys::llama_seq_id,
+        length: usize,
+    ) -> (llama_cpp_sys::llama_seq_id, usize) {
+        let cached = self
+            .cache
+            .remove(cached_seq_id)
+            .expect("the cached prefix exists");
+        let context = &self
+            .shared
+            .as_ref()
+            .expect("cached prefixes live in the context")
+            .context;
+
+        if length == cached.tokens.len() {
+            // The prompt continues the cached sequence, so the sequence is taken over.
+            return (cached.seq_id, length);
+        }
+
+        // Share the matching cells with a new sequence and keep the prefix for other prompts.
+        let seq_id = self.free_seq_ids.pop().expect("a sequence ID is free");
+        unsafe {
+            llama_cpp_sys::llama_memory_seq_cp(
+                context.get_memory(),
+                cached.seq_id,
+                seq_id,
+                0,
+                length as i32,
+            )
+        };
+        // Memories which copy whole sequences keep the cells past the prefix, which must go.
+        let length = truncate_sequence(context, seq_id, length);
+        // The prefix was just removed from the cache, so inserting it again evicts nothing.
+        self.cache.insert(cached);
+        (seq_id, length)
+    }
+
+    /// Move pending requests into free sequences of the shared context.
+    fn admit(&mut self) {
+        // Requests cancelled while they were queued are dropped without being decoded.
+        self.pending.retain(|request| {
+            let cancelled = request.cancellation_token.is_cancelled();
+            if cancelled {
+                let _guard = request.span.enter();
+                tracing::debug!("Request was cancelled before it started");
+            }
+            !cancelled
+        });
+
+        while self.active.len() < MAX_PARALLEL_SEQUENCES {
+            let Some(request) = self.pending.front() else {
+                break;
+            };
+
+            // Sequences decoded together apply the same LoRA adapters, so switching them waits
+            // for the running sequences to finish.
+            let lora_adapters = &request.sampler_properties.lora_adapters;
+            if *lora_adapters != self.lora_adapters && !self.active.is_empty() {
+                break;
+            }
 
-                    number_of_processed_tokens += token_chunk.len() as i32;
+            let required_cells = request.required_cells(self.model.max_context_size() as usize);
+            let mut reuse = match &request.input {
+                PendingInput::Tokens(tokens) => self.cache.find(tokens, lora_adapters),
+                PendingInput::Multimodal(_) => None,
+            };
 
-                    decode_batch(&context, batch.0)?;
+            // Evict the least recently used prefixes until the request fits, keeping the one it
+            // reuses for last.
+            while !self.fits(required_cells) && !self.cache.is_empty() {
+                let victim = self
+                    .cache
+                    .eviction_victim(reuse.map(|(seq_id, _)| seq_id))
+                    .expect("the cache isn't empty");
+                if reuse.is_some_and(|(seq_id, _)| seq_id == victim) {
+                    reuse = None;
                 }
+                self.evict(victim);
             }
-            llama_cpp_sys::mtmd_input_chunk_type::MTMD_INPUT_CHUNK_TYPE_IMAGE
-            | llama_cpp_sys::mtmd_input_chunk_type::MTMD_INPUT_CHUNK_TYPE_AUDIO => {
-                let encode_result =
-                    unsafe { llama_cpp_sys::mtmd_encode_chunk(mtmd_context.0, chunk_ptr) };
-                if encode_result != 0 {
-                    return Err(anyhow::anyhow!(
-                        "Failed to encode multimodal chunk: {encode_result}",
-                    ));
+
+            if !self.fits(required_cells) {
+                if !self.active.is_empty() {
+                    // Wait for running sequences to free their cells.
+                    break;
                 }
-                let embedding_ptr = unsafe { llama_cpp_sys::mtmd_get_output_embd(mtmd_context.0) };
-                if embedding_ptr.is_null() {
-                    return Err(anyhow::anyhow!("Failed to get encoded embeddings"));
+
+                // Nothing is running, so the context can be replaced by one that is large enough.
+                self.shared = None;
+                self.lora_adapters.clear();
+                match SharedContext::new(
+                    &self.model,
+                    self.drafter.as_ref().map(|drafter| drafter.model.as_ref()),
+                    self.context_size_for(required_cells),
+                ) {
+                    Ok(shared) => self.shared = Some(shared),
+                    Err(error) => {
+                        let request = self.pending.pop_front().expect("request was peeked");
+                        let _guard = request.span.enter();
+                        send_error(&request.token_sender, error);
+                        continue;
+                    }
                 }
+            }
 
-                // Decode the embeddings using helper function equivalent
-                let number_of_tokens_in_chunk =
-                    unsafe { llama_cpp_sys::mtmd_input_chunk_get_n_tokens(chunk_ptr) };
-                let chunk_n_pos = unsafe { llama_cpp_sys::mtmd_input_chunk_get_n_pos(chunk_ptr) };
+            let request = self.pending.pop_front().expect("request was peeked");
+            if request.sampler_properties.lora_adapters != self.lora_adapters {
+                let context = &self.shared.as_ref().expect("the request fits").context;
+                if let Err(error) = self
+                    .model
+                    .apply_lora_adapters(context, &request.sampler_properties.lora_adapters)
+                {
+                    self.lora_adapters.clear();
+                    let _guard = request.span.enter();
+                    send_error(&request.token_sender, error.into());
+                    continue;
+                }
+                self.lora_adapters = request.sampler_properties.lora_adapters.clone();
+            }
 
-                let number_of_embeddings = unsafe {
-                    llama_cpp_sys::llama_model_n_embd(llama_cpp_sys::llama_get_model(context.0))
-                };
+            let (seq_id, cached_tokens) = match reuse {
+                Some((cached_seq_id, length)) => self.reuse_prefix(cached_seq_id, length),
+                None => (self.free_seq_ids.pop().expect("a sequence ID is free"), 0),
+            };
+            self.start_sequence(seq_id, cached_tokens, request);
+        }
+    }
 
-                // Process embeddings in batches
-                let mut embedding_index = 0usize;
-                while embedding_index < number_of_tokens_in_chunk as usize {
-                    let tokens_to_process = std::cmp::min(
-                        MIN_BATCH_SIZE as usize,
-                        number_of_tokens_in_chunk as usize - embedding_index,
-                    ) as i32;
+    /// Start decoding a request as sequence `seq_id`, whose first `cached_tokens` are already in
+    /// the KV cache.
+    fn start_sequence(
+        &mut self,
+        seq_id: llama_cpp_sys::llama_seq_id,
+        cached_tokens: usize,
+        request: PendingRequest,
+    ) {
+        let span = request.span.clone();
+        let _guard = span.enter();
 
-                    let embd_offset = unsafe {
-                        embedding_ptr.add(embedding_index * number_of_embeddings as usize)
-                    };
+        let sampler = match crate::Sampler::new(
+            &request.sampler_properties,
+            &self.vocab,
+            self.model.training_context_size(),
+        ) {
+            Ok(sampler) => sampler,
+            Err(error) => {
+                self.release_seq_id(seq_id);
+                send_error(&request.token_sender, error.into());
+                return;
+            }
+        };
 
-                    let batch = llama_cpp_sys::llama_batch {
-                        n_tokens: tokens_to_process,
-                        token: std::ptr::null_mut(),
-                        embd: embd_offset,
-                        pos: std::ptr::null_mut(),
-                        n_seq_id: std::ptr::null_mut(),
-                        seq_id: std::ptr::null_mut(),
-                        logits: std::ptr::null_mut(),
-                    };
+        // Drop whatever follows the reused prefix in a sequence that was taken over, or the whole
+        // sequence if the memory can't drop part of it.
+        let cached_tokens = match self.shared.as_ref() {
+            Some(shared) => truncate_sequence(&shared.context, seq_id, cached_tokens),
+            None => cached_tokens,
+        };
+        if cached_tokens > 0 {
+            tracing::debug!("Reusing {cached_tokens} cached prompt tokens");
+        }
+        let reserved_cells = request.required_cells(self.model.max_context_size() as usize);
+        if let Err(e) = request.input_token_count_oneshot.send(InputTokenCount {
+            input_tokens: request.input_token_count as i64,
+            cached_tokens: cached_tokens as i64,
+        }) {
+            tracing::error!("Failed to send token count: {:#?}", e);
+        }
 
-                    decode_batch(&context, batch)?;
+        let mut sequence = Sequence {
+            seq_id,
+            prompt: Vec::new(),
+            prompt_offset: cached_tokens,
+            prefill: None,
+            next_token: None,
+            drafted: Vec::new(),
+            generated: Vec::new(),
+            cacheable: false,
+            speculative: false,
+            draft_cells: 0,
+            drafted_token_count: 0,
+            accepted_token_count: 0,
+            lora_adapters: request.sampler_properties.lora_adapters.clone(),
+            position: cached_tokens as i32,
+            used_cells: cached_tokens,
+            reserved_cells,
+            max_predict: request.sampler_properties.max_predict,
+            logits_index: None,
+            sampler,
+            top_logprobs: request.sampler_properties.top_logprobs,
+            token_sender: request.token_sender,
+            cancellation_token: request.cancellation_token,
+            generated_token_count: 0,
+            start_time: std::time::Instant::now(),
+            span: request.span,
+        };
+        self.reserved_cells += sequence.reserved_cells;
 
-                    embedding_index += tokens_to_process as usize;
+        match request.input {
+            PendingInput::Tokens(tokens) => {
+                // The draft model only keeps active sequences, so it decodes the prompt anew.
+                if let Some(draft) = self
+                    .shared
+                    .as_ref()
+                    .and_then(|shared| shared.draft.as_ref())
+                {
+                    draft.truncate(seq_id, 0);
                 }
-                number_of_processed_tokens += chunk_n_pos;
+                sequence.prompt = tokens;
+                sequence.cacheable = true;
+                sequence.speculative = self.drafter.is_some();
             }
-            #[allow(unreachable_patterns)]
-            x => {
-                return Err(anyhow::anyhow!("Unsupported chunk type: {}", x as i32));
+            PendingInput::Multimodal(prefill) => sequence.prefill = Some(prefill),
+        }
+
+        self.active.push(sequence);
+    }
+    /// Decode the next piece of the multimodal chunks of every sequence still prefilling them.
+    fn prefill_multimodal(&mut self) {
+        let (Some(shared), Some(mtmd_context)) =
+            (self.shared.as_mut(), self.model.mtmd_context.as_ref())
+        else {
+            return;
+        };
+        let mut failed = Vec::new();
+        for (index, sequence) in self.active.iter_mut().enumerate() {
+            if sequence.prefill.is_none() {
+                continue;
+            }
+            let span = sequence.span.clone();
+            let _guard = span.enter();
+            if let Err(error) = prefill_multimodal_piece(shared, mtmd_context, sequence) {
+                failed.push((index, error));
             }
         }
+        for (index, error) in failed.into_iter().rev() {
+            let sequence = self.active.remove(index);
+            self.retire(sequence, Err(error));
+        }
     }
 
-    // Start generation loop
-    loop {
-        let n_ctx_used =
-            unsafe { llama_cpp_sys::llama_memory_seq_pos_max(context.get_memory(), seq_id) };
-        let n_ctx_needed = n_ctx_used + 1;
-        if n_ctx_needed > context.context_size() as i32 {
-            return Err(anyhow::anyhow!(
-                "Context size exceeded: {n_ctx_needed} > {}",
-                context.context_size()
-            ));
+    /// Remove a sequence from the shared context and report how it finished.
+    fn retire(
+        &mut self,
+        mut sequence: Sequence,
+        result: anyhow::Result<sauropod_inference_engine_api::StopReason>,
+    ) {
+        self.reserved_cells -= sequence.reserved_cells;
+
+        let span = sequence.span.clone();
+        let _guard = span.enter();
+        if sequence.drafted_token_count > 0 {
+            let acceptance_rate =
+                sequence.accepted_token_count as f64 / sequence.drafted_token_count as f64;
+            span.record("drafted_tokens", sequence.drafted_token_count);
+            span.record("accepted_tokens", sequence.accepted_token_count);
+            span.record("acceptance_rate", acceptance_rate);
+            tracing::debug!(
+                "Accepted {} of {} drafted tokens ({:.1}%)",
+                sequence.accepted_token_count,
+                sequence.drafted_token_count,
+                acceptance_rate * 100.0
+            );
         }
+        match result {
+            Ok(stop_reason) => {
+                let duration = std::time::Instant::now() - sequence.start_time;
+                let tokens_per_second =
+                    sequence.generated_token_count as f64 / duration.as_secs_f64();
+                tracing::debug!(
+                    "Inference stopped ({stop_reason:?}) after {duration:.2?} with {tokens_per_second} tok/s"
+                );
+                send_stop(&sequence.token_sender, stop_reason);
+
+                if sequence.cacheable {
+                    let seq_id = sequence.seq_id;
+                    let lora_adapters = std::mem::take(&mut sequence.lora_adapters);
+                    self.cache_prefix(seq_id, sequence.into_cached_tokens(), lora_adapters);
+                } else {
+                    self.release_seq_id(sequence.seq_id);
+                }
+            }
+            Err(error) => {
+                self.release_seq_id(sequence.seq_id);
+                send_error(&sequence.token_sender, error);
+            }
+        }
+    }
+
+    /// Decode one batch containing the next token of every generating sequence and as many
+    /// prompt tokens as fit, then sample the sequences whose logits were computed.
+    fn step(&mut self) {
+        for sequence in std::mem::take(&mut self.active) {
+            if sequence.cancellation_token.is_cancelled() {
+                {
+                    let _guard = sequence.span.enter();
+                    tracing::debug!(
+                        "Generation cancelled after {} tokens",
+                        sequence.generated_token_count
+                    );
+                }
+                self.retire(
+                    sequence,
+                    Ok(sauropod_inference_engine_api::StopReason::Cancelled),
+                );
+            } else if sequence.generated_token_count >= sequence.max_predict {
+                self.retire(
+                    sequence,
+                    Ok(sauropod_inference_engine_api::StopReason::MaxTokens),
+                );
+            } else if sequence.next_token.is_some()
+                && sequence.used_cells >= sequence.reserved_cells
+            {
+                // The context can't hold `max_predict` tokens, so the output ends early.
+                self.retire(
+                    sequence,
+                    Ok(sauropod_inference_engine_api::StopReason::ContextFull),
+                );
+            } else {
+                self.active.push(sequence);
+            }
+        }
+
+        self.prefill_multimodal();
+        self.draft();
+
+        let Some(shared) = self.shared.as_mut() else {
+            return;
+        };
+        let capacity = shared.context.batch_size() as usize;
+        shared.batch.clear();
+
+        // Generated tokens go first so that running sequences keep streaming while prompts
+        // are being processed. Drafted tokens follow the token they continue.
+        for sequence in self.active.iter_mut() {
+            sequence.logits_index = None;
+            if let Some(token) = sequence.next_token.take() {
+                sequence.generated.push(token as u32);
+                sequence.logits_index = Some(shared.batch.len() as i32);
+                for &token in std::iter::once(&token).chain(&sequence.drafted) {
+                    unsafe {
+                        shared
+                            .batch
+                            .push(token, sequence.position, sequence.seq_id, true)
+                    };
+                    sequence.position += 1;
+                    sequence.used_cells += 1;
+                }
+            }
+        }
+
+        for sequence in self.active.iter_mut() {
+            while sequence.prompt_offset < sequence.prompt.len() && shared.batch.len() < capacity {
+                let is_last_prompt_token = sequence.prompt_offset + 1 == sequence.prompt.len();
+                if is_last_prompt_token {
+                    sequence.logits_index = Some(shared.batch.len() as i32);
+                }
+                unsafe {
+                    shared.batch.push(
+                        sequence.prompt[sequence.prompt_offset] as i32, // u32 -> i32
+                        sequence.position,
+                        sequence.seq_id,
+                        is_last_prompt_token,
+                    )
+                };
+                sequence.prompt_offset += 1;
+                sequence.position += 1;
+                sequence.used_cells += 1;
+            }
+        }
+
+        if shared.batch.is_empty() {
+            return;
+        }
+
+        if let Err(decode_error) = decode_batch(&shared.context, shared.batch.0) {
+            for sequence in std::mem::take(&mut self.active) {
+                let error = anyhow::anyhow!("{decode_error:#}");
+                self.retire(sequence, Err(error));
+            }
+            return;
+        }
+
+        'sequences: for mut sequence in std::mem::take(&mut self.active) {
+            let Some(logits_index) = sequence.logits_index.take() else {
+                self.active.push(sequence);
+                continue;
+            };
+
+            let span = sequence.span.clone();
+            let _guard = span.enter();
+
+            let shared = self.shared.as_ref().expect("context was used to decode");
+            let context = &shared.context;
+
+            // Sample after each drafted token until the sampled token differs from the draft.
+            let drafted = std::mem::take(&mut sequence.drafted);
+            let mut sampled = Vec::with_capacity(drafted.len() + 1);
+            for offset in 0..=drafted.len() {
+                let index = logits_index + offset as i32;
+                let new_token_id = sequence.sampler.sample(context, index);
+                let logprobs = sequence
+                    .top_logprobs
+                    .map(|top_count| {
+                        context
+                            .logits(index, &self.vocab)
+                            .map(|logits| token_logprobs(logits, new_token_id as usize, top_count))
+                    })
+                    .transpose();
+                let logprobs = match logprobs {
+                    Ok(logprobs) => logprobs,
+                    Err(error) => {
+                        self.retire(sequence, Err(error.into()));
+                        continue 'sequences;
+                    }
+                };
+                sampled.push((new_token_id, logprobs));
+                if self.vocab.is_end_of_generation(new_token_id as u32)
+                    || drafted.get(offset) != Some(&new_token_id)
+                {
+                    break;
+                }
+            }
+
+            // Drop the rejected tokens from the KV caches.
+            let accepted = sampled.len() - 1;
+            let rejected = drafted.len() - accepted;
+            let mut decode_anew = false;
+            if rejected > 0 {
+                sequence.position -= rejected as i32;
+                sequence.used_cells -= rejected;
+                let position = sequence.position as usize;
+                decode_anew = truncate_sequence(context, sequence.seq_id, position) != position;
+            }
+            if decode_anew {
+                // The memory can't drop the rejected tokens alone, so it lost the whole sequence.
+                tracing::warn!("Decoding the sequence anew without drafted tokens");
+                sequence.speculative = false;
+                if let Some(draft) = shared.draft.as_ref() {
+                    draft.truncate(sequence.seq_id, 0);
+                }
+                sequence.draft_cells = 0;
+            } else if let Some(draft) = shared.draft.as_ref().filter(|_| sequence.speculative) {
+                let kept_cells = sequence.draft_cells.min(sequence.position as usize);
+                sequence.draft_cells = draft.truncate(sequence.seq_id, kept_cells);
+            }
+            sequence
+                .generated
+                .extend(drafted[..accepted].iter().map(|&token| token as u32));
+            sequence.drafted_token_count += drafted.len();
+            sequence.accepted_token_count += accepted;
+
+            let mut next_token = None;
+            for (new_token_id, logprobs) in sampled {
+                sequence.generated_token_count += 1;
+                if self.vocab.is_end_of_generation(new_token_id as u32) {
+                    self.retire(
+                        sequence,
+                        Ok(sauropod_inference_engine_api::StopReason::EndOfGeneration),
+                    );
+                    continue 'sequences;
+                }
+
+                if let Err(send_error) =
+                    sequence
+                        .token_sender
+                        .send(Ok(Generated::Token(SampledToken {
+                            token: new_token_id as u32,
+                            logprobs,
+                        })))
+                {
+                    tracing::error!(
+                        "Failed to send token {new_token_id} back to sender: {:#?}",
+                        send_error
+                    );
+                    // The receiver is gone so the sequence can be dropped.
+                    self.retire(
+                        sequence,
+                        Ok(sauropod_inference_engine_api::StopReason::Cancelled),
+                    );
+                    continue 'sequences;
+                }
+                next_token = Some(new_token_id);
+            }
 
-        generated_token_count += 1;
-        let mut new_token_id = sampler.sample(&context);
-        if vocab.is_end_of_generation(new_token_id as u32) {
-            let duration = std::time::Instant::now() - start_time;
-            let tokens_per_second = generated_token_count as f64 / duration.as_secs_f64();
-            tracing::debug!("Inference completed in {duration:.2?} with {tokens_per_second} tok/s");
-            return Ok(());
+            if decode_anew {
+                // The tokens in the KV cache and the last sampled token become the prompt, whose
+                // logits sample the token following them.
+                sequence.prompt.truncate(sequence.prompt_offset);
+                sequence.prompt.append(&mut sequence.generated);
+                sequence.prompt.extend(next_token.map(|token| token as u32));
+                sequence.prompt_offset = 0;
+                sequence.position = 0;
+                sequence.used_cells = 0;
+                self.active.push(sequence);
+                continue;
+            }
+
+            // The next batch includes the last sampled token
+            sequence.next_token = next_token;
+            self.active.push(sequence);
         }
+    }
 
-        if let Err(send_error) = sender.blocking_send(Ok(new_token_id as u32)) {
-            tracing::error!("Failed to send token back to sender: {:#?}", send_error);
-            return Ok(());
+    /// Draft the tokens following the next token of each speculative sequence.
+    ///
+    /// The draft model first decodes the tokens of a sequence it hasn't seen yet. Tokens are then
+    /// drafted greedily for all sequences together until each has its share of the batch.
+    fn draft(&mut self) {
+        let (Some(drafter), Some(shared)) = (self.drafter.as_ref(), self.shared.as_mut()) else {
+            return;
+        };
+        let Some(draft) = shared.draft.as_mut() else {
+            return;
+        };
+
+        // Every verified token needs logits, so the drafts of all sequences share one batch.
+        let share = (shared.context.batch_size() as usize / self.active.len().max(1))
+            .saturating_sub(1)
+            .min(drafter.length);
+        // The drafted tokens are sampled from the shared part of the vocabularies.
+        let token_count = self.vocab.token_count().min(drafter.vocab.token_count());
+
+        let mut drafting = Vec::new();
+        for (index, sequence) in self.active.iter_mut().enumerate() {
+            let Some(next_token) = sequence.next_token else {
+                continue;
+            };
+            if !sequence.speculative {
+                continue;
+            }
+            // The verified tokens must fit in the reserved cells and in `max_predict`.
+            let length = share
+                .min(sequence.reserved_cells - sequence.used_cells - 1)
+                .min(sequence.max_predict - sequence.generated_token_count - 1);
+            if length == 0 {
+                continue;
+            }
+
+            let _guard = sequence.span.enter();
+            let undrafted = sequence.undrafted_tokens(next_token);
+            let batch_size = draft.context.batch_size() as usize;
+            let mut result = Ok(());
+            for (chunk_index, chunk) in undrafted.chunks(batch_size).enumerate() {
+                draft.batch.clear();
+                for (offset, &token) in chunk.iter().enumerate() {
+                    // Only the last token needs logits to draft the token following it
+                    let is_last_token = chunk_index * batch_size + offset + 1 == undrafted.len();
+                    unsafe {
+                        draft.batch.push(
+                            token,
+                            sequence.draft_cells as i32,
+                            sequence.seq_id,
+                            is_last_token,
+                        )
+                    };
+                    sequence.draft_cells += 1;
+                }
+                result = decode_batch(&draft.context, draft.batch.0);
+                if result.is_err() {
+                    break;
+                }
+            }
+            let logits = result.and_then(|()| {
+                Ok(draft
+                    .context
+                    .logits(draft.batch.len() as i32 - 1, &drafter.vocab)?)
+            });
+            let logits = match logits {
+                Ok(logits) => logits,
+                Err(error) => {
+                    tracing::warn!("Failed to decode the prompt with the draft model: {error:#}");
+                    draft.truncate(sequence.seq_id, 0);
+                    sequence.draft_cells = 0;
+                    continue;
+                }
+            };
+            let token = most_likely_token(&logits[..token_count]);
+            sequence.drafted.push(token);
+            if length > 1 && !self.vocab.is_end_of_generation(token as u32) {
+                drafting.push((index, length));
+            }
         }
 
-        // Continue with next token
-        let batch = unsafe { llama_cpp_sys::llama_batch_get_one(&mut new_token_id as *mut _, 1) };
-        decode_batch(&context, batch)?;
+        while !drafting.is_empty() {
+            draft.batch.clear();
+            for &(index, _) in &drafting {
+                let sequence = &mut self.active[index];
+                let token = *sequence.drafted.last().expect("a token was drafted");
+                unsafe {
+                    draft
+                        .batch
+                        .push(token, sequence.draft_cells as i32, sequence.seq_id, true)
+                };
+                sequence.draft_cells += 1;
+            }
+
+            if let Err(error) = decode_batch(&draft.context, draft.batch.0) {
+                tracing::warn!("Failed to draft tokens: {error:#}");
+                // Keep the tokens drafted so far, but decode the sequences anew next time.
+                for &(index, _) in &drafting {
+                    let sequence = &mut self.active[index];
+                    draft.truncate(sequence.seq_id, 0);
+                    sequence.draft_cells = 0;
+                }
+                break;
+            }
+
+            let mut batch_index = 0;
+            drafting.retain(|&(index, length)| {
+                let sequence = &mut self.active[index];
+                let logits = draft.context.logits(batch_index, &drafter.vocab);
+                batch_index += 1;
+                let logits = match logits {
+                    Ok(logits) => logits,
+                    Err(error) => {
+                        // Keep the tokens drafted so far
+                        tracing::warn!("Failed to draft tokens: {error:#}");
+                        return false;
+                    }
+                };
+                let token = most_likely_token(&logits[..token_count]);
+                sequence.drafted.push(token);
+                sequence.drafted.len() < length && !self.vocab.is_end_of_generation(token as u32)
+            });
+        }
     }
 }
 
@@ -473,60 +1732,313 @@ fn run_for_multimodal(
 fn inference_thread(
     name: String,
     model: Arc<crate::Model>,
+    draft_model: Option<Arc<crate::Model>>,
     mut input_rx: tokio::sync::mpsc::Receiver<GenerationRequest>,
 ) -> anyhow::Result<()> {
+    let mut scheduler = BatchScheduler::new(name, model, draft_model)?;
     loop {
-        let Some(request) = input_rx.blocking_recv() else {
-            // No more inputs, exit the loop.
-            break;
-        };
+        if scheduler.is_idle() {
+            let Some(request) = input_rx.blocking_recv() else {
+                // No more inputs, exit the loop.
+                break;
+            };
+            scheduler.enqueue(request);
+        }
 
-        let span = tracing::info_span!(parent: None, "llama.cpp inference", model = %name);
-        span.follows_from(request.parent_span_id);
-        let _guard = span.enter();
-        let maybe_error = match request.input {
-            GenerationRequestInput::Tokens(tokens) => run_for_tokens(
-                &model,
+        // Pick up requests which arrived while the previous batch was decoding.
+        while let Ok(request) = input_rx.try_recv() {
+            scheduler.enqueue(request);
+        }
+
+        scheduler.admit();
+        scheduler.step();
+    }
+
+    tracing::info!("LLM inference thread exiting");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_logprobs() {
+        let logits = [1.0f32, 3.0, 2.0, 0.0];
+        let logprobs = token_logprobs(&logits, 2, 2);
+
+        let log_sum_exp = logits.iter().map(|logit| logit.exp()).sum::<f32>().ln();
+        assert!((logprobs.logprob - (2.0 - log_sum_exp)).abs() < 1e-5);
+        assert_eq!(
+            logprobs
+                .top
+                .iter()
+                .map(|(token, _)| *token)
+                .collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+        assert!((logprobs.top[0].1 - (3.0 - log_sum_exp)).abs() < 1e-5);
+
+        assert!(token_logprobs(&logits, 0, 0).top.is_empty());
+    }
+
+    fn cached_prefix(
+        seq_id: llama_cpp_sys::llama_seq_id,
+        tokens: &[sauropod_inference_engine_api::Token],
+    ) -> CachedPrefix {
+        CachedPrefix {
+            seq_id,
+            tokens: tokens.to_vec(),
+            lora_adapters: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_common_prefix_length() {
+        assert_eq!(common_prefix_length(&[1, 2, 3], &[1, 2, 4]), 2);
+        assert_eq!(common_prefix_length(&[1, 2], &[1, 2, 3]), 2);
+        assert_eq!(common_prefix_length(&[1, 2, 3], &[4, 2, 3]), 0);
+        assert_eq!(common_prefix_length(&[], &[1]), 0);
+    }
+
+    #[test]
+    fn test_prefix_cache_find() {
+        let mut cache = PrefixCache::default();
+        cache.insert(cached_prefix(0, &[1, 2, 3, 4]));
+        cache.insert(cached_prefix(1, &[1, 2, 5]));
+        cache.insert(CachedPrefix {
+            lora_adapters: vec![sauropod_config::LoraAdapterConfig {
+                adapter: sauropod_config::ConfigModelSource::LocalPath("adapter.gguf".to_string()),
+                scale: 1.0,
+            }],
+            ..cached_prefix(2, &[1, 2, 3, 4, 5])
+        });
+
+        // The longest prefix decoded with the same LoRA adapters wins.
+        assert_eq!(cache.find(&[1, 2, 3, 4, 5, 6], &[]), Some((0, 4)));
+        assert_eq!(cache.find(&[1, 2, 5, 6], &[]), Some((1, 3)));
+        // The final prompt token is always decoded.
+        assert_eq!(cache.find(&[1, 2, 3, 4], &[]), Some((0, 3)));
+        assert_eq!(cache.find(&[1], &[]), None);
+        assert_eq!(cache.find(&[7, 8], &[]), None);
+    }
+
+    #[test]
+    fn test_prefix_cache_eviction() {
+        let mut cache = PrefixCache::default();
+        assert_eq!(cache.eviction_victim(None), None);
+        for seq_id in 0..MAX_CACHED_SEQUENCES as llama_cpp_sys::llama_seq_id {
+            assert_eq!(cache.insert(cached_prefix(seq_id, &[seq_id as u32])), None);
+        }
+        assert_eq!(cache.cells(), MAX_CACHED_SEQUENCES);
+
+        // The least recently used prefix is evicted, unless it is about to be reused.
+        assert_eq!(cache.eviction_victim(None), Some(0));
+        assert_eq!(cache.eviction_victim(Some(0)), Some(1));
+
+        // A full cache evicts its least recently used prefix.
+        assert_eq!(cache.insert(cached_prefix(100, &[100])), Some(0));
+        assert_eq!(cache.eviction_victim(None), Some(1));
+
+        // Reusing a prefix makes it the most recently used.
+        let reused = cache.remove(1).unwrap();
+        assert_eq!(cache.insert(reused), None);
+        assert_eq!(cache.eviction_victim(None), Some(2));
+
+        let mut cache = PrefixCache::default();
+        cache.insert(cached_prefix(3, &[1]));
+        assert_eq!(cache.eviction_victim(Some(3)), Some(3));
+        assert!(cache.remove(3).is_some());
+        assert!(cache.is_empty());
+    }
+
+    /// Generate up to `max_predict` tokens greedily from `prompt`.
+    async fn generate_greedily_with_limit(
+        thread: Arc<ModelInferenceThread>,
+        prompt: &str,
+        max_predict: usize,
+    ) -> Vec<sauropod_inference_engine_api::Token> {
+        use sauropod_inference_engine_api::LlmModel as _;
+        use tokio_stream::StreamExt as _;
+
+        let tokens = thread.model.tokenize(prompt).unwrap();
+        let sampler_properties = sauropod_inference_engine_api::SamplerProperties {
+            top_k: Some(1),
+            temperature: 0.0,
+            max_predict,
+            ..Default::default()
+        };
+        thread
+            .generate_from_tokens(
+                sampler_properties,
                 tokens,
-                &request.sampler_properties,
-                request.input_token_count_oneshot,
-                &request.token_sender,
-            ),
-            GenerationRequestInput::Text {
-                content,
-                multimodal_data,
-            } if multimodal_data.is_empty() => match model.tokenize(&content) {
-                Ok(tokens) => run_for_tokens(
-                    &model,
-                    tokens,
-                    &request.sampler_properties,
-                    request.input_token_count_oneshot,
-                    &request.token_sender,
-                ),
-                Err(e) => Err(e.into()),
-            },
-            GenerationRequestInput::Text {
-                content,
-                multimodal_data,
-            } => run_for_multimodal(
-                &model,
-                content,
-                multimodal_data,
-                &request.sampler_properties,
-                request.input_token_count_oneshot,
-                &request.token_sender,
-            ),
+                sauropod_inference_engine_api::CancellationToken::new(),
+            )
+            .await
+            .unwrap()
+            .map(|token| token.unwrap())
+            .collect()
+            .await
+    }
+
+    /// Generate up to 64 tokens greedily from `prompt`.
+    async fn generate_greedily(
+        thread: Arc<ModelInferenceThread>,
+        prompt: &str,
+    ) -> Vec<sauropod_inference_engine_api::Token> {
+        generate_greedily_with_limit(thread, prompt, 64).await
+    }
+
+    /// Download the files of SmolLM2 135M.
+    async fn download_smollm(files: &[&str]) -> Vec<std::path::PathBuf> {
+        let repo = sauropod_config::HuggingfacePath {
+            repo: "unsloth/SmolLM2-135M-Instruct-GGUF".to_string(),
+            revision: None,
+            path_or_quantization: None,
         };
+        let repo_interface = sauropod_huggingface::RepositoryInterface::new().unwrap();
+        let repository_info = repo_interface.get_repository_metadata(&repo).await.unwrap();
+        repository_info.download(files).await.unwrap()
+    }
 
-        if let Err(decode_error) = maybe_error {
-            tracing::error!("Error during inference: {:#?}", decode_error);
-            // Send an error back to the sender
-            if let Err(send_error) = request.token_sender.blocking_send(Err(decode_error)) {
-                tracing::error!("Failed to send error back to sender: {:#?}", send_error);
-            }
+    #[tokio::test]
+    async fn test_concurrent_sequences_match_sequential_decoding() {
+        const CONTEXT_SIZE: usize = 256;
+        let model_files = download_smollm(&["SmolLM2-135M-Instruct-Q8_0.gguf"]).await;
+        let settings = crate::ModelSettings {
+            context_size: Some(CONTEXT_SIZE as u32),
+            ..Default::default()
+        };
+        let model = Arc::new(
+            crate::Model::from_file(&model_files[0], None, &settings)
+                .await
+                .unwrap(),
+        );
+        let thread =
+            Arc::new(ModelInferenceThread::new("batched".to_string(), model, None).unwrap());
+
+        let prompts = [
+            "List the days of the week.",
+            "Write a long story about a dinosaur.",
+            "Write a long poem about the sea.",
+            "Hi.",
+        ]
+        .map(|question| format!("<|im_start|>user\n{question}<|im_end|>\n<|im_start|>assistant\n"));
+        let lengths = prompts
+            .iter()
+            .map(|prompt| thread.model.tokenize(prompt).unwrap().len())
+            .collect::<Vec<_>>();
+        // The first three requests fill the context, so the last one, which needs as many cells
+        // as the short first request, is admitted when that request finishes.
+        let short_cells = lengths[0] + 8;
+        let remaining_cells = CONTEXT_SIZE - short_cells - lengths[1] - lengths[2];
+        let max_predicts = [
+            8,
+            remaining_cells / 2,
+            remaining_cells - remaining_cells / 2,
+            short_cells - lengths[3],
+        ];
+        let requests = prompts.into_iter().zip(max_predicts).collect::<Vec<_>>();
+
+        let mut expected = Vec::new();
+        for (prompt, max_predict) in &requests {
+            expected.push(generate_greedily_with_limit(thread.clone(), prompt, *max_predict).await);
+        }
+
+        // Record which request each token belongs to in the order they arrive.
+        let arrivals = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let tasks = requests
+            .iter()
+            .cloned()
+            .enumerate()
+            .map(|(index, (prompt, max_predict))| {
+                let thread = thread.clone();
+                let arrivals = arrivals.clone();
+                tokio::spawn(async move {
+                    use sauropod_inference_engine_api::LlmModel as _;
+                    use tokio_stream::StreamExt as _;
+
+                    let tokens = thread.model.tokenize(&prompt).unwrap();
+                    let sampler_properties = sauropod_inference_engine_api::SamplerProperties {
+                        top_k: Some(1),
+                        temperature: 0.0,
+                        max_predict,
+                        ..Default::default()
+                    };
+                    let mut stream = thread
+                        .generate_from_tokens(
+                            sampler_properties,
+                            tokens,
+                            sauropod_inference_engine_api::CancellationToken::new(),
+                        )
+                        .await
+                        .unwrap();
+                    let mut output = Vec::new();
+                    while let Some(token) = stream.next().await {
+                        output.push(token.unwrap());
+                        arrivals.lock().unwrap().push(index);
+                    }
+                    output
+                })
+            })
+            .collect::<Vec<_>>();
+        let mut outputs = Vec::new();
+        for task in tasks {
+            outputs.push(task.await.unwrap());
         }
+
+        assert!(expected.iter().all(|tokens| !tokens.is_empty()));
+        assert_eq!(outputs, expected);
+
+        let arrivals = arrivals.lock().unwrap();
+        let first = |index| arrivals.iter().position(|&i| i == index).unwrap();
+        let last = |index| arrivals.iter().rposition(|&i| i == index).unwrap();
+        // The first requests are decoded together.
+        assert!(first(1) < last(0), "{arrivals:?}");
+        // The short request finishes while the others keep generating.
+        assert!(last(0) < last(1) && last(0) < last(2), "{arrivals:?}");
+        // The request which didn't fit starts once the short request has finished.
+        assert!(last(0) < first(3), "{arrivals:?}");
     }
 
-    tracing::info!("LLM inference thread exiting");
-    Ok(())
+    #[tokio::test]
+    async fn test_speculative_decoding_matches_greedy_decoding() {
+        let model_files = download_smollm(&[
+            "SmolLM2-135M-Instruct-Q8_0.gguf",
+            "SmolLM2-135M-Instruct-Q4_K_M.gguf",
+        ])
+        .await;
+
+        let settings = crate::ModelSettings {
+            context_size: Some(2048),
+            draft_length: Some(4),
+            ..Default::default()
+        };
+        let model = Arc::new(
+            crate::Model::from_file(&model_files[0], None, &settings)
+                .await
+                .unwrap(),
+        );
+        // The draft is a smaller quantization, so some of its tokens are rejected.
+        let draft_model = crate::Model::draft_from_file(&model_files[1], &settings)
+            .await
+            .unwrap();
+        assert!(model.shares_vocabulary_with(&draft_model).unwrap());
+
+        let prompt =
+            "<|im_start|>user\nList the days of the week.<|im_end|>\n<|im_start|>assistant\n";
+        let greedy = ModelInferenceThread::new("greedy".to_string(), model.clone(), None).unwrap();
+        let expected = generate_greedily(Arc::new(greedy), prompt).await;
+
+        let speculative = ModelInferenceThread::new(
+            "speculative".to_string(),
+            model,
+            Some(Arc::new(draft_model)),
+        )
+        .unwrap();
+        let output = generate_greedily(Arc::new(speculative), prompt).await;
+
+        assert!(!expected.is_empty());
+        assert_eq!(output, expected);
+    }
 }
@@ -1,8 +1,10 @@
 //! Sauropod's bindings around [llama.cpp](https://github.com/ggml-org/llama.cpp).
 
+mod embedding_thread;
 mod inference_thread;
 mod mtmd;
 
+pub use embedding_thread::EmbeddingInferenceThread;
 pub use inference_thread::ModelInferenceThread;
 use mtmd::MtmdContext;
 
@@ -29,6 +31,8 @@ pub enum Error {
     FailedToCreateMtmdBitmap,
     #[error("Failed to create llama.cpp sampler")]
     FailedToCreateSampler,
+    #[error("Failed to parse the sampling grammar")]
+    InvalidGrammar,
     #[error("The model doesn't have a chat template")]
     NoChatTemplate,
     #[error("The model has an invalid chat template: {0}")]
@@ -41,6 +45,20 @@ pub enum Error {
     MtmdImagePreprocessingError,
     #[error("MTMD tokenization failed with error code {0}")]
     MtmdTokenizationError(i32),
+    #[error("Quantizing the KV cache requires flash attention")]
+    QuantizedKvCacheWithoutFlashAttention,
+    #[error("The draft model's vocabulary doesn't match the model's vocabulary")]
+    DraftVocabularyMismatch,
+    #[error("Failed to load LoRA adapter from {0}")]
+    FailedToLoadLoraAdapter(String),
+    #[error("The LoRA adapter {0} isn't loaded")]
+    LoraAdapterNotLoaded(String),
+    #[error("Failed to apply LoRA adapter {0}")]
+    FailedToApplyLoraAdapter(String),
+    #[error("The model doesn't use rank pooling, so it can't rerank documents")]
+    NotAReranker,
+    #[error("The last decoded batch has no logits at index {0}")]
+    MissingLogits(i32),
 }
 
 const TRACING_TARGET: &str = "llama.cpp";
@@ -127,6 +145,11 @@ impl Vocab {
     pub fn as_ptr(&self) -> *const llama_cpp_sys::llama_vocab {
         self.0
     }
+
+    /// The number of tokens in the vocabulary.
+    pub fn token_count(&self) -> usize {
+        unsafe { llama_cpp_sys::llama_vocab_n_tokens(self.0) }.max(0) as usize
+    }
 }
 
 #[repr(transparent)]
@@ -135,6 +158,11 @@ unsafe impl Send for Context {}
 unsafe impl Sync for Context {}
 
 impl Context {
+    /// The maximum number of tokens `llama_decode` accepts at once.
+    pub fn batch_size(&self) -> u32 {
+        unsafe { llama_cpp_sys::llama_n_batch(self.0) }
+    }
+
     pub fn context_size(&self) -> u32 {
         unsafe { llama_cpp_sys::llama_n_ctx(self.0) }
     }
@@ -142,6 +170,18 @@ impl Context {
     pub fn get_memory(&self) -> llama_cpp_sys::llama_memory_t {
         unsafe { llama_cpp_sys::llama_get_memory(self.0) }
     }
+
+    /// The logits at `index` of the last decoded batch, one per token of `vocab`.
+    pub fn logits(&self, index: i32, vocab: &Vocab) -> Result<&[f32], Error> {
+        unsafe {
+            let logits = llama_cpp_sys::llama_get_logits_ith(self.0, index);
+            if logits.is_null() {
+                return Err(Error::MissingLogits(index));
+            }
+            let vocab_size = llama_cpp_sys::llama_vocab_n_tokens(vocab.0) as usize;
+            Ok(std::slice::from_raw_parts(logits, vocab_size))
+        }
+    }
 }
 
 impl Drop for Context {
@@ -158,8 +198,13 @@ unsafe impl Send for Sampler {}
 unsafe impl Sync for Sampler {}
 
 impl Sampler {
+    /// Create the sampler chain for a generation.
+    ///
+    /// `training_context_size` bounds the tokens searched by DRY sampling.
     pub fn new(
         sampler_properties: &sauropod_inference_engine_api::SamplerProperties,
+        vocab: &Vocab,
+        training_context_size: u32,
     ) -> Result<Self, Error> {
         let sampler = unsafe {
             llama_cpp_sys::llama_sampler_chain_init(
@@ -169,79 +214,145 @@ impl Sampler {
         if sampler.is_null() {
             return Err(Error::FailedToCreateSampler);
         }
+        // Wrap the chain immediately so it's freed on error
+        let result = Self(sampler);
+        let vocab_size = unsafe { llama_cpp_sys::llama_vocab_n_tokens(vocab.0) };
+        let seed = sampler_properties
+            .seed
+            .unwrap_or(llama_cpp_sys::llama_default_seed);
 
-        if let Some(top_k) = sampler_properties.top_k {
-            unsafe {
-                llama_cpp_sys::llama_sampler_chain_add(
-                    sampler,
-                    llama_cpp_sys::llama_sampler_init_top_k(top_k as i32),
-                );
+        if let Some(grammar) = &sampler_properties.grammar {
+            let grammar = std::ffi::CString::new(grammar.as_str())?;
+            let grammar_sampler = unsafe {
+                llama_cpp_sys::llama_sampler_init_grammar(
+                    vocab.0,
+                    grammar.as_ptr(),
+                    c"root".as_ptr(),
+                )
+            };
+            if grammar_sampler.is_null() {
+                return Err(Error::InvalidGrammar);
             }
+            result.add(grammar_sampler);
         }
 
-        if let Some(top_p) = sampler_properties.top_p {
-            unsafe {
-                llama_cpp_sys::llama_sampler_chain_add(
-                    sampler,
-                    llama_cpp_sys::llama_sampler_init_top_p(top_p as f32, 1),
-                );
-            }
+        if !sampler_properties.logit_bias.is_empty() {
+            let logit_bias: Vec<_> = sampler_properties
+                .logit_bias
+                .iter()
+                .map(|&(token, bias)| llama_cpp_sys::llama_logit_bias {
+                    token: token as i32,
+                    bias,
+                })
+                .collect();
+            result.add(unsafe {
+                llama_cpp_sys::llama_sampler_init_logit_bias(
+                    vocab_size,
+                    logit_bias.len() as i32,
+                    logit_bias.as_ptr(),
+                )
+            });
         }
 
-        if let Some(min_p) = sampler_properties.min_p {
-            unsafe {
-                llama_cpp_sys::llama_sampler_chain_add(
-                    sampler,
-                    llama_cpp_sys::llama_sampler_init_min_p(min_p as f32, 1),
-                );
-            }
+        if sampler_properties.repetition_penalty.is_some()
+            || sampler_properties.frequency_penalty.is_some()
+            || sampler_properties.presence_penalty.is_some()
+        {
+            result.add(unsafe {
+                llama_cpp_sys::llama_sampler_init_penalties(
+                    sampler_properties.penalty_window as i32,
+                    sampler_properties.repetition_penalty.unwrap_or(1.0) as f32,
+                    sampler_properties.frequency_penalty.unwrap_or(0.0) as f32,
+                    sampler_properties.presence_penalty.unwrap_or(0.0) as f32,
+                )
+            });
         }
 
-        unsafe {
-            llama_cpp_sys::llama_sampler_chain_add(
-                sampler,
-                llama_cpp_sys::llama_sampler_init_temp(sampler_properties.temperature as f32),
-            );
+        if let Some(dry) = &sampler_properties.dry {
+            let sequence_breakers = dry
+                .sequence_breakers
+                .iter()
+                .map(|breaker| std::ffi::CString::new(breaker.as_str()))
+                .collect::<Result<Vec<_>, _>>()?;
+            let mut sequence_breaker_pointers: Vec<_> = sequence_breakers
+                .iter()
+                .map(|breaker| breaker.as_ptr())
+                .collect();
+            result.add(unsafe {
+                llama_cpp_sys::llama_sampler_init_dry(
+                    vocab.0,
+                    training_context_size as i32,
+                    dry.multiplier as f32,
+                    dry.base as f32,
+                    dry.allowed_length as i32,
+                    dry.penalty_window as i32,
+                    sequence_breaker_pointers.as_mut_ptr(),
+                    sequence_breaker_pointers.len(),
+                )
+            });
         }
 
-        let repetition_penalty = sampler_properties.repetition_penalty.unwrap_or(0.0);
-        unsafe {
-            llama_cpp_sys::llama_sampler_chain_add(
-                sampler,
-                llama_cpp_sys::llama_sampler_init_penalties(
-                    if sampler_properties.repetition_penalty.is_some() {
-                        1
-                    } else {
-                        0
-                    },
-                    repetition_penalty as f32,
-                    0.0,
-                    0.0,
-                ),
-            );
+        if let Some(mirostat) = &sampler_properties.mirostat {
+            // Mirostat picks the token itself and replaces the truncation samplers
+            result.add(unsafe {
+                llama_cpp_sys::llama_sampler_init_temp(sampler_properties.temperature as f32)
+            });
+            result.add(match mirostat.version {
+                sauropod_config::MirostatVersion::V1 => unsafe {
+                    llama_cpp_sys::llama_sampler_init_mirostat(
+                        vocab_size,
+                        seed,
+                        mirostat.tau as f32,
+                        mirostat.eta as f32,
+                        100,
+                    )
+                },
+                sauropod_config::MirostatVersion::V2 => unsafe {
+                    llama_cpp_sys::llama_sampler_init_mirostat_v2(
+                        seed,
+                        mirostat.tau as f32,
+                        mirostat.eta as f32,
+                    )
+                },
+            });
+            return Ok(result);
         }
 
-        unsafe {
-            llama_cpp_sys::llama_sampler_chain_add(
-                sampler,
-                llama_cpp_sys::llama_sampler_init_softmax(),
-            );
+        if let Some(top_k) = sampler_properties.top_k {
+            result.add(unsafe { llama_cpp_sys::llama_sampler_init_top_k(top_k as i32) });
+        }
 
-            llama_cpp_sys::llama_sampler_chain_add(
-                sampler,
-                llama_cpp_sys::llama_sampler_init_dist(llama_cpp_sys::llama_default_seed),
-            );
+        if let Some(typical_p) = sampler_properties.typical_p {
+            result.add(unsafe { llama_cpp_sys::llama_sampler_init_typical(typical_p as f32, 1) });
         }
 
-        Ok(Self(sampler))
-    }
+        if let Some(top_p) = sampler_properties.top_p {
+            result.add(unsafe { llama_cpp_sys::llama_sampler_init_top_p(top_p as f32, 1) });
+        }
 
-    pub fn sample(&self, context: &Context) -> i32 {
-        let token = unsafe { llama_cpp_sys::llama_sampler_sample(self.0, context.0, -1) };
-        unsafe {
-            llama_cpp_sys::llama_sampler_accept(self.0, token);
+        if let Some(min_p) = sampler_properties.min_p {
+            result.add(unsafe { llama_cpp_sys::llama_sampler_init_min_p(min_p as f32, 1) });
         }
-        token
+
+        result.add(unsafe {
+            llama_cpp_sys::llama_sampler_init_temp(sampler_properties.temperature as f32)
+        });
+        result.add(unsafe { llama_cpp_sys::llama_sampler_init_softmax() });
+        result.add(unsafe { llama_cpp_sys::llama_sampler_init_dist(seed) });
+
+        Ok(result)
+    }
+
+    /// Add a sampler to the end of the chain, which takes ownership of it.
+    fn add(&self, sampler: *mut llama_cpp_sys::llama_sampler) {
+        unsafe { llama_cpp_sys::llama_sampler_chain_add(self.0, sampler) };
+    }
+
+    /// Sample a token from the logits at `index` of the last decoded batch.
+    ///
+    /// `llama_sampler_sample` accepts the token, which advances the grammar and penalty state.
+    pub fn sample(&self, context: &Context, index: i32) -> i32 {
+        unsafe { llama_cpp_sys::llama_sampler_sample(self.0, context.0, index) }
     }
 }
 
@@ -294,34 +405,217 @@ fn get_devices() -> Vec<llama_cpp_sys::ggml_backend_dev_t> {
 
 pub struct OwnedBatch(pub llama_cpp_sys::llama_batch);
 
+impl OwnedBatch {
+    /// Allocate a token batch with room for `capacity` tokens of one sequence each.
+    pub fn new(capacity: i32) -> Self {
+        Self(unsafe { llama_cpp_sys::llama_batch_init(capacity, 0, 1) })
+    }
+
+    /// The number of tokens in the batch.
+    pub fn len(&self) -> usize {
+        self.0.n_tokens as usize
+    }
+
+    /// Whether the batch is empty.
+    pub fn is_empty(&self) -> bool {
+        self.0.n_tokens == 0
+    }
+
+    /// Remove all tokens from the batch.
+    pub fn clear(&mut self) {
+        self.0.n_tokens = 0;
+    }
+
+    /// Append a token for `seq_id` to the batch.
+    ///
+    /// # Safety
+    ///
+    /// The batch must have been allocated with room for more than [`OwnedBatch::len`] tokens.
+    pub unsafe fn push(
+        &mut self,
+        token: llama_cpp_sys::llama_token,
+        position: llama_cpp_sys::llama_pos,
+        seq_id: llama_cpp_sys::llama_seq_id,
+        logits: bool,
+    ) {
+        let index = self.len();
+        unsafe {
+            *self.0.token.add(index) = token;
+            *self.0.pos.add(index) = position;
+            *self.0.n_seq_id.add(index) = 1;
+            **self.0.seq_id.add(index) = seq_id;
+            *self.0.logits.add(index) = logits as i8;
+        }
+        self.0.n_tokens += 1;
+    }
+}
+
 impl Drop for OwnedBatch {
     fn drop(&mut self) {
         unsafe { llama_cpp_sys::llama_batch_free(self.0) };
     }
 }
 
+/// The maximum context size of an embedding model.
+///
+/// Non-causal models need the whole batch in one micro-batch, so this bounds the compute buffer.
+const MAX_EMBEDDING_CONTEXT_SIZE: u32 = 8192;
+
+/// The maximum difference between the vocabulary sizes of a model and its draft model.
+const MAX_DRAFT_VOCABULARY_DIFFERENCE: usize = 128;
+
+/// Get the pooling type from the value of the `<architecture>.pooling_type` GGUF key.
+fn pooling_type_from_metadata(value: i64) -> llama_cpp_sys::llama_pooling_type {
+    match value {
+        2 => llama_cpp_sys::llama_pooling_type::LLAMA_POOLING_TYPE_CLS,
+        3 => llama_cpp_sys::llama_pooling_type::LLAMA_POOLING_TYPE_LAST,
+        4 => llama_cpp_sys::llama_pooling_type::LLAMA_POOLING_TYPE_RANK,
+        // Embeddings are always pooled per sequence, so models without pooling use the mean
+        _ => llama_cpp_sys::llama_pooling_type::LLAMA_POOLING_TYPE_MEAN,
+    }
+}
+
+/// Settings for loading a model and creating its contexts.
+#[derive(Clone, Debug, Default)]
+pub struct ModelSettings {
+    /// The maximum number of tokens in the context, or the training context size if `None`.
+    pub context_size: Option<u32>,
+    /// The number of layers offloaded to the GPU, or all layers if `None`.
+    pub gpu_layers: Option<u32>,
+    /// The number of CPU threads, or llama.cpp's default if `None`.
+    pub threads: Option<u32>,
+    /// Whether to use flash attention, which is enabled if `None`.
+    pub flash_attention: Option<bool>,
+    /// The data type of the KV cache, or f16 if `None`.
+    pub kv_cache_type: Option<sauropod_config::KvCacheType>,
+    /// The maximum number of tokens drafted per step with a draft model.
+    pub draft_length: Option<u32>,
+}
+
+impl From<&sauropod_config::ModelConfig> for ModelSettings {
+    fn from(model_config: &sauropod_config::ModelConfig) -> Self {
+        Self {
+            context_size: model_config.context_size,
+            gpu_layers: model_config.gpu_layers,
+            threads: model_config.threads,
+            flash_attention: model_config.flash_attention,
+            kv_cache_type: model_config.kv_cache_type,
+            draft_length: model_config.draft_length,
+        }
+    }
+}
+
+impl ModelSettings {
+    /// The maximum number of tokens drafted per step, 8 if unset.
+    pub fn draft_length(&self) -> usize {
+        self.draft_length.map_or(8, |length| length as usize)
+    }
+
+    fn flash_attention(&self) -> bool {
+        self.flash_attention.unwrap_or(true)
+    }
+
+    fn kv_cache_type(&self) -> llama_cpp_sys::ggml_type {
+        match self.kv_cache_type {
+            None | Some(sauropod_config::KvCacheType::F16) => {
+                llama_cpp_sys::ggml_type::GGML_TYPE_F16
+            }
+            Some(sauropod_config::KvCacheType::Q8_0) => llama_cpp_sys::ggml_type::GGML_TYPE_Q8_0,
+            Some(sauropod_config::KvCacheType::Q4_0) => llama_cpp_sys::ggml_type::GGML_TYPE_Q4_0,
+        }
+    }
+}
+
+/// A LoRA adapter loaded for a model.
+struct LoraAdapter(*mut llama_cpp_sys::llama_adapter_lora);
+unsafe impl Send for LoraAdapter {}
+
+impl Drop for LoraAdapter {
+    fn drop(&mut self) {
+        if !self.0.is_null() {
+            unsafe { llama_cpp_sys::llama_adapter_lora_free(self.0) };
+        }
+    }
+}
+
 pub struct Model {
     ptr: *mut llama_cpp_sys::llama_model,
-    chat_template: String,
+    chat_template: Option<String>,
     model_type: sauropod_output_parser::ModelType,
+    pooling_type: llama_cpp_sys::llama_pooling_type,
     mtmd_context: Option<MtmdContext>,
+    settings: ModelSettings,
+    /// The LoRA adapters loaded for the model, which any of its contexts can apply.
+    lora_adapters: std::sync::Mutex<
+        std::collections::HashMap<sauropod_config::ConfigModelSource, LoraAdapter>,
+    >,
 }
 unsafe impl Send for Model {}
 unsafe impl Sync for Model {}
 
 impl Model {
-    /// Create a new model from a file.
+    /// Create a new chat model from a file.
     pub async fn from_file(
         path: &std::path::Path,
         projector: Option<&std::path::Path>,
+        settings: &ModelSettings,
+    ) -> Result<Self, Error> {
+        let model = Self::load(path, projector, settings).await?;
+        if model.chat_template.is_none() {
+            return Err(Error::NoChatTemplate);
+        }
+        Ok(model)
+    }
+
+    /// Create a draft model for speculative decoding from a file.
+    ///
+    /// Draft models don't need a chat template since they only continue prompts rendered for the
+    /// model they draft for.
+    pub async fn draft_from_file(
+        path: &std::path::Path,
+        settings: &ModelSettings,
+    ) -> Result<Self, Error> {
+        Self::load(path, None, settings).await
+    }
+
+    /// Create a new embedding model from a file.
+    pub async fn embedding_from_file(path: &std::path::Path) -> Result<Self, Error> {
+        Self::load(path, None, &ModelSettings::default()).await
+    }
+
+    /// Create a new reranking model, whose GGUF metadata must select rank pooling, from a file.
+    pub async fn reranker_from_file(path: &std::path::Path) -> Result<Self, Error> {
+        let model = Self::load(path, None, &ModelSettings::default()).await?;
+        if !model.is_reranker() {
+            return Err(Error::NotAReranker);
+        }
+        Ok(model)
+    }
+
+    /// Load a model and its metadata from a file.
+    async fn load(
+        path: &std::path::Path,
+        projector: Option<&std::path::Path>,
+        settings: &ModelSettings,
     ) -> Result<Self, Error> {
         init();
 
+        // llama.cpp only supports a quantized V cache with flash attention
+        if !matches!(
+            settings.kv_cache_type,
+            None | Some(sauropod_config::KvCacheType::F16)
+        ) && !settings.flash_attention()
+        {
+            return Err(Error::QuantizedKvCacheWithoutFlashAttention);
+        }
+
         let mut devices = get_devices();
         let mut progress_bar = indicatif::ProgressBar::new(u8::MAX as u64);
 
         let mut init_params = unsafe { llama_cpp_sys::llama_model_default_params() };
-        init_params.n_gpu_layers = i32::MAX;
+        init_params.n_gpu_layers = settings
+            .gpu_layers
+            .map_or(i32::MAX, |layers| layers.min(i32::MAX as u32) as i32);
         init_params.progress_callback = Some(log_progress);
         init_params.progress_callback_user_data =
             &mut progress_bar as *mut _ as *mut std::os::raw::c_void;
@@ -342,6 +636,8 @@ impl Model {
         let mut metadata = sauropod_gguf::GgufMetadataParser::from_file(path).await?;
         let mut model_architecture = None;
         let mut model_chat_template = None;
+        let mut pooling_type = None;
+        let mut format_tokens = Vec::new();
         while let Some(entry) = metadata.get_next().await? {
             if entry.key == sauropod_gguf::CHAT_TEMPLATE_KEY {
                 if let sauropod_gguf::GgufValue::String(template) = entry.value {
@@ -361,16 +657,40 @@ impl Model {
                         entry.value
                     );
                 }
+            } else if entry.key == sauropod_gguf::TOKENS_KEY {
+                if let sauropod_gguf::GgufValue::Array(tokens) = entry.value {
+                    format_tokens.extend(tokens.into_iter().filter_map(|token| match token {
+                        sauropod_gguf::GgufValue::String(token)
+                            if sauropod_output_parser::FORMAT_TOKENS.contains(&token.as_str()) =>
+                        {
+                            Some(token)
+                        }
+                        _ => None,
+                    }));
+                } else {
+                    tracing::error!("Expected tokens to be an array");
+                }
+            } else if entry.key.ends_with(".pooling_type") {
+                match entry.value {
+                    sauropod_gguf::GgufValue::UInt32(value) => pooling_type = Some(value as i64),
+                    sauropod_gguf::GgufValue::Int32(value) => pooling_type = Some(value as i64),
+                    _ => tracing::error!(
+                        "Expected pooling type to be an integer, but got: {:#?}",
+                        entry.value
+                    ),
+                }
             }
         }
 
-        let model_type = match model_architecture.as_deref() {
-            Some(x) if x.starts_with("gemma") => {
-                sauropod_output_parser::ModelType::MarkdownToolCall
-            }
-            Some(x) if x.starts_with("qwen") => sauropod_output_parser::ModelType::Qwen3,
-            _ => sauropod_output_parser::ModelType::Unknown,
-        };
+        let model_type = sauropod_output_parser::detect_model_type(
+            model_architecture.as_deref(),
+            model_chat_template.as_deref(),
+            &format_tokens,
+        );
+        tracing::debug!(
+            "Detected the output format of {}: {model_type:?}",
+            path.display()
+        );
 
         let mtmd_context = if let Some(projector) = projector {
             let mut context_params = unsafe { llama_cpp_sys::mtmd_context_params_default() };
@@ -389,17 +709,23 @@ impl Model {
 
         Ok(Self {
             ptr: ctx,
-            chat_template: match model_chat_template {
-                Some(template) => template,
-                None => {
-                    return Err(Error::NoChatTemplate);
-                }
-            },
+            chat_template: model_chat_template,
             model_type,
+            pooling_type: pooling_type.map_or(
+                llama_cpp_sys::llama_pooling_type::LLAMA_POOLING_TYPE_MEAN,
+                pooling_type_from_metadata,
+            ),
             mtmd_context,
+            settings: settings.clone(),
+            lora_adapters: Default::default(),
         })
     }
 
+    /// The chat template from the model's metadata.
+    pub fn chat_template(&self) -> &str {
+        self.chat_template.as_deref().unwrap_or_default()
+    }
+
     pub fn get_vocab(&self) -> Result<Vocab, Error> {
         let vocab = unsafe { llama_cpp_sys::llama_model_get_vocab(self.ptr) };
         if vocab.is_null() {
@@ -413,6 +739,60 @@ impl Model {
     pub fn tokenize(
         &self,
         prompt: &str,
+    ) -> Result<Vec<sauropod_inference_engine_api::Token>, Error> {
+        self.tokenize_with_special_tokens(prompt, true)
+    }
+
+    /// Tokenize a query and a document into the input of a reranking model.
+    ///
+    /// The query and the document are separated by the special tokens of the vocabulary, as in
+    /// llama.cpp's server. Special tokens in the texts themselves are not parsed.
+    pub fn tokenize_rerank_input(
+        &self,
+        query: &str,
+        document: &str,
+    ) -> Result<Vec<sauropod_inference_engine_api::Token>, Error> {
+        let vocab = self.get_vocab()?;
+        let query = self.tokenize_with_special_tokens(query, false)?;
+        let document = self.tokenize_with_special_tokens(document, false)?;
+        let (add_bos, add_eos, add_sep) = unsafe {
+            (
+                llama_cpp_sys::llama_vocab_get_add_bos(vocab.0),
+                llama_cpp_sys::llama_vocab_get_add_eos(vocab.0),
+                llama_cpp_sys::llama_vocab_get_add_sep(vocab.0),
+            )
+        };
+        let (bos, eos, sep) = unsafe {
+            (
+                llama_cpp_sys::llama_vocab_bos(vocab.0) as sauropod_inference_engine_api::Token,
+                llama_cpp_sys::llama_vocab_eos(vocab.0) as sauropod_inference_engine_api::Token,
+                llama_cpp_sys::llama_vocab_sep(vocab.0) as sauropod_inference_engine_api::Token,
+            )
+        };
+
+        let mut tokens = Vec::with_capacity(query.len() + document.len() + 4);
+        if add_bos {
+            tokens.push(bos);
+        }
+        tokens.extend(query);
+        if add_eos {
+            tokens.push(eos);
+        }
+        if add_sep {
+            tokens.push(sep);
+        }
+        tokens.extend(document);
+        if add_eos {
+            tokens.push(eos);
+        }
+        Ok(tokens)
+    }
+
+    /// Tokenize a prompt, adding and parsing special tokens if `special` is set.
+    fn tokenize_with_special_tokens(
+        &self,
+        prompt: &str,
+        special: bool,
     ) -> Result<Vec<sauropod_inference_engine_api::Token>, Error> {
         let c_str = std::ffi::CString::new(prompt).unwrap();
         let vocab = self.get_vocab()?;
@@ -423,8 +803,8 @@ impl Model {
                 c_str.as_bytes().len() as i32,
                 std::ptr::null_mut(),
                 0,
-                true, // add_special
-                true, // parse_special
+                special, // add_special
+                special, // parse_special
             )
         };
         let mut tokens =
@@ -437,8 +817,8 @@ impl Model {
                 c_str.as_bytes().len() as i32,
                 tokens.as_mut_ptr() as *mut i32,
                 token_count,
-                true, // add_special
-                true, // parse_special
+                special, // add_special
+                special, // parse_special
             )
         };
         if tokenization_result < 0 {
@@ -448,13 +828,20 @@ impl Model {
         Ok(tokens)
     }
 
-    fn llama_context(&self, prompt_length: i64, prediction_length: i64) -> Result<Context, Error> {
+    /// Create a context shared by up to `max_sequences` sequences.
+    fn llama_context(&self, context_size: u32, max_sequences: u32) -> Result<Context, Error> {
         let mut context_params = unsafe { llama_cpp_sys::llama_context_default_params() };
-        context_params.n_ctx = prompt_length as u32 + prediction_length as u32 - 1;
-        context_params.flash_attn = true;
-        context_params.n_batch = (prompt_length as u32).max(llama_cpp_sys::ggml_kq_mask_pad);
+        context_params.n_ctx = context_size;
+        context_params.flash_attn = self.settings.flash_attention();
+        context_params.type_k = self.settings.kv_cache_type();
+        context_params.type_v = self.settings.kv_cache_type();
+        if let Some(threads) = self.settings.threads {
+            context_params.n_threads = threads as i32;
+            context_params.n_threads_batch = threads as i32;
+        }
+        context_params.n_batch = context_params.n_batch.max(llama_cpp_sys::ggml_kq_mask_pad);
         context_params.n_ubatch = llama_cpp_sys::ggml_kq_mask_pad;
-        context_params.n_seq_max = 1;
+        context_params.n_seq_max = max_sequences;
         context_params.kv_unified = true;
         context_params.swa_full = true;
         context_params.no_perf = false;
@@ -466,6 +853,144 @@ impl Model {
         Ok(Context(ctx))
     }
 
+    /// Load the LoRA adapter `adapter` from the file at `path` unless it's already loaded.
+    pub fn load_lora_adapter(
+        &self,
+        adapter: &sauropod_config::ConfigModelSource,
+        path: &std::path::Path,
+    ) -> Result<(), Error> {
+        let mut lora_adapters = self.lora_adapters.lock().unwrap();
+        if lora_adapters.contains_key(adapter) {
+            return Ok(());
+        }
+
+        let path_str = std::ffi::CString::new(path.as_os_str().as_encoded_bytes())?;
+        let lora_adapter =
+            unsafe { llama_cpp_sys::llama_adapter_lora_init(self.ptr, path_str.as_ptr()) };
+        if lora_adapter.is_null() {
+            return Err(Error::FailedToLoadLoraAdapter(format!(
+                "{}",
+                path.display()
+            )));
+        }
+        tracing::debug!("Loaded LoRA adapter {adapter} from {}", path.display());
+        lora_adapters.insert(adapter.clone(), LoraAdapter(lora_adapter));
+        Ok(())
+    }
+
+    /// Whether the LoRA adapter `adapter` is loaded.
+    pub fn has_lora_adapter(&self, adapter: &sauropod_config::ConfigModelSource) -> bool {
+        self.lora_adapters.lock().unwrap().contains_key(adapter)
+    }
+
+    /// Replace the LoRA adapters applied by `context` with `adapters`.
+    fn apply_lora_adapters(
+        &self,
+        context: &Context,
+        adapters: &[sauropod_config::LoraAdapterConfig],
+    ) -> Result<(), Error> {
+        let lora_adapters = self.lora_adapters.lock().unwrap();
+        unsafe { llama_cpp_sys::llama_clear_adapter_lora(context.0) };
+        for adapter in adapters {
+            let Some(lora_adapter) = lora_adapters.get(&adapter.adapter) else {
+                unsafe { llama_cpp_sys::llama_clear_adapter_lora(context.0) };
+                return Err(Error::LoraAdapterNotLoaded(adapter.adapter.to_string()));
+            };
+            if unsafe {
+                llama_cpp_sys::llama_set_adapter_lora(context.0, lora_adapter.0, adapter.scale)
+            } != 0
+            {
+                unsafe { llama_cpp_sys::llama_clear_adapter_lora(context.0) };
+                return Err(Error::FailedToApplyLoraAdapter(adapter.adapter.to_string()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether `draft` tokenizes text into the same tokens as this model.
+    ///
+    /// Vocabularies may be padded with a few unused tokens, so only the shared tokens must match.
+    fn shares_vocabulary_with(&self, draft: &Model) -> Result<bool, Error> {
+        let vocab = self.get_vocab()?;
+        let draft_vocab = draft.get_vocab()?;
+        let token_count = vocab.token_count();
+        let draft_token_count = draft_vocab.token_count();
+        if token_count.abs_diff(draft_token_count) > MAX_DRAFT_VOCABULARY_DIFFERENCE {
+            return Ok(false);
+        }
+
+        let same_type = unsafe {
+            llama_cpp_sys::llama_vocab_type(vocab.0)
+                == llama_cpp_sys::llama_vocab_type(draft_vocab.0)
+        };
+        let same_tokens = (0..token_count.min(draft_token_count) as i32).all(|token| unsafe {
+            std::ffi::CStr::from_ptr(llama_cpp_sys::llama_vocab_get_text(vocab.0, token))
+                == std::ffi::CStr::from_ptr(llama_cpp_sys::llama_vocab_get_text(
+                    draft_vocab.0,
+                    token,
+                ))
+        });
+        Ok(same_type && same_tokens)
+    }
+
+    /// Create a context which outputs pooled embeddings for up to `max_sequences` sequences.
+    fn embedding_context(&self, max_sequences: u32) -> Result<Context, Error> {
+        let context_size = self.training_context_size().min(MAX_EMBEDDING_CONTEXT_SIZE);
+        let mut context_params = unsafe { llama_cpp_sys::llama_context_default_params() };
+        context_params.n_ctx = context_size;
+        context_params.n_batch = context_size;
+        context_params.n_ubatch = context_size;
+        context_params.n_seq_max = max_sequences;
+        context_params.kv_unified = true;
+        context_params.embeddings = true;
+        context_params.pooling_type = self.pooling_type;
+        context_params.no_perf = false;
+
+        let ctx = unsafe { llama_cpp_sys::llama_init_from_model(self.ptr, context_params) };
+        if ctx.is_null() {
+            return Err(Error::FailedToCreateContext);
+        }
+        Ok(Context(ctx))
+    }
+
+    /// The number of dimensions of the model's embeddings.
+    ///
+    /// Reranking models output one score per classifier output instead.
+    pub fn embedding_dimensions(&self) -> usize {
+        if self.is_reranker() {
+            unsafe { llama_cpp_sys::llama_model_n_cls_out(self.ptr) as usize }
+        } else {
+            unsafe { llama_cpp_sys::llama_model_n_embd(self.ptr) }.max(0) as usize
+        }
+    }
+
+    /// Whether the model scores inputs with rank pooling.
+    pub fn is_reranker(&self) -> bool {
+        self.pooling_type == llama_cpp_sys::llama_pooling_type::LLAMA_POOLING_TYPE_RANK
+    }
+
+    /// Whether the model has an encoder which must be run with `llama_encode`.
+    pub fn has_encoder(&self) -> bool {
+        unsafe { llama_cpp_sys::llama_model_has_encoder(self.ptr) }
+    }
+
+    /// The context size the model was trained with.
+    pub fn training_context_size(&self) -> u32 {
+        unsafe { llama_cpp_sys::llama_model_n_ctx_train(self.ptr) }.max(0) as u32
+    }
+
+    /// The configured context size, if any.
+    pub fn configured_context_size(&self) -> Option<u32> {
+        self.settings.context_size
+    }
+
+    /// The maximum number of tokens in a context.
+    pub fn max_context_size(&self) -> u32 {
+        self.settings
+            .context_size
+            .unwrap_or_else(|| self.training_context_size())
+    }
+
     /// Whether the model supports vision.
     pub fn supports_vision(&self) -> bool {
         self.mtmd_context
@@ -499,6 +1024,8 @@ impl Model {
 
 impl Drop for Model {
     fn drop(&mut self) {
+        // Adapters must be freed before the model they belong to
+        self.lora_adapters.get_mut().unwrap().clear();
         if !self.ptr.is_null() {
             unsafe { llama_cpp_sys::llama_model_free(self.ptr) };
         }
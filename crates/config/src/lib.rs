@@ -4,6 +4,8 @@ use std::{collections::HashMap, path::PathBuf};
 
 mod model_source;
 pub use model_source::*;
+mod schema;
+pub use schema::*;
 
 #[derive(Clone, Debug, Default)]
 pub struct ClapConfigSource {
@@ -32,12 +34,185 @@ impl ClapConfigSource {
     }
 }
 
+/// What a model is used for.
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    Hash,
+    serde::Deserialize,
+    serde::Serialize,
+    utoipa::ToSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum ModelKind {
+    /// A chat model used by the Responses and Chat Completions APIs.
+    #[default]
+    Chat,
+    /// An embedding model used by the Embeddings API.
+    Embedding,
+    /// A reranking model used by the Rerank API.
+    Rerank,
+}
+
+/// The text opening and closing a section of a model's output.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize, utoipa::ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct DelimitersConfig {
+    /// The text opening the section.
+    pub start: String,
+    /// The text closing the section.
+    pub end: String,
+}
+
+/// How the output of a model is parsed into text, reasoning and tool calls.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputParserConfig {
+    /// Reasoning in `<think>` and tool calls in `<tool_call>` tags.
+    Qwen3,
+    /// Tool calls in a ```` ```tool_call ```` Markdown block.
+    Markdown,
+    /// The harmony response format of gpt-oss.
+    Harmony,
+    /// Llama 3 JSON function calls.
+    Llama3,
+    /// Mistral `[TOOL_CALLS]` arrays.
+    Mistral,
+    /// Hermes-style `<tool_call>` tags.
+    Hermes,
+    /// DeepSeek-R1 reasoning.
+    DeepseekR1,
+    /// Output is only text.
+    None,
+    /// Custom delimiters.
+    Custom {
+        /// The delimiters of the reasoning.
+        #[serde(default)]
+        reasoning: Option<DelimitersConfig>,
+        /// The delimiters of a tool call containing a JSON object with the `name` and `arguments` of the call.
+        #[serde(default)]
+        tool_call: Option<DelimitersConfig>,
+    },
+}
+
+/// DRY ("don't repeat yourself") sampling, which penalizes extending sequences that already occurred.
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize, utoipa::ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct DryConfig {
+    /// The strength of the penalty.
+    pub multiplier: f64,
+    /// The base of the penalty, which grows exponentially with the length of the repetition.
+    #[serde(default = "DryConfig::default_base")]
+    pub base: f64,
+    /// The length of repeated sequences which isn't penalized.
+    #[serde(default = "DryConfig::default_allowed_length")]
+    pub allowed_length: i64,
+    /// The number of recent tokens searched for repetitions, -1 for the whole context.
+    #[serde(default = "DryConfig::default_penalty_window")]
+    pub penalty_window: i64,
+    /// Text which ends the sequences searched for repetitions.
+    #[serde(default = "DryConfig::default_sequence_breakers")]
+    pub sequence_breakers: Vec<String>,
+}
+
+impl DryConfig {
+    fn default_base() -> f64 {
+        1.75
+    }
+
+    fn default_allowed_length() -> i64 {
+        2
+    }
+
+    fn default_penalty_window() -> i64 {
+        -1
+    }
+
+    fn default_sequence_breakers() -> Vec<String> {
+        ["\n", ":", "\"", "*"].map(String::from).to_vec()
+    }
+}
+
+/// The version of the Mirostat algorithm.
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize, utoipa::ToSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum MirostatVersion {
+    V1,
+    V2,
+}
+
+/// Mirostat sampling, which targets a perplexity instead of using top-k, top-p, min-p and typical-p.
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize, utoipa::ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct MirostatConfig {
+    /// The version of the algorithm.
+    #[serde(default = "MirostatConfig::default_version")]
+    pub version: MirostatVersion,
+    /// The target surprise of the output.
+    #[serde(default = "MirostatConfig::default_tau")]
+    pub tau: f64,
+    /// The learning rate.
+    #[serde(default = "MirostatConfig::default_eta")]
+    pub eta: f64,
+}
+
+impl MirostatConfig {
+    fn default_version() -> MirostatVersion {
+        MirostatVersion::V2
+    }
+
+    fn default_tau() -> f64 {
+        5.0
+    }
+
+    fn default_eta() -> f64 {
+        0.1
+    }
+}
+
+/// The data type of the keys and values stored in the KV cache.
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize, utoipa::ToSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum KvCacheType {
+    F16,
+    Q8_0,
+    Q4_0,
+}
+
+/// A LoRA adapter which can be applied to a model.
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize, utoipa::ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct LoraAdapterConfig {
+    /// The path or Hugging Face file of the adapter in GGUF format.
+    pub adapter: ConfigModelSource,
+    /// How strongly the adapter is applied.
+    #[serde(default = "LoraAdapterConfig::default_scale")]
+    pub scale: f32,
+}
+
+impl LoraAdapterConfig {
+    fn default_scale() -> f32 {
+        1.0
+    }
+}
+
 /// Configuration for a model.
-#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize, utoipa::ToSchema)]
 #[serde(deny_unknown_fields)]
 pub struct ModelConfig {
     /// The path or Hugging Face repo of the model.
     pub model: ConfigModelSource,
+    /// What the model is used for.
+    #[serde(default)]
+    pub kind: ModelKind,
     /// The project to use for multimodal models.
     pub multimodal_projector: Option<ConfigModelSource>,
     /// The system prompt for the model.
@@ -52,14 +227,69 @@ pub struct ModelConfig {
     /// The top_k sampling parameter for the model.
     pub top_k: Option<i64>,
     /// The minimum probability for the model.
-    pub min_p: Option<i64>,
+    pub min_p: Option<f64>,
+    /// The seed of the random number generator used for sampling.
+    pub seed: Option<u32>,
+    /// The penalty for tokens proportional to how often they occurred.
+    pub frequency_penalty: Option<f64>,
+    /// The penalty for tokens which occurred.
+    pub presence_penalty: Option<f64>,
+    /// The penalty dividing the probability of tokens which occurred.
+    pub repetition_penalty: Option<f64>,
+    /// The number of recent tokens considered by the penalties, -1 for the whole context.
+    pub penalty_window: Option<i64>,
+    /// The locally typical sampling parameter.
+    pub typical_p: Option<f64>,
+    /// DRY sampling settings.
+    pub dry: Option<DryConfig>,
+    /// Mirostat sampling settings.
+    pub mirostat: Option<MirostatConfig>,
+    /// Biases added to the logits of tokens, keyed by token ID.
+    #[serde(default)]
+    pub logit_bias: HashMap<String, f64>,
+    /// Text which ends the output when the model produces it.
+    #[serde(default)]
+    pub stop: Vec<String>,
     /// Jinja template for the chat.
     #[serde(default)]
     pub chat_template: Option<String>,
+    /// How to parse the output of the model instead of detecting it from the model file.
+    #[serde(default)]
+    pub output_parser: Option<OutputParserConfig>,
+    /// The maximum number of tokens in the context, shared by concurrent requests.
+    ///
+    /// Defaults to the context size the model was trained with.
+    pub context_size: Option<u32>,
+    /// The number of layers offloaded to the GPU, or all layers if unset.
+    pub gpu_layers: Option<u32>,
+    /// The number of CPU threads used for inference.
+    pub threads: Option<u32>,
+    /// Whether to use flash attention.
+    pub flash_attention: Option<bool>,
+    /// The data type of the KV cache.
+    pub kv_cache_type: Option<KvCacheType>,
+    /// A smaller model sharing the vocabulary of `model` which drafts tokens for speculative
+    /// decoding.
+    pub draft_model: Option<ConfigModelSource>,
+    /// The maximum number of tokens drafted per decoding step.
+    pub draft_length: Option<u32>,
+    /// The LoRA adapters which requests can apply to the model, by name.
+    #[serde(default)]
+    pub lora_adapters: HashMap<String, LoraAdapterConfig>,
+    /// The names of the LoRA adapters applied unless a request selects its own.
+    #[serde(default)]
+    pub active_lora_adapters: Vec<String>,
+    /// Whether to load the model on its first request instead of at startup.
+    #[serde(default)]
+    pub lazy: bool,
+    /// The number of seconds without requests after which the model is unloaded.
+    ///
+    /// Unloaded models are loaded again on their next request.
+    pub idle_timeout_seconds: Option<u64>,
 }
 
 /// Voice model configuration.
-#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize, utoipa::ToSchema)]
 #[serde(deny_unknown_fields, tag = "type", rename_all = "snake_case")]
 pub enum VoiceConfig {
     Kokoro {
@@ -98,7 +328,7 @@ impl VoiceConfig {
 }
 
 /// Speech to text model configuration.
-#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize, utoipa::ToSchema)]
 #[serde(deny_unknown_fields, tag = "type", rename_all = "snake_case")]
 pub enum SpeechToTextConfig {
     Parakeet {
@@ -138,7 +368,9 @@ impl SpeechToTextConfig {
 }
 
 /// Configuration for authentication.
-#[derive(Clone, Default, Debug, serde::Deserialize, serde::Serialize)]
+#[derive(
+    Clone, Default, Debug, PartialEq, serde::Deserialize, serde::Serialize, utoipa::ToSchema,
+)]
 #[serde(deny_unknown_fields, tag = "type", rename_all = "snake_case")]
 pub enum AuthenticationConfig {
     /// A single hard-coded API key.
@@ -151,7 +383,7 @@ pub enum AuthenticationConfig {
 }
 
 /// Sauropod configuration.
-#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize, utoipa::ToSchema)]
 #[serde(deny_unknown_fields)]
 pub struct Config {
     /// Whether to log verbosely.
@@ -185,6 +417,15 @@ pub struct Config {
     pub vad_model: Option<ConfigModelSource>,
     #[serde(default)]
     pub authentication: AuthenticationConfig,
+    /// The memory in MiB the models in `models` may use together.
+    ///
+    /// The least recently used models are unloaded to make room for a model being loaded. The
+    /// voices and the speech-to-text and VAD models stay loaded, so they aren't counted.
+    #[serde(default)]
+    pub model_memory_budget_mib: Option<u64>,
+    /// The API key of the admin API, which is disabled if unset.
+    #[serde(default)]
+    pub admin_api_key: Option<String>,
 }
 
 impl Config {
@@ -248,8 +489,8 @@ impl Config {
         Ok(settings.try_deserialize::<Config>()?)
     }
 
-    /// Load the configuration.
-    pub fn load(cli_overrides: ClapConfigSource) -> anyhow::Result<Self> {
+    /// The path of the configuration file loaded by `Config::load`.
+    pub fn default_file_path() -> anyhow::Result<PathBuf> {
         let dirs = match directories::ProjectDirs::from("io", "sauropod", "sauropod") {
             Some(dirs) => dirs,
             None => {
@@ -257,14 +498,56 @@ impl Config {
             }
         };
 
-        let config = dirs.config_dir().join("config.toml");
-
         // Create the configuration directory.
         if !dirs.config_dir().exists() {
             std::fs::create_dir_all(dirs.config_dir())?;
         }
 
-        Self::load_from_file(config, cli_overrides)
+        Ok(dirs.config_dir().join("config.toml"))
+    }
+
+    /// Load the configuration.
+    pub fn load(cli_overrides: ClapConfigSource) -> anyhow::Result<Self> {
+        Self::load_from_file(Self::default_file_path()?, cli_overrides)
+    }
+
+    /// Check the settings which can't be checked while deserializing.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(self.vad_model.is_some(), "A VAD model must be configured");
+        if let AuthenticationConfig::ApiKey { api_key } = &self.authentication {
+            anyhow::ensure!(!api_key.is_empty(), "The API key must not be empty");
+        }
+        if let Some(admin_api_key) = &self.admin_api_key {
+            anyhow::ensure!(
+                !admin_api_key.is_empty(),
+                "The admin API key must not be empty"
+            );
+        }
+        for (name, model_config) in &self.models {
+            for adapter in &model_config.active_lora_adapters {
+                anyhow::ensure!(
+                    model_config.lora_adapters.contains_key(adapter),
+                    "The active LoRA adapter '{adapter}' of {name} isn't configured"
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the settings which only apply at startup differ from the `previous` configuration.
+    ///
+    /// `models`, `voices`, `authentication`, `stt_model` and `vad_model` are applied while the
+    /// server runs.
+    pub fn requires_restart(&self, previous: &Config) -> bool {
+        let reloaded = Config {
+            models: previous.models.clone(),
+            voices: previous.voices.clone(),
+            authentication: previous.authentication.clone(),
+            stt_model: previous.stt_model.clone(),
+            vad_model: previous.vad_model.clone(),
+            ..self.clone()
+        };
+        reloaded != *previous
     }
 }
 
@@ -281,6 +564,218 @@ impl Default for Config {
             stt_model: Self::default_stt_model(),
             vad_model: Self::default_vad_model(),
             authentication: AuthenticationConfig::default(),
+            model_memory_budget_mib: None,
+            admin_api_key: None,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_model_config(toml: &str) -> ModelConfig {
+        config::Config::builder()
+            .add_source(config::File::from_str(toml, config::FileFormat::Toml))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_output_parser_config() {
+        let model_config = parse_model_config(
+            r#"
+            model = "model.gguf"
+            output_parser = "harmony"
+            "#,
+        );
+        assert_eq!(
+            model_config.output_parser,
+            Some(OutputParserConfig::Harmony)
+        );
+
+        let model_config = parse_model_config(
+            r#"
+            model = "model.gguf"
+
+            [output_parser.custom]
+            tool_call = { start = "<function_call>", end = "</function_call>" }
+            "#,
+        );
+        assert_eq!(
+            model_config.output_parser,
+            Some(OutputParserConfig::Custom {
+                reasoning: None,
+                tool_call: Some(DelimitersConfig {
+                    start: "<function_call>".to_string(),
+                    end: "</function_call>".to_string(),
+                }),
+            })
+        );
+    }
+
+    #[test]
+    fn test_sampling_config() {
+        let model_config = parse_model_config(
+            r#"
+            model = "model.gguf"
+            min_p = 0.05
+            seed = 42
+            stop = ["</answer>"]
+            logit_bias = { "15043" = -100.0 }
+            dry = { multiplier = 0.8 }
+            mirostat = { tau = 4.0 }
+            "#,
+        );
+        assert_eq!(model_config.min_p, Some(0.05));
+        assert_eq!(model_config.seed, Some(42));
+        assert_eq!(model_config.stop, vec!["</answer>".to_string()]);
+        assert_eq!(model_config.logit_bias.get("15043"), Some(&-100.0));
+
+        let dry = model_config.dry.unwrap();
+        assert_eq!(dry.multiplier, 0.8);
+        assert_eq!(dry.allowed_length, 2);
+        assert_eq!(dry.sequence_breakers.len(), 4);
+
+        let mirostat = model_config.mirostat.unwrap();
+        assert_eq!(mirostat.version, MirostatVersion::V2);
+        assert_eq!(mirostat.tau, 4.0);
+        assert_eq!(mirostat.eta, 0.1);
+    }
+
+    #[test]
+    fn test_runtime_config() {
+        let model_config = parse_model_config(
+            r#"
+            model = "model.gguf"
+            context_size = 16384
+            gpu_layers = 20
+            threads = 8
+            flash_attention = false
+            kv_cache_type = "q8_0"
+            "#,
+        );
+        assert_eq!(model_config.context_size, Some(16384));
+        assert_eq!(model_config.gpu_layers, Some(20));
+        assert_eq!(model_config.threads, Some(8));
+        assert_eq!(model_config.flash_attention, Some(false));
+        assert_eq!(model_config.kv_cache_type, Some(KvCacheType::Q8_0));
+    }
+
+    #[test]
+    fn test_draft_model_config() {
+        let model_config = parse_model_config(
+            r#"
+            model = "model.gguf"
+            draft_model = "draft.gguf"
+            draft_length = 4
+            "#,
+        );
+        assert!(model_config.draft_model.is_some());
+        assert_eq!(model_config.draft_length, Some(4));
+
+        let model_config = parse_model_config(
+            r#"
+            model = "model.gguf"
+            "#,
+        );
+        assert!(model_config.draft_model.is_none());
+    }
+
+    #[test]
+    fn test_lazy_loading_config() {
+        let model_config = parse_model_config(
+            r#"
+            model = "model.gguf"
+            lazy = true
+            idle_timeout_seconds = 600
+            "#,
+        );
+        assert!(model_config.lazy);
+        assert_eq!(model_config.idle_timeout_seconds, Some(600));
+
+        let model_config = parse_model_config(
+            r#"
+            model = "model.gguf"
+            "#,
+        );
+        assert!(!model_config.lazy);
+        assert_eq!(model_config.idle_timeout_seconds, None);
+    }
+
+    #[test]
+    fn test_validate_config() {
+        let mut config = Config::default();
+        assert!(config.validate().is_ok());
+
+        config.models.insert(
+            "qwen3".to_string(),
+            parse_model_config(
+                r#"
+                model = "model.gguf"
+                active_lora_adapters = ["support"]
+                "#,
+            ),
+        );
+        assert!(config.validate().is_err());
+
+        let mut config = Config {
+            authentication: AuthenticationConfig::ApiKey {
+                api_key: String::new(),
+            },
+            ..Config::default()
+        };
+        assert!(config.validate().is_err());
+
+        config.authentication = AuthenticationConfig::Database;
+        config.vad_model = None;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_requires_restart() {
+        let previous = Config::default();
+        let mut config = Config {
+            authentication: AuthenticationConfig::Database,
+            stt_model: None,
+            ..Config::default()
+        };
+        config.models.insert(
+            "qwen3".to_string(),
+            parse_model_config("model = \"model.gguf\""),
+        );
+        assert!(!config.requires_restart(&previous));
+
+        config.port = 3000;
+        assert!(config.requires_restart(&previous));
+    }
+
+    #[test]
+    fn test_lora_adapter_config() {
+        let model_config = parse_model_config(
+            r#"
+            model = "model.gguf"
+            active_lora_adapters = ["support"]
+
+            [lora_adapters]
+            support = { adapter = "support.gguf", scale = 0.5 }
+            legal = { adapter = { repo = "example/legal-lora", file = "legal.gguf" } }
+            "#,
+        );
+        assert_eq!(model_config.active_lora_adapters, vec!["support"]);
+        assert_eq!(model_config.lora_adapters["support"].scale, 0.5);
+        assert_eq!(model_config.lora_adapters["legal"].scale, 1.0);
+        assert_eq!(
+            model_config.lora_adapters["legal"].adapter,
+            ConfigModelSource::HuggingFace(HuggingfacePath {
+                repo: "example/legal-lora".to_string(),
+                revision: None,
+                path_or_quantization: Some(PathOrQuantization::FilePath {
+                    file: "legal.gguf".to_string(),
+                }),
+            })
+        );
+    }
+}
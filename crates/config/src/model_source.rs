@@ -1,4 +1,6 @@
-#[derive(Clone, Debug, PartialEq, Eq, Hash, serde::Deserialize, serde::Serialize)]
+#[derive(
+    Clone, Debug, PartialEq, Eq, Hash, serde::Deserialize, serde::Serialize, utoipa::ToSchema,
+)]
 #[serde(untagged)]
 pub enum ConfigModelSource {
     /// A model from a local path.
@@ -34,6 +36,45 @@ pub struct HuggingfacePath {
     pub path_or_quantization: Option<PathOrQuantization>,
 }
 
+impl utoipa::PartialSchema for HuggingfacePath {
+    fn schema() -> utoipa::openapi::RefOr<utoipa::openapi::schema::Schema> {
+        use utoipa::openapi::schema::{ObjectBuilder, Type};
+
+        // The flattened `path_or_quantization` can't be derived, so its fields are listed instead
+        let string = |description: &str| {
+            ObjectBuilder::new()
+                .schema_type(Type::String)
+                .description(Some(description))
+        };
+        ObjectBuilder::new()
+            .description(Some("A model from a Hugging Face repository."))
+            .property(
+                "repo",
+                string(
+                    "The repository name, for example \"meta-llama/Llama-4-Scout-17B-16E-Instruct\".",
+                ),
+            )
+            .required("repo")
+            .property(
+                "revision",
+                string(
+                    "The revision of the repository, for example \"main\" or a specific commit hash.",
+                ),
+            )
+            .property(
+                "quantization",
+                string("The quantization type, for example \"Q8_0\"."),
+            )
+            .property(
+                "file",
+                string("The file path, for example \"model.onnx\"."),
+            )
+            .into()
+    }
+}
+
+impl utoipa::ToSchema for HuggingfacePath {}
+
 impl std::str::FromStr for HuggingfacePath {
     type Err = anyhow::Error;
 
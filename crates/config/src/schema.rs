@@ -0,0 +1,101 @@
+//! JSON Schema of the configuration file.
+
+use utoipa::{PartialSchema as _, ToSchema as _};
+
+use crate::Config;
+
+/// The JSON Schema dialect of the schema, which OpenAPI 3.1 schemas are compatible with.
+const JSON_SCHEMA_DIALECT: &str = "https://json-schema.org/draft/2020-12/schema";
+
+/// Generate a JSON Schema describing `Config`, for editors to validate and complete config files.
+pub fn json_schema() -> serde_json::Value {
+    let mut schemas = Vec::new();
+    Config::schemas(&mut schemas);
+    let mut definitions = serde_json::Map::new();
+    for (name, schema) in schemas {
+        definitions.insert(name, serde_json::to_value(schema).unwrap_or_default());
+    }
+    definitions.insert(
+        Config::name().to_string(),
+        serde_json::to_value(Config::schema()).unwrap_or_default(),
+    );
+
+    let mut schema = serde_json::json!({
+        "$schema": JSON_SCHEMA_DIALECT,
+        "title": "Sauropod configuration",
+        "$ref": format!("#/$defs/{}", Config::name()),
+        "$defs": definitions,
+    });
+    // The schemas reference each other as OpenAPI components
+    rewrite_references(&mut schema);
+    schema
+}
+
+/// Point the references to OpenAPI components at the definitions of the JSON Schema.
+fn rewrite_references(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(object) => {
+            for (key, value) in object.iter_mut() {
+                if key == "$ref"
+                    && let serde_json::Value::String(reference) = value
+                    && let Some(name) = reference.strip_prefix("#/components/schemas/")
+                {
+                    *reference = format!("#/$defs/{name}");
+                } else {
+                    rewrite_references(value);
+                }
+            }
+        }
+        serde_json::Value::Array(array) => array.iter_mut().for_each(rewrite_references),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Collect the references of a schema.
+    fn references<'a>(value: &'a serde_json::Value, output: &mut Vec<&'a str>) {
+        match value {
+            serde_json::Value::Object(object) => {
+                for (key, value) in object {
+                    match value {
+                        serde_json::Value::String(reference) if key == "$ref" => {
+                            output.push(reference)
+                        }
+                        _ => references(value, output),
+                    }
+                }
+            }
+            serde_json::Value::Array(array) => {
+                array.iter().for_each(|value| references(value, output))
+            }
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn test_json_schema() {
+        let schema = json_schema();
+        let definitions = schema["$defs"].as_object().unwrap();
+        for name in ["Config", "ModelConfig", "ConfigModelSource", "VoiceConfig"] {
+            assert!(definitions.contains_key(name), "{name} is missing");
+        }
+        assert!(
+            definitions["Config"]["properties"]["models"].is_object(),
+            "{:#}",
+            definitions["Config"]
+        );
+
+        let mut output = Vec::new();
+        references(&schema, &mut output);
+        assert!(!output.is_empty());
+        for reference in output {
+            let name = reference
+                .strip_prefix("#/$defs/")
+                .unwrap_or_else(|| panic!("{reference} isn't a definition"));
+            assert!(definitions.contains_key(name), "{reference} is missing");
+        }
+    }
+}
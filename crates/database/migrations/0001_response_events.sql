@@ -0,0 +1,8 @@
+-- Stream events of background responses, which are replayed to clients that reconnect
+CREATE TABLE IF NOT EXISTS "response_event"
+(
+    response_id     TEXT NOT NULL REFERENCES "response"(response_id) ON DELETE CASCADE,
+    sequence_number INTEGER NOT NULL,
+    event           TEXT NOT NULL,
+    PRIMARY KEY (response_id, sequence_number)
+);
@@ -2,6 +2,7 @@ use tokio::io::{AsyncRead, AsyncReadExt};
 
 pub const CHAT_TEMPLATE_KEY: &str = "tokenizer.chat_template";
 pub const ARCHITECTURE_KEY: &str = "general.architecture";
+pub const TOKENS_KEY: &str = "tokenizer.ggml.tokens";
 
 const MAX_STRING_LENGTH: u64 = 1_000_000;
 
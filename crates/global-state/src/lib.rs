@@ -9,14 +9,42 @@ use sauropod_inference_engine::Model;
 /// Global state accessor for Axum routes.
 pub type AxumGlobalState = axum::extract::State<Arc<GlobalState>>;
 
+/// A response which is being generated.
+struct InFlightResponse {
+    /// The user who requested the response.
+    user_id: sauropod_users::UserId,
+    /// Stops the generation of the response.
+    cancellation_token: tokio_util::sync::CancellationToken,
+}
+
+/// Keeps a response cancellable by ID until it's dropped.
+pub struct InFlightResponseGuard {
+    global_state: Arc<GlobalState>,
+    response_id: String,
+}
+
+impl Drop for InFlightResponseGuard {
+    fn drop(&mut self) {
+        self.global_state
+            .in_flight_responses
+            .lock()
+            .unwrap()
+            .remove(&self.response_id);
+    }
+}
+
 /// The global state of the application.
 pub struct GlobalState {
-    /// The configuration.
-    pub config: sauropod_config::Config,
+    /// The configuration, which is replaced when it's reloaded.
+    config: std::sync::RwLock<Arc<sauropod_config::Config>>,
+    /// Held while reloading the configuration so that reloads apply one after another.
+    reloading: tokio::sync::Mutex<()>,
     /// The database instance.
     database: sauropod_database::Database,
     /// The loaded models.
     loaded_models: sauropod_model_loading::LoadedModels,
+    /// The responses which are being generated by ID.
+    in_flight_responses: std::sync::Mutex<std::collections::HashMap<String, InFlightResponse>>,
 }
 
 impl GlobalState {
@@ -48,12 +76,94 @@ impl GlobalState {
             .await?;
 
         Ok(Self {
-            config: config.clone(),
+            config: std::sync::RwLock::new(Arc::new(config.clone())),
+            reloading: tokio::sync::Mutex::new(()),
             database,
             loaded_models,
+            in_flight_responses: Default::default(),
         })
     }
 
+    /// Get the configuration in effect.
+    pub fn config(&self) -> Arc<sauropod_config::Config> {
+        self.config.read().unwrap().clone()
+    }
+
+    /// Apply a new configuration while the server runs.
+    ///
+    /// Only the changes to `models`, `voices`, `authentication`, `stt_model` and `vad_model` are
+    /// applied, and the other settings need a restart. The running configuration is kept if the
+    /// new one is invalid or its VAD, STT or voice models fail to load. Models which fail to
+    /// load are reported in the error, while the other changes are applied.
+    pub async fn reload_config(&self, config: sauropod_config::Config) -> anyhow::Result<()> {
+        config.validate()?;
+        let _reloading = self.reloading.lock().await;
+        let previous = self.config();
+        if config.requires_restart(&previous) {
+            tracing::warn!(
+                "Only models, voices, authentication, stt_model and vad_model are reloaded - restart the server to apply the other changes"
+            );
+        }
+
+        self.loaded_models
+            .reload_audio_models(&previous, &config)
+            .instrument(tracing::info_span!("Reload audio models"))
+            .await?;
+        let models_reload = self
+            .loaded_models
+            .reload_models(&previous.models, &config.models)
+            .instrument(tracing::info_span!("Reload models"))
+            .await;
+
+        let mut changes = Vec::new();
+        for (description, names) in [
+            ("added", &models_reload.changes.added),
+            ("replaced", &models_reload.changes.replaced),
+            ("removed", &models_reload.changes.removed),
+        ] {
+            if !names.is_empty() {
+                changes.push(format!("{description} {}", names.join(", ")));
+            }
+        }
+        for (setting, changed) in [
+            ("voices", config.voices != previous.voices),
+            (
+                "authentication",
+                config.authentication != previous.authentication,
+            ),
+            ("stt_model", config.stt_model != previous.stt_model),
+            ("vad_model", config.vad_model != previous.vad_model),
+        ] {
+            if changed {
+                changes.push(format!("changed {setting}"));
+            }
+        }
+
+        *self.config.write().unwrap() = Arc::new(sauropod_config::Config {
+            models: models_reload.model_configs,
+            voices: config.voices,
+            authentication: config.authentication,
+            stt_model: config.stt_model,
+            vad_model: config.vad_model,
+            ..(*previous).clone()
+        });
+
+        if changes.is_empty() {
+            tracing::info!("Reloaded the configuration without changes");
+        } else {
+            tracing::info!("Reloaded the configuration: {}", changes.join("; "));
+        }
+        if !models_reload.errors.is_empty() {
+            let errors: Vec<String> = models_reload
+                .errors
+                .iter()
+                .map(|(name, e)| format!("{name}: {e:#}"))
+                .collect();
+            anyhow::bail!("Failed to load models - {}", errors.join("; "));
+        }
+        Ok(())
+    }
+
     /// Get a reference to the database.
     pub fn database(&self) -> &sauropod_database::Database {
         &self.database
@@ -64,6 +174,22 @@ impl GlobalState {
         self.loaded_models.get_model(model_name).await
     }
 
+    /// Get a loaded embedding model by name.
+    pub async fn get_embedding_model(
+        &self,
+        model_name: &str,
+    ) -> Option<sauropod_inference_engine::EmbeddingModelPointer> {
+        self.loaded_models.get_embedding_model(model_name).await
+    }
+
+    /// Get a loaded reranking model by name.
+    pub async fn get_rerank_model(
+        &self,
+        model_name: &str,
+    ) -> Option<sauropod_inference_engine::RerankModelPointer> {
+        self.loaded_models.get_rerank_model(model_name).await
+    }
+
     /// Get a loaded voice model by name.
     pub async fn get_voice_model(
         &self,
@@ -72,15 +198,48 @@ impl GlobalState {
         self.loaded_models.get_voice_model(model_name).await
     }
 
-    /// Get all the loaded models.
-    pub async fn get_all_models(
-        &self,
-    ) -> tokio::sync::RwLockReadGuard<'_, std::collections::HashMap<String, Arc<Model>>> {
-        self.loaded_models.get_all_models().await
+    /// Get the names of all the configured models.
+    pub async fn get_model_names(&self) -> Vec<String> {
+        self.loaded_models.get_model_names().await
     }
 
     /// Get all the loaded models.
     pub fn get_loaded_models(&self) -> &sauropod_model_loading::LoadedModels {
         &self.loaded_models
     }
+
+    /// Track a response which is being generated so that it can be cancelled by its ID.
+    ///
+    /// The response is tracked until the returned guard is dropped.
+    pub fn track_response(
+        self: &Arc<Self>,
+        response_id: String,
+        user_id: sauropod_users::UserId,
+        cancellation_token: tokio_util::sync::CancellationToken,
+    ) -> InFlightResponseGuard {
+        self.in_flight_responses.lock().unwrap().insert(
+            response_id.clone(),
+            InFlightResponse {
+                user_id,
+                cancellation_token,
+            },
+        );
+        InFlightResponseGuard {
+            global_state: self.clone(),
+            response_id,
+        }
+    }
+
+    /// Cancel a response of a user which is being generated.
+    ///
+    /// Returns false if the user has no such response in progress.
+    pub fn cancel_response(&self, response_id: &str, user_id: sauropod_users::UserId) -> bool {
+        match self.in_flight_responses.lock().unwrap().get(response_id) {
+            Some(response) if response.user_id == user_id => {
+                response.cancellation_token.cancel();
+                true
+            }
+            _ => false,
+        }
+    }
 }
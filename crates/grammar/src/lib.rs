@@ -0,0 +1,799 @@
+//! Conversion of JSON schemas to [GBNF](https://github.com/ggml-org/llama.cpp/blob/master/grammars/README.md) grammars.
+//!
+//! The conversion follows llama.cpp's `json_schema_to_grammar` so the grammars can be used for constrained sampling.
+
+use std::collections::{BTreeMap, HashMap};
+
+/// The name of the rule that a grammar starts from.
+pub const ROOT_RULE: &str = "root";
+
+/// Error type.
+#[derive(thiserror::Error, Debug)]
+pub enum GrammarError {
+    #[error("Only local JSON schema references are supported, got {0}")]
+    UnsupportedReference(String),
+    #[error("Failed to resolve JSON schema reference {0}")]
+    UnresolvedReference(String),
+    #[error("Unsupported JSON schema type {0}")]
+    UnsupportedType(String),
+    #[error("The JSON schema can never be satisfied")]
+    Unsatisfiable,
+    #[error("Invalid JSON schema: {0}")]
+    InvalidSchema(String),
+}
+
+/// Rules shared by JSON grammars and the rules they depend on.
+const PRIMITIVE_RULES: &[(&str, &str, &[&str])] = &[
+    ("space", r#"| " " | "\n" [ \t]{0,20}"#, &[]),
+    ("boolean", r#"("true" | "false") space"#, &["space"]),
+    ("decimal-part", "[0-9]{1,16}", &[]),
+    ("integral-part", "[0] | [1-9] [0-9]{0,15}", &[]),
+    (
+        "number",
+        r#"("-"? integral-part) ("." decimal-part)? ([eE] [-+]? integral-part)? space"#,
+        &["integral-part", "decimal-part", "space"],
+    ),
+    (
+        "integer",
+        r#"("-"? integral-part) space"#,
+        &["integral-part", "space"],
+    ),
+    (
+        "value",
+        "object | array | string | number | boolean | null",
+        &["object", "array", "string", "number", "boolean", "null"],
+    ),
+    (
+        "object",
+        r#""{" space ( string ":" space value ("," space string ":" space value)* )? "}" space"#,
+        &["string", "value", "space"],
+    ),
+    (
+        "array",
+        r#""[" space ( value ("," space value)* )? "]" space"#,
+        &["value", "space"],
+    ),
+    (
+        "char",
+        r#"[^"\\\x7F\x00-\x1F] | [\\] (["\\bfnrt] | "u" [0-9a-fA-F]{4})"#,
+        &[],
+    ),
+    ("string", r#""\"" char* "\"" space"#, &["char", "space"]),
+    ("null", r#""null" space"#, &["space"]),
+];
+
+/// Format text as a GBNF string literal.
+pub fn literal(text: &str) -> String {
+    let mut result = String::with_capacity(text.len() + 2);
+    result.push('"');
+    for c in text.chars() {
+        match c {
+            '\\' => result.push_str(r"\\"),
+            '"' => result.push_str(r#"\""#),
+            '\n' => result.push_str(r"\n"),
+            '\r' => result.push_str(r"\r"),
+            '\t' => result.push_str(r"\t"),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+/// The GBNF repetition operator for `min` to `max` occurrences.
+fn repetition_suffix(min: usize, max: Option<usize>) -> String {
+    match (min, max) {
+        (0, None) => "*".to_string(),
+        (1, None) => "+".to_string(),
+        (0, Some(1)) => "?".to_string(),
+        (min, None) => format!("{{{min},}}"),
+        (min, Some(max)) if min == max => format!("{{{min}}}"),
+        (min, Some(max)) => format!("{{{min},{max}}}"),
+    }
+}
+
+/// Build an expression matching `min` to `max` occurrences of `item` joined by `separator`.
+fn build_repetition(item: &str, min: usize, max: Option<usize>, separator: &str) -> String {
+    if max == Some(0) {
+        return String::new();
+    }
+    if separator.is_empty() {
+        return format!("{item}{}", repetition_suffix(min, max));
+    }
+
+    let rest = build_repetition(
+        &format!("( {separator} {item} )"),
+        min.saturating_sub(1),
+        max.map(|max| max - 1),
+        "",
+    );
+    let result = if rest.is_empty() {
+        item.to_string()
+    } else {
+        format!("{item} {rest}")
+    };
+    if min == 0 {
+        format!("( {result} )?")
+    } else {
+        result
+    }
+}
+
+/// Escape a character for use in a GBNF character class.
+fn escape_char_class(c: char) -> String {
+    match c {
+        '\\' | ']' | '^' | '-' => format!("\\{c}"),
+        '\n' => r"\n".to_string(),
+        '\r' => r"\r".to_string(),
+        '\t' => r"\t".to_string(),
+        c => c.to_string(),
+    }
+}
+
+/// Replace characters which aren't allowed in rule names.
+fn sanitize_rule_name(name: &str) -> String {
+    let sanitized: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect();
+    if sanitized.is_empty() {
+        "rule".to_string()
+    } else {
+        sanitized
+    }
+}
+
+/// Get an unsigned integer keyword of a schema.
+fn get_count(schema: &serde_json::Map<String, serde_json::Value>, key: &str) -> Option<usize> {
+    schema
+        .get(key)
+        .and_then(|value| value.as_u64())
+        .map(|value| value as usize)
+}
+
+/// A GBNF grammar under construction.
+#[derive(Default)]
+pub struct GrammarBuilder {
+    rules: BTreeMap<String, String>,
+    /// The schema that `$ref`s are resolved against.
+    root_schema: serde_json::Value,
+    /// The rule names of the `$ref`s which have been converted.
+    refs: HashMap<String, String>,
+}
+
+impl GrammarBuilder {
+    /// Create an empty grammar.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a rule and return its name.
+    ///
+    /// The name is made unique if a different rule with the same name already exists.
+    pub fn add_rule(&mut self, name: &str, body: &str) -> String {
+        let name = sanitize_rule_name(name);
+        let mut unique_name = name.clone();
+        let mut index = 0;
+        while let Some(existing) = self.rules.get(&unique_name) {
+            if existing == body {
+                return unique_name;
+            }
+            index += 1;
+            unique_name = format!("{name}{index}");
+        }
+        self.rules.insert(unique_name.clone(), body.to_string());
+        unique_name
+    }
+
+    /// Reserve an unused rule name so that the rule can refer to itself before its body is known.
+    fn reserve_rule(&mut self, name: &str) -> String {
+        let name = sanitize_rule_name(name);
+        let mut unique_name = name.clone();
+        let mut index = 0;
+        while self.rules.contains_key(&unique_name) {
+            index += 1;
+            unique_name = format!("{name}{index}");
+        }
+        self.rules.insert(unique_name.clone(), String::new());
+        unique_name
+    }
+
+    /// Add one of the JSON primitive rules, such as `space` or `string`, and the rules it depends on.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `name` isn't a primitive rule.
+    pub fn add_primitive(&mut self, name: &str) -> String {
+        if !self.rules.contains_key(name) {
+            let (_, body, dependencies) = PRIMITIVE_RULES
+                .iter()
+                .find(|(primitive, _, _)| *primitive == name)
+                .expect("primitive rule exists");
+            self.rules.insert(name.to_string(), body.to_string());
+            for dependency in *dependencies {
+                self.add_primitive(dependency);
+            }
+        }
+        name.to_string()
+    }
+
+    /// Add a rule matching any text which doesn't contain `excluded` and return its name.
+    ///
+    /// The rule is built from the states of a string matching automaton for `excluded`, where each
+    /// state is the length of the prefix of `excluded` matched so far.
+    pub fn add_text_excluding(&mut self, name: &str, excluded: &str) -> String {
+        let pattern: Vec<char> = excluded.chars().collect();
+        if pattern.is_empty() {
+            return self.add_rule(name, r"[^\x00]*");
+        }
+        let mut pattern_chars = pattern.clone();
+        pattern_chars.sort_unstable();
+        pattern_chars.dedup();
+
+        // The state after reading `c` is the longest prefix of the pattern which ends the text read.
+        let next_state = |state: usize, c: char| {
+            let mut text = pattern[..state].to_vec();
+            text.push(c);
+            (1..=text.len().min(pattern.len()))
+                .rev()
+                .find(|&length| text[text.len() - length..] == pattern[..length])
+                .unwrap_or(0)
+        };
+
+        let states: Vec<String> = (0..pattern.len())
+            .map(|state| self.reserve_rule(&format!("{name}-{state}")))
+            .collect();
+        let other_chars: String = pattern_chars
+            .iter()
+            .map(|c| escape_char_class(*c))
+            .collect();
+        for (state, rule) in states.iter().enumerate() {
+            let mut alternatives = vec![format!("[^{other_chars}] {}", states[0])];
+            for c in &pattern_chars {
+                let next = next_state(state, *c);
+                if next < pattern.len() {
+                    alternatives.push(format!("{} {}", literal(&c.to_string()), states[next]));
+                }
+            }
+            self.rules
+                .insert(rule.clone(), format!("( {} )?", alternatives.join(" | ")));
+        }
+        states[0].clone()
+    }
+
+    /// Add the rules matching JSON which conforms to `schema` and return the name of the rule.
+    pub fn add_json_schema(
+        &mut self,
+        schema: &serde_json::Value,
+        name: &str,
+    ) -> Result<String, GrammarError> {
+        self.root_schema = schema.clone();
+        self.refs.clear();
+        self.visit(schema, name)
+    }
+
+    /// Add a rule for a schema and return its name.
+    fn visit(&mut self, schema: &serde_json::Value, name: &str) -> Result<String, GrammarError> {
+        let body = self.visit_body(schema, name)?;
+        // Refer to primitives and references directly instead of aliasing them.
+        if name != ROOT_RULE && self.rules.contains_key(&body) {
+            return Ok(body);
+        }
+        Ok(self.add_rule(name, &body))
+    }
+
+    /// Get the rule body for a schema.
+    fn visit_body(
+        &mut self,
+        schema: &serde_json::Value,
+        name: &str,
+    ) -> Result<String, GrammarError> {
+        let schema = match schema {
+            serde_json::Value::Bool(true) => return Ok(self.add_primitive("value")),
+            serde_json::Value::Bool(false) => return Err(GrammarError::Unsatisfiable),
+            serde_json::Value::Object(schema) => schema,
+            other => {
+                return Err(GrammarError::InvalidSchema(format!(
+                    "expected an object or a boolean, got {other}"
+                )));
+            }
+        };
+        let prefix = if name == ROOT_RULE {
+            String::new()
+        } else {
+            format!("{name}-")
+        };
+
+        if let Some(reference) = schema.get("$ref").and_then(|value| value.as_str()) {
+            return self.visit_reference(reference);
+        }
+
+        if let Some(alternatives) = schema
+            .get("oneOf")
+            .or_else(|| schema.get("anyOf"))
+            .and_then(|value| value.as_array())
+        {
+            return self.visit_alternatives(alternatives, name);
+        }
+
+        if let Some(parts) = schema.get("allOf").and_then(|value| value.as_array()) {
+            let merged = self.merge_all_of(parts)?;
+            return self.visit_body(&merged, name);
+        }
+
+        if let Some(value) = schema.get("const") {
+            self.add_primitive("space");
+            return Ok(format!("{} space", literal(&value.to_string())));
+        }
+
+        if let Some(values) = schema.get("enum").and_then(|value| value.as_array()) {
+            let alternatives = values
+                .iter()
+                .map(|value| literal(&value.to_string()))
+                .collect::<Vec<_>>()
+                .join(" | ");
+            self.add_primitive("space");
+            return Ok(format!("({alternatives}) space"));
+        }
+
+        let schema_type = schema.get("type");
+        if let Some(types) = schema_type.and_then(|value| value.as_array()) {
+            let alternatives = types
+                .iter()
+                .map(|schema_type| {
+                    let mut alternative = schema.clone();
+                    alternative.insert("type".to_string(), schema_type.clone());
+                    serde_json::Value::Object(alternative)
+                })
+                .collect::<Vec<_>>();
+            return self.visit_alternatives(&alternatives, name);
+        }
+
+        let schema_type = match schema_type {
+            None => None,
+            Some(serde_json::Value::String(schema_type)) => Some(schema_type.as_str()),
+            Some(other) => {
+                return Err(GrammarError::InvalidSchema(format!(
+                    "type must be a string or an array, got {other}"
+                )));
+            }
+        };
+
+        match schema_type {
+            None | Some("object")
+                if schema.contains_key("properties")
+                    || schema
+                        .get("additionalProperties")
+                        .is_some_and(|additional| additional != &serde_json::Value::Bool(true)) =>
+            {
+                let empty = serde_json::Map::new();
+                let properties = schema
+                    .get("properties")
+                    .and_then(|value| value.as_object())
+                    .unwrap_or(&empty);
+                let required = schema
+                    .get("required")
+                    .and_then(|value| value.as_array())
+                    .map(|required| {
+                        required
+                            .iter()
+                            .filter_map(|value| value.as_str())
+                            .collect::<Vec<_>>()
+                    })
+                    .unwrap_or_default();
+                self.build_object_body(
+                    properties,
+                    &required,
+                    name,
+                    schema.get("additionalProperties"),
+                )
+            }
+            Some("object") => Ok(self.add_primitive("object")),
+            None | Some("array")
+                if schema.contains_key("items") || schema.contains_key("prefixItems") =>
+            {
+                self.build_array_body(schema, &prefix)
+            }
+            Some("array") if schema.contains_key("minItems") || schema.contains_key("maxItems") => {
+                self.build_array_body(schema, &prefix)
+            }
+            Some("array") => Ok(self.add_primitive("array")),
+            Some("string")
+                if schema.contains_key("minLength") || schema.contains_key("maxLength") =>
+            {
+                let char_rule = self.add_primitive("char");
+                self.add_primitive("space");
+                let repetition = build_repetition(
+                    &char_rule,
+                    get_count(schema, "minLength").unwrap_or(0),
+                    get_count(schema, "maxLength"),
+                    "",
+                );
+                Ok(format!(r#""\"" {repetition} "\"" space"#))
+            }
+            Some(primitive @ ("string" | "number" | "integer" | "boolean" | "null")) => {
+                Ok(self.add_primitive(primitive))
+            }
+            None => Ok(self.add_primitive("value")),
+            Some(other) => Err(GrammarError::UnsupportedType(other.to_string())),
+        }
+    }
+
+    /// Get the rule body matching any of the alternatives.
+    fn visit_alternatives(
+        &mut self,
+        alternatives: &[serde_json::Value],
+        name: &str,
+    ) -> Result<String, GrammarError> {
+        let rules = alternatives
+            .iter()
+            .enumerate()
+            .map(|(index, alternative)| {
+                let alternative_name = if name == ROOT_RULE {
+                    format!("alternative-{index}")
+                } else {
+                    format!("{name}-{index}")
+                };
+                self.visit(alternative, &alternative_name)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(rules.join(" | "))
+    }
+
+    /// Get the rule name for a `$ref`.
+    fn visit_reference(&mut self, reference: &str) -> Result<String, GrammarError> {
+        if let Some(rule) = self.refs.get(reference) {
+            return Ok(rule.clone());
+        }
+
+        let Some(pointer) = reference.strip_prefix('#') else {
+            return Err(GrammarError::UnsupportedReference(reference.to_string()));
+        };
+        let target = self
+            .root_schema
+            .pointer(pointer)
+            .cloned()
+            .ok_or_else(|| GrammarError::UnresolvedReference(reference.to_string()))?;
+
+        // Reserve the rule name first so that recursive references terminate.
+        let target_name = pointer.rsplit('/').next().unwrap_or(pointer);
+        let rule = self.reserve_rule(&format!("ref-{target_name}"));
+        self.refs.insert(reference.to_string(), rule.clone());
+        let body = self.visit_body(&target, &rule)?;
+        self.rules.insert(rule.clone(), body);
+        Ok(rule)
+    }
+
+    /// Merge the object schemas of an `allOf`.
+    fn merge_all_of(
+        &mut self,
+        parts: &[serde_json::Value],
+    ) -> Result<serde_json::Value, GrammarError> {
+        let mut properties = serde_json::Map::new();
+        let mut required = Vec::new();
+        for part in parts {
+            let part = match part.get("$ref").and_then(|value| value.as_str()) {
+                Some(reference) => {
+                    let pointer = reference
+                        .strip_prefix('#')
+                        .ok_or_else(|| GrammarError::UnsupportedReference(reference.to_string()))?;
+                    self.root_schema
+                        .pointer(pointer)
+                        .cloned()
+                        .ok_or_else(|| GrammarError::UnresolvedReference(reference.to_string()))?
+                }
+                None => part.clone(),
+            };
+            if let Some(part_properties) =
+                part.get("properties").and_then(|value| value.as_object())
+            {
+                properties.extend(part_properties.clone());
+            }
+            if let Some(part_required) = part.get("required").and_then(|value| value.as_array()) {
+                required.extend(part_required.iter().cloned());
+            }
+        }
+
+        Ok(serde_json::json!({
+            "type": "object",
+            "properties": properties,
+            "required": required,
+        }))
+    }
+
+    /// Get the rule body for an object with known properties.
+    fn build_object_body(
+        &mut self,
+        properties: &serde_json::Map<String, serde_json::Value>,
+        required: &[&str],
+        name: &str,
+        additional_properties: Option<&serde_json::Value>,
+    ) -> Result<String, GrammarError> {
+        let prefix = if name == ROOT_RULE {
+            String::new()
+        } else {
+            format!("{name}-")
+        };
+        self.add_primitive("space");
+
+        let mut required_kvs = Vec::new();
+        // Optional key-value rules. A key of `None` stands for additional properties.
+        let mut optional_kvs: Vec<(Option<String>, String)> = Vec::new();
+        for (property, property_schema) in properties {
+            let property_name = format!("{prefix}{property}");
+            let value_rule = self.visit(property_schema, &property_name)?;
+            let kv_rule = self.add_rule(
+                &format!("{property_name}-kv"),
+                &format!(
+                    r#"{} space ":" space {value_rule}"#,
+                    literal(&serde_json::Value::String(property.clone()).to_string())
+                ),
+            );
+            if required.contains(&property.as_str()) {
+                required_kvs.push(kv_rule);
+            } else {
+                optional_kvs.push((Some(property.clone()), kv_rule));
+            }
+        }
+
+        match additional_properties {
+            None | Some(serde_json::Value::Bool(false)) => {}
+            Some(additional_schema) => {
+                let value_rule =
+                    self.visit(additional_schema, &format!("{prefix}additional-value"))?;
+                let string_rule = self.add_primitive("string");
+                let kv_rule = self.add_rule(
+                    &format!("{prefix}additional-kv"),
+                    &format!(r#"{string_rule} ":" space {value_rule}"#),
+                );
+                optional_kvs.push((None, kv_rule));
+            }
+        }
+
+        let mut body = String::from(r#""{" space"#);
+        if !required_kvs.is_empty() {
+            body.push(' ');
+            body.push_str(&required_kvs.join(r#" "," space "#));
+        }
+        if !optional_kvs.is_empty() {
+            body.push_str(" ( ");
+            if !required_kvs.is_empty() {
+                body.push_str(r#""," space ( "#);
+            }
+            let alternatives = (0..optional_kvs.len())
+                .map(|index| self.build_optional_kvs(&prefix, &optional_kvs[index..], false))
+                .collect::<Vec<_>>()
+                .join(" | ");
+            body.push_str(&alternatives);
+            if !required_kvs.is_empty() {
+                body.push_str(" )");
+            }
+            body.push_str(" )?");
+        }
+        body.push_str(r#" "}" space"#);
+        Ok(body)
+    }
+
+    /// Build the expression for the optional key-value pairs starting with `kvs[0]`.
+    fn build_optional_kvs(
+        &mut self,
+        prefix: &str,
+        kvs: &[(Option<String>, String)],
+        first_is_optional: bool,
+    ) -> String {
+        let (key, kv_rule) = &kvs[0];
+        let mut result = match key {
+            None => self.add_rule(
+                &format!("{prefix}additional-kvs"),
+                &format!(r#"{kv_rule} ( "," space {kv_rule} )*"#),
+            ),
+            Some(_) if first_is_optional => format!(r#"( "," space {kv_rule} )?"#),
+            Some(_) => kv_rule.clone(),
+        };
+        if kvs.len() > 1 {
+            let rest = self.build_optional_kvs(prefix, &kvs[1..], true);
+            let key = key.as_deref().unwrap_or("additional");
+            let rest_rule = self.add_rule(&format!("{prefix}{key}-rest"), &rest);
+            result.push(' ');
+            result.push_str(&rest_rule);
+        }
+        result
+    }
+
+    /// Get the rule body for an array.
+    fn build_array_body(
+        &mut self,
+        schema: &serde_json::Map<String, serde_json::Value>,
+        prefix: &str,
+    ) -> Result<String, GrammarError> {
+        self.add_primitive("space");
+        if let Some(prefix_items) = schema.get("prefixItems").and_then(|value| value.as_array()) {
+            let items = prefix_items
+                .iter()
+                .enumerate()
+                .map(|(index, item)| self.visit(item, &format!("{prefix}tuple-{index}")))
+                .collect::<Result<Vec<_>, _>>()?;
+            return Ok(format!(
+                r#""[" space {} "]" space"#,
+                items.join(r#" "," space "#)
+            ));
+        }
+
+        let item_rule = match schema.get("items") {
+            Some(items) => self.visit(items, &format!("{prefix}item"))?,
+            None => self.add_primitive("value"),
+        };
+        let repetition = build_repetition(
+            &item_rule,
+            get_count(schema, "minItems").unwrap_or(0),
+            get_count(schema, "maxItems"),
+            r#""," space"#,
+        );
+        Ok(format!(r#""[" space {repetition} "]" space"#))
+    }
+
+    /// Format the grammar.
+    pub fn build(&self) -> String {
+        let mut grammar = String::new();
+        if let Some(root) = self.rules.get(ROOT_RULE) {
+            grammar.push_str(&format!("{ROOT_RULE} ::= {root}\n"));
+        }
+        for (name, body) in &self.rules {
+            if name != ROOT_RULE {
+                grammar.push_str(&format!("{name} ::= {body}\n"));
+            }
+        }
+        grammar
+    }
+}
+
+/// Create a grammar matching JSON which conforms to `schema`.
+pub fn json_schema_grammar(schema: &serde_json::Value) -> Result<String, GrammarError> {
+    let mut builder = GrammarBuilder::new();
+    builder.add_json_schema(schema, ROOT_RULE)?;
+    Ok(builder.build())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Get the body of a rule in a formatted grammar.
+    fn rule<'a>(grammar: &'a str, name: &str) -> &'a str {
+        let prefix = format!("{name} ::= ");
+        grammar
+            .lines()
+            .find_map(|line| line.strip_prefix(&prefix))
+            .unwrap_or_else(|| panic!("rule {name} not found in:\n{grammar}"))
+    }
+
+    #[test]
+    fn test_literal_escaping() {
+        assert_eq!(literal("a\"b\\c\nd"), r#""a\"b\\c\nd""#);
+    }
+
+    #[test]
+    fn test_required_properties() {
+        let grammar = json_schema_grammar(&serde_json::json!({
+            "type": "object",
+            "properties": {
+                "name": {"type": "string"},
+                "age": {"type": "integer"}
+            },
+            "required": ["name", "age"],
+            "additionalProperties": false
+        }))
+        .unwrap();
+
+        assert_eq!(
+            rule(&grammar, "root"),
+            r#""{" space age-kv "," space name-kv "}" space"#
+        );
+        assert_eq!(
+            rule(&grammar, "name-kv"),
+            r#""\"name\"" space ":" space string"#
+        );
+        assert_eq!(
+            rule(&grammar, "age-kv"),
+            r#""\"age\"" space ":" space integer"#
+        );
+        assert!(grammar.contains("string ::= "));
+        assert!(grammar.contains("integer ::= "));
+    }
+
+    #[test]
+    fn test_optional_properties() {
+        let grammar = json_schema_grammar(&serde_json::json!({
+            "type": "object",
+            "properties": {
+                "a": {"type": "boolean"},
+                "b": {"type": "null"}
+            }
+        }))
+        .unwrap();
+
+        assert_eq!(
+            rule(&grammar, "root"),
+            r#""{" space ( a-kv a-rest | b-kv )? "}" space"#
+        );
+        assert_eq!(rule(&grammar, "a-rest"), r#"( "," space b-kv )?"#);
+    }
+
+    #[test]
+    fn test_enum_and_array() {
+        let grammar = json_schema_grammar(&serde_json::json!({
+            "type": "array",
+            "items": {"enum": ["red", "green"]},
+            "minItems": 1,
+            "maxItems": 3
+        }))
+        .unwrap();
+
+        assert_eq!(
+            rule(&grammar, "root"),
+            r#""[" space item ( "," space item ){0,2} "]" space"#
+        );
+        assert_eq!(rule(&grammar, "item"), r#"("\"red\"" | "\"green\"") space"#);
+    }
+
+    #[test]
+    fn test_recursive_reference() {
+        let grammar = json_schema_grammar(&serde_json::json!({
+            "$ref": "#/$defs/node",
+            "$defs": {
+                "node": {
+                    "type": "object",
+                    "properties": {
+                        "children": {"type": "array", "items": {"$ref": "#/$defs/node"}}
+                    },
+                    "required": ["children"]
+                }
+            }
+        }))
+        .unwrap();
+
+        assert_eq!(rule(&grammar, "root"), "ref-node");
+        assert_eq!(
+            rule(&grammar, "ref-node-children"),
+            r#""[" space ( ref-node ( "," space ref-node )* )? "]" space"#
+        );
+    }
+
+    #[test]
+    fn test_untyped_object() {
+        let grammar = json_schema_grammar(&serde_json::json!({"type": "object"})).unwrap();
+        assert_eq!(rule(&grammar, "root"), "object");
+        assert!(grammar.contains("value ::= "));
+    }
+
+    #[test]
+    fn test_text_excluding() {
+        let mut builder = GrammarBuilder::new();
+        let text_rule = builder.add_text_excluding("text", "aab");
+        builder.add_rule(ROOT_RULE, &text_rule);
+        let grammar = builder.build();
+
+        assert_eq!(rule(&grammar, "root"), "text-0");
+        assert_eq!(
+            rule(&grammar, "text-0"),
+            r#"( [^ab] text-0 | "a" text-1 | "b" text-0 )?"#
+        );
+        assert_eq!(
+            rule(&grammar, "text-1"),
+            r#"( [^ab] text-0 | "a" text-2 | "b" text-0 )?"#
+        );
+        // Reading "b" after "aa" would complete the excluded text.
+        assert_eq!(
+            rule(&grammar, "text-2"),
+            r#"( [^ab] text-0 | "a" text-2 )?"#
+        );
+    }
+
+    #[test]
+    fn test_false_schema_is_unsatisfiable() {
+        assert!(matches!(
+            json_schema_grammar(&serde_json::json!(false)),
+            Err(GrammarError::Unsatisfiable)
+        ));
+    }
+}
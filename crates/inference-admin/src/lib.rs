@@ -0,0 +1,111 @@
+//! Admin API to manage the models of a running server.
+
+use axum::response::IntoResponse as _;
+
+mod routes;
+pub use routes::*;
+mod types;
+pub use types::*;
+
+/// Convert the status of a model to its API representation.
+fn admin_model(status: sauropod_model_loading::ModelStatus) -> AdminModel {
+    let (state, error) = match status.state {
+        sauropod_model_loading::ModelState::Loading => (AdminModelState::Loading, None),
+        sauropod_model_loading::ModelState::Ready => (AdminModelState::Ready, None),
+        sauropod_model_loading::ModelState::Failed(error) => (AdminModelState::Failed, Some(error)),
+        sauropod_model_loading::ModelState::Unloaded => (AdminModelState::Unloaded, None),
+    };
+    AdminModel {
+        id: status.name,
+        kind: match status.model_config.kind {
+            sauropod_config::ModelKind::Chat => AdminModelKind::Chat,
+            sauropod_config::ModelKind::Embedding => AdminModelKind::Embedding,
+            sauropod_config::ModelKind::Rerank => AdminModelKind::Rerank,
+        },
+        source: status.model_config.model.to_string(),
+        state,
+        error,
+        memory_bytes: status.memory,
+    }
+}
+
+/// Get the status of one model.
+async fn get_admin_model(
+    global_state: &sauropod_global_state::GlobalState,
+    name: &str,
+) -> Option<AdminModel> {
+    global_state
+        .get_loaded_models()
+        .get_model_statuses()
+        .await
+        .into_iter()
+        .find(|status| status.name == name)
+        .map(admin_model)
+}
+
+/// Respond with the status of a model after it was changed.
+async fn model_update_response(
+    global_state: &sauropod_global_state::GlobalState,
+    name: &str,
+    result: Result<(), sauropod_model_loading::ModelUpdateError>,
+) -> axum::response::Response {
+    match result {
+        Ok(()) => match get_admin_model(global_state, name).await {
+            Some(model) => axum::Json(model).into_response(),
+            None => sauropod_inference_http::HttpResponse::<()>::NotFound(None).into_response(),
+        },
+        Err(e @ sauropod_model_loading::ModelUpdateError::AlreadyExists(_)) => {
+            sauropod_inference_http::HttpResponse::<()>::BadRequest(e.to_string()).into_response()
+        }
+        Err(e @ sauropod_model_loading::ModelUpdateError::NotFound(_)) => {
+            sauropod_inference_http::HttpResponse::<()>::NotFound(Some(e.to_string()))
+                .into_response()
+        }
+        Err(sauropod_model_loading::ModelUpdateError::Failed(e)) => {
+            tracing::error!("Failed to load {name}: {e:#?}");
+            sauropod_inference_http::HttpResponse::<()>::InternalServerError(format!(
+                "Failed to load {name}: {e:#}"
+            ))
+            .into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn model_config() -> sauropod_config::ModelConfig {
+        serde_json::from_value(serde_json::json!({
+            "model": { "repo": "unsloth/Qwen3-8B-GGUF", "quantization": "Q4_K_M" },
+            "lazy": true,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_admin_model() {
+        let model = admin_model(sauropod_model_loading::ModelStatus {
+            name: "qwen3".to_string(),
+            model_config: model_config(),
+            state: sauropod_model_loading::ModelState::Failed("Out of memory".to_string()),
+            memory: None,
+        });
+        assert_eq!(model.id, "qwen3");
+        assert_eq!(model.kind, AdminModelKind::Chat);
+        assert_eq!(model.source, "huggingface.co/unsloth/Qwen3-8B-GGUF:Q4_K_M");
+        assert_eq!(model.state, AdminModelState::Failed);
+        assert_eq!(model.error.as_deref(), Some("Out of memory"));
+        assert_eq!(model.memory_bytes, None);
+
+        let model = admin_model(sauropod_model_loading::ModelStatus {
+            name: "qwen3".to_string(),
+            model_config: model_config(),
+            state: sauropod_model_loading::ModelState::Ready,
+            memory: Some(1024),
+        });
+        assert_eq!(model.state, AdminModelState::Ready);
+        assert_eq!(model.error, None);
+        assert_eq!(model.memory_bytes, Some(1024));
+    }
+}
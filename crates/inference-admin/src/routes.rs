@@ -0,0 +1,124 @@
+use axum::extract::State;
+use axum::response::IntoResponse;
+
+use crate::{AdminModel, ListAdminModelsResponse};
+
+#[utoipa::path(
+    get,
+    path = "/admin/models",
+    description = "Lists the configured models with their state and estimated memory.",
+    tag = "Admin",
+    responses(
+        (status = 200, description = "OK", body = ListAdminModelsResponse),
+        (status = 401, description = "Unauthorized", body = sauropod_inference_http::Error)
+    )
+)]
+pub async fn list_admin_models(
+    State(global_state): sauropod_global_state::AxumGlobalState,
+) -> axum::response::Response {
+    let statuses = global_state.get_loaded_models().get_model_statuses().await;
+    axum::Json(ListAdminModelsResponse {
+        data: statuses.into_iter().map(crate::admin_model).collect(),
+    })
+    .into_response()
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/models/{model}",
+    description = "Adds a model with the options of a `[models.<name>]` configuration table, loading it unless it's lazy.",
+    tag = "Admin",
+    params(
+        ("model" = String, Path, description = "The name of the model to add")
+    ),
+    request_body(content = Object, description = "The model configuration"),
+    responses(
+        (status = 200, description = "Model added", body = AdminModel),
+        (status = 400, description = "Invalid configuration or the model exists", body = sauropod_inference_http::Error),
+        (status = 401, description = "Unauthorized", body = sauropod_inference_http::Error),
+        (status = 500, description = "The model failed to load", body = sauropod_inference_http::Error)
+    )
+)]
+pub async fn add_admin_model(
+    model: axum::extract::Path<String>,
+    State(global_state): sauropod_global_state::AxumGlobalState,
+    axum::Json(body): axum::Json<serde_json::Value>,
+) -> axum::response::Response {
+    let model_config = match serde_json::from_value::<sauropod_config::ModelConfig>(body) {
+        Ok(model_config) => model_config,
+        Err(e) => {
+            return sauropod_inference_http::HttpResponse::<()>::BadRequest(format!(
+                "Invalid model configuration: {e}"
+            ))
+            .into_response();
+        }
+    };
+    let result = global_state
+        .get_loaded_models()
+        .add_model(&model.0, model_config)
+        .await;
+    crate::model_update_response(&global_state, &model.0, result).await
+}
+
+#[utoipa::path(
+    put,
+    path = "/admin/models/{model}",
+    description = "Replaces the configuration of a model. The new model is loaded before requests switch to it, and requests in flight finish on the previous model.",
+    tag = "Admin",
+    params(
+        ("model" = String, Path, description = "The name of the model to replace")
+    ),
+    request_body(content = Object, description = "The model configuration"),
+    responses(
+        (status = 200, description = "Model replaced", body = AdminModel),
+        (status = 400, description = "Invalid configuration", body = sauropod_inference_http::Error),
+        (status = 401, description = "Unauthorized", body = sauropod_inference_http::Error),
+        (status = 404, description = "Model not found", body = sauropod_inference_http::Error),
+        (status = 500, description = "The model failed to load", body = sauropod_inference_http::Error)
+    )
+)]
+pub async fn replace_admin_model(
+    model: axum::extract::Path<String>,
+    State(global_state): sauropod_global_state::AxumGlobalState,
+    axum::Json(body): axum::Json<serde_json::Value>,
+) -> axum::response::Response {
+    let model_config = match serde_json::from_value::<sauropod_config::ModelConfig>(body) {
+        Ok(model_config) => model_config,
+        Err(e) => {
+            return sauropod_inference_http::HttpResponse::<()>::BadRequest(format!(
+                "Invalid model configuration: {e}"
+            ))
+            .into_response();
+        }
+    };
+    let result = global_state
+        .get_loaded_models()
+        .replace_model(&model.0, model_config)
+        .await;
+    crate::model_update_response(&global_state, &model.0, result).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/models/{model}/unload",
+    description = "Unloads a model, along with the other names sharing it. The model is loaded again by its next request.",
+    tag = "Admin",
+    params(
+        ("model" = String, Path, description = "The name of the model to unload")
+    ),
+    responses(
+        (status = 200, description = "Model unloaded", body = AdminModel),
+        (status = 401, description = "Unauthorized", body = sauropod_inference_http::Error),
+        (status = 404, description = "Model not found", body = sauropod_inference_http::Error)
+    )
+)]
+pub async fn unload_admin_model(
+    model: axum::extract::Path<String>,
+    State(global_state): sauropod_global_state::AxumGlobalState,
+) -> axum::response::Response {
+    let result = global_state
+        .get_loaded_models()
+        .unload_model(&model.0)
+        .await;
+    crate::model_update_response(&global_state, &model.0, result).await
+}
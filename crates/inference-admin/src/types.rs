@@ -0,0 +1,51 @@
+//! Admin API types.
+
+/// What a model is used for.
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize, utoipa::ToSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum AdminModelKind {
+    Chat,
+    Embedding,
+    Rerank,
+}
+
+/// The state of a model.
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize, utoipa::ToSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum AdminModelState {
+    /// The model is being loaded.
+    Loading,
+    /// The model is in memory and serving requests.
+    Ready,
+    /// The model failed to load.
+    Failed,
+    /// The model isn't in memory and is loaded by its next request.
+    Unloaded,
+}
+
+/// A configured model.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+pub struct AdminModel {
+    /// The name of the model.
+    pub id: String,
+    pub kind: AdminModelKind,
+    /// The path or Hugging Face repo the model is loaded from.
+    pub source: String,
+    pub state: AdminModelState,
+    /// The error the model failed to load with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// The estimated number of bytes the model uses while it's in memory.
+    pub memory_bytes: Option<u64>,
+}
+
+/// The configured models.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+pub struct ListAdminModelsResponse {
+    /// The models ordered by name.
+    pub data: Vec<AdminModel>,
+}
@@ -7,6 +7,8 @@ use sauropod_openai_api::{CreateSpeechRequest, CreateSpeechRequestStreamFormat};
 
 mod routes;
 pub use routes::*;
+mod transcription;
+pub use transcription::*;
 
 async fn run_tts(
     global_state: std::sync::Arc<sauropod_global_state::GlobalState>,
@@ -3,6 +3,8 @@ use axum::response::IntoResponse;
 
 use sauropod_openai_api::{CreateSpeechRequest, Response};
 
+use crate::{CreateTranscriptionRequest, CreateTranscriptionResponseJson};
+
 #[utoipa::path(
     post,
     path = "/v1/audio/speech",
@@ -47,3 +49,47 @@ pub async fn create_speech(
         }
     }
 }
+
+#[utoipa::path(
+    post,
+    path = "/v1/audio/transcriptions",
+    tag = "Audio",
+    request_body(content = CreateTranscriptionRequest, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "Transcription created", body = CreateTranscriptionResponseJson),
+        (status = 400, description = "Invalid request", body = sauropod_inference_http::Error),
+        (status = 404, description = "No speech-to-text model is loaded", body = sauropod_inference_http::Error),
+        (status = 500, description = "Error occured", body = sauropod_inference_http::Error)
+    )
+)]
+pub async fn create_transcription(
+    State(loaded_models): sauropod_global_state::AxumGlobalState,
+    multipart: axum::extract::Multipart,
+) -> axum::response::Response {
+    let request = match CreateTranscriptionRequest::from_multipart(multipart).await {
+        Ok(request) => request,
+        Err(e) => {
+            tracing::error!("Failed to parse transcription request: {e}");
+            return (
+                axum::http::StatusCode::BAD_REQUEST,
+                axum::Json(sauropod_inference_http::Error {
+                    error: format!("Failed to parse request: {e}"),
+                }),
+            )
+                .into_response();
+        }
+    };
+    match crate::create_transcription_impl(loaded_models, request).await {
+        Ok(response) => response,
+        Err(e) => {
+            tracing::error!("Failed to create transcription: {e:#?}");
+            (
+                axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+                axum::Json(sauropod_inference_http::Error {
+                    error: format!("Internal server error: {e}"),
+                }),
+            )
+                .into_response()
+        }
+    }
+}
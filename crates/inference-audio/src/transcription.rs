@@ -0,0 +1,688 @@
+//! Transcription of uploaded audio files with the speech-to-text model.
+
+use anyhow::Context as _;
+use axum::response::IntoResponse as _;
+use axum::response::sse::Event as SseEvent;
+use futures::StreamExt as _;
+use rubato::Resampler as _;
+
+/// Maximum size of an uploaded audio file.
+pub const MAX_TRANSCRIPTION_FILE_SIZE: usize = 512 * 1024 * 1024;
+
+/// Sample rate expected by the VAD and speech-to-text models.
+const SAMPLE_RATE: usize = 16_000;
+/// Number of input frames per resampler chunk.
+const RESAMPLE_CHUNK_SIZE: usize = 1024;
+/// Score above which a VAD frame is considered to contain speech.
+const VAD_THRESHOLD: f32 = 0.5;
+/// Number of silent VAD frames which end a segment (500ms).
+const MIN_SILENCE_FRAMES: usize = 25;
+/// Maximum number of VAD frames in a segment (25s).
+const MAX_SEGMENT_FRAMES: usize = 1250;
+/// Audio kept before and after the speech in a segment (200ms).
+const SEGMENT_PADDING_SAMPLES: usize = SAMPLE_RATE / 5;
+/// Number of VAD windows or segments queued at once.
+const CONCURRENT_REQUESTS: usize = 8;
+
+/// The format of a transcription response.
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    serde::Deserialize,
+    serde::Serialize,
+    utoipa::ToSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum TranscriptionResponseFormat {
+    #[default]
+    Json,
+    Text,
+    Srt,
+    VerboseJson,
+    Vtt,
+}
+
+/// A request to transcribe an audio file.
+#[derive(Debug, Default, utoipa::ToSchema)]
+pub struct CreateTranscriptionRequest {
+    /// The audio file to transcribe in WAV, MP3, FLAC or OGG format.
+    #[schema(value_type = String, format = Binary)]
+    pub file: Vec<u8>,
+    /// The name of the uploaded file, used as a hint for the audio format.
+    #[schema(ignore)]
+    pub file_name: Option<String>,
+    /// The model to use. The loaded speech-to-text model is always used.
+    pub model: String,
+    /// The language of the audio.
+    pub language: Option<String>,
+    /// The format of the response.
+    pub response_format: Option<TranscriptionResponseFormat>,
+    /// Stream the transcript as server-sent events.
+    pub stream: Option<bool>,
+}
+
+impl CreateTranscriptionRequest {
+    /// Read the request from a multipart form.
+    pub async fn from_multipart(mut multipart: axum::extract::Multipart) -> anyhow::Result<Self> {
+        let mut request = Self::default();
+        let mut has_file = false;
+        while let Some(field) = multipart.next_field().await? {
+            let name = field.name().unwrap_or_default().to_string();
+            match name.as_str() {
+                "file" => {
+                    request.file_name = field.file_name().map(str::to_string);
+                    request.file = field.bytes().await?.to_vec();
+                    has_file = true;
+                }
+                "model" => request.model = field.text().await?,
+                "language" => request.language = Some(field.text().await?),
+                "response_format" => {
+                    request.response_format = Some(
+                        serde_json::from_value(serde_json::Value::String(field.text().await?))
+                            .context("Invalid response_format")?,
+                    )
+                }
+                "stream" => {
+                    request.stream = Some(field.text().await?.parse().context("Invalid stream")?)
+                }
+                // Other parameters such as the prompt and temperature are not supported by the speech-to-text models
+                _ => tracing::debug!("Ignoring transcription parameter {name}"),
+            }
+        }
+        anyhow::ensure!(has_file, "No audio file was provided");
+        Ok(request)
+    }
+}
+
+/// Audio usage of a transcription.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, utoipa::ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TranscriptionUsage {
+    Duration {
+        /// Duration of the input audio in seconds.
+        seconds: f64,
+    },
+}
+
+/// A transcribed segment of the audio.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, utoipa::ToSchema)]
+pub struct TranscriptionSegment {
+    /// The index of the segment.
+    pub id: usize,
+    /// The start of the segment in seconds.
+    pub start: f64,
+    /// The end of the segment in seconds.
+    pub end: f64,
+    /// The text of the segment.
+    pub text: String,
+}
+
+/// The `json` transcription response.
+#[derive(Clone, Debug, serde::Serialize, utoipa::ToSchema)]
+pub struct CreateTranscriptionResponseJson {
+    /// The transcribed text.
+    pub text: String,
+    pub usage: TranscriptionUsage,
+}
+
+/// The `verbose_json` transcription response.
+#[derive(Clone, Debug, serde::Serialize, utoipa::ToSchema)]
+pub struct CreateTranscriptionResponseVerboseJson {
+    /// The language of the audio, if it was provided in the request.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    /// Duration of the input audio in seconds.
+    pub duration: f64,
+    /// The transcribed text.
+    pub text: String,
+    /// The transcribed segments.
+    pub segments: Vec<TranscriptionSegment>,
+    pub usage: TranscriptionUsage,
+}
+
+/// Events sent when a transcription is streamed.
+#[derive(Clone, Debug, serde::Serialize, utoipa::ToSchema)]
+#[serde(tag = "type")]
+pub enum CreateTranscriptionResponseStreamEvent {
+    /// Text was added to the transcript.
+    #[serde(rename = "transcript.text.delta")]
+    TranscriptTextDelta { delta: String },
+    /// The transcript is complete.
+    #[serde(rename = "transcript.text.done")]
+    TranscriptTextDone {
+        text: String,
+        usage: TranscriptionUsage,
+    },
+}
+
+/// Decode an audio file to mono samples at its original sample rate.
+fn decode_audio(data: Vec<u8>, file_name: Option<&str>) -> anyhow::Result<(Vec<f32>, u32)> {
+    use symphonia::core::errors::Error as SymphoniaError;
+
+    let mut hint = symphonia::core::probe::Hint::new();
+    if let Some(extension) = file_name
+        .and_then(|file_name| std::path::Path::new(file_name).extension())
+        .and_then(|extension| extension.to_str())
+    {
+        hint.with_extension(extension);
+    }
+    let source = symphonia::core::io::MediaSourceStream::new(
+        Box::new(std::io::Cursor::new(data)),
+        Default::default(),
+    );
+    let mut format = symphonia::default::get_probe()
+        .format(&hint, source, &Default::default(), &Default::default())
+        .context("Unsupported audio format")?
+        .format;
+    let track = format
+        .default_track()
+        .context("The audio file contains no tracks")?;
+    let track_id = track.id;
+    let sample_rate = track
+        .codec_params
+        .sample_rate
+        .context("The audio file has no sample rate")?;
+    let mut decoder =
+        symphonia::default::get_codecs().make(&track.codec_params, &Default::default())?;
+
+    let mut samples = Vec::new();
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(SymphoniaError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                break;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            Err(SymphoniaError::DecodeError(e)) => {
+                tracing::warn!("Skipping corrupt audio packet: {e}");
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let spec = *decoded.spec();
+        let channels = spec.channels.count();
+        let mut buffer =
+            symphonia::core::audio::SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+        buffer.copy_interleaved_ref(decoded);
+        // Mix all the channels down to mono
+        samples.extend(
+            buffer
+                .samples()
+                .chunks(channels)
+                .map(|frame| frame.iter().sum::<f32>() / channels as f32),
+        );
+    }
+
+    Ok((samples, sample_rate))
+}
+
+/// Resample mono audio to the internal sample rate.
+fn resample(samples: Vec<f32>, sample_rate: u32) -> anyhow::Result<Vec<f32>> {
+    if sample_rate as usize == SAMPLE_RATE {
+        return Ok(samples);
+    }
+
+    let ratio = SAMPLE_RATE as f64 / sample_rate as f64;
+    let mut resampler = rubato::SincFixedIn::<f32>::new(
+        ratio,
+        1.0,
+        rubato::SincInterpolationParameters {
+            sinc_len: 128,
+            f_cutoff: 0.95,
+            interpolation: rubato::SincInterpolationType::Linear,
+            oversampling_factor: 128,
+            window: rubato::WindowFunction::BlackmanHarris2,
+        },
+        RESAMPLE_CHUNK_SIZE,
+        1,
+    )?;
+
+    let expected_len = (samples.len() as f64 * ratio).round() as usize;
+    let delay = resampler.output_delay();
+    let mut output = Vec::with_capacity(expected_len + delay + RESAMPLE_CHUNK_SIZE);
+    let mut chunks = samples.chunks_exact(RESAMPLE_CHUNK_SIZE);
+    for chunk in chunks.by_ref() {
+        output.extend_from_slice(&resampler.process(&[chunk], None)?[0]);
+    }
+    output.extend_from_slice(&resampler.process_partial(Some(&[chunks.remainder()]), None)?[0]);
+    // Flush the samples which are still delayed in the resampler
+    while output.len() < expected_len + delay {
+        output.extend_from_slice(&resampler.process_partial(None::<&[&[f32]]>, None)?[0]);
+    }
+
+    output.drain(..delay);
+    output.truncate(expected_len);
+    Ok(output)
+}
+
+/// Decode an audio file and resample it to the internal sample rate.
+fn load_audio(data: Vec<u8>, file_name: Option<&str>) -> anyhow::Result<Vec<f32>> {
+    let (samples, sample_rate) = decode_audio(data, file_name)?;
+    resample(samples, sample_rate)
+}
+
+/// Get the VAD score of every frame of the audio.
+async fn vad_scores(
+    vad_model: &std::sync::Arc<sauropod_vad::VadThread>,
+    samples: &[f32],
+) -> anyhow::Result<Vec<f32>> {
+    let mut scores = vec![0.0; samples.len().div_ceil(sauropod_vad::Vad::FRAME_SIZE)];
+    let mut windows = samples
+        .chunks(sauropod_vad::Vad::CONTEXT_SAMPLES)
+        .map(|window| {
+            let mut window = window.to_vec();
+            window.resize(sauropod_vad::Vad::CONTEXT_SAMPLES, 0.0);
+            window
+        })
+        .enumerate();
+    let mut pending = futures::stream::FuturesOrdered::new();
+    loop {
+        while pending.len() < CONCURRENT_REQUESTS
+            && let Some((index, window)) = windows.next()
+        {
+            let vad_model = vad_model.clone();
+            pending.push_back(async move {
+                vad_model
+                    .enqueue(window)
+                    .await
+                    .map(|classifications| (index, classifications))
+            });
+        }
+        let Some(result) = pending.next().await else {
+            break;
+        };
+
+        let (index, classifications) = result.context("VAD processing failed")?;
+        let window_start = index * sauropod_vad::Vad::CONTEXT_SAMPLES;
+        for classification in classifications {
+            let frame = (window_start + classification.range.start) / sauropod_vad::Vad::FRAME_SIZE;
+            if let Some(score) = scores.get_mut(frame) {
+                *score = classification.score;
+            }
+        }
+    }
+    Ok(scores)
+}
+
+/// Split audio into segments of speech at silence boundaries.
+///
+/// `scores` contains the VAD score of each frame. The returned ranges are in samples.
+fn speech_segments(scores: &[f32], sample_count: usize) -> Vec<std::ops::Range<usize>> {
+    let mut frame_segments = Vec::new();
+    let mut segment_start = None;
+    let mut last_speech = 0;
+    for (frame, score) in scores.iter().enumerate() {
+        if *score > VAD_THRESHOLD {
+            segment_start.get_or_insert(frame);
+            last_speech = frame;
+        } else if let Some(start) = segment_start
+            && frame - last_speech >= MIN_SILENCE_FRAMES
+        {
+            frame_segments.push(start..last_speech + 1);
+            segment_start = None;
+        }
+
+        // Cut segments which are too long at the quietest frame of their second half
+        if let Some(start) = segment_start
+            && frame + 1 - start >= MAX_SEGMENT_FRAMES
+        {
+            let cut = (start + MAX_SEGMENT_FRAMES / 2..=frame)
+                .min_by(|a, b| scores[*a].total_cmp(&scores[*b]))
+                .unwrap_or(frame);
+            frame_segments.push(start..cut);
+            segment_start = (last_speech >= cut).then_some(cut);
+        }
+    }
+    if let Some(start) = segment_start {
+        frame_segments.push(start..last_speech + 1);
+    }
+
+    let mut segments: Vec<std::ops::Range<usize>> = Vec::with_capacity(frame_segments.len());
+    for frames in frame_segments {
+        let previous_end = segments.last().map_or(0, |segment| segment.end);
+        let start = (frames.start * sauropod_vad::Vad::FRAME_SIZE)
+            .saturating_sub(SEGMENT_PADDING_SAMPLES)
+            .max(previous_end);
+        let end = (frames.end * sauropod_vad::Vad::FRAME_SIZE + SEGMENT_PADDING_SAMPLES)
+            .min(sample_count);
+        if start < end {
+            segments.push(start..end);
+        }
+    }
+    segments
+}
+
+/// Format a timestamp as `HH:MM:SS<separator>mmm`.
+fn format_timestamp(seconds: f64, separator: char) -> String {
+    let milliseconds = (seconds * 1000.0).round() as u64;
+    format!(
+        "{:02}:{:02}:{:02}{separator}{:03}",
+        milliseconds / 3_600_000,
+        (milliseconds / 60_000) % 60,
+        (milliseconds / 1000) % 60,
+        milliseconds % 1000
+    )
+}
+
+/// Format segments as SubRip subtitles.
+fn format_srt(segments: &[TranscriptionSegment]) -> String {
+    segments
+        .iter()
+        .map(|segment| {
+            format!(
+                "{}\n{} --> {}\n{}\n\n",
+                segment.id + 1,
+                format_timestamp(segment.start, ','),
+                format_timestamp(segment.end, ','),
+                segment.text
+            )
+        })
+        .collect()
+}
+
+/// Format segments as WebVTT subtitles.
+fn format_vtt(segments: &[TranscriptionSegment]) -> String {
+    let mut vtt = "WEBVTT\n\n".to_string();
+    for segment in segments {
+        vtt.push_str(&format!(
+            "{} --> {}\n{}\n\n",
+            format_timestamp(segment.start, '.'),
+            format_timestamp(segment.end, '.'),
+            segment.text
+        ));
+    }
+    vtt
+}
+
+/// Drop the segments without speech and number the rest consecutively.
+fn non_empty_segments(segments: Vec<TranscriptionSegment>) -> Vec<TranscriptionSegment> {
+    segments
+        .into_iter()
+        .filter(|segment| !segment.text.is_empty())
+        .enumerate()
+        .map(|(id, segment)| TranscriptionSegment { id, ..segment })
+        .collect()
+}
+
+/// Join the text of the segments.
+fn transcript_text(segments: &[TranscriptionSegment]) -> String {
+    segments
+        .iter()
+        .map(|segment| segment.text.as_str())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn text_response(content_type: &str, body: String) -> anyhow::Result<axum::response::Response> {
+    Ok(axum::response::Response::builder()
+        .header("Content-Type", content_type)
+        .body(axum::body::Body::from(body))?)
+}
+
+pub async fn create_transcription_impl(
+    global_state: std::sync::Arc<sauropod_global_state::GlobalState>,
+    request: CreateTranscriptionRequest,
+) -> anyhow::Result<axum::response::Response> {
+    let loaded_models = global_state.get_loaded_models();
+    let Some(stt_model) = loaded_models.stt_model() else {
+        return Ok(sauropod_inference_http::HttpResponse::<()>::NotFound(Some(
+            "No speech-to-text model is loaded".to_string(),
+        ))
+        .into_response());
+    };
+    let response_format = request.response_format.unwrap_or_default();
+    let stream = request.stream.unwrap_or(false);
+    if stream
+        && !matches!(
+            response_format,
+            TranscriptionResponseFormat::Json | TranscriptionResponseFormat::Text
+        )
+    {
+        return Ok(sauropod_inference_http::HttpResponse::<()>::BadRequest(
+            "Streaming is only supported for the json and text response formats".to_string(),
+        )
+        .into_response());
+    }
+
+    let CreateTranscriptionRequest {
+        file,
+        file_name,
+        language,
+        ..
+    } = request;
+    let samples =
+        match tokio::task::spawn_blocking(move || load_audio(file, file_name.as_deref())).await? {
+            Ok(samples) => samples,
+            Err(e) => {
+                return Ok(
+                    sauropod_inference_http::HttpResponse::<()>::BadRequest(format!(
+                        "Failed to decode the audio file: {e:#}"
+                    ))
+                    .into_response(),
+                );
+            }
+        };
+    let duration = samples.len() as f64 / SAMPLE_RATE as f64;
+    let usage = TranscriptionUsage::Duration { seconds: duration };
+
+    let scores = vad_scores(&loaded_models.vad_model(), &samples).await?;
+    let segment_audio: Vec<_> = speech_segments(&scores, samples.len())
+        .into_iter()
+        .map(|range| (range.clone(), samples[range].to_vec()))
+        .collect();
+    drop(samples);
+    tracing::debug!(
+        "Transcribing {duration:.1}s of audio in {} segments",
+        segment_audio.len()
+    );
+
+    let mut segments = futures::stream::iter(segment_audio.into_iter().enumerate().map(
+        move |(id, (range, audio))| {
+            let stt_model = stt_model.clone();
+            async move {
+                let text = stt_model
+                    .enqueue(audio)
+                    .await
+                    .context("Speech to text transcription failed")?;
+                anyhow::Ok(TranscriptionSegment {
+                    id,
+                    start: range.start as f64 / SAMPLE_RATE as f64,
+                    end: range.end as f64 / SAMPLE_RATE as f64,
+                    text: text.trim().to_string(),
+                })
+            }
+        },
+    ))
+    .buffered(CONCURRENT_REQUESTS);
+
+    if stream {
+        let stream = async_stream::stream! {
+            let mut text = String::new();
+            while let Some(segment) = segments.next().await {
+                let segment = segment?;
+                if segment.text.is_empty() {
+                    continue;
+                }
+                let delta = if text.is_empty() {
+                    segment.text
+                } else {
+                    format!(" {}", segment.text)
+                };
+                text.push_str(&delta);
+                yield SseEvent::default().json_data(
+                    CreateTranscriptionResponseStreamEvent::TranscriptTextDelta { delta },
+                ).map_err(anyhow::Error::from);
+            }
+            yield SseEvent::default().json_data(
+                CreateTranscriptionResponseStreamEvent::TranscriptTextDone { text, usage },
+            ).map_err(anyhow::Error::from);
+        };
+        return Ok(axum::response::Sse::new(stream).into_response());
+    }
+
+    let mut transcribed = Vec::new();
+    while let Some(segment) = segments.next().await {
+        transcribed.push(segment?);
+    }
+    let segments = non_empty_segments(transcribed);
+
+    match response_format {
+        TranscriptionResponseFormat::Json => Ok(axum::Json(CreateTranscriptionResponseJson {
+            text: transcript_text(&segments),
+            usage,
+        })
+        .into_response()),
+        TranscriptionResponseFormat::VerboseJson => {
+            Ok(axum::Json(CreateTranscriptionResponseVerboseJson {
+                language,
+                duration,
+                text: transcript_text(&segments),
+                segments,
+                usage,
+            })
+            .into_response())
+        }
+        TranscriptionResponseFormat::Text => {
+            text_response("text/plain; charset=utf-8", transcript_text(&segments))
+        }
+        TranscriptionResponseFormat::Srt => {
+            text_response("text/plain; charset=utf-8", format_srt(&segments))
+        }
+        TranscriptionResponseFormat::Vtt => {
+            text_response("text/vtt; charset=utf-8", format_vtt(&segments))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Encode 16 bit PCM samples as a WAV file.
+    fn wav_file(samples: &[i16], sample_rate: u32, channels: u16) -> Vec<u8> {
+        let data_len = (samples.len() * 2) as u32;
+        let mut wav = Vec::new();
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+        wav.extend_from_slice(b"WAVEfmt ");
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&channels.to_le_bytes());
+        wav.extend_from_slice(&sample_rate.to_le_bytes());
+        wav.extend_from_slice(&(sample_rate * channels as u32 * 2).to_le_bytes());
+        wav.extend_from_slice(&(channels * 2).to_le_bytes());
+        wav.extend_from_slice(&16u16.to_le_bytes());
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&data_len.to_le_bytes());
+        for sample in samples {
+            wav.extend_from_slice(&sample.to_le_bytes());
+        }
+        wav
+    }
+
+    #[test]
+    fn test_load_audio_mixes_and_resamples() {
+        // One second of stereo audio at 8kHz with opposite channels
+        let samples: Vec<i16> = (0..8000).flat_map(|_| [16384, -16384]).collect();
+        let audio = load_audio(wav_file(&samples, 8000, 2), Some("audio.wav")).unwrap();
+        assert_eq!(audio.len(), SAMPLE_RATE);
+        assert!(audio.iter().all(|sample| sample.abs() < 1e-3));
+    }
+
+    #[test]
+    fn test_load_audio_rejects_invalid_files() {
+        assert!(load_audio(b"not audio".to_vec(), Some("audio.mp3")).is_err());
+    }
+
+    #[test]
+    fn test_speech_segments_split_on_silence() {
+        let frame = sauropod_vad::Vad::FRAME_SIZE;
+        let mut scores = vec![0.0; 200];
+        scores[10..50].fill(0.9);
+        // A short pause doesn't end the segment
+        scores[60..80].fill(0.9);
+        scores[150..170].fill(0.9);
+
+        let segments = speech_segments(&scores, scores.len() * frame);
+        assert_eq!(
+            segments,
+            vec![
+                10 * frame - SEGMENT_PADDING_SAMPLES..80 * frame + SEGMENT_PADDING_SAMPLES,
+                150 * frame - SEGMENT_PADDING_SAMPLES..170 * frame + SEGMENT_PADDING_SAMPLES,
+            ]
+        );
+        assert!(speech_segments(&[0.0; 100], 100 * frame).is_empty());
+    }
+
+    #[test]
+    fn test_speech_segments_split_long_speech() {
+        let frame = sauropod_vad::Vad::FRAME_SIZE;
+        let mut scores = vec![0.9; MAX_SEGMENT_FRAMES * 2 - 200];
+        scores[MAX_SEGMENT_FRAMES - 100] = 0.6;
+
+        let segments = speech_segments(&scores, scores.len() * frame);
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[0].start, 0);
+        assert_eq!(segments[1].end, scores.len() * frame);
+        assert!(segments.iter().all(
+            |segment| segment.len() <= MAX_SEGMENT_FRAMES * frame + 2 * SEGMENT_PADDING_SAMPLES
+        ));
+        // The segments are cut at the quietest frame and don't overlap
+        assert_eq!(
+            segments[0].end,
+            (MAX_SEGMENT_FRAMES - 100) * frame + SEGMENT_PADDING_SAMPLES
+        );
+        assert_eq!(segments[1].start, segments[0].end);
+    }
+
+    #[test]
+    fn test_subtitle_formats() {
+        let segments = non_empty_segments(vec![
+            TranscriptionSegment {
+                id: 0,
+                start: 0.0,
+                end: 2.5,
+                text: "Hello there.".to_string(),
+            },
+            TranscriptionSegment {
+                id: 1,
+                start: 2.5,
+                end: 3.0,
+                text: String::new(),
+            },
+            TranscriptionSegment {
+                id: 2,
+                start: 3661.25,
+                end: 3662.0,
+                text: "Goodbye.".to_string(),
+            },
+        ]);
+        assert_eq!(
+            segments
+                .iter()
+                .map(|segment| segment.id)
+                .collect::<Vec<_>>(),
+            [0, 1]
+        );
+        assert_eq!(
+            format_srt(&segments),
+            "1\n00:00:00,000 --> 00:00:02,500\nHello there.\n\n2\n01:01:01,250 --> 01:01:02,000\nGoodbye.\n\n"
+        );
+        assert_eq!(
+            format_vtt(&segments),
+            "WEBVTT\n\n00:00:00.000 --> 00:00:02.500\nHello there.\n\n01:01:01.250 --> 01:01:02.000\nGoodbye.\n\n"
+        );
+        assert_eq!(transcript_text(&segments), "Hello there. Goodbye.");
+    }
+}
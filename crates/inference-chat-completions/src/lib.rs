@@ -0,0 +1,942 @@
+//! OpenAI-compatible Chat Completions API.
+
+use axum::response::sse::Event as SseEvent;
+use axum::{response::IntoResponse, response::Sse};
+
+use sauropod_openai_api::{
+    CreateResponse, EasyInputMessage, EasyInputMessageContent, EasyInputMessageRole,
+    EasyInputMessageType, InputContent, InputItem, InputMessage, InputMessageContentList,
+    InputMessageRole, InputMessageType, InputTextContent, InputTextContentType, Item, OutputItem,
+    Response, ResponseStreamEvent,
+};
+
+mod routes;
+pub use routes::*;
+mod types;
+pub use types::*;
+
+/// Get the text of a message which can't contain images.
+fn message_text(content: &ChatCompletionMessageContent) -> anyhow::Result<String> {
+    match content {
+        ChatCompletionMessageContent::Text(text) => Ok(text.clone()),
+        ChatCompletionMessageContent::Parts(parts) => parts
+            .iter()
+            .map(|part| match part {
+                ChatCompletionContentPart::Text { text } => Ok(text.as_str()),
+                ChatCompletionContentPart::ImageUrl { .. } => {
+                    anyhow::bail!("Images are only supported in user messages")
+                }
+            })
+            .collect::<anyhow::Result<Vec<_>>>()
+            .map(|parts| parts.concat()),
+    }
+}
+
+/// Convert the messages of a chat completion request to Responses API input items.
+fn input_items(messages: &[ChatCompletionRequestMessage]) -> anyhow::Result<Vec<InputItem>> {
+    let mut items = Vec::with_capacity(messages.len());
+    for message in messages {
+        match message {
+            ChatCompletionRequestMessage::System { content, .. }
+            | ChatCompletionRequestMessage::Developer { content, .. } => {
+                items.push(InputItem::Item(Item::InputMessage(InputMessage {
+                    content: InputMessageContentList(vec![InputContent::InputTextContent(
+                        InputTextContent {
+                            text: message_text(content)?,
+                            r#type: InputTextContentType::InputText,
+                        },
+                    )]),
+                    role: InputMessageRole::System,
+                    status: None,
+                    r#type: Some(InputMessageType::Message),
+                })));
+            }
+            ChatCompletionRequestMessage::User { content, .. } => {
+                let content = match content {
+                    ChatCompletionMessageContent::Text(text) => {
+                        EasyInputMessageContent::Variant0(text.clone())
+                    }
+                    ChatCompletionMessageContent::Parts(parts) => {
+                        EasyInputMessageContent::InputMessageContentList(InputMessageContentList(
+                            parts
+                                .iter()
+                                .map(|part| match part {
+                                    ChatCompletionContentPart::Text { text } => {
+                                        InputContent::InputTextContent(InputTextContent {
+                                            text: text.clone(),
+                                            r#type: InputTextContentType::InputText,
+                                        })
+                                    }
+                                    ChatCompletionContentPart::ImageUrl { image_url } => {
+                                        InputContent::InputImageContent {
+                                            detail: image_url.detail.clone(),
+                                            file_id: None,
+                                            image_url: Some(image_url.url.clone()),
+                                        }
+                                    }
+                                })
+                                .collect(),
+                        ))
+                    }
+                };
+                items.push(InputItem::EasyInputMessage(EasyInputMessage {
+                    content,
+                    role: EasyInputMessageRole::User,
+                    r#type: Some(EasyInputMessageType::Message),
+                }));
+            }
+            ChatCompletionRequestMessage::Assistant {
+                content,
+                tool_calls,
+                ..
+            } => {
+                let text = content.as_ref().map(message_text).transpose()?;
+                if let Some(text) = text.filter(|text| !text.is_empty()) {
+                    items.push(InputItem::Item(Item::OutputMessage {
+                        content: vec![sauropod_openai_api::OutputContent::OutputTextContent {
+                            annotations: Vec::new(),
+                            logprobs: None,
+                            text,
+                        }],
+                        id: uuid::Uuid::new_v4().to_string(),
+                        role: sauropod_openai_api::OutputMessageRole::Assistant,
+                        status: sauropod_openai_api::Status::Completed,
+                    }));
+                }
+                for tool_call in tool_calls.iter().flatten() {
+                    items.push(InputItem::Item(Item::FunctionToolCall {
+                        arguments: tool_call.function.arguments.clone(),
+                        call_id: tool_call.id.clone(),
+                        id: tool_call.id.clone(),
+                        name: tool_call.function.name.clone(),
+                        status: Some(sauropod_openai_api::Status::Completed),
+                    }));
+                }
+            }
+            ChatCompletionRequestMessage::Tool {
+                content,
+                tool_call_id,
+            } => {
+                items.push(InputItem::Item(Item::FunctionCallOutputItemParam {
+                    call_id: tool_call_id.clone(),
+                    id: None,
+                    output: message_text(content)?,
+                    status: None,
+                }));
+            }
+        }
+    }
+    Ok(items)
+}
+
+/// Convert a chat completion request to the equivalent Responses API request.
+fn create_response_request(
+    request: &CreateChatCompletionRequest,
+) -> anyhow::Result<CreateResponse> {
+    if request.n.is_some_and(|n| n != 1) {
+        anyhow::bail!("Only one choice can be generated");
+    }
+
+    let tools = request.tools.as_ref().map(|tools| {
+        tools
+            .iter()
+            .map(|tool| sauropod_openai_api::Tool::FunctionTool {
+                description: tool.function.description.clone(),
+                name: tool.function.name.clone(),
+                parameters: tool.function.parameters.clone(),
+                strict: tool.function.strict,
+            })
+            .collect()
+    });
+    let tool_choice = request
+        .tool_choice
+        .as_ref()
+        .map(|tool_choice| match tool_choice {
+            ChatCompletionToolChoiceOption::Mode(mode) => {
+                sauropod_openai_api::ResponsePropertiesToolChoice::ToolChoiceOptions(mode.clone())
+            }
+            ChatCompletionToolChoiceOption::Named(named) => {
+                sauropod_openai_api::ResponsePropertiesToolChoice::ToolChoiceFunction(
+                    sauropod_openai_api::ToolChoiceFunction {
+                        name: named.function.name.clone(),
+                        r#type: Default::default(),
+                    },
+                )
+            }
+        });
+    let text = request.response_format.as_ref().map(|response_format| {
+        let format = match response_format {
+            ChatCompletionResponseFormat::Text => {
+                sauropod_openai_api::TextResponseFormatConfiguration::ResponseFormatText {}
+            }
+            ChatCompletionResponseFormat::JsonObject => {
+                sauropod_openai_api::TextResponseFormatConfiguration::ResponseFormatJsonObject {}
+            }
+            ChatCompletionResponseFormat::JsonSchema { json_schema } => {
+                sauropod_openai_api::TextResponseFormatConfiguration::TextResponseFormatJsonSchema {
+                    description: json_schema.description.clone(),
+                    name: json_schema.name.clone(),
+                    schema: sauropod_openai_api::ResponseFormatJsonSchemaSchema {
+                        extra_fields: json_schema.schema.clone().unwrap_or_default(),
+                    },
+                    strict: json_schema.strict,
+                }
+            }
+        };
+        sauropod_openai_api::ResponsePropertiesText {
+            format: Some(format),
+            verbosity: None,
+        }
+    });
+
+    Ok(CreateResponse {
+        create_model_response_properties: sauropod_openai_api::CreateModelResponseProperties {
+            model_response_properties: sauropod_openai_api::ModelResponseProperties {
+                temperature: request.temperature,
+                top_p: request.top_p,
+                user: request.user.clone(),
+                ..Default::default()
+            },
+            top_logprobs: None,
+        },
+        response_properties: sauropod_openai_api::ResponseProperties {
+            max_output_tokens: request.max_completion_tokens.or(request.max_tokens),
+            model: Some(sauropod_openai_api::ModelId(request.model.clone())),
+            text,
+            tool_choice,
+            tools,
+            ..Default::default()
+        },
+        input: Some(sauropod_openai_api::CreateResponseInput::Variant1(
+            input_items(&request.messages)?,
+        )),
+        parallel_tool_calls: request.parallel_tool_calls,
+        stream: request.stream,
+        ..Default::default()
+    })
+}
+
+/// Get the sampling parameters of a request which take precedence over the model's configuration.
+fn sampling_overrides(
+    request: &CreateChatCompletionRequest,
+) -> sauropod_inference_engine_api::SamplingOverrides {
+    sauropod_inference_engine_api::SamplingOverrides {
+        top_k: request.top_k,
+        min_p: request.min_p,
+        typical_p: request.typical_p,
+        seed: request.seed,
+        repetition_penalty: request.repetition_penalty,
+        frequency_penalty: request.frequency_penalty,
+        presence_penalty: request.presence_penalty,
+        penalty_window: request.penalty_window,
+        dry: request.dry.clone(),
+        mirostat: request.mirostat.clone(),
+        logit_bias: request.logit_bias.clone(),
+        stop: request.stop.clone(),
+        lora_adapters: request.lora_adapters.clone(),
+    }
+}
+
+/// Get the reason a completed response stopped.
+fn finish_reason(response: &Response) -> ChatCompletionFinishReason {
+    // A tool call cut off by the output limit isn't a call the client can make
+    if response.incomplete_details.is_some() {
+        return ChatCompletionFinishReason::Length;
+    }
+    if response
+        .output
+        .iter()
+        .any(|item| matches!(item, OutputItem::FunctionToolCall { .. }))
+    {
+        return ChatCompletionFinishReason::ToolCalls;
+    }
+    match (
+        &response.usage,
+        response.response_properties.max_output_tokens,
+    ) {
+        (Some(usage), Some(max_output_tokens)) if usage.output_tokens >= max_output_tokens => {
+            ChatCompletionFinishReason::Length
+        }
+        _ => ChatCompletionFinishReason::Stop,
+    }
+}
+
+/// Get the token usage of a completed response.
+fn completion_usage(response: &Response) -> CompletionUsage {
+    response
+        .usage
+        .as_ref()
+        .map(|usage| CompletionUsage {
+            prompt_tokens: usage.input_tokens,
+            completion_tokens: usage.output_tokens,
+            total_tokens: usage.input_tokens + usage.output_tokens,
+            prompt_tokens_details: PromptTokensDetails {
+                cached_tokens: usage.input_tokens_details.cached_tokens,
+            },
+        })
+        .unwrap_or_default()
+}
+
+/// A tool call whose chunks are being streamed.
+struct StreamedToolCall {
+    index: i64,
+    /// Whether the name of the function has been sent.
+    name_sent: bool,
+    /// The arguments sent so far.
+    arguments: String,
+}
+
+/// Converts the events of a streamed response to chat completion chunks.
+struct ChunkCreator {
+    id: String,
+    created: i64,
+    model: String,
+    /// The tool calls of the completion so far, by call ID.
+    tool_calls: std::collections::HashMap<String, StreamedToolCall>,
+}
+
+impl ChunkCreator {
+    fn new(model: String) -> Self {
+        Self {
+            id: format!("chatcmpl-{}", uuid::Uuid::new_v4()),
+            created: chrono::Utc::now().timestamp(),
+            model,
+            tool_calls: std::collections::HashMap::new(),
+        }
+    }
+
+    fn chunk(
+        &self,
+        delta: ChatCompletionStreamResponseDelta,
+        finish_reason: Option<ChatCompletionFinishReason>,
+    ) -> CreateChatCompletionStreamResponse {
+        CreateChatCompletionStreamResponse {
+            id: self.id.clone(),
+            object: ChatCompletionChunkObject::ChatCompletionChunk,
+            created: self.created,
+            model: self.model.clone(),
+            choices: vec![ChatCompletionStreamChoice {
+                index: 0,
+                delta,
+                finish_reason,
+            }],
+            usage: None,
+        }
+    }
+
+    /// The first chunk, which sets the role of the message.
+    fn start(&self) -> CreateChatCompletionStreamResponse {
+        self.chunk(
+            ChatCompletionStreamResponseDelta {
+                role: Some(ChatCompletionResponseRole::Assistant),
+                content: Some(String::new()),
+                ..Default::default()
+            },
+            None,
+        )
+    }
+
+    /// Get the chunk for an event which adds to the completion.
+    fn push_event(
+        &mut self,
+        event: &ResponseStreamEvent,
+    ) -> Option<CreateChatCompletionStreamResponse> {
+        let delta = match event {
+            ResponseStreamEvent::ResponseTextDeltaEvent { delta, .. } => {
+                ChatCompletionStreamResponseDelta {
+                    content: Some(delta.clone()),
+                    ..Default::default()
+                }
+            }
+            ResponseStreamEvent::ResponseReasoningSummaryTextDeltaEvent { delta, .. } => {
+                ChatCompletionStreamResponseDelta {
+                    reasoning_content: Some(delta.clone()),
+                    ..Default::default()
+                }
+            }
+            ResponseStreamEvent::ResponseOutputItemAddedEvent {
+                item: OutputItem::FunctionToolCall { call_id, name, .. },
+                ..
+            } => {
+                let index = self.tool_calls.len() as i64;
+                self.tool_calls.insert(
+                    call_id.clone(),
+                    StreamedToolCall {
+                        index,
+                        name_sent: !name.is_empty(),
+                        arguments: String::new(),
+                    },
+                );
+                Self::tool_call_delta(ChatCompletionMessageToolCallChunk {
+                    index,
+                    id: Some(call_id.clone()),
+                    r#type: Some(ChatCompletionToolType::Function),
+                    function: Some(ChatCompletionFunctionCallChunk {
+                        name: (!name.is_empty()).then(|| name.clone()),
+                        arguments: Some(String::new()),
+                    }),
+                })
+            }
+            ResponseStreamEvent::ResponseFunctionCallArgumentsDeltaEvent {
+                delta, item_id, ..
+            } => {
+                let tool_call = self.tool_calls.get_mut(item_id)?;
+                tool_call.arguments.push_str(delta);
+                Self::tool_call_delta(ChatCompletionMessageToolCallChunk {
+                    index: tool_call.index,
+                    id: None,
+                    r#type: None,
+                    function: Some(ChatCompletionFunctionCallChunk {
+                        name: None,
+                        arguments: Some(delta.clone()),
+                    }),
+                })
+            }
+            // The name may only be known, and the arguments may only be complete, once the whole
+            // call has been parsed
+            ResponseStreamEvent::ResponseOutputItemDoneEvent {
+                item:
+                    OutputItem::FunctionToolCall {
+                        arguments,
+                        call_id,
+                        name,
+                        ..
+                    },
+                ..
+            } => {
+                let tool_call = self.tool_calls.get_mut(call_id)?;
+                let name = (!tool_call.name_sent && !name.is_empty()).then(|| name.clone());
+                tool_call.name_sent |= name.is_some();
+                // The deltas may differ from the final arguments in surrounding whitespace
+                let remaining_arguments = arguments
+                    .strip_prefix(tool_call.arguments.as_str())
+                    .filter(|remaining| !remaining.is_empty())
+                    .map(str::to_string);
+                if name.is_none() && remaining_arguments.is_none() {
+                    return None;
+                }
+                if let Some(remaining_arguments) = &remaining_arguments {
+                    tool_call.arguments.push_str(remaining_arguments);
+                }
+                Self::tool_call_delta(ChatCompletionMessageToolCallChunk {
+                    index: tool_call.index,
+                    id: None,
+                    r#type: None,
+                    function: Some(ChatCompletionFunctionCallChunk {
+                        name,
+                        arguments: remaining_arguments,
+                    }),
+                })
+            }
+            _ => return None,
+        };
+        Some(self.chunk(delta, None))
+    }
+
+    fn tool_call_delta(
+        tool_call: ChatCompletionMessageToolCallChunk,
+    ) -> ChatCompletionStreamResponseDelta {
+        ChatCompletionStreamResponseDelta {
+            tool_calls: Some(vec![tool_call]),
+            ..Default::default()
+        }
+    }
+
+    /// The last chunks of the completion.
+    fn finish(
+        &self,
+        response: &Response,
+        include_usage: bool,
+    ) -> Vec<CreateChatCompletionStreamResponse> {
+        let mut chunks = vec![self.chunk(Default::default(), Some(finish_reason(response)))];
+        if include_usage {
+            chunks.push(CreateChatCompletionStreamResponse {
+                choices: Vec::new(),
+                usage: Some(completion_usage(response)),
+                ..self.chunk(Default::default(), None)
+            });
+        }
+        chunks
+    }
+
+    /// Create the whole completion from a completed response.
+    fn completion(&self, response: &Response) -> CreateChatCompletionResponse {
+        let mut content = None::<String>;
+        let mut reasoning_content = None::<String>;
+        let mut tool_calls = Vec::new();
+        for item in &response.output {
+            match item {
+                OutputItem::OutputMessage {
+                    content: message_content,
+                    ..
+                } => {
+                    for part in message_content {
+                        if let sauropod_openai_api::OutputContent::OutputTextContent {
+                            text, ..
+                        } = part
+                        {
+                            content.get_or_insert_default().push_str(text);
+                        }
+                    }
+                }
+                OutputItem::ReasoningItem { summary, .. } => {
+                    for part in summary {
+                        reasoning_content
+                            .get_or_insert_default()
+                            .push_str(&part.text);
+                    }
+                }
+                OutputItem::FunctionToolCall {
+                    arguments,
+                    call_id,
+                    name,
+                    ..
+                } => tool_calls.push(ChatCompletionMessageToolCall {
+                    id: call_id.clone(),
+                    r#type: ChatCompletionToolType::Function,
+                    function: ChatCompletionFunctionCall {
+                        name: name.clone(),
+                        arguments: arguments.clone(),
+                    },
+                }),
+                _ => {}
+            }
+        }
+
+        CreateChatCompletionResponse {
+            id: self.id.clone(),
+            object: ChatCompletionObject::ChatCompletion,
+            created: self.created,
+            model: self.model.clone(),
+            choices: vec![ChatCompletionChoice {
+                index: 0,
+                message: ChatCompletionResponseMessage {
+                    role: ChatCompletionResponseRole::Assistant,
+                    content,
+                    reasoning_content,
+                    tool_calls: (!tool_calls.is_empty()).then_some(tool_calls),
+                },
+                finish_reason: finish_reason(response),
+            }],
+            usage: Some(completion_usage(response)),
+        }
+    }
+}
+
+pub async fn create_chat_completion_impl(
+    global_state: std::sync::Arc<sauropod_global_state::GlobalState>,
+    request: CreateChatCompletionRequest,
+) -> anyhow::Result<axum::response::Response> {
+    tracing::debug!("create chat completion request: {:#?}", request);
+    let Some(model) = global_state.get_model(&request.model).await else {
+        return Ok(
+            sauropod_inference_http::HttpResponse::<()>::NotFound(Some(format!(
+                "Model '{}' not found",
+                request.model
+            )))
+            .into_response(),
+        );
+    };
+
+    let create_response = match create_response_request(&request) {
+        Ok(create_response) => create_response,
+        Err(e) => {
+            return Ok(
+                sauropod_inference_http::HttpResponse::<()>::BadRequest(format!(
+                    "Invalid request: {e}"
+                ))
+                .into_response(),
+            );
+        }
+    };
+    let sampling = sampling_overrides(&request);
+    if let Err(e) = sauropod_inference_engine_api::SamplerProperties::new(
+        &sauropod_inference_engine_api::make_response(&create_response),
+        &model.model_config,
+        model.get_model_type(),
+        &sampling,
+    ) {
+        return Ok(
+            sauropod_inference_http::HttpResponse::<()>::BadRequest(format!(
+                "Invalid request: {e}"
+            ))
+            .into_response(),
+        );
+    }
+    let render_context = match sauropod_prompt_templates::RenderContext::from_create_response(
+        &create_response,
+        model.get_system_prompt(),
+        model.get_model_type(),
+    ) {
+        Ok(render_context) => render_context,
+        Err(e) => {
+            return Ok(
+                sauropod_inference_http::HttpResponse::<()>::BadRequest(format!(
+                    "Invalid messages: {e}"
+                ))
+                .into_response(),
+            );
+        }
+    };
+
+    let options = sauropod_inference_engine::GenerationOptions {
+        sampling,
+        ..Default::default()
+    };
+    let mut chunk_creator = ChunkCreator::new(request.model.clone());
+    let mut events = model
+        .generate_stream_with_options(create_response, render_context, options)
+        .await?;
+
+    if request.stream.unwrap_or(false) {
+        let include_usage = request
+            .stream_options
+            .as_ref()
+            .and_then(|stream_options| stream_options.include_usage)
+            .unwrap_or(false);
+        let stream = async_stream::stream! {
+            yield SseEvent::default().json_data(chunk_creator.start()).map_err(anyhow::Error::from);
+            for await event in events {
+                match event {
+                    Ok(ResponseStreamEvent::ResponseCompletedEvent { response, .. })
+                    | Ok(ResponseStreamEvent::ResponseIncompleteEvent { response, .. }) => {
+                        for chunk in chunk_creator.finish(&response, include_usage) {
+                            yield SseEvent::default().json_data(chunk).map_err(anyhow::Error::from);
+                        }
+                    }
+                    Ok(event) => {
+                        if let Some(chunk) = chunk_creator.push_event(&event) {
+                            yield SseEvent::default().json_data(chunk).map_err(anyhow::Error::from);
+                        }
+                    }
+                    Err(e) => {
+                        yield Err(e);
+                        break;
+                    }
+                }
+            }
+            yield Ok(SseEvent::default().data("[DONE]"));
+        };
+        Ok(Sse::new(stream).into_response())
+    } else {
+        use tokio_stream::StreamExt as _;
+
+        while let Some(event) = events.next().await {
+            if let ResponseStreamEvent::ResponseCompletedEvent { response, .. }
+            | ResponseStreamEvent::ResponseIncompleteEvent { response, .. } = event?
+            {
+                return Ok(axum::Json(chunk_creator.completion(&response)).into_response());
+            }
+        }
+        anyhow::bail!("No ResponseCompletedEvent was generated by the model")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(value: serde_json::Value) -> CreateChatCompletionRequest {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn completed_response(
+        create_response: &CreateResponse,
+        output: Vec<OutputItem>,
+        output_tokens: i64,
+    ) -> Response {
+        let mut response = sauropod_inference_engine_api::make_response(create_response);
+        response.output = output;
+        response.usage = Some(sauropod_openai_api::ResponseUsage {
+            input_tokens: 10,
+            input_tokens_details: sauropod_openai_api::ResponseUsageInputTokensDetails {
+                cached_tokens: 4,
+            },
+            output_tokens,
+            output_tokens_details: sauropod_openai_api::ResponseUsageOutputTokensDetails {
+                reasoning_tokens: 0,
+            },
+            total_tokens: 10 + output_tokens,
+        });
+        response
+    }
+
+    #[test]
+    fn test_messages_are_converted_to_input_items() {
+        let create_response = create_response_request(&request(serde_json::json!({
+            "model": "test",
+            "messages": [
+                {"role": "system", "content": "Be brief."},
+                {"role": "user", "content": [{"type": "text", "text": "Weather?"}]},
+                {"role": "assistant", "content": null, "tool_calls": [{
+                    "id": "call-1",
+                    "type": "function",
+                    "function": {"name": "get_weather", "arguments": "{}"}
+                }]},
+                {"role": "tool", "tool_call_id": "call-1", "content": "Sunny"}
+            ],
+            "max_tokens": 20,
+            "response_format": {"type": "json_object"}
+        })))
+        .unwrap();
+
+        let Some(sauropod_openai_api::CreateResponseInput::Variant1(items)) =
+            &create_response.input
+        else {
+            panic!("Expected input items");
+        };
+        assert_eq!(items.len(), 4);
+        assert!(matches!(
+            &items[0],
+            InputItem::Item(Item::InputMessage(InputMessage {
+                role: InputMessageRole::System,
+                ..
+            }))
+        ));
+        assert!(matches!(
+            &items[1],
+            InputItem::EasyInputMessage(EasyInputMessage {
+                role: EasyInputMessageRole::User,
+                ..
+            })
+        ));
+        assert!(matches!(
+            &items[2],
+            InputItem::Item(Item::FunctionToolCall { call_id, name, .. })
+                if call_id == "call-1" && name == "get_weather"
+        ));
+        assert!(matches!(
+            &items[3],
+            InputItem::Item(Item::FunctionCallOutputItemParam { call_id, output, .. })
+                if call_id == "call-1" && output == "Sunny"
+        ));
+        assert_eq!(
+            create_response.response_properties.max_output_tokens,
+            Some(20)
+        );
+        assert!(matches!(
+            create_response
+                .response_properties
+                .text
+                .and_then(|text| text.format),
+            Some(sauropod_openai_api::TextResponseFormatConfiguration::ResponseFormatJsonObject {})
+        ));
+    }
+
+    #[test]
+    fn test_multiple_choices_are_rejected() {
+        assert!(
+            create_response_request(&request(serde_json::json!({
+                "model": "test",
+                "messages": [{"role": "user", "content": "Hi"}],
+                "n": 2
+            })))
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_sampling_overrides_from_request() {
+        let sampling = sampling_overrides(&request(serde_json::json!({
+            "model": "test",
+            "messages": [{"role": "user", "content": "Hi"}],
+            "seed": 7,
+            "stop": "STOP",
+            "logit_bias": {"2": 5},
+            "top_k": 40,
+            "mirostat": {"version": "v2", "tau": 5.0, "eta": 0.1}
+        })));
+        assert_eq!(sampling.seed, Some(7));
+        assert_eq!(sampling.stop.map(Vec::from), Some(vec!["STOP".to_string()]));
+        assert_eq!(sampling.logit_bias.unwrap()["2"], 5.0);
+        assert_eq!(sampling.top_k, Some(40));
+        assert!(sampling.mirostat.is_some());
+    }
+
+    #[test]
+    fn test_stream_events_are_converted_to_chunks() {
+        let mut chunk_creator = ChunkCreator::new("test".to_string());
+        let text_chunk = chunk_creator
+            .push_event(&ResponseStreamEvent::ResponseTextDeltaEvent {
+                content_index: 0,
+                delta: "Hello".to_string(),
+                item_id: "item".to_string(),
+                logprobs: Vec::new(),
+                output_index: 0,
+                sequence_number: 0,
+            })
+            .unwrap();
+        assert_eq!(
+            text_chunk.choices[0].delta.content.as_deref(),
+            Some("Hello")
+        );
+
+        let tool_call_chunks = |chunk: CreateChatCompletionStreamResponse| {
+            chunk.choices[0].delta.tool_calls.clone().unwrap()
+        };
+        let mut tool_call = OutputItem::FunctionToolCall {
+            arguments: String::new(),
+            call_id: "call-1".to_string(),
+            id: "call-1".to_string(),
+            name: "get_weather".to_string(),
+            status: Some(sauropod_openai_api::Status::InProgress),
+        };
+        let header = tool_call_chunks(
+            chunk_creator
+                .push_event(&ResponseStreamEvent::ResponseOutputItemAddedEvent {
+                    item: tool_call.clone(),
+                    output_index: 1,
+                    sequence_number: 1,
+                })
+                .unwrap(),
+        );
+        assert_eq!(header[0].index, 0);
+        assert_eq!(header[0].id.as_deref(), Some("call-1"));
+        let function = header[0].function.as_ref().unwrap();
+        assert_eq!(function.name.as_deref(), Some("get_weather"));
+        assert_eq!(function.arguments.as_deref(), Some(""));
+
+        for (sequence_number, delta) in [(2, r#"{"city":"#), (3, r#""Paris"}"#)] {
+            let arguments = tool_call_chunks(
+                chunk_creator
+                    .push_event(
+                        &ResponseStreamEvent::ResponseFunctionCallArgumentsDeltaEvent {
+                            delta: delta.to_string(),
+                            item_id: "call-1".to_string(),
+                            output_index: 1,
+                            sequence_number,
+                        },
+                    )
+                    .unwrap(),
+            );
+            assert_eq!(arguments[0].index, 0);
+            assert!(arguments[0].id.is_none());
+            let function = arguments[0].function.as_ref().unwrap();
+            assert!(function.name.is_none());
+            assert_eq!(function.arguments.as_deref(), Some(delta));
+        }
+
+        // Everything has been streamed by the time the call is done
+        if let OutputItem::FunctionToolCall {
+            arguments, status, ..
+        } = &mut tool_call
+        {
+            *arguments = r#"{"city":"Paris"}"#.to_string();
+            *status = Some(sauropod_openai_api::Status::Completed);
+        }
+        assert!(
+            chunk_creator
+                .push_event(&ResponseStreamEvent::ResponseOutputItemDoneEvent {
+                    item: tool_call.clone(),
+                    output_index: 1,
+                    sequence_number: 4,
+                })
+                .is_none()
+        );
+
+        let create_response = CreateResponse::default();
+        let response = completed_response(&create_response, vec![tool_call], 7);
+        let chunks = chunk_creator.finish(&response, true);
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(
+            chunks[0].choices[0].finish_reason,
+            Some(ChatCompletionFinishReason::ToolCalls)
+        );
+        assert!(chunks[1].choices.is_empty());
+        assert_eq!(
+            chunks[1].usage,
+            Some(CompletionUsage {
+                prompt_tokens: 10,
+                completion_tokens: 7,
+                total_tokens: 17,
+                prompt_tokens_details: PromptTokensDetails { cached_tokens: 4 },
+            })
+        );
+    }
+
+    #[test]
+    fn test_tool_call_name_and_arguments_sent_when_done() {
+        let mut chunk_creator = ChunkCreator::new("test".to_string());
+        let tool_call = |arguments: &str, name: &str| OutputItem::FunctionToolCall {
+            arguments: arguments.to_string(),
+            call_id: "call-1".to_string(),
+            id: "call-1".to_string(),
+            name: name.to_string(),
+            status: None,
+        };
+        chunk_creator
+            .push_event(&ResponseStreamEvent::ResponseOutputItemAddedEvent {
+                item: tool_call("", ""),
+                output_index: 0,
+                sequence_number: 0,
+            })
+            .unwrap();
+        let chunk = chunk_creator
+            .push_event(&ResponseStreamEvent::ResponseOutputItemDoneEvent {
+                item: tool_call("{}", "f"),
+                output_index: 0,
+                sequence_number: 1,
+            })
+            .unwrap();
+        let tool_calls = chunk.choices[0].delta.tool_calls.as_ref().unwrap();
+        let function = tool_calls[0].function.as_ref().unwrap();
+        assert_eq!(function.name.as_deref(), Some("f"));
+        assert_eq!(function.arguments.as_deref(), Some("{}"));
+    }
+
+    #[test]
+    fn test_completion_from_response() {
+        let create_response = CreateResponse {
+            response_properties: sauropod_openai_api::ResponseProperties {
+                max_output_tokens: Some(3),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let response = completed_response(
+            &create_response,
+            vec![OutputItem::OutputMessage {
+                content: vec![sauropod_openai_api::OutputContent::OutputTextContent {
+                    annotations: Vec::new(),
+                    logprobs: None,
+                    text: "One two".to_string(),
+                }],
+                id: "message".to_string(),
+                role: sauropod_openai_api::OutputMessageRole::Assistant,
+                status: sauropod_openai_api::Status::Completed,
+            }],
+            3,
+        );
+
+        let completion = ChunkCreator::new("test".to_string()).completion(&response);
+        let choice = &completion.choices[0];
+        assert_eq!(choice.message.content.as_deref(), Some("One two"));
+        assert!(choice.message.tool_calls.is_none());
+        assert_eq!(choice.finish_reason, ChatCompletionFinishReason::Length);
+    }
+
+    #[test]
+    fn test_truncated_tool_call_finishes_with_length() {
+        let create_response = CreateResponse::default();
+        let mut response = completed_response(
+            &create_response,
+            vec![OutputItem::FunctionToolCall {
+                arguments: r#"{"city":"Par"#.to_string(),
+                call_id: "call-1".to_string(),
+                id: "call-1".to_string(),
+                name: "get_weather".to_string(),
+                status: Some(sauropod_openai_api::Status::Incomplete),
+            }],
+            5,
+        );
+        response.incomplete_details = Some(sauropod_openai_api::ResponseIncompleteDetails {
+            reason: Some(sauropod_openai_api::ResponseIncompleteDetailsReason::MaxOutputTokens),
+        });
+
+        let completion = ChunkCreator::new("test".to_string()).completion(&response);
+        assert_eq!(
+            completion.choices[0].finish_reason,
+            ChatCompletionFinishReason::Length
+        );
+    }
+}
@@ -0,0 +1,51 @@
+use axum::extract::State;
+use axum::response::IntoResponse;
+
+use crate::{CreateChatCompletionRequest, CreateChatCompletionResponse};
+
+#[utoipa::path(
+    post,
+    path = "/v1/chat/completions",
+    tag = "Chat",
+    request_body = CreateChatCompletionRequest,
+    responses(
+        (status = 200, description = "Chat completion created", body = CreateChatCompletionResponse),
+        (status = 400, description = "Invalid request", body = sauropod_inference_http::Error),
+        (status = 404, description = "Model not found", body = sauropod_inference_http::Error),
+        (status = 500, description = "Error occured", body = sauropod_inference_http::Error)
+    )
+)]
+pub async fn create_chat_completion(
+    State(global_state): sauropod_global_state::AxumGlobalState,
+    axum::Json(request): axum::Json<serde_json::Value>,
+) -> axum::response::Response {
+    let request = match serde_json::from_value::<CreateChatCompletionRequest>(request.clone()) {
+        Ok(request) => request,
+        Err(e) => {
+            tracing::error!(
+                "Failed to parse request: {e}\n{}",
+                serde_json::to_string_pretty(&request).unwrap()
+            );
+            return (
+                axum::http::StatusCode::BAD_REQUEST,
+                axum::Json(sauropod_inference_http::Error {
+                    error: format!("Failed to parse request: {e}"),
+                }),
+            )
+                .into_response();
+        }
+    };
+    match crate::create_chat_completion_impl(global_state, request).await {
+        Ok(response) => response,
+        Err(e) => {
+            tracing::error!("Failed to create chat completion: {e:#?}");
+            (
+                axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+                axum::Json(sauropod_inference_http::Error {
+                    error: format!("Internal server error: {e}"),
+                }),
+            )
+                .into_response()
+        }
+    }
+}
@@ -0,0 +1,468 @@
+//! Chat Completions API types.
+
+/// A request to create a chat completion.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+pub struct CreateChatCompletionRequest {
+    /// The model used to generate the completion.
+    pub model: String,
+    /// The messages of the conversation so far.
+    pub messages: Vec<ChatCompletionRequestMessage>,
+    /// The functions the model may call.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<ChatCompletionTool>>,
+    /// Controls which tool, if any, is called by the model.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_choice: Option<ChatCompletionToolChoiceOption>,
+    /// Whether to enable parallel function calling during tool use.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parallel_tool_calls: Option<bool>,
+    /// The format that the model must output.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<ChatCompletionResponseFormat>,
+    /// Up to 4 sequences where the model will stop generating further tokens.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop: Option<sauropod_inference_engine_api::StopConfiguration>,
+    /// The maximum number of tokens that can be generated. Deprecated in favor of `max_completion_tokens`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<i64>,
+    /// An upper bound for the number of tokens that can be generated, including reasoning tokens.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_completion_tokens: Option<i64>,
+    /// The sampling temperature between 0 and 2.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+    /// The probability mass for nucleus sampling.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f64>,
+    /// The seed of the sampler, which makes the output deterministic.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u32>,
+    /// The penalty for tokens proportional to how often they occurred, between -2 and 2.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frequency_penalty: Option<f64>,
+    /// The penalty for tokens which occurred, between -2 and 2.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub presence_penalty: Option<f64>,
+    /// Biases added to the logits of tokens, keyed by token ID.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub logit_bias: Option<std::collections::HashMap<String, f64>>,
+    /// Sample from the K most likely tokens. Not part of the OpenAI API.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_k: Option<i64>,
+    /// The minimum probability of a token relative to the most likely one. Not part of the OpenAI API.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_p: Option<f64>,
+    /// The locally typical sampling parameter. Not part of the OpenAI API.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub typical_p: Option<f64>,
+    /// The penalty dividing the probability of repeated tokens. Not part of the OpenAI API.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repetition_penalty: Option<f64>,
+    /// The number of recent tokens used by the penalties, or -1 for all. Not part of the OpenAI API.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub penalty_window: Option<i64>,
+    /// DRY sampling settings. Not part of the OpenAI API.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dry: Option<sauropod_config::DryConfig>,
+    /// Mirostat sampling settings. Not part of the OpenAI API.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mirostat: Option<sauropod_config::MirostatConfig>,
+    /// The names of the LoRA adapters to apply instead of the model's active adapters. Not part
+    /// of the OpenAI API.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lora_adapters: Option<Vec<String>>,
+    /// How many completions to generate. Only 1 is supported.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub n: Option<i64>,
+    /// Whether to stream the completion as server-sent events.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stream: Option<bool>,
+    /// Options for streaming responses.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stream_options: Option<ChatCompletionStreamOptions>,
+    /// A stable identifier for the end-user.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+}
+
+/// A message in a chat completion request.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+#[serde(tag = "role")]
+pub enum ChatCompletionRequestMessage {
+    #[serde(rename = "system")]
+    System {
+        /// The contents of the message.
+        content: ChatCompletionMessageContent,
+        /// An optional name for the participant.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
+    },
+    #[serde(rename = "developer")]
+    Developer {
+        /// The contents of the message.
+        content: ChatCompletionMessageContent,
+        /// An optional name for the participant.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
+    },
+    #[serde(rename = "user")]
+    User {
+        /// The contents of the message.
+        content: ChatCompletionMessageContent,
+        /// An optional name for the participant.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
+    },
+    #[serde(rename = "assistant")]
+    Assistant {
+        /// The contents of the message.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        content: Option<ChatCompletionMessageContent>,
+        /// The tool calls generated by the model.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        tool_calls: Option<Vec<ChatCompletionMessageToolCall>>,
+        /// An optional name for the participant.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
+    },
+    #[serde(rename = "tool")]
+    Tool {
+        /// The contents of the message.
+        content: ChatCompletionMessageContent,
+        /// The tool call that this message is responding to.
+        tool_call_id: String,
+    },
+}
+
+/// The contents of a message.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+#[serde(untagged)]
+pub enum ChatCompletionMessageContent {
+    Text(String),
+    Parts(Vec<ChatCompletionContentPart>),
+}
+
+/// A part of the contents of a message.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+#[serde(tag = "type")]
+pub enum ChatCompletionContentPart {
+    #[serde(rename = "text")]
+    Text {
+        /// The text content.
+        text: String,
+    },
+    #[serde(rename = "image_url")]
+    ImageUrl {
+        /// The image to include in the message.
+        image_url: ChatCompletionImageUrl,
+    },
+}
+
+/// An image in a message.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+pub struct ChatCompletionImageUrl {
+    /// Either a URL of the image or the base64 encoded image data.
+    pub url: String,
+    /// The detail level of the image.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<sauropod_openai_api::InputImageContentDetail>,
+}
+
+/// The type of a tool. Always `function`.
+#[derive(Clone, Copy, Debug, Default, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+pub enum ChatCompletionToolType {
+    #[default]
+    #[serde(rename = "function")]
+    Function,
+}
+
+/// A tool call generated by the model.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+pub struct ChatCompletionMessageToolCall {
+    /// The ID of the tool call.
+    pub id: String,
+    /// The type of the tool.
+    #[serde(rename = "type")]
+    pub r#type: ChatCompletionToolType,
+    /// The function that the model called.
+    pub function: ChatCompletionFunctionCall,
+}
+
+/// A function called by the model.
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+pub struct ChatCompletionFunctionCall {
+    /// The name of the function to call.
+    pub name: String,
+    /// The arguments to call the function with, as generated by the model in JSON format.
+    pub arguments: String,
+}
+
+/// A tool the model may call.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+pub struct ChatCompletionTool {
+    /// The type of the tool.
+    #[serde(rename = "type")]
+    pub r#type: ChatCompletionToolType,
+    /// The function definition.
+    pub function: ChatCompletionFunctionDefinition,
+}
+
+/// The definition of a function the model may call.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+pub struct ChatCompletionFunctionDefinition {
+    /// The name of the function.
+    pub name: String,
+    /// A description of what the function does.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// The parameters the function accepts, described as a JSON Schema object.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parameters: Option<serde_json::Map<String, serde_json::Value>>,
+    /// Whether to enable strict schema adherence when generating the function call.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strict: Option<bool>,
+}
+
+/// Controls which tool, if any, is called by the model.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+#[serde(untagged)]
+pub enum ChatCompletionToolChoiceOption {
+    /// `none`, `auto` or `required`.
+    Mode(sauropod_openai_api::ToolChoiceOptions),
+    /// Forces the model to call a specific function.
+    Named(ChatCompletionNamedToolChoice),
+}
+
+/// Specifies a function the model must call.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+pub struct ChatCompletionNamedToolChoice {
+    /// The type of the tool.
+    #[serde(rename = "type")]
+    pub r#type: ChatCompletionToolType,
+    /// The function to call.
+    pub function: ChatCompletionNamedFunction,
+}
+
+/// The name of a function to call.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+pub struct ChatCompletionNamedFunction {
+    /// The name of the function to call.
+    pub name: String,
+}
+
+/// The format that the model must output.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+#[serde(tag = "type")]
+pub enum ChatCompletionResponseFormat {
+    #[serde(rename = "text")]
+    Text,
+    #[serde(rename = "json_object")]
+    JsonObject,
+    #[serde(rename = "json_schema")]
+    JsonSchema {
+        /// The JSON schema the output must conform to.
+        json_schema: ChatCompletionJsonSchema,
+    },
+}
+
+/// A JSON schema response format.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+pub struct ChatCompletionJsonSchema {
+    /// The name of the response format.
+    pub name: String,
+    /// A description of what the response format is for.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// The schema for the response format, described as a JSON Schema object.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema: Option<serde_json::Map<String, serde_json::Value>>,
+    /// Whether to enable strict schema adherence when generating the output.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strict: Option<bool>,
+}
+
+/// Options for streaming responses.
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+pub struct ChatCompletionStreamOptions {
+    /// Whether to stream an additional chunk with the token usage before the end of the stream.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub include_usage: Option<bool>,
+}
+
+/// The reason the model stopped generating tokens.
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+pub enum ChatCompletionFinishReason {
+    #[serde(rename = "stop")]
+    Stop,
+    #[serde(rename = "length")]
+    Length,
+    #[serde(rename = "tool_calls")]
+    ToolCalls,
+    #[serde(rename = "content_filter")]
+    ContentFilter,
+}
+
+/// The role of the author of a generated message. Always `assistant`.
+#[derive(Clone, Copy, Debug, Default, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+pub enum ChatCompletionResponseRole {
+    #[default]
+    #[serde(rename = "assistant")]
+    Assistant,
+}
+
+/// A chat completion message generated by the model.
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+pub struct ChatCompletionResponseMessage {
+    /// The role of the author of the message.
+    pub role: ChatCompletionResponseRole,
+    /// The contents of the message.
+    pub content: Option<String>,
+    /// The reasoning of the model before its answer.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reasoning_content: Option<String>,
+    /// The tool calls generated by the model.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<ChatCompletionMessageToolCall>>,
+}
+
+/// A chat completion choice.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+pub struct ChatCompletionChoice {
+    /// The index of the choice in the list of choices.
+    pub index: i64,
+    /// The message generated by the model.
+    pub message: ChatCompletionResponseMessage,
+    /// The reason the model stopped generating tokens.
+    pub finish_reason: ChatCompletionFinishReason,
+}
+
+/// The object type of a chat completion. Always `chat.completion`.
+#[derive(Clone, Copy, Debug, Default, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+pub enum ChatCompletionObject {
+    #[default]
+    #[serde(rename = "chat.completion")]
+    ChatCompletion,
+}
+
+/// A chat completion response.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+pub struct CreateChatCompletionResponse {
+    /// A unique identifier for the chat completion.
+    pub id: String,
+    /// The object type.
+    pub object: ChatCompletionObject,
+    /// The Unix timestamp (in seconds) of when the chat completion was created.
+    pub created: i64,
+    /// The model used for the chat completion.
+    pub model: String,
+    /// The chat completion choices.
+    pub choices: Vec<ChatCompletionChoice>,
+    /// Usage statistics for the completion request.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<CompletionUsage>,
+}
+
+/// A tool call in a streamed chat completion.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+pub struct ChatCompletionMessageToolCallChunk {
+    /// The index of the tool call in the message.
+    pub index: i64,
+    /// The ID of the tool call.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// The type of the tool.
+    #[serde(default, rename = "type", skip_serializing_if = "Option::is_none")]
+    pub r#type: Option<ChatCompletionToolType>,
+    /// The function that the model called.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub function: Option<ChatCompletionFunctionCallChunk>,
+}
+
+/// Part of a function called by the model in a streamed chat completion.
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+pub struct ChatCompletionFunctionCallChunk {
+    /// The name of the function to call, sent in the first chunk of the tool call.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// The next part of the arguments to call the function with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub arguments: Option<String>,
+}
+
+/// A chat completion delta generated by streamed model responses.
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+pub struct ChatCompletionStreamResponseDelta {
+    /// The role of the author of the message.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub role: Option<ChatCompletionResponseRole>,
+    /// The contents of the chunk message.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+    /// The reasoning of the model before its answer.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reasoning_content: Option<String>,
+    /// The tool calls generated by the model.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<ChatCompletionMessageToolCallChunk>>,
+}
+
+/// A streamed chat completion choice.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+pub struct ChatCompletionStreamChoice {
+    /// The index of the choice in the list of choices.
+    pub index: i64,
+    /// A chat completion delta.
+    pub delta: ChatCompletionStreamResponseDelta,
+    /// The reason the model stopped generating tokens, sent in the last chunk of the choice.
+    pub finish_reason: Option<ChatCompletionFinishReason>,
+}
+
+/// The object type of a chat completion chunk. Always `chat.completion.chunk`.
+#[derive(Clone, Copy, Debug, Default, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+pub enum ChatCompletionChunkObject {
+    #[default]
+    #[serde(rename = "chat.completion.chunk")]
+    ChatCompletionChunk,
+}
+
+/// A streamed chunk of a chat completion response.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+pub struct CreateChatCompletionStreamResponse {
+    /// A unique identifier for the chat completion. Each chunk has the same ID.
+    pub id: String,
+    /// The object type.
+    pub object: ChatCompletionChunkObject,
+    /// The Unix timestamp (in seconds) of when the chat completion was created.
+    pub created: i64,
+    /// The model used for the chat completion.
+    pub model: String,
+    /// The chat completion choices. Empty for the usage chunk.
+    pub choices: Vec<ChatCompletionStreamChoice>,
+    /// Usage statistics, only sent in the last chunk when `stream_options.include_usage` is set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<CompletionUsage>,
+}
+
+/// Usage statistics for a completion request.
+#[derive(
+    Clone, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize, utoipa::ToSchema,
+)]
+pub struct CompletionUsage {
+    /// The number of tokens in the prompt.
+    pub prompt_tokens: i64,
+    /// The number of tokens in the generated completion.
+    pub completion_tokens: i64,
+    /// The total number of tokens used in the request.
+    pub total_tokens: i64,
+    /// Breakdown of the tokens used in the prompt.
+    pub prompt_tokens_details: PromptTokensDetails,
+}
+
+/// Breakdown of the tokens used in a prompt.
+#[derive(
+    Clone, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize, utoipa::ToSchema,
+)]
+pub struct PromptTokensDetails {
+    /// The number of prompt tokens reused from the model's cache.
+    pub cached_tokens: i64,
+}
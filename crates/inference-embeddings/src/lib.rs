@@ -0,0 +1,209 @@
+//! OpenAI-compatible Embeddings API.
+
+use axum::response::IntoResponse as _;
+use base64::Engine as _;
+
+use sauropod_inference_engine_api::EmbeddingInput;
+
+mod routes;
+pub use routes::*;
+mod types;
+pub use types::*;
+
+/// The maximum number of inputs in one request.
+const MAX_INPUTS: usize = 2048;
+
+/// Convert the input of a request to the inputs of the embedding model.
+fn embedding_inputs(input: EmbeddingRequestInput) -> anyhow::Result<Vec<EmbeddingInput>> {
+    let inputs = match input {
+        EmbeddingRequestInput::Text(text) => vec![EmbeddingInput::Text(text)],
+        EmbeddingRequestInput::Texts(texts) => {
+            texts.into_iter().map(EmbeddingInput::Text).collect()
+        }
+        EmbeddingRequestInput::Tokens(tokens) => vec![EmbeddingInput::Tokens(tokens)],
+        EmbeddingRequestInput::TokenLists(token_lists) => token_lists
+            .into_iter()
+            .map(EmbeddingInput::Tokens)
+            .collect(),
+    };
+    anyhow::ensure!(!inputs.is_empty(), "The input must not be empty");
+    anyhow::ensure!(
+        inputs.len() <= MAX_INPUTS,
+        "The input must not contain more than {MAX_INPUTS} items"
+    );
+    anyhow::ensure!(
+        inputs.iter().all(|input| match input {
+            EmbeddingInput::Text(text) => !text.is_empty(),
+            EmbeddingInput::Tokens(tokens) => !tokens.is_empty(),
+        }),
+        "The input must not contain empty items"
+    );
+    Ok(inputs)
+}
+
+/// Truncate a normalized embedding to `dimensions` and normalize it again.
+fn truncate_embedding(embedding: &mut Vec<f32>, dimensions: usize) {
+    if dimensions >= embedding.len() {
+        return;
+    }
+    embedding.truncate(dimensions);
+    let norm = embedding.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        embedding.iter_mut().for_each(|x| *x /= norm);
+    }
+}
+
+/// Encode an embedding in the requested format.
+fn encode_embedding(
+    embedding: Vec<f32>,
+    encoding_format: EmbeddingEncodingFormat,
+) -> EmbeddingVector {
+    match encoding_format {
+        EmbeddingEncodingFormat::Float => EmbeddingVector::Float(embedding),
+        EmbeddingEncodingFormat::Base64 => {
+            let bytes: Vec<u8> = embedding.iter().flat_map(|x| x.to_le_bytes()).collect();
+            EmbeddingVector::Base64(base64::engine::general_purpose::STANDARD.encode(bytes))
+        }
+    }
+}
+
+pub async fn create_embedding_impl(
+    global_state: std::sync::Arc<sauropod_global_state::GlobalState>,
+    request: CreateEmbeddingRequest,
+) -> anyhow::Result<axum::response::Response> {
+    let Some(model) = global_state.get_embedding_model(&request.model).await else {
+        return Ok(
+            sauropod_inference_http::HttpResponse::<()>::NotFound(Some(format!(
+                "Embedding model '{}' not found",
+                request.model
+            )))
+            .into_response(),
+        );
+    };
+
+    let model_dimensions = model.embedding_dimensions();
+    let dimensions = match request.dimensions {
+        None => model_dimensions,
+        Some(dimensions) if dimensions >= 1 && dimensions as usize <= model_dimensions => {
+            dimensions as usize
+        }
+        Some(dimensions) => {
+            return Ok(
+                sauropod_inference_http::HttpResponse::<()>::BadRequest(format!(
+                    "dimensions must be between 1 and {model_dimensions}, got {dimensions}"
+                ))
+                .into_response(),
+            );
+        }
+    };
+    let inputs = match embedding_inputs(request.input) {
+        Ok(inputs) => inputs,
+        Err(e) => {
+            return Ok(
+                sauropod_inference_http::HttpResponse::<()>::BadRequest(format!(
+                    "Invalid input: {e}"
+                ))
+                .into_response(),
+            );
+        }
+    };
+
+    let response = model.embed(inputs).await?;
+    let encoding_format = request.encoding_format.unwrap_or_default();
+    let data = response
+        .embeddings
+        .into_iter()
+        .enumerate()
+        .map(|(index, mut embedding)| {
+            truncate_embedding(&mut embedding, dimensions);
+            Embedding {
+                index,
+                embedding: encode_embedding(embedding, encoding_format),
+                object: EmbeddingObject::Embedding,
+            }
+        })
+        .collect();
+
+    Ok(axum::Json(CreateEmbeddingResponse {
+        data,
+        model: request.model,
+        object: EmbeddingListObject::List,
+        usage: EmbeddingUsage {
+            prompt_tokens: response.input_token_count,
+            total_tokens: response.input_token_count,
+        },
+    })
+    .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_inputs() {
+        let inputs = |value: serde_json::Value| {
+            embedding_inputs(
+                serde_json::from_value::<CreateEmbeddingRequest>(serde_json::json!({
+                    "model": "embed",
+                    "input": value,
+                }))
+                .unwrap()
+                .input,
+            )
+        };
+
+        assert_eq!(
+            inputs(serde_json::json!("Hello")).unwrap(),
+            vec![EmbeddingInput::Text("Hello".to_string())]
+        );
+        assert_eq!(
+            inputs(serde_json::json!(["a", "b"])).unwrap(),
+            vec![
+                EmbeddingInput::Text("a".to_string()),
+                EmbeddingInput::Text("b".to_string())
+            ]
+        );
+        assert_eq!(
+            inputs(serde_json::json!([1, 2])).unwrap(),
+            vec![EmbeddingInput::Tokens(vec![1, 2])]
+        );
+        assert_eq!(
+            inputs(serde_json::json!([[1], [2, 3]])).unwrap(),
+            vec![
+                EmbeddingInput::Tokens(vec![1]),
+                EmbeddingInput::Tokens(vec![2, 3])
+            ]
+        );
+        assert!(inputs(serde_json::json!([])).is_err());
+        assert!(inputs(serde_json::json!(["a", ""])).is_err());
+    }
+
+    #[test]
+    fn test_truncate_embedding() {
+        let mut embedding = vec![0.6, 0.0, 0.8];
+        truncate_embedding(&mut embedding, 3);
+        assert_eq!(embedding, vec![0.6, 0.0, 0.8]);
+
+        let mut embedding = vec![0.6, 0.0, 0.8];
+        truncate_embedding(&mut embedding, 2);
+        assert_eq!(embedding, vec![1.0, 0.0]);
+    }
+
+    #[test]
+    fn test_encode_embedding_as_base64() {
+        let EmbeddingVector::Base64(encoded) =
+            encode_embedding(vec![1.0, -2.0], EmbeddingEncodingFormat::Base64)
+        else {
+            panic!("Expected a base64 embedding");
+        };
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(encoded)
+            .unwrap();
+        let decoded: Vec<f32> = bytes
+            .chunks_exact(4)
+            .map(|x| f32::from_le_bytes(x.try_into().unwrap()))
+            .collect();
+        assert_eq!(decoded, vec![1.0, -2.0]);
+    }
+}
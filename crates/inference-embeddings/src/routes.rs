@@ -0,0 +1,51 @@
+use axum::extract::State;
+use axum::response::IntoResponse;
+
+use crate::{CreateEmbeddingRequest, CreateEmbeddingResponse};
+
+#[utoipa::path(
+    post,
+    path = "/v1/embeddings",
+    tag = "Embeddings",
+    request_body = CreateEmbeddingRequest,
+    responses(
+        (status = 200, description = "Embeddings created", body = CreateEmbeddingResponse),
+        (status = 400, description = "Invalid request", body = sauropod_inference_http::Error),
+        (status = 404, description = "Model not found", body = sauropod_inference_http::Error),
+        (status = 500, description = "Error occured", body = sauropod_inference_http::Error)
+    )
+)]
+pub async fn create_embedding(
+    State(global_state): sauropod_global_state::AxumGlobalState,
+    axum::Json(request): axum::Json<serde_json::Value>,
+) -> axum::response::Response {
+    let request = match serde_json::from_value::<CreateEmbeddingRequest>(request.clone()) {
+        Ok(request) => request,
+        Err(e) => {
+            tracing::error!(
+                "Failed to parse request: {e}\n{}",
+                serde_json::to_string_pretty(&request).unwrap()
+            );
+            return (
+                axum::http::StatusCode::BAD_REQUEST,
+                axum::Json(sauropod_inference_http::Error {
+                    error: format!("Failed to parse request: {e}"),
+                }),
+            )
+                .into_response();
+        }
+    };
+    match crate::create_embedding_impl(global_state, request).await {
+        Ok(response) => response,
+        Err(e) => {
+            tracing::error!("Failed to create embeddings: {e:#?}");
+            (
+                axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+                axum::Json(sauropod_inference_http::Error {
+                    error: format!("Internal server error: {e}"),
+                }),
+            )
+                .into_response()
+        }
+    }
+}
@@ -0,0 +1,112 @@
+//! Embeddings API types.
+
+/// A request to create embeddings.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+pub struct CreateEmbeddingRequest {
+    /// The text or tokens to embed.
+    pub input: EmbeddingRequestInput,
+    /// The embedding model to use.
+    pub model: String,
+    /// The format of the returned embeddings.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encoding_format: Option<EmbeddingEncodingFormat>,
+    /// The number of dimensions the embeddings are truncated to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dimensions: Option<i64>,
+    /// A stable identifier for the end-user.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+}
+
+/// The input of an embedding request.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+#[serde(untagged)]
+pub enum EmbeddingRequestInput {
+    /// A single text.
+    Text(String),
+    /// A list of texts.
+    Texts(Vec<String>),
+    /// A single list of tokens.
+    Tokens(Vec<u32>),
+    /// A list of token lists.
+    TokenLists(Vec<Vec<u32>>),
+}
+
+/// The format of the returned embeddings.
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    serde::Serialize,
+    serde::Deserialize,
+    utoipa::ToSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum EmbeddingEncodingFormat {
+    /// A list of floats.
+    #[default]
+    Float,
+    /// Base64 encoded little-endian 32 bit floats.
+    Base64,
+}
+
+/// The object type of an embedding.
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize, utoipa::ToSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum EmbeddingObject {
+    Embedding,
+}
+
+/// An embedding vector.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+#[serde(untagged)]
+pub enum EmbeddingVector {
+    Float(Vec<f32>),
+    Base64(String),
+}
+
+/// An embedding of one input.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+pub struct Embedding {
+    /// The index of the input in the request.
+    pub index: usize,
+    /// The embedding vector.
+    pub embedding: EmbeddingVector,
+    pub object: EmbeddingObject,
+}
+
+/// Token usage of an embedding request.
+#[derive(
+    Clone, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize, utoipa::ToSchema,
+)]
+pub struct EmbeddingUsage {
+    /// The number of tokens in the input.
+    pub prompt_tokens: i64,
+    /// The total number of tokens used.
+    pub total_tokens: i64,
+}
+
+/// The object type of an embedding response.
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize, utoipa::ToSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum EmbeddingListObject {
+    List,
+}
+
+/// The embeddings of a request.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+pub struct CreateEmbeddingResponse {
+    /// One embedding per input.
+    pub data: Vec<Embedding>,
+    /// The model used to create the embeddings.
+    pub model: String,
+    pub object: EmbeddingListObject,
+    pub usage: EmbeddingUsage,
+}
@@ -0,0 +1,675 @@
+//! Grammars for constraining the output of a model.
+
+use sauropod_grammar::{GrammarBuilder, GrammarError, ROOT_RULE, literal};
+use sauropod_openai_api::{
+    ResponsePropertiesToolChoice, TextResponseFormatConfiguration, ToolChoiceAllowed,
+    ToolChoiceAllowedMode, ToolChoiceFunction, ToolChoiceOptions,
+};
+use sauropod_output_parser::ModelType;
+
+/// The delimiters of the reasoning of Qwen 3 and DeepSeek-R1 models.
+const THINK_START: &str = "<think>";
+const THINK_END: &str = "</think>";
+
+/// The recipient in the header of a harmony message calling a function.
+const HARMONY_FUNCTION_RECIPIENT: &str = "to=functions.";
+
+/// Add the rule for reasoning opened by the grammar expression `start` and closed by `end`, which
+/// may contain anything but `end`, and return its name.
+fn add_reasoning_rule(builder: &mut GrammarBuilder, start: &str, end: &str) -> String {
+    let content_rule = builder.add_text_excluding("reasoning-content", end);
+    builder.add_rule(
+        "reasoning",
+        &format!(r"{start} {content_rule} {} [ \t\n]*", literal(end)),
+    )
+}
+
+/// Make `output_rule` the root of the grammar, allowing for any preamble the model emits before its output.
+fn add_root_rule(builder: &mut GrammarBuilder, model_type: &ModelType, output_rule: &str) {
+    match model_type {
+        ModelType::Qwen3 => {
+            let reasoning_rule = add_reasoning_rule(builder, &literal(THINK_START), THINK_END);
+            builder.add_rule(ROOT_RULE, &format!("{reasoning_rule}? {output_rule}"));
+        }
+        ModelType::Harmony => {
+            // Analysis may precede the final message
+            let analysis_rule = add_harmony_analysis_rule(builder);
+            let preamble_rule = builder.add_rule(
+                "preamble",
+                &format!(r#"{analysis_rule}? "<|channel|>final<|message|>""#),
+            );
+            builder.add_rule(ROOT_RULE, &format!("{preamble_rule} {output_rule}"));
+        }
+        ModelType::DeepSeekR1 => {
+            // The reasoning may have been opened by the chat template
+            let start = format!("{}?", literal(THINK_START));
+            let reasoning_rule = add_reasoning_rule(builder, &start, THINK_END);
+            builder.add_rule(ROOT_RULE, &format!("{reasoning_rule}? {output_rule}"));
+        }
+        ModelType::Custom(format) => match &format.reasoning {
+            Some(delimiters) => {
+                let reasoning_rule =
+                    add_reasoning_rule(builder, &literal(&delimiters.start), &delimiters.end);
+                builder.add_rule(ROOT_RULE, &format!("{reasoning_rule}? {output_rule}"));
+            }
+            None => {
+                builder.add_rule(ROOT_RULE, output_rule);
+            }
+        },
+        ModelType::MarkdownToolCall
+        | ModelType::Llama3
+        | ModelType::Mistral
+        | ModelType::Hermes
+        | ModelType::Unknown => {
+            builder.add_rule(ROOT_RULE, output_rule);
+        }
+    }
+}
+
+/// Add the rule for a harmony analysis message followed by the start of the next assistant message.
+fn add_harmony_analysis_rule(builder: &mut GrammarBuilder) -> String {
+    let content_rule = builder.add_text_excluding("analysis-content", "<|end|>");
+    builder.add_rule(
+        "analysis",
+        &format!(
+            r#""<|channel|>analysis<|message|>" {content_rule} "<|end|>" "<|start|>assistant""#
+        ),
+    )
+}
+
+/// A function tool the model may call.
+struct Function<'a> {
+    name: &'a str,
+    parameters: Option<&'a serde_json::Map<String, serde_json::Value>>,
+    /// Whether the arguments must conform to `parameters` rather than just be a JSON object.
+    strict: bool,
+}
+
+/// How the `tool_choice` of a request constrains the output.
+enum ToolConstraint<'a> {
+    /// The model may call a tool or respond with text.
+    Unconstrained,
+    /// The model must not call a tool.
+    Forbidden,
+    /// The model must call one of the functions.
+    Required(Vec<Function<'a>>),
+}
+
+/// Get the constraint on tool calls from the `tool_choice` of a request.
+fn tool_constraint(
+    response_properties: &sauropod_openai_api::ResponseProperties,
+) -> anyhow::Result<ToolConstraint<'_>> {
+    let functions: Vec<Function> = response_properties
+        .tools
+        .iter()
+        .flatten()
+        .filter_map(|tool| match tool {
+            sauropod_openai_api::Tool::FunctionTool {
+                name,
+                parameters,
+                strict,
+                ..
+            } => Some(Function {
+                name,
+                parameters: parameters.as_ref(),
+                strict: strict.unwrap_or(true),
+            }),
+            _ => None,
+        })
+        .collect();
+
+    let required = match &response_properties.tool_choice {
+        None
+        | Some(ResponsePropertiesToolChoice::ToolChoiceOptions(ToolChoiceOptions::Auto))
+        | Some(ResponsePropertiesToolChoice::ToolChoiceAllowed(ToolChoiceAllowed {
+            mode: ToolChoiceAllowedMode::Auto,
+            ..
+        })) => return Ok(ToolConstraint::Unconstrained),
+        Some(ResponsePropertiesToolChoice::ToolChoiceOptions(ToolChoiceOptions::None)) => {
+            return Ok(if functions.is_empty() {
+                ToolConstraint::Unconstrained
+            } else {
+                ToolConstraint::Forbidden
+            });
+        }
+        Some(ResponsePropertiesToolChoice::ToolChoiceOptions(ToolChoiceOptions::Required)) => {
+            functions
+        }
+        Some(ResponsePropertiesToolChoice::ToolChoiceFunction(ToolChoiceFunction {
+            name, ..
+        })) => {
+            let named: Vec<Function> = functions
+                .into_iter()
+                .filter(|function| function.name == name)
+                .collect();
+            if named.is_empty() {
+                anyhow::bail!("tool_choice names the function '{name}' which isn't in tools");
+            }
+            named
+        }
+        Some(ResponsePropertiesToolChoice::ToolChoiceAllowed(ToolChoiceAllowed {
+            mode: ToolChoiceAllowedMode::Required,
+            tools,
+            ..
+        })) => functions
+            .into_iter()
+            .filter(|function| {
+                tools.iter().any(|tool| {
+                    tool.get("type").and_then(|value| value.as_str()) == Some("function")
+                        && tool.get("name").and_then(|value| value.as_str()) == Some(function.name)
+                })
+            })
+            .collect(),
+        // Built-in tools aren't supported so these don't constrain the output
+        Some(
+            ResponsePropertiesToolChoice::ToolChoiceTypes(_)
+            | ResponsePropertiesToolChoice::ToolChoiceMCP(_)
+            | ResponsePropertiesToolChoice::ToolChoiceCustom(_),
+        ) => return Ok(ToolConstraint::Unconstrained),
+    };
+
+    if required.is_empty() {
+        anyhow::bail!("tool_choice requires a function call but no function tools are available");
+    }
+    Ok(ToolConstraint::Required(required))
+}
+
+/// Whether the model may call a function tool in output which isn't constrained by a grammar.
+///
+/// Output constrained to a JSON text format or to a required tool call can't contain a call made without the model's tool call delimiters.
+pub fn offers_unconstrained_tool_calls(
+    response_properties: &sauropod_openai_api::ResponseProperties,
+) -> bool {
+    let has_functions = response_properties
+        .tools
+        .iter()
+        .flatten()
+        .any(|tool| matches!(tool, sauropod_openai_api::Tool::FunctionTool { .. }));
+    let json_format = matches!(
+        response_properties
+            .text
+            .as_ref()
+            .and_then(|text| text.format.as_ref()),
+        Some(
+            TextResponseFormatConfiguration::TextResponseFormatJsonSchema { .. }
+                | TextResponseFormatConfiguration::ResponseFormatJsonObject {}
+        )
+    );
+    has_functions
+        && !json_format
+        && matches!(
+            tool_constraint(response_properties),
+            Ok(ToolConstraint::Unconstrained)
+        )
+}
+
+/// Add the rule for the arguments of a call to `function`, which only have to be a JSON object if
+/// the function isn't strict.
+fn add_arguments_rule(
+    builder: &mut GrammarBuilder,
+    function: &Function,
+) -> Result<String, GrammarError> {
+    let parameters = match function.parameters {
+        Some(parameters) if function.strict && !parameters.is_empty() => {
+            serde_json::Value::Object(parameters.clone())
+        }
+        _ => serde_json::json!({"type": "object"}),
+    };
+    builder.add_json_schema(&parameters, &format!("{}-arguments", function.name))
+}
+
+/// Add the rule for a harmony message calling `function`, which ends the output.
+fn add_harmony_function_call_rule(
+    builder: &mut GrammarBuilder,
+    function: &Function,
+) -> Result<String, GrammarError> {
+    let arguments_rule = add_arguments_rule(builder, function)?;
+    Ok(builder.add_rule(
+        &format!("{}-call", function.name),
+        &format!(
+            r#"{} " <|constrain|>json"? "<|message|>" {arguments_rule} "<|call|>""#,
+            literal(&format!(
+                "<|channel|>commentary {HARMONY_FUNCTION_RECIPIENT}{}",
+                function.name
+            )),
+        ),
+    ))
+}
+
+/// Add the rule for a call to `function` in the JSON format the output parsers expect.
+///
+/// The arguments of functions which aren't strict only have to be a JSON object.
+fn add_function_call_rule(
+    builder: &mut GrammarBuilder,
+    function: &Function,
+) -> Result<String, GrammarError> {
+    let arguments_rule = add_arguments_rule(builder, function)?;
+    let space = builder.add_primitive("space");
+    Ok(builder.add_rule(
+        &format!("{}-call", function.name),
+        &format!(
+            r#""{{" {space} {} {space} ":" {space} {} {space} "," {space} {} {space} ":" {space} {arguments_rule} "}}" {space}"#,
+            literal(r#""name""#),
+            literal(&serde_json::Value::String(function.name.to_string()).to_string()),
+            literal(r#""arguments""#),
+        ),
+    ))
+}
+
+/// Create the grammar enforcing the requested tool choice and text format of a response.
+///
+/// A required tool call takes precedence over the text format. Returns `None` when the output isn't constrained.
+pub fn response_grammar(
+    response_properties: &sauropod_openai_api::ResponseProperties,
+    model_type: &ModelType,
+    parallel_tool_calls: bool,
+) -> anyhow::Result<Option<String>> {
+    let format = response_properties
+        .text
+        .as_ref()
+        .and_then(|text| text.format.as_ref());
+
+    let mut builder = GrammarBuilder::new();
+    let output_rule = match (tool_constraint(response_properties)?, format) {
+        (ToolConstraint::Required(functions), _) if matches!(model_type, ModelType::Harmony) => {
+            // The function is addressed in the header of a commentary message rather than the
+            // final message the root rule expects
+            let calls = functions
+                .iter()
+                .map(|function| add_harmony_function_call_rule(&mut builder, function))
+                .collect::<Result<Vec<_>, _>>()?
+                .join(" | ");
+            let analysis_rule = add_harmony_analysis_rule(&mut builder);
+            builder.add_rule(ROOT_RULE, &format!("{analysis_rule}? ( {calls} )"));
+            return Ok(Some(builder.build()));
+        }
+        (ToolConstraint::Required(functions), _) => {
+            let Some((open, close)) = model_type.tool_call_envelope() else {
+                anyhow::bail!("The model doesn't support tool calls");
+            };
+            let calls = functions
+                .iter()
+                .map(|function| add_function_call_rule(&mut builder, function))
+                .collect::<Result<Vec<_>, _>>()?
+                .join(" | ");
+            if parallel_tool_calls && open.ends_with('[') {
+                // The calls are elements of a single JSON array
+                builder.add_rule(
+                    "tool-call",
+                    &format!(
+                        r#"{} ( {calls} ) ( "," " "? ( {calls} ) )* {}"#,
+                        literal(open),
+                        literal(close)
+                    ),
+                )
+            } else {
+                let call_rule = builder.add_rule(
+                    "tool-call",
+                    &format!("{} ( {calls} ) {}", literal(open), literal(close)),
+                );
+                // Without a closing delimiter the end of one call can't be told apart from the start of the next
+                if parallel_tool_calls && !close.is_empty() {
+                    builder.add_rule(
+                        "tool-calls",
+                        &format!(r"{call_rule} ( [ \t\n]* {call_rule} )*"),
+                    )
+                } else {
+                    call_rule
+                }
+            }
+        }
+        (_, Some(TextResponseFormatConfiguration::TextResponseFormatJsonSchema { schema, .. })) => {
+            builder.add_json_schema(
+                &serde_json::Value::Object(schema.extra_fields.clone()),
+                "output",
+            )?
+        }
+        (_, Some(TextResponseFormatConfiguration::ResponseFormatJsonObject {})) => {
+            builder.add_json_schema(&serde_json::json!({"type": "object"}), "output")?
+        }
+        (ToolConstraint::Forbidden, _) => match model_type.tool_call_envelope() {
+            Some((open, _)) => builder.add_text_excluding("text", open.trim_end()),
+            None if matches!(model_type, ModelType::Harmony) => {
+                // Any channel may be used as long as no message is addressed to a function
+                let text_rule = builder.add_text_excluding("text", HARMONY_FUNCTION_RECIPIENT);
+                builder.add_rule(ROOT_RULE, &text_rule);
+                return Ok(Some(builder.build()));
+            }
+            None => return Ok(None),
+        },
+        (
+            ToolConstraint::Unconstrained,
+            None | Some(TextResponseFormatConfiguration::ResponseFormatText {}),
+        ) => return Ok(None),
+    };
+    add_root_rule(&mut builder, model_type, &output_rule);
+    Ok(Some(builder.build()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn properties_with_format(
+        format: serde_json::Value,
+    ) -> sauropod_openai_api::ResponseProperties {
+        serde_json::from_value(serde_json::json!({ "text": { "format": format } })).unwrap()
+    }
+
+    fn properties_with_tool_choice(
+        tool_choice: serde_json::Value,
+    ) -> sauropod_openai_api::ResponseProperties {
+        serde_json::from_value(serde_json::json!({
+            "tools": [
+                {
+                    "type": "function",
+                    "name": "get_weather",
+                    "parameters": {
+                        "type": "object",
+                        "properties": {"city": {"type": "string"}},
+                        "required": ["city"]
+                    },
+                    "strict": true
+                },
+                {"type": "function", "name": "get_time", "parameters": null, "strict": true}
+            ],
+            "tool_choice": tool_choice
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_text_format_is_unconstrained() {
+        let properties = properties_with_format(serde_json::json!({"type": "text"}));
+        assert_eq!(
+            response_grammar(&properties, &ModelType::Unknown, false).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_json_object_format() {
+        let properties = properties_with_format(serde_json::json!({"type": "json_object"}));
+        let grammar = response_grammar(&properties, &ModelType::MarkdownToolCall, false)
+            .unwrap()
+            .unwrap();
+        assert!(grammar.starts_with("root ::= object\n"), "{grammar}");
+    }
+
+    #[test]
+    fn test_json_object_format_in_harmony_final_channel() {
+        let properties = properties_with_format(serde_json::json!({"type": "json_object"}));
+        let grammar = response_grammar(&properties, &ModelType::Harmony, false)
+            .unwrap()
+            .unwrap();
+        assert!(
+            grammar.starts_with("root ::= preamble object\n"),
+            "{grammar}"
+        );
+        assert!(
+            grammar.contains(r#""<|channel|>final<|message|>""#),
+            "{grammar}"
+        );
+    }
+
+    #[test]
+    fn test_json_schema_format_allows_reasoning() {
+        let properties = properties_with_format(serde_json::json!({
+            "type": "json_schema",
+            "name": "answer",
+            "schema": {
+                "type": "object",
+                "properties": {"answer": {"type": "string"}},
+                "required": ["answer"]
+            }
+        }));
+        let grammar = response_grammar(&properties, &ModelType::Qwen3, false)
+            .unwrap()
+            .unwrap();
+        assert!(
+            grammar.starts_with("root ::= reasoning? output\n"),
+            "{grammar}"
+        );
+        assert!(grammar.contains(r#"output-answer-kv ::= "\"answer\"" space ":" space string"#));
+    }
+
+    #[test]
+    fn test_required_tool_choice() {
+        let properties = properties_with_tool_choice(serde_json::json!("required"));
+        let grammar = response_grammar(&properties, &ModelType::Qwen3, false)
+            .unwrap()
+            .unwrap();
+        assert!(
+            grammar.starts_with("root ::= reasoning? tool-call\n"),
+            "{grammar}"
+        );
+        assert!(
+            grammar.contains(
+                r#"tool-call ::= "<tool_call>\n" ( get-weather-call | get-time-call ) "\n</tool_call>""#
+            ),
+            "{grammar}"
+        );
+        assert!(grammar.contains(
+            r#"get-weather-call ::= "{" space "\"name\"" space ":" space "\"get_weather\"" space "," space "\"arguments\"" space ":" space get-weather-arguments "}" space"#
+        ));
+    }
+
+    #[test]
+    fn test_reasoning_may_contain_closing_tags() {
+        let properties = properties_with_format(serde_json::json!({"type": "json_object"}));
+        let grammar = response_grammar(&properties, &ModelType::Qwen3, false)
+            .unwrap()
+            .unwrap();
+        assert!(
+            grammar.contains(r#"reasoning ::= "<think>" reasoning-content-0 "</think>""#),
+            "{grammar}"
+        );
+        // Only the closing tag of the reasoning ends it, so `</` may be followed by anything else
+        assert!(
+            grammar.contains(r#"reasoning-content-2 ::= ( [^/<>hiknt] reasoning-content-0 |"#),
+            "{grammar}"
+        );
+
+        let grammar = response_grammar(&properties, &ModelType::DeepSeekR1, false)
+            .unwrap()
+            .unwrap();
+        assert!(
+            grammar.contains(r#"reasoning ::= "<think>"? reasoning-content-0 "</think>""#),
+            "{grammar}"
+        );
+    }
+
+    #[test]
+    fn test_non_strict_function_arguments_are_any_object() {
+        let mut properties = properties_with_tool_choice(serde_json::json!("required"));
+        for tool in properties.tools.iter_mut().flatten() {
+            if let sauropod_openai_api::Tool::FunctionTool { strict, .. } = tool {
+                *strict = Some(false);
+            }
+        }
+        let grammar = response_grammar(&properties, &ModelType::Qwen3, false)
+            .unwrap()
+            .unwrap();
+        assert!(!grammar.contains("city"), "{grammar}");
+        assert!(
+            grammar.contains(r#""\"arguments\"" space ":" space object "}" space"#),
+            "{grammar}"
+        );
+    }
+
+    #[test]
+    fn test_required_tool_choice_with_custom_delimiters() {
+        let properties = properties_with_tool_choice(serde_json::json!("required"));
+        let model_type =
+            ModelType::Custom(std::sync::Arc::new(sauropod_output_parser::CustomFormat {
+                reasoning: Some(sauropod_output_parser::Delimiters {
+                    start: "<reasoning>".to_string(),
+                    end: "</reasoning>".to_string(),
+                }),
+                tool_call: Some(sauropod_output_parser::Delimiters {
+                    start: "<function_call>".to_string(),
+                    end: "</function_call>".to_string(),
+                }),
+            }));
+        let grammar = response_grammar(&properties, &model_type, false)
+            .unwrap()
+            .unwrap();
+        assert!(
+            grammar.starts_with("root ::= reasoning? tool-call\n"),
+            "{grammar}"
+        );
+        assert!(
+            grammar.contains(r#"reasoning ::= "<reasoning>" reasoning-content-0 "</reasoning>""#),
+            "{grammar}"
+        );
+        assert!(
+            grammar.contains(
+                r#"tool-call ::= "<function_call>" ( get-weather-call | get-time-call ) "</function_call>""#
+            ),
+            "{grammar}"
+        );
+    }
+
+    #[test]
+    fn test_required_parallel_tool_calls() {
+        let properties = properties_with_tool_choice(serde_json::json!("required"));
+        let grammar = response_grammar(&properties, &ModelType::Qwen3, true)
+            .unwrap()
+            .unwrap();
+        assert!(
+            grammar.starts_with("root ::= reasoning? tool-calls\n"),
+            "{grammar}"
+        );
+        assert!(
+            grammar.contains(r"tool-calls ::= tool-call ( [ \t\n]* tool-call )*"),
+            "{grammar}"
+        );
+
+        let grammar = response_grammar(&properties, &ModelType::Mistral, true)
+            .unwrap()
+            .unwrap();
+        assert!(
+            grammar.contains(
+                r#"tool-call ::= "[TOOL_CALLS][" ( get-weather-call | get-time-call ) ( "," " "? ( get-weather-call | get-time-call ) )* "]""#
+            ),
+            "{grammar}"
+        );
+    }
+
+    #[test]
+    fn test_required_tool_choice_in_mistral_array() {
+        let properties = properties_with_tool_choice(serde_json::json!("required"));
+        let grammar = response_grammar(&properties, &ModelType::Mistral, false)
+            .unwrap()
+            .unwrap();
+        assert!(grammar.starts_with("root ::= tool-call\n"), "{grammar}");
+        assert!(
+            grammar.contains(
+                r#"tool-call ::= "[TOOL_CALLS][" ( get-weather-call | get-time-call ) "]""#
+            ),
+            "{grammar}"
+        );
+    }
+
+    #[test]
+    fn test_named_function_tool_choice() {
+        let properties = properties_with_tool_choice(
+            serde_json::json!({"type": "function", "name": "get_time"}),
+        );
+        let grammar = response_grammar(&properties, &ModelType::MarkdownToolCall, false)
+            .unwrap()
+            .unwrap();
+        assert!(
+            grammar.contains(r#"tool-call ::= "```tool_call\n" ( get-time-call ) "\n```""#),
+            "{grammar}"
+        );
+        assert!(!grammar.contains("get-weather-call"));
+
+        let properties =
+            properties_with_tool_choice(serde_json::json!({"type": "function", "name": "missing"}));
+        assert!(response_grammar(&properties, &ModelType::MarkdownToolCall, false).is_err());
+    }
+
+    #[test]
+    fn test_none_tool_choice_excludes_tool_calls() {
+        let properties = properties_with_tool_choice(serde_json::json!("none"));
+        let grammar = response_grammar(&properties, &ModelType::MarkdownToolCall, false)
+            .unwrap()
+            .unwrap();
+        assert!(grammar.starts_with("root ::= text-0\n"), "{grammar}");
+        assert!(!grammar.contains("tool-call"));
+        assert_eq!(
+            response_grammar(&properties, &ModelType::Unknown, false).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_required_tool_choice_in_harmony_commentary() {
+        let properties = properties_with_tool_choice(serde_json::json!("required"));
+        let grammar = response_grammar(&properties, &ModelType::Harmony, true)
+            .unwrap()
+            .unwrap();
+        assert!(
+            grammar.starts_with("root ::= analysis? ( get-weather-call | get-time-call )\n"),
+            "{grammar}"
+        );
+        assert!(
+            grammar.contains(
+                r#"get-weather-call ::= "<|channel|>commentary to=functions.get_weather" " <|constrain|>json"? "<|message|>" get-weather-arguments "<|call|>""#
+            ),
+            "{grammar}"
+        );
+        assert!(!grammar.contains("final"), "{grammar}");
+    }
+
+    #[test]
+    fn test_named_function_tool_choice_in_harmony() {
+        let properties = properties_with_tool_choice(
+            serde_json::json!({"type": "function", "name": "get_time"}),
+        );
+        let grammar = response_grammar(&properties, &ModelType::Harmony, false)
+            .unwrap()
+            .unwrap();
+        assert!(
+            grammar.starts_with("root ::= analysis? ( get-time-call )\n"),
+            "{grammar}"
+        );
+        assert!(!grammar.contains("get-weather-call"));
+    }
+
+    #[test]
+    fn test_none_tool_choice_excludes_harmony_function_calls() {
+        let properties = properties_with_tool_choice(serde_json::json!("none"));
+        let grammar = response_grammar(&properties, &ModelType::Harmony, false)
+            .unwrap()
+            .unwrap();
+        assert!(grammar.starts_with("root ::= text-0\n"), "{grammar}");
+        assert!(!grammar.contains("-call"), "{grammar}");
+    }
+
+    #[test]
+    fn test_offers_unconstrained_tool_calls() {
+        assert!(offers_unconstrained_tool_calls(
+            &properties_with_tool_choice(serde_json::json!("auto"))
+        ));
+        assert!(!offers_unconstrained_tool_calls(
+            &properties_with_tool_choice(serde_json::json!("none"))
+        ));
+        assert!(!offers_unconstrained_tool_calls(
+            &properties_with_tool_choice(serde_json::json!("required"))
+        ));
+        assert!(!offers_unconstrained_tool_calls(&properties_with_format(
+            serde_json::json!({"type": "text"})
+        )));
+
+        let mut properties = properties_with_tool_choice(serde_json::json!("auto"));
+        properties.text = properties_with_format(serde_json::json!({
+            "type": "json_schema",
+            "name": "person",
+            "schema": {"type": "object", "properties": {"name": {"type": "string"}}}
+        }))
+        .text;
+        assert!(!offers_unconstrained_tool_calls(&properties));
+    }
+}
@@ -2,10 +2,18 @@
 
 use std::sync::Arc;
 
+mod grammar;
+pub use grammar::{offers_unconstrained_tool_calls, response_grammar};
 mod response_stream;
 pub use response_stream::ResponseStreamCreator;
 mod sampling;
-pub use sampling::SamplerProperties;
+pub use sampling::{
+    DEFAULT_MAX_OUTPUT_TOKENS, SamplerProperties, SamplingOverrides, StopConfiguration,
+};
+mod stop_sequences;
+mod tool_call_arguments;
+
+pub use tokio_util::sync::CancellationToken;
 
 /// Create an empty response from a request.
 pub fn make_response(
@@ -19,11 +27,20 @@ pub fn make_response(
         usage: None,
         error: None,
         incomplete_details: None,
-        model_response_properties: request
-            .create_model_response_properties
-            .model_response_properties
-            .clone(),
-        parallel_tool_calls: false,
+        model_response_properties: sauropod_openai_api::ModelResponseProperties {
+            top_logprobs: request
+                .create_model_response_properties
+                .top_logprobs
+                .or(request
+                    .create_model_response_properties
+                    .model_response_properties
+                    .top_logprobs),
+            ..request
+                .create_model_response_properties
+                .model_response_properties
+                .clone()
+        },
+        parallel_tool_calls: request.parallel_tool_calls.unwrap_or(true),
         response_properties: request.response_properties.clone(),
         status: None,
         instructions: None,
@@ -31,6 +48,7 @@ pub fn make_response(
 }
 
 /// A local model artifact
+#[derive(Clone)]
 pub enum ModelPath {
     /// GGUF model file.
     GGUF(std::path::PathBuf),
@@ -70,6 +88,8 @@ pub type TokenSequence = Vec<Token>;
 pub struct GenerateFromTextResponse {
     /// The number of input tokens.
     pub input_token_count: i64,
+    /// The number of input tokens reused from the model's cache.
+    pub cached_token_count: i64,
     /// The output stream from the LLM.
     pub stream: PartStream,
 }
@@ -78,20 +98,43 @@ pub struct GenerateFromTextResponse {
 #[async_trait::async_trait]
 pub trait LlmModel: Send + Sync {
     /// Generate text from a prompt and receive a stream of tokens.
+    ///
+    /// The generation stops once `cancellation_token` is cancelled.
     async fn generate_from_tokens(
         self: Arc<Self>,
         sampler_properties: SamplerProperties,
         tokens: TokenSequence,
+        cancellation_token: CancellationToken,
     ) -> anyhow::Result<TokenStream>;
 
     /// Generate text from a prompt and receive a stream of text pieces.
+    ///
+    /// The generation stops once `cancellation_token` is cancelled.
     async fn generate_from_text(
         self: Arc<Self>,
         sampler_properties: SamplerProperties,
         text: String,
         multimodal_data: Vec<sauropod_prompt_templates::MultimodalData>,
+        cancellation_token: CancellationToken,
     ) -> anyhow::Result<GenerateFromTextResponse>;
 
+    /// Count the tokens of a prompt.
+    fn count_tokens(&self, text: &str) -> anyhow::Result<usize>;
+
+    /// The maximum number of tokens in the model's context.
+    fn context_size(&self) -> usize;
+
+    /// Load the LoRA adapter `adapter` from the file at `path` so that generations can apply it.
+    ///
+    /// Loading an adapter which is already loaded does nothing.
+    fn load_lora_adapter(
+        &self,
+        adapter: &sauropod_config::ConfigModelSource,
+        _path: &std::path::Path,
+    ) -> anyhow::Result<()> {
+        anyhow::bail!("The model doesn't support LoRA adapters, so {adapter} can't be loaded")
+    }
+
     /// Get the Jinja template for the model.
     fn get_model_chat_template(&self) -> &str;
 
@@ -102,11 +145,103 @@ pub trait LlmModel: Send + Sync {
     fn supports_vision(&self) -> bool;
 }
 
+/// An input to an embedding model.
+#[derive(Clone, Debug, PartialEq)]
+pub enum EmbeddingInput {
+    /// Text which is tokenized by the model.
+    Text(String),
+    /// Tokens from the model's vocabulary.
+    Tokens(TokenSequence),
+}
+
+/// The result of an `embed` call.
+pub struct EmbedResponse {
+    /// One normalized embedding per input, in the order of the inputs.
+    pub embeddings: Vec<Vec<f32>>,
+    /// The number of input tokens.
+    pub input_token_count: i64,
+}
+
+/// A model which creates embeddings.
+#[async_trait::async_trait]
+pub trait EmbeddingModel: Send + Sync {
+    /// Create a pooled embedding for each input.
+    async fn embed(self: Arc<Self>, inputs: Vec<EmbeddingInput>) -> anyhow::Result<EmbedResponse>;
+
+    /// The number of dimensions of the embeddings.
+    fn embedding_dimensions(&self) -> usize;
+}
+
+/// The result of a `rerank` call.
+pub struct RerankResponse {
+    /// One relevance score per document, in the order of the documents.
+    pub scores: Vec<f32>,
+    /// The number of input tokens.
+    pub input_token_count: i64,
+}
+
+/// A model which scores the relevance of documents to a query.
+#[async_trait::async_trait]
+pub trait RerankModel: Send + Sync {
+    /// Score each document against the query.
+    async fn rerank(
+        self: Arc<Self>,
+        query: String,
+        documents: Vec<String>,
+    ) -> anyhow::Result<RerankResponse>;
+}
+
 /// Boxed stream of tokens.
 pub type TokenStream = futures_core::stream::BoxStream<'static, anyhow::Result<Token>>;
 
+/// Why a generation stopped.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum StopReason {
+    /// The model generated an end of generation token.
+    #[default]
+    EndOfGeneration,
+    /// The maximum number of tokens was generated.
+    MaxTokens,
+    /// The output reached a stop sequence.
+    StopSequence,
+    /// The generation was cancelled.
+    Cancelled,
+    /// The model's context has no room for more tokens.
+    ContextFull,
+}
+
+/// A piece of generated text.
+#[derive(Clone, Debug, Default)]
+pub struct Part {
+    /// The text of the generated token.
+    pub text: String,
+    /// The log probability of the token and its most likely alternatives, if requested.
+    pub logprob: Option<sauropod_openai_api::LogProb>,
+    /// Why the generation stopped, only set on the last part which has no token.
+    pub stop_reason: Option<StopReason>,
+}
+
+impl From<String> for Part {
+    fn from(text: String) -> Self {
+        Self {
+            text,
+            ..Default::default()
+        }
+    }
+}
+
+impl Part {
+    /// Create the last part of a generation.
+    pub fn stop(stop_reason: StopReason) -> Self {
+        Self {
+            stop_reason: Some(stop_reason),
+            ..Default::default()
+        }
+    }
+}
+
 /// Boxed stream of text parts.
-pub type PartStream = futures_core::stream::BoxStream<'static, anyhow::Result<String>>;
+pub type PartStream = futures_core::stream::BoxStream<'static, anyhow::Result<Part>>;
 
 /// Boxed stream of response events.
 pub type ResponseStream = futures_core::stream::BoxStream<
@@ -24,6 +24,13 @@ pub struct ResponseStreamCreator {
     parser: Box<dyn sauropod_output_parser::ModelOutputParser>,
     reasoning_state: Option<ReasoningState>,
     tool_call_state: Option<ToolCallState>,
+    stop_sequences: crate::stop_sequences::StopSequences,
+    /// Why the generation stopped.
+    stop_reason: crate::StopReason,
+    /// Whether a tool call finished when the response allows only one.
+    tool_call_limit_reached: bool,
+    /// The log probabilities of the tokens whose text hasn't been emitted yet.
+    pending_logprobs: Vec<sauropod_openai_api::LogProb>,
 }
 
 struct ReasoningState {
@@ -32,7 +39,16 @@ struct ReasoningState {
 
 struct ToolCallState {
     item_id: String,
+    /// The name of the function when the output format gives it separately from the arguments.
+    name: Option<String>,
     buffer: String,
+    /// Reads the arguments when the output format gives them together with the name.
+    arguments: Option<crate::tool_call_arguments::ToolCallArguments>,
+    /// Whether the output item added event has been emitted.
+    ///
+    /// When the name is given with the arguments, the event is emitted once the arguments start
+    /// so that it carries the name.
+    added: bool,
 }
 
 impl ResponseStreamCreator {
@@ -64,6 +80,10 @@ impl ResponseStreamCreator {
             parser: output_parser,
             reasoning_state: None,
             tool_call_state: None,
+            stop_sequences: Default::default(),
+            stop_reason: crate::StopReason::EndOfGeneration,
+            tool_call_limit_reached: false,
+            pending_logprobs: Vec::new(),
         }
     }
 
@@ -160,10 +180,16 @@ impl ResponseStreamCreator {
                 // Append the text to the existing content
                 if let Some(sauropod_openai_api::OutputContent::OutputTextContent {
                     text: existing_text,
+                    logprobs: existing_logprobs,
                     ..
                 }) = content.get_mut(self.content_index as usize)
                 {
                     existing_text.push_str(&text);
+                    if !text.is_empty() && !self.pending_logprobs.is_empty() {
+                        existing_logprobs
+                            .get_or_insert_with(Vec::new)
+                            .extend(self.pending_logprobs.iter().cloned());
+                    }
                 }
 
                 id.clone()
@@ -190,7 +216,11 @@ impl ResponseStreamCreator {
                     delta: text,
                     item_id: message_id,
                     output_index: self.output_index,
-                    logprobs: vec![],
+                    logprobs: self
+                        .pending_logprobs
+                        .drain(..)
+                        .map(sauropod_openai_api::ResponseLogProb::from)
+                        .collect(),
                 },
             );
         }
@@ -212,9 +242,30 @@ impl ResponseStreamCreator {
         }
 
         for event in parsed_events {
+            // Anything after a stop sequence or the only tool call allowed is dropped
+            if self.is_stopped() {
+                break;
+            }
+            // Text held back in case it started a stop sequence is emitted before anything else
+            if !matches!(event, sauropod_output_parser::Event::Text(_)) {
+                let held_back_text = self.stop_sequences.flush();
+                if !held_back_text.is_empty() {
+                    events.extend(self.push_output_text(held_back_text));
+                }
+                // Tokens of reasoning and tool calls have no log probabilities in the output
+                self.pending_logprobs.clear();
+            }
             match event {
+                // Stop sequences only apply to the output text, not to reasoning or tool calls
                 sauropod_output_parser::Event::Text(t) => {
-                    events.extend(self.push_text_internal(t.to_string()));
+                    let text = self.stop_sequences.push(t);
+                    if self.stop_sequences.is_stopped() {
+                        self.set_stop_reason(crate::StopReason::StopSequence);
+                    }
+                    // Nothing is emitted while the text may be the start of a stop sequence
+                    if !text.is_empty() || t.is_empty() {
+                        events.extend(self.push_output_text(text));
+                    }
                 }
                 sauropod_output_parser::Event::Reasoning(t) => {
                     events.extend(self.push_reasoning_delta(t));
@@ -222,6 +273,9 @@ impl ResponseStreamCreator {
                 sauropod_output_parser::Event::ReasoningEnd => {
                     events.extend(self.finish_reasoning());
                 }
+                sauropod_output_parser::Event::ToolCallName(name) => {
+                    events.extend(self.open_tool_call_item(Some(name)));
+                }
                 sauropod_output_parser::Event::ToolCall(t) => {
                     events.extend(self.push_tool_call_delta(t));
                 }
@@ -233,14 +287,77 @@ impl ResponseStreamCreator {
         events
     }
 
+    /// Pushes text of the output message, except whitespace separating tool calls which doesn't
+    /// start a message.
+    fn push_output_text(&mut self, text: String) -> Vec<sauropod_openai_api::ResponseStreamEvent> {
+        if text.trim().is_empty()
+            && !self.output_item_open
+            && matches!(
+                self.response.output.last(),
+                Some(sauropod_openai_api::OutputItem::FunctionToolCall { .. })
+            )
+        {
+            return Vec::new();
+        }
+        self.push_text_internal(text)
+    }
+
+    /// Record the number of input tokens, `cached_tokens` of which were reused from the model's cache.
+    pub fn set_input_tokens(&mut self, input_tokens: i64, cached_tokens: i64) {
+        let Some(usage) = self.response.usage.as_mut() else {
+            unreachable!()
+        };
+        usage.input_tokens = input_tokens;
+        usage.input_tokens_details.cached_tokens = cached_tokens;
+    }
+
+    /// End the output before any of `stop_sequences`, which aren't included in the output.
+    pub fn set_stop_sequences(&mut self, stop_sequences: Vec<String>) {
+        self.stop_sequences = crate::stop_sequences::StopSequences::new(stop_sequences);
+    }
+
+    /// Whether the output has reached a stop sequence, after which further parts are ignored.
+    pub fn is_stopped(&self) -> bool {
+        self.stop_sequences.is_stopped() || self.tool_call_limit_reached
+    }
+
+    /// Record why the generation stopped, which determines the status the response finishes with.
+    pub fn set_stop_reason(&mut self, stop_reason: crate::StopReason) {
+        self.stop_reason = stop_reason;
+    }
+
     /// Call push text and update the token content in the internal response state.
-    pub fn push_part(&mut self, part: String) -> Vec<sauropod_openai_api::ResponseStreamEvent> {
+    pub fn push_part(
+        &mut self,
+        part: impl Into<crate::Part>,
+    ) -> Vec<sauropod_openai_api::ResponseStreamEvent> {
+        let part = part.into();
+        if let Some(stop_reason) = part.stop_reason {
+            self.set_stop_reason(stop_reason);
+            return Vec::new();
+        }
+
+        let in_reasoning = self.reasoning_state.is_some();
         let Some(usage) = self.response.usage.as_mut() else {
             unreachable!()
         };
         usage.output_tokens += 1;
-        usage.total_tokens += 1;
-        self.push_text(part)
+        if self.is_stopped() {
+            return Vec::new();
+        }
+        self.pending_logprobs.extend(part.logprob);
+
+        // The log probabilities stay queued until the text of their tokens is emitted
+        let events = self.push_text(part.text);
+
+        // A token belongs to the reasoning if it's part of a reasoning span, including its end
+        if in_reasoning || self.reasoning_state.is_some() {
+            let Some(usage) = self.response.usage.as_mut() else {
+                unreachable!()
+            };
+            usage.output_tokens_details.reasoning_tokens += 1;
+        }
+        events
     }
 
     /// Closes the current content part if one is open and emits a ResponseContentPartDoneEvent.
@@ -258,7 +375,7 @@ impl ResponseStreamCreator {
             && let Some(output_content) = content.get(self.content_index as usize)
         {
             // If this is a text content part with non-empty text, emit ResponseTextDoneEvent first
-            if let sauropod_openai_api::OutputContent::OutputTextContent { text, .. } =
+            if let sauropod_openai_api::OutputContent::OutputTextContent { text, logprobs, .. } =
                 output_content
                 && !text.is_empty()
             {
@@ -269,7 +386,12 @@ impl ResponseStreamCreator {
                         item_id: id.clone(),
                         output_index: self.output_index,
                         text: text.clone(),
-                        logprobs: vec![],
+                        logprobs: logprobs
+                            .iter()
+                            .flatten()
+                            .cloned()
+                            .map(sauropod_openai_api::ResponseLogProb::from)
+                            .collect(),
                     },
                 );
             }
@@ -407,20 +529,35 @@ impl ResponseStreamCreator {
     ) -> Vec<sauropod_openai_api::ResponseStreamEvent> {
         let mut events = Vec::new();
         if self.tool_call_state.is_none() {
-            events.extend(self.open_tool_call_item());
+            events.extend(self.open_tool_call_item(None));
         }
 
-        if let Some(state) = self.tool_call_state.as_mut() {
-            state.buffer.push_str(delta);
-            if let Some(sauropod_openai_api::OutputItem::FunctionToolCall { arguments, .. }) =
-                self.response.output.get_mut(self.output_index as usize)
-            {
-                arguments.push_str(delta);
-            }
+        let Some(state) = self.tool_call_state.as_mut() else {
+            return events;
+        };
+        state.buffer.push_str(delta);
+        // Only the arguments are streamed, without the JSON giving the name around them
+        let (delta, has_arguments) = match state.arguments.as_mut() {
+            Some(arguments) => (arguments.push(delta), arguments.has_arguments()),
+            None => (delta.to_string(), true),
+        };
+        if has_arguments {
+            events.extend(self.add_tool_call_item());
+        }
+        if delta.is_empty() {
+            return events;
+        }
+
+        if let Some(sauropod_openai_api::OutputItem::FunctionToolCall { arguments, .. }) =
+            self.response.output.get_mut(self.output_index as usize)
+        {
+            arguments.push_str(&delta);
+        }
+        if let Some(state) = self.tool_call_state.as_ref() {
             events.push(
                 sauropod_openai_api::ResponseStreamEvent::ResponseFunctionCallArgumentsDeltaEvent {
                     sequence_number: get_next_sequence_number(&self.sequence_number),
-                    delta: delta.to_string(),
+                    delta,
                     item_id: state.item_id.clone(),
                     output_index: self.output_index,
                 },
@@ -429,21 +566,63 @@ impl ResponseStreamCreator {
         events
     }
 
+    /// Emit the output item added event of the current tool call, with the name of its function
+    /// if it's known, unless it has already been emitted.
+    fn add_tool_call_item(&mut self) -> Option<sauropod_openai_api::ResponseStreamEvent> {
+        let state = self.tool_call_state.as_mut()?;
+        if state.added {
+            return None;
+        }
+        state.added = true;
+        let function_name = state
+            .arguments
+            .as_ref()
+            .and_then(|arguments| arguments.name());
+        let item = self.response.output.get_mut(self.output_index as usize)?;
+        if let (
+            sauropod_openai_api::OutputItem::FunctionToolCall { name, .. },
+            Some(function_name),
+        ) = (&mut *item, function_name)
+        {
+            *name = function_name.to_string();
+        }
+        Some(
+            sauropod_openai_api::ResponseStreamEvent::ResponseOutputItemAddedEvent {
+                sequence_number: get_next_sequence_number(&self.sequence_number),
+                item: item.clone(),
+                output_index: self.output_index,
+            },
+        )
+    }
+
     fn finish_tool_call(&mut self) -> Vec<sauropod_openai_api::ResponseStreamEvent> {
         let mut events = Vec::new();
+        events.extend(self.add_tool_call_item());
         let Some(state) = self.tool_call_state.take() else {
             return events;
         };
 
         let mut args_string = state.buffer.clone();
         let mut name = String::new();
-        if let Ok(val) = serde_json::from_str::<serde_json::Value>(&state.buffer)
+        if let Some(function_name) = state.name {
+            name = function_name;
+            args_string = state.buffer.trim().to_string();
+        } else if let Some(arguments) = state
+            .arguments
+            .as_ref()
+            .filter(|arguments| arguments.has_arguments())
+        {
+            // The arguments are the same as the streamed deltas
+            name = arguments.name().unwrap_or_default().to_string();
+            args_string = arguments.arguments().to_string();
+        } else if let Ok(val) = serde_json::from_str::<serde_json::Value>(&state.buffer)
             && let Some(obj) = val.as_object()
         {
             if let Some(n) = obj.get("name").and_then(|v| v.as_str()) {
                 name = n.to_string();
             }
-            if let Some(arg) = obj.get("arguments") {
+            // Llama 3 calls functions with `parameters`
+            if let Some(arg) = obj.get("arguments").or_else(|| obj.get("parameters")) {
                 args_string = arg.to_string();
             }
         }
@@ -471,6 +650,9 @@ impl ResponseStreamCreator {
 
         events.extend(self.close_current_output_item());
         self.output_index += 1;
+        if !self.response.parallel_tool_calls {
+            self.tool_call_limit_reached = true;
+        }
         events
     }
 
@@ -520,9 +702,13 @@ impl ResponseStreamCreator {
         events
     }
 
-    fn open_tool_call_item(&mut self) -> Vec<sauropod_openai_api::ResponseStreamEvent> {
+    fn open_tool_call_item(
+        &mut self,
+        name: Option<String>,
+    ) -> Vec<sauropod_openai_api::ResponseStreamEvent> {
         let mut events = Vec::new();
         self.ensure_response_created(&mut events);
+        events.extend(self.finish_tool_call());
         events.extend(self.close_current_content_part());
         events.extend(self.close_current_output_item());
 
@@ -531,27 +717,32 @@ impl ResponseStreamCreator {
             arguments: String::new(),
             call_id: call_id.clone(),
             id: call_id.clone(),
-            name: String::new(),
+            name: name.clone().unwrap_or_default(),
             status: Some(sauropod_openai_api::Status::InProgress),
         };
 
-        self.response.output.push(function_item.clone());
+        self.response.output.push(function_item);
         self.output_index = (self.response.output.len() - 1) as i64;
         self.output_item_open = true;
         self.content_index = 0;
 
-        events.push(
-            sauropod_openai_api::ResponseStreamEvent::ResponseOutputItemAddedEvent {
-                sequence_number: get_next_sequence_number(&self.sequence_number),
-                item: function_item,
-                output_index: self.output_index,
-            },
-        );
-
+        let arguments = name
+            .is_none()
+            .then(crate::tool_call_arguments::ToolCallArguments::default);
         self.tool_call_state = Some(ToolCallState {
             item_id: call_id,
+            name,
             buffer: String::new(),
+            arguments,
+            added: false,
         });
+        if self
+            .tool_call_state
+            .as_ref()
+            .is_some_and(|state| state.name.is_some())
+        {
+            events.extend(self.add_tool_call_item());
+        }
         events
     }
 
@@ -559,18 +750,56 @@ impl ResponseStreamCreator {
     pub fn finish(&mut self) -> Vec<sauropod_openai_api::ResponseStreamEvent> {
         let mut events = Vec::new();
 
+        // Emit the text which was held back in case it started a stop sequence
+        let held_back_text = self.stop_sequences.flush();
+        if !held_back_text.is_empty() {
+            events.extend(self.push_output_text(held_back_text));
+        }
+
+        // Complete a tool call whose end was not generated
+        events.extend(self.finish_tool_call());
+
         // Close any open content part first
         events.extend(self.close_current_content_part());
 
         // Close any open output item
         events.extend(self.close_current_output_item());
 
-        self.response.status = Some(sauropod_openai_api::ResponseStatus::Completed);
+        self.response.status = Some(match self.stop_reason {
+            crate::StopReason::Cancelled => sauropod_openai_api::ResponseStatus::Cancelled,
+            crate::StopReason::MaxTokens | crate::StopReason::ContextFull => {
+                self.response.incomplete_details =
+                    Some(sauropod_openai_api::ResponseIncompleteDetails {
+                        reason: Some(
+                            sauropod_openai_api::ResponseIncompleteDetailsReason::MaxOutputTokens,
+                        ),
+                    });
+                sauropod_openai_api::ResponseStatus::Incomplete
+            }
+            crate::StopReason::EndOfGeneration | crate::StopReason::StopSequence => {
+                sauropod_openai_api::ResponseStatus::Completed
+            }
+        });
+        if let Some(usage) = self.response.usage.as_mut() {
+            usage.total_tokens = usage.input_tokens + usage.output_tokens;
+        }
 
+        let sequence_number = get_next_sequence_number(&self.sequence_number);
+        let response = self.response.clone();
         events.push(
-            sauropod_openai_api::ResponseStreamEvent::ResponseCompletedEvent {
-                sequence_number: get_next_sequence_number(&self.sequence_number),
-                response: self.response.clone(),
+            if matches!(
+                response.status,
+                Some(sauropod_openai_api::ResponseStatus::Incomplete)
+            ) {
+                sauropod_openai_api::ResponseStreamEvent::ResponseIncompleteEvent {
+                    sequence_number,
+                    response,
+                }
+            } else {
+                sauropod_openai_api::ResponseStreamEvent::ResponseCompletedEvent {
+                    sequence_number,
+                    response,
+                }
             },
         );
         events
@@ -851,4 +1080,414 @@ mod tests {
             _ => panic!("Expected ResponseContentPartDoneEvent"),
         }
     }
+
+    #[test]
+    fn test_cached_tokens_reported_in_usage() {
+        let initial_response = create_test_response();
+        let mut creator = ResponseStreamCreator::new(
+            sauropod_output_parser::get_model_parser(sauropod_output_parser::ModelType::Unknown),
+            initial_response,
+        );
+
+        creator.set_input_tokens(120, 96);
+        creator.push_part("Hello".to_string());
+
+        let finish_events = creator.finish();
+        match finish_events.last() {
+            Some(sauropod_openai_api::ResponseStreamEvent::ResponseCompletedEvent {
+                response,
+                ..
+            }) => {
+                let usage = response.usage.as_ref().expect("usage should be set");
+                assert_eq!(usage.input_tokens, 120);
+                assert_eq!(usage.input_tokens_details.cached_tokens, 96);
+                assert_eq!(usage.output_tokens, 1);
+            }
+            _ => panic!("Expected ResponseCompletedEvent"),
+        }
+    }
+
+    #[test]
+    fn test_output_ends_at_stop_sequence() {
+        let mut creator = ResponseStreamCreator::new(
+            sauropod_output_parser::get_model_parser(sauropod_output_parser::ModelType::Unknown),
+            create_test_response(),
+        );
+        creator.set_stop_sequences(vec!["STOP".to_string()]);
+
+        creator.push_part("Hello ST".to_string());
+        assert!(!creator.is_stopped());
+        creator.push_part("OP and more".to_string());
+        assert!(creator.is_stopped());
+
+        let finish_events = creator.finish();
+        match finish_events.last() {
+            Some(sauropod_openai_api::ResponseStreamEvent::ResponseCompletedEvent {
+                response,
+                ..
+            }) => match response.output.as_slice() {
+                [OutputItem::OutputMessage { content, .. }] => match content.as_slice() {
+                    [sauropod_openai_api::OutputContent::OutputTextContent { text, .. }] => {
+                        assert_eq!(text, "Hello ");
+                    }
+                    other => panic!("Unexpected content {other:?}"),
+                },
+                other => panic!("Unexpected output {other:?}"),
+            },
+            _ => panic!("Expected ResponseCompletedEvent"),
+        }
+    }
+
+    #[test]
+    fn test_stop_sequences_only_apply_to_output_text() {
+        let mut creator = ResponseStreamCreator::new(
+            sauropod_output_parser::get_model_parser(sauropod_output_parser::ModelType::Qwen3),
+            Response {
+                parallel_tool_calls: true,
+                ..create_test_response()
+            },
+        );
+        creator.set_stop_sequences(vec!["STOP".to_string()]);
+
+        for part in [
+            "<think>",
+            "Don't STOP",
+            "</think>",
+            "<tool_call>",
+            r#"{"name": "f", "arguments": {"x": "STOP"}}"#,
+            "</tool_call>",
+            "Done ST",
+            "OP",
+        ] {
+            creator.push_part(part.to_string());
+        }
+        assert!(creator.is_stopped());
+
+        match creator.finish().last() {
+            Some(sauropod_openai_api::ResponseStreamEvent::ResponseCompletedEvent {
+                response,
+                ..
+            }) => match response.output.as_slice() {
+                [
+                    OutputItem::ReasoningItem { .. },
+                    OutputItem::FunctionToolCall { arguments, .. },
+                    OutputItem::OutputMessage { content, .. },
+                ] => {
+                    assert_eq!(arguments, r#"{"x": "STOP"}"#);
+                    match content.as_slice() {
+                        [sauropod_openai_api::OutputContent::OutputTextContent { text, .. }] => {
+                            assert_eq!(text, "Done ");
+                        }
+                        other => panic!("Unexpected content {other:?}"),
+                    }
+                }
+                other => panic!("Unexpected output {other:?}"),
+            },
+            _ => panic!("Expected ResponseCompletedEvent"),
+        }
+    }
+
+    #[test]
+    fn test_cancelled_response_status() {
+        let mut creator = ResponseStreamCreator::new(
+            sauropod_output_parser::get_model_parser(sauropod_output_parser::ModelType::Unknown),
+            create_test_response(),
+        );
+        creator.push_part("Hello".to_string());
+        creator.set_stop_reason(crate::StopReason::Cancelled);
+
+        match creator.finish().last() {
+            Some(sauropod_openai_api::ResponseStreamEvent::ResponseCompletedEvent {
+                response,
+                ..
+            }) => {
+                assert!(matches!(response.status, Some(ResponseStatus::Cancelled)));
+                assert_eq!(response.output.len(), 1);
+            }
+            _ => panic!("Expected ResponseCompletedEvent"),
+        }
+    }
+
+    #[test]
+    fn test_incomplete_response_status() {
+        let mut creator = ResponseStreamCreator::new(
+            sauropod_output_parser::get_model_parser(sauropod_output_parser::ModelType::Unknown),
+            create_test_response(),
+        );
+        creator.set_input_tokens(3, 0);
+        creator.push_part("Hello".to_string());
+        creator.push_part(" world".to_string());
+        creator.push_part(crate::Part::stop(crate::StopReason::MaxTokens));
+
+        match creator.finish().last() {
+            Some(sauropod_openai_api::ResponseStreamEvent::ResponseIncompleteEvent {
+                response,
+                ..
+            }) => {
+                assert!(matches!(response.status, Some(ResponseStatus::Incomplete)));
+                let usage = response.usage.as_ref().unwrap();
+                assert_eq!(usage.output_tokens, 2);
+                assert_eq!(usage.total_tokens, 5);
+                assert!(matches!(
+                    response.incomplete_details,
+                    Some(sauropod_openai_api::ResponseIncompleteDetails {
+                        reason: Some(
+                            sauropod_openai_api::ResponseIncompleteDetailsReason::MaxOutputTokens
+                        ),
+                    })
+                ));
+            }
+            _ => panic!("Expected ResponseIncompleteEvent"),
+        }
+    }
+
+    #[test]
+    fn test_reasoning_tokens_reported_in_usage() {
+        let mut creator = ResponseStreamCreator::new(
+            sauropod_output_parser::get_model_parser(sauropod_output_parser::ModelType::Qwen3),
+            create_test_response(),
+        );
+        for token in ["<think>", "Let me", " think", "</think>", "Hi", " there"] {
+            creator.push_part(token.to_string());
+        }
+
+        match creator.finish().last() {
+            Some(sauropod_openai_api::ResponseStreamEvent::ResponseCompletedEvent {
+                response,
+                ..
+            }) => {
+                let usage = response.usage.as_ref().unwrap();
+                assert_eq!(usage.output_tokens, 6);
+                assert_eq!(usage.output_tokens_details.reasoning_tokens, 4);
+            }
+            _ => panic!("Expected ResponseCompletedEvent"),
+        }
+    }
+
+    #[test]
+    fn test_named_tool_call_completed_at_finish() {
+        let mut creator = ResponseStreamCreator::new(
+            sauropod_output_parser::get_model_parser(sauropod_output_parser::ModelType::Harmony),
+            create_test_response(),
+        );
+        for part in [
+            "<|channel|>",
+            "commentary to=functions.get_weather",
+            " <|constrain|>json",
+            "<|message|>",
+            r#"{"city": "Paris"}"#,
+        ] {
+            creator.push_part(part.to_string());
+        }
+
+        match creator.finish().last() {
+            Some(sauropod_openai_api::ResponseStreamEvent::ResponseCompletedEvent {
+                response,
+                ..
+            }) => match response.output.as_slice() {
+                [
+                    OutputItem::FunctionToolCall {
+                        name,
+                        arguments,
+                        status,
+                        ..
+                    },
+                ] => {
+                    assert_eq!(name, "get_weather");
+                    assert_eq!(arguments, r#"{"city": "Paris"}"#);
+                    assert!(matches!(status, Some(Status::Completed)));
+                }
+                other => panic!("Unexpected output {other:?}"),
+            },
+            _ => panic!("Expected ResponseCompletedEvent"),
+        }
+    }
+
+    /// Get the name and call ID of the tool calls in the completed response.
+    fn completed_tool_calls(creator: &mut ResponseStreamCreator) -> Vec<(String, String)> {
+        match creator.finish().last() {
+            Some(sauropod_openai_api::ResponseStreamEvent::ResponseCompletedEvent {
+                response,
+                ..
+            }) => response
+                .output
+                .iter()
+                .map(|item| match item {
+                    OutputItem::FunctionToolCall { name, call_id, .. } => {
+                        (name.clone(), call_id.clone())
+                    }
+                    other => panic!("Unexpected output {other:?}"),
+                })
+                .collect(),
+            _ => panic!("Expected ResponseCompletedEvent"),
+        }
+    }
+
+    const TWO_TOOL_CALLS: &str = "<tool_call>\n{\"name\": \"a\", \"arguments\": {}}\n</tool_call>\n<tool_call>\n{\"name\": \"b\", \"arguments\": {}}\n</tool_call>";
+
+    #[test]
+    fn test_parallel_tool_calls() {
+        let mut creator = ResponseStreamCreator::new(
+            sauropod_output_parser::get_model_parser(sauropod_output_parser::ModelType::Qwen3),
+            Response {
+                parallel_tool_calls: true,
+                ..create_test_response()
+            },
+        );
+        let events = creator.push_part(TWO_TOOL_CALLS.to_string());
+        let done_indices: Vec<i64> = events
+            .iter()
+            .filter_map(|event| {
+                match event {
+                sauropod_openai_api::ResponseStreamEvent::ResponseFunctionCallArgumentsDoneEvent {
+                    output_index,
+                    ..
+                } => Some(*output_index),
+                _ => None,
+            }
+            })
+            .collect();
+        assert_eq!(done_indices, vec![0, 1]);
+        assert!(!creator.is_stopped());
+
+        let calls = completed_tool_calls(&mut creator);
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[0].0, "a");
+        assert_eq!(calls[1].0, "b");
+        assert_ne!(calls[0].1, calls[1].1);
+    }
+
+    #[test]
+    fn test_stop_after_first_tool_call_without_parallel_tool_calls() {
+        let mut creator = ResponseStreamCreator::new(
+            sauropod_output_parser::get_model_parser(sauropod_output_parser::ModelType::Qwen3),
+            create_test_response(),
+        );
+        creator.push_part(TWO_TOOL_CALLS.to_string());
+        assert!(creator.is_stopped());
+
+        let calls = completed_tool_calls(&mut creator);
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].0, "a");
+    }
+
+    #[test]
+    fn test_logprobs_of_output_text() {
+        let mut creator = ResponseStreamCreator::new(
+            sauropod_output_parser::get_model_parser(sauropod_output_parser::ModelType::Qwen3),
+            create_test_response(),
+        );
+        let logprob = |token: &str| sauropod_openai_api::LogProb {
+            bytes: token.bytes().map(i64::from).collect(),
+            logprob: -0.5,
+            token: token.to_string(),
+            top_logprobs: vec![sauropod_openai_api::TopLogProb {
+                bytes: token.bytes().map(i64::from).collect(),
+                logprob: -0.5,
+                token: token.to_string(),
+            }],
+        };
+
+        creator.push_part(crate::Part {
+            text: "<think>".to_string(),
+            logprob: Some(logprob("<think>")),
+            stop_reason: None,
+        });
+        creator.push_part(crate::Part {
+            text: "Hmm</think>".to_string(),
+            logprob: Some(logprob("Hmm</think>")),
+            stop_reason: None,
+        });
+        let events = creator.push_part(crate::Part {
+            text: "Yes".to_string(),
+            logprob: Some(logprob("Yes")),
+            stop_reason: None,
+        });
+        match events.last() {
+            Some(sauropod_openai_api::ResponseStreamEvent::ResponseTextDeltaEvent {
+                logprobs,
+                ..
+            }) => {
+                assert_eq!(logprobs.len(), 1);
+                assert_eq!(logprobs[0].token, "Yes");
+                assert_eq!(logprobs[0].top_logprobs.as_ref().unwrap().len(), 1);
+            }
+            other => panic!("Unexpected event {other:?}"),
+        }
+
+        match creator.finish().last() {
+            Some(sauropod_openai_api::ResponseStreamEvent::ResponseCompletedEvent {
+                response,
+                ..
+            }) => match response.output.as_slice() {
+                [
+                    OutputItem::ReasoningItem { .. },
+                    OutputItem::OutputMessage { content, .. },
+                ] => match content.as_slice() {
+                    [
+                        sauropod_openai_api::OutputContent::OutputTextContent {
+                            logprobs: Some(logprobs),
+                            ..
+                        },
+                    ] => {
+                        assert_eq!(logprobs.len(), 1);
+                        assert_eq!(logprobs[0].token, "Yes");
+                    }
+                    other => panic!("Unexpected content {other:?}"),
+                },
+                other => panic!("Unexpected output {other:?}"),
+            },
+            _ => panic!("Expected ResponseCompletedEvent"),
+        }
+    }
+
+    #[test]
+    fn test_logprobs_of_held_back_text() {
+        let mut creator = ResponseStreamCreator::new(
+            sauropod_output_parser::get_model_parser(sauropod_output_parser::ModelType::Unknown),
+            create_test_response(),
+        );
+        creator.set_stop_sequences(vec!["END".to_string()]);
+        let logprob = |token: &str| sauropod_openai_api::LogProb {
+            bytes: token.bytes().map(i64::from).collect(),
+            logprob: -0.5,
+            token: token.to_string(),
+            top_logprobs: Vec::new(),
+        };
+
+        // The text may be the start of the stop sequence so it's held back
+        let events = creator.push_part(crate::Part {
+            text: "EN".to_string(),
+            logprob: Some(logprob("EN")),
+            stop_reason: None,
+        });
+        assert!(
+            !events.iter().any(|event| matches!(
+                event,
+                sauropod_openai_api::ResponseStreamEvent::ResponseTextDeltaEvent { .. }
+            )),
+            "{events:?}"
+        );
+
+        let events = creator.push_part(crate::Part {
+            text: "d".to_string(),
+            logprob: Some(logprob("d")),
+            stop_reason: None,
+        });
+        match events.last() {
+            Some(sauropod_openai_api::ResponseStreamEvent::ResponseTextDeltaEvent {
+                delta,
+                logprobs,
+                ..
+            }) => {
+                assert_eq!(delta, "ENd");
+                let tokens: Vec<&str> = logprobs
+                    .iter()
+                    .map(|logprob| logprob.token.as_str())
+                    .collect();
+                assert_eq!(tokens, ["EN", "d"]);
+            }
+            other => panic!("Unexpected event {other:?}"),
+        }
+    }
 }
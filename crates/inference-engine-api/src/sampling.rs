@@ -1,38 +1,307 @@
+use std::collections::HashMap;
+
+/// The maximum number of alternatives returned with the log probability of a token.
+const MAX_TOP_LOGPROBS: i64 = 20;
+
+/// The number of recent tokens considered by the penalties if it isn't configured.
+const DEFAULT_PENALTY_WINDOW: i64 = 64;
+
+/// The maximum number of output tokens if neither the request nor the model sets it.
+pub const DEFAULT_MAX_OUTPUT_TOKENS: i64 = 4096;
+
 /// Properties for the sampler used in the model.
-#[derive(Clone)]
+#[derive(Clone, Debug, Default)]
 pub struct SamplerProperties {
     /// The top K tokens to sample from.
     pub top_k: Option<i64>,
     /// The minimum probability for sampling.
-    pub min_p: Option<i64>,
+    pub min_p: Option<f64>,
     /// The temperature for sampling.
     pub temperature: f64,
     /// The top probability for nucleus sampling.
     pub top_p: Option<f64>,
+    /// The locally typical sampling parameter.
+    pub typical_p: Option<f64>,
     /// The maximum number of tokens to predict.
     pub max_predict: usize,
+    /// The seed of the random number generator, or `None` for a random seed.
+    pub seed: Option<u32>,
     /// Repetition penalty for sampling.
     pub repetition_penalty: Option<f64>,
+    /// The penalty for tokens proportional to how often they occurred.
+    pub frequency_penalty: Option<f64>,
+    /// The penalty for tokens which occurred.
+    pub presence_penalty: Option<f64>,
+    /// The number of recent tokens considered by the penalties, -1 for the whole context.
+    pub penalty_window: i64,
+    /// DRY sampling settings.
+    pub dry: Option<sauropod_config::DryConfig>,
+    /// Mirostat sampling settings.
+    pub mirostat: Option<sauropod_config::MirostatConfig>,
+    /// Biases added to the logits of tokens.
+    pub logit_bias: Vec<(crate::Token, f32)>,
+    /// Text which ends the output when the model produces it.
+    pub stop: Vec<String>,
+    /// A GBNF grammar that the output must match.
+    pub grammar: Option<String>,
+    /// The number of most likely alternatives returned with the log probability of each token.
+    ///
+    /// Log probabilities aren't computed if this is `None`.
+    pub top_logprobs: Option<usize>,
+    /// The LoRA adapters applied to the model during the generation.
+    pub lora_adapters: Vec<sauropod_config::LoraAdapterConfig>,
+}
+
+/// Sampling parameters of a request which take precedence over the model's configuration.
+#[derive(Clone, Debug, Default, serde::Deserialize)]
+pub struct SamplingOverrides {
+    pub top_k: Option<i64>,
+    pub min_p: Option<f64>,
+    pub typical_p: Option<f64>,
+    pub seed: Option<u32>,
+    pub repetition_penalty: Option<f64>,
+    pub frequency_penalty: Option<f64>,
+    pub presence_penalty: Option<f64>,
+    pub penalty_window: Option<i64>,
+    pub dry: Option<sauropod_config::DryConfig>,
+    pub mirostat: Option<sauropod_config::MirostatConfig>,
+    pub logit_bias: Option<HashMap<String, f64>>,
+    pub stop: Option<StopConfiguration>,
+    /// The names of the LoRA adapters to apply instead of the model's active adapters.
+    pub lora_adapters: Option<Vec<String>>,
+}
+
+/// Sequences where the model will stop generating further tokens, given as a single string or a
+/// list of strings.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+#[serde(untagged)]
+pub enum StopConfiguration {
+    Single(String),
+    Multiple(Vec<String>),
+}
+
+impl From<StopConfiguration> for Vec<String> {
+    fn from(value: StopConfiguration) -> Self {
+        match value {
+            StopConfiguration::Single(stop) => vec![stop],
+            StopConfiguration::Multiple(stop) => stop,
+        }
+    }
+}
+
+/// Parse logit biases keyed by token ID.
+fn parse_logit_bias(logit_bias: &HashMap<String, f64>) -> anyhow::Result<Vec<(crate::Token, f32)>> {
+    let mut parsed = logit_bias
+        .iter()
+        .map(|(token, bias)| {
+            let token = token
+                .parse::<crate::Token>()
+                .map_err(|_| anyhow::anyhow!("Invalid token ID in logit bias: {token:?}"))?;
+            Ok((token, *bias as f32))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    parsed.sort_by_key(|(token, _)| *token);
+    Ok(parsed)
 }
 
 impl SamplerProperties {
     pub fn new(
         response: &sauropod_openai_api::Response,
         model_config: &sauropod_config::ModelConfig,
-    ) -> Self {
-        Self {
-            top_k: model_config.top_k,
-            min_p: model_config.min_p,
+        model_type: &sauropod_output_parser::ModelType,
+        overrides: &SamplingOverrides,
+    ) -> anyhow::Result<Self> {
+        Ok(Self {
+            top_k: overrides.top_k.or(model_config.top_k),
+            min_p: overrides.min_p.or(model_config.min_p),
             temperature: response
                 .model_response_properties
                 .temperature
+                .or(model_config.temperature)
                 .unwrap_or(0.8),
-            top_p: response.model_response_properties.top_p,
+            top_p: response
+                .model_response_properties
+                .top_p
+                .or(model_config.top_p),
+            typical_p: overrides.typical_p.or(model_config.typical_p),
             max_predict: response
                 .response_properties
                 .max_output_tokens
-                .unwrap_or(4096) as usize,
-            repetition_penalty: None,
-        }
+                .or(model_config.maximum_tokens)
+                .unwrap_or(DEFAULT_MAX_OUTPUT_TOKENS) as usize,
+            seed: overrides.seed.or(model_config.seed),
+            repetition_penalty: overrides
+                .repetition_penalty
+                .or(model_config.repetition_penalty),
+            frequency_penalty: overrides
+                .frequency_penalty
+                .or(model_config.frequency_penalty),
+            presence_penalty: overrides.presence_penalty.or(model_config.presence_penalty),
+            penalty_window: overrides
+                .penalty_window
+                .or(model_config.penalty_window)
+                .unwrap_or(DEFAULT_PENALTY_WINDOW),
+            dry: overrides.dry.clone().or_else(|| model_config.dry.clone()),
+            mirostat: overrides
+                .mirostat
+                .clone()
+                .or_else(|| model_config.mirostat.clone()),
+            logit_bias: parse_logit_bias(
+                overrides
+                    .logit_bias
+                    .as_ref()
+                    .unwrap_or(&model_config.logit_bias),
+            )?,
+            stop: overrides
+                .stop
+                .clone()
+                .map(Vec::from)
+                .unwrap_or_else(|| model_config.stop.clone()),
+            top_logprobs: response
+                .model_response_properties
+                .top_logprobs
+                .map(|top_logprobs| top_logprobs.clamp(0, MAX_TOP_LOGPROBS) as usize),
+            grammar: crate::grammar::response_grammar(
+                &response.response_properties,
+                model_type,
+                response.parallel_tool_calls,
+            )?,
+            lora_adapters: overrides
+                .lora_adapters
+                .as_ref()
+                .unwrap_or(&model_config.active_lora_adapters)
+                .iter()
+                .map(|name| {
+                    model_config
+                        .lora_adapters
+                        .get(name)
+                        .cloned()
+                        .ok_or_else(|| anyhow::anyhow!("Unknown LoRA adapter '{name}'"))
+                })
+                .collect::<anyhow::Result<_>>()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn model_config() -> sauropod_config::ModelConfig {
+        serde_json::from_value(serde_json::json!({
+            "model": "model.gguf",
+            "seed": 1,
+            "min_p": 0.05,
+            "stop": ["</answer>"],
+            "logit_bias": {"7": 1.0},
+            "lora_adapters": {
+                "support": {"adapter": "support.gguf", "scale": 0.5},
+                "legal": {"adapter": "legal.gguf"}
+            },
+            "active_lora_adapters": ["support"]
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_overrides_take_precedence() {
+        let response = crate::make_response(&Default::default());
+        let overrides: SamplingOverrides = serde_json::from_value(serde_json::json!({
+            "model": "default",
+            "seed": 42,
+            "stop": "STOP",
+            "logit_bias": {"15043": -100, "2": 5}
+        }))
+        .unwrap();
+
+        let properties = SamplerProperties::new(
+            &response,
+            &model_config(),
+            &sauropod_output_parser::ModelType::Unknown,
+            &overrides,
+        )
+        .unwrap();
+        assert_eq!(properties.seed, Some(42));
+        assert_eq!(properties.min_p, Some(0.05));
+        assert_eq!(properties.stop, vec!["STOP".to_string()]);
+        assert_eq!(properties.logit_bias, vec![(2, 5.0), (15043, -100.0)]);
+        assert_eq!(properties.penalty_window, DEFAULT_PENALTY_WINDOW);
+
+        let properties = SamplerProperties::new(
+            &response,
+            &model_config(),
+            &sauropod_output_parser::ModelType::Unknown,
+            &Default::default(),
+        )
+        .unwrap();
+        assert_eq!(properties.seed, Some(1));
+        assert_eq!(properties.stop, vec!["</answer>".to_string()]);
+        assert_eq!(properties.logit_bias, vec![(7, 1.0)]);
+    }
+
+    #[test]
+    fn test_invalid_logit_bias() {
+        let overrides: SamplingOverrides =
+            serde_json::from_value(serde_json::json!({"logit_bias": {"hello": 1}})).unwrap();
+        assert!(
+            SamplerProperties::new(
+                &crate::make_response(&Default::default()),
+                &model_config(),
+                &sauropod_output_parser::ModelType::Unknown,
+                &overrides,
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_lora_adapter_selection() {
+        let response = crate::make_response(&Default::default());
+        let properties = SamplerProperties::new(
+            &response,
+            &model_config(),
+            &sauropod_output_parser::ModelType::Unknown,
+            &Default::default(),
+        )
+        .unwrap();
+        assert_eq!(properties.lora_adapters.len(), 1);
+        assert_eq!(properties.lora_adapters[0].scale, 0.5);
+
+        let overrides: SamplingOverrides =
+            serde_json::from_value(serde_json::json!({"lora_adapters": ["legal"]})).unwrap();
+        let properties = SamplerProperties::new(
+            &response,
+            &model_config(),
+            &sauropod_output_parser::ModelType::Unknown,
+            &overrides,
+        )
+        .unwrap();
+        assert_eq!(
+            properties.lora_adapters[0].adapter,
+            sauropod_config::ConfigModelSource::LocalPath("legal.gguf".to_string())
+        );
+
+        let overrides: SamplingOverrides =
+            serde_json::from_value(serde_json::json!({"lora_adapters": []})).unwrap();
+        let properties = SamplerProperties::new(
+            &response,
+            &model_config(),
+            &sauropod_output_parser::ModelType::Unknown,
+            &overrides,
+        )
+        .unwrap();
+        assert!(properties.lora_adapters.is_empty());
+
+        let overrides: SamplingOverrides =
+            serde_json::from_value(serde_json::json!({"lora_adapters": ["medical"]})).unwrap();
+        assert!(
+            SamplerProperties::new(
+                &response,
+                &model_config(),
+                &sauropod_output_parser::ModelType::Unknown,
+                &overrides,
+            )
+            .is_err()
+        );
     }
 }
@@ -0,0 +1,112 @@
+/// Finds stop sequences in streamed text.
+///
+/// Text which may be the start of a stop sequence is held back until it's known whether the stop sequence follows.
+#[derive(Default)]
+pub(crate) struct StopSequences {
+    sequences: Vec<String>,
+    /// Text which hasn't been emitted yet.
+    pending: String,
+    /// Whether a stop sequence has been found.
+    stopped: bool,
+}
+
+/// The length of the longest suffix of `text` which is a strict prefix of `sequence`.
+fn partial_match_length(text: &str, sequence: &str) -> usize {
+    (1..sequence.len().min(text.len() + 1))
+        .rev()
+        .find(|&length| sequence.is_char_boundary(length) && text.ends_with(&sequence[..length]))
+        .unwrap_or(0)
+}
+
+impl StopSequences {
+    pub(crate) fn new(sequences: Vec<String>) -> Self {
+        Self {
+            sequences: sequences
+                .into_iter()
+                .filter(|sequence| !sequence.is_empty())
+                .collect(),
+            pending: String::new(),
+            stopped: false,
+        }
+    }
+
+    /// Whether a stop sequence has been found.
+    pub(crate) fn is_stopped(&self) -> bool {
+        self.stopped
+    }
+
+    /// Add generated text and return the text which can be emitted.
+    pub(crate) fn push(&mut self, text: &str) -> String {
+        if self.stopped {
+            return String::new();
+        }
+        if self.sequences.is_empty() {
+            return text.to_string();
+        }
+
+        self.pending.push_str(text);
+        if let Some(position) = self
+            .sequences
+            .iter()
+            .filter_map(|sequence| self.pending.find(sequence.as_str()))
+            .min()
+        {
+            self.stopped = true;
+            let mut emitted = std::mem::take(&mut self.pending);
+            emitted.truncate(position);
+            return emitted;
+        }
+
+        let held_back = self
+            .sequences
+            .iter()
+            .map(|sequence| partial_match_length(&self.pending, sequence))
+            .max()
+            .unwrap_or(0);
+        let held_back_text = self.pending.split_off(self.pending.len() - held_back);
+        std::mem::replace(&mut self.pending, held_back_text)
+    }
+
+    /// Take the text which was held back when generation ends.
+    pub(crate) fn flush(&mut self) -> String {
+        std::mem::take(&mut self.pending)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_no_stop_sequences() {
+        let mut stop = StopSequences::new(vec![]);
+        assert_eq!(stop.push("hello"), "hello");
+        assert!(!stop.is_stopped());
+    }
+
+    #[test]
+    fn test_stop_sequence_split_across_parts() {
+        let mut stop = StopSequences::new(vec!["END".to_string()]);
+        assert_eq!(stop.push("one E"), "one ");
+        assert_eq!(stop.push("N"), "");
+        assert_eq!(stop.push("Dtwo"), "");
+        assert!(stop.is_stopped());
+        assert_eq!(stop.push("three"), "");
+    }
+
+    #[test]
+    fn test_partial_match_is_released() {
+        let mut stop = StopSequences::new(vec!["END".to_string()]);
+        assert_eq!(stop.push("EN"), "");
+        assert_eq!(stop.push("d"), "ENd");
+        assert_eq!(stop.push("E"), "");
+        assert_eq!(stop.flush(), "E");
+        assert!(!stop.is_stopped());
+    }
+
+    #[test]
+    fn test_earliest_stop_sequence_wins() {
+        let mut stop = StopSequences::new(vec!["b".to_string(), "a".to_string()]);
+        assert_eq!(stop.push("xab"), "x");
+    }
+}
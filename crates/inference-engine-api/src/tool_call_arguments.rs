@@ -0,0 +1,239 @@
+/// Where the reading of a tool call is.
+#[derive(Default, PartialEq)]
+enum Position {
+    /// Before the arguments.
+    #[default]
+    Envelope,
+    /// In the arguments.
+    Arguments,
+    /// After the arguments, in the rest of the envelope.
+    Done,
+}
+
+/// Reads the name and arguments of a tool call which gives them together as JSON, such as
+/// `{"name": "get_weather", "arguments": {"city": "Paris"}}`, while it's generated.
+///
+/// This lets the arguments be streamed without the JSON around them.
+#[derive(Default)]
+pub(crate) struct ToolCallArguments {
+    position: Position,
+    /// The depth of the objects and arrays of the envelope, or of the arguments once they start.
+    depth: usize,
+    in_string: bool,
+    escaped: bool,
+    /// The string of the envelope being read, including its quotes.
+    string: String,
+    /// The last key of the envelope.
+    key: Option<String>,
+    /// Whether the value of `key` is being read.
+    in_value: bool,
+    name: Option<String>,
+    /// The text of the arguments read so far.
+    arguments: String,
+}
+
+impl ToolCallArguments {
+    /// Add generated text of the tool call and return the text of the arguments it contains.
+    pub(crate) fn push(&mut self, text: &str) -> String {
+        let mut output = String::new();
+        for c in text.chars() {
+            match self.position {
+                Position::Envelope | Position::Done => self.push_envelope(c, &mut output),
+                Position::Arguments => self.push_arguments(c, &mut output),
+            }
+        }
+        self.arguments.push_str(&output);
+        output
+    }
+
+    /// The name of the function, once it has been read.
+    pub(crate) fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Whether the arguments have started.
+    pub(crate) fn has_arguments(&self) -> bool {
+        self.position != Position::Envelope
+    }
+
+    /// The text of the arguments read so far.
+    pub(crate) fn arguments(&self) -> &str {
+        &self.arguments
+    }
+
+    fn push_envelope(&mut self, c: char, output: &mut String) {
+        if self.in_string {
+            if self.depth == 1 {
+                self.string.push(c);
+            }
+            if self.escaped {
+                self.escaped = false;
+            } else if c == '\\' {
+                self.escaped = true;
+            } else if c == '"' {
+                self.in_string = false;
+                if self.depth == 1 {
+                    let string = serde_json::from_str(&std::mem::take(&mut self.string)).ok();
+                    if !self.in_value {
+                        self.key = string;
+                    } else if self.key.as_deref() == Some("name") {
+                        self.name = string;
+                    }
+                }
+            }
+            return;
+        }
+
+        // Llama 3 calls functions with `parameters`
+        if self.position == Position::Envelope
+            && self.depth == 1
+            && self.in_value
+            && matches!(self.key.as_deref(), Some("arguments" | "parameters"))
+            && !c.is_whitespace()
+        {
+            self.position = Position::Arguments;
+            self.depth = 0;
+            self.push_arguments(c, output);
+            return;
+        }
+
+        match c {
+            '"' => {
+                self.in_string = true;
+                if self.depth == 1 {
+                    self.string.push(c);
+                }
+            }
+            '{' | '[' => self.depth += 1,
+            '}' | ']' => self.depth = self.depth.saturating_sub(1),
+            ':' if self.depth == 1 => self.in_value = true,
+            ',' if self.depth == 1 => {
+                self.in_value = false;
+                self.key = None;
+            }
+            _ => {}
+        }
+    }
+
+    fn push_arguments(&mut self, c: char, output: &mut String) {
+        if self.in_string {
+            output.push(c);
+            if self.escaped {
+                self.escaped = false;
+            } else if c == '\\' {
+                self.escaped = true;
+            } else if c == '"' {
+                self.in_string = false;
+                if self.depth == 0 {
+                    self.end_arguments();
+                }
+            }
+            return;
+        }
+
+        match c {
+            '"' => {
+                self.in_string = true;
+                output.push(c);
+            }
+            '{' | '[' => {
+                self.depth += 1;
+                output.push(c);
+            }
+            '}' | ']' if self.depth > 0 => {
+                self.depth -= 1;
+                output.push(c);
+                if self.depth == 0 {
+                    self.end_arguments();
+                }
+            }
+            // A number, boolean or null ends at the delimiter following it
+            c if self.depth == 0 && (c.is_whitespace() || matches!(c, ',' | '}' | ']')) => {
+                self.end_arguments();
+                self.push_envelope(c, output);
+            }
+            c => output.push(c),
+        }
+    }
+
+    /// Return to the envelope, whose other values may follow the arguments.
+    fn end_arguments(&mut self) {
+        self.position = Position::Done;
+        self.depth = 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Push `text` one character at a time and return the streamed arguments.
+    fn stream(tool_call: &mut ToolCallArguments, text: &str) -> String {
+        text.chars()
+            .map(|c| tool_call.push(&c.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_arguments_are_streamed_without_the_envelope() {
+        let mut tool_call = ToolCallArguments::default();
+        assert_eq!(tool_call.push(r#"{"name": "get_"#), "");
+        assert_eq!(tool_call.name(), None);
+        assert_eq!(tool_call.push(r#"weather", "arguments": "#), "");
+        assert_eq!(tool_call.name(), Some("get_weather"));
+        assert!(!tool_call.has_arguments());
+        assert_eq!(tool_call.push(r#"{"city": "Pa"#), r#"{"city": "Pa"#);
+        assert!(tool_call.has_arguments());
+        assert_eq!(tool_call.push(r#"ris"}}"#), r#"ris"}"#);
+        assert_eq!(tool_call.arguments(), r#"{"city": "Paris"}"#);
+    }
+
+    #[test]
+    fn test_nested_and_escaped_arguments() {
+        let mut tool_call = ToolCallArguments::default();
+        let arguments = r#"{"query": "a \"}\" b", "filters": [{"x": [1, 2]}], "n": {}}"#;
+        let streamed = stream(
+            &mut tool_call,
+            &format!(r#"{{"arguments": {arguments}, "name": "search\"er"}}"#),
+        );
+        assert_eq!(streamed, arguments);
+        assert_eq!(tool_call.name(), Some("search\"er"));
+    }
+
+    #[test]
+    fn test_parameters_and_scalar_arguments() {
+        let mut tool_call = ToolCallArguments::default();
+        assert_eq!(
+            stream(&mut tool_call, r#"{"name": "f", "parameters": {"a": 1}}"#),
+            r#"{"a": 1}"#
+        );
+
+        let mut tool_call = ToolCallArguments::default();
+        assert_eq!(
+            stream(
+                &mut tool_call,
+                r#"{"name": "f", "arguments": "{\"a\": 1}"}"#
+            ),
+            r#""{\"a\": 1}""#
+        );
+
+        let mut tool_call = ToolCallArguments::default();
+        assert_eq!(
+            stream(&mut tool_call, r#"{"name": "f", "arguments": null}"#),
+            "null"
+        );
+    }
+
+    #[test]
+    fn test_other_values_are_skipped() {
+        let mut tool_call = ToolCallArguments::default();
+        assert_eq!(
+            stream(
+                &mut tool_call,
+                r#"{"id": {"arguments": 1}, "note": "\"arguments\": 2", "arguments": 3}"#
+            ),
+            "3"
+        );
+        assert_eq!(tool_call.name(), None);
+    }
+}
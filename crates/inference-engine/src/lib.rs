@@ -5,9 +5,31 @@ use sauropod_inference_engine_api::LlmModel;
 
 mod model_file_selector;
 
+pub use sauropod_llama_cpp::ModelSettings;
+
 /// A pointer to a model.
 pub type ModelPointer = Arc<dyn LlmModel + Send + 'static>;
 
+/// A pointer to an embedding model.
+pub type EmbeddingModelPointer =
+    Arc<dyn sauropod_inference_engine_api::EmbeddingModel + Send + 'static>;
+
+/// A pointer to a reranking model.
+pub type RerankModelPointer = Arc<dyn sauropod_inference_engine_api::RerankModel + Send + 'static>;
+
+/// Settings for a generation which aren't part of a Responses API request.
+#[derive(Clone, Debug, Default)]
+pub struct GenerationOptions {
+    /// Sampling parameters which take precedence over the model's configuration.
+    pub sampling: sauropod_inference_engine_api::SamplingOverrides,
+    /// The ID to give the response instead of a random one.
+    pub response_id: Option<String>,
+    /// Stops the generation when cancelled.
+    ///
+    /// The token is also cancelled when the response stream is dropped.
+    pub cancellation_token: sauropod_inference_engine_api::CancellationToken,
+}
+
 pub struct Model {
     /// The pointer to the loaded model.
     pub underlying_model: ModelPointer,
@@ -19,6 +41,49 @@ pub struct Model {
     pub supports_audio_input: bool,
     /// Whether the model supports image input.
     pub supports_image_input: bool,
+    /// The output format of the model.
+    model_type: sauropod_output_parser::ModelType,
+}
+
+/// Get the output format configured for a model.
+fn configured_model_type(
+    output_parser: &sauropod_config::OutputParserConfig,
+) -> anyhow::Result<sauropod_output_parser::ModelType> {
+    use sauropod_config::OutputParserConfig;
+    use sauropod_output_parser::ModelType;
+
+    let delimiters = |delimiters: &Option<sauropod_config::DelimitersConfig>| {
+        delimiters
+            .as_ref()
+            .map(|delimiters| {
+                if delimiters.start.is_empty() || delimiters.end.is_empty() {
+                    anyhow::bail!("Output parser delimiters can't be empty");
+                }
+                Ok(sauropod_output_parser::Delimiters {
+                    start: delimiters.start.clone(),
+                    end: delimiters.end.clone(),
+                })
+            })
+            .transpose()
+    };
+
+    Ok(match output_parser {
+        OutputParserConfig::Qwen3 => ModelType::Qwen3,
+        OutputParserConfig::Markdown => ModelType::MarkdownToolCall,
+        OutputParserConfig::Harmony => ModelType::Harmony,
+        OutputParserConfig::Llama3 => ModelType::Llama3,
+        OutputParserConfig::Mistral => ModelType::Mistral,
+        OutputParserConfig::Hermes => ModelType::Hermes,
+        OutputParserConfig::DeepseekR1 => ModelType::DeepSeekR1,
+        OutputParserConfig::None => ModelType::Unknown,
+        OutputParserConfig::Custom {
+            reasoning,
+            tool_call,
+        } => ModelType::Custom(Arc::new(sauropod_output_parser::CustomFormat {
+            reasoning: delimiters(reasoning)?,
+            tool_call: delimiters(tool_call)?,
+        })),
+    })
 }
 
 impl Model {
@@ -33,6 +98,11 @@ impl Model {
                 .unwrap_or_else(|| underlying_model.get_model_chat_template().to_string()),
         )?;
 
+        let model_type = match &model_config.output_parser {
+            Some(output_parser) => configured_model_type(output_parser)?,
+            None => underlying_model.get_model_type(),
+        };
+
         let supports_audio_input = underlying_model.supports_audio();
         let supports_image_input = underlying_model.supports_vision();
         Ok(Self {
@@ -41,23 +111,93 @@ impl Model {
             model_config,
             supports_audio_input,
             supports_image_input,
+            model_type,
         })
     }
 
+    /// Get the output format of the model.
+    pub fn get_model_type(&self) -> &sauropod_output_parser::ModelType {
+        &self.model_type
+    }
+
     /// Get the system prompt for the model.
     pub fn get_system_prompt(&self) -> Option<&str> {
         self.model_config.system_prompt.as_deref()
     }
 
+    /// Fit the prompt rendered from `render_context` and `output_tokens` into the model's context.
+    ///
+    /// With `truncate`, the fewest oldest messages are dropped from `render_context` until they
+    /// fit. Returns whether the prompt fits.
+    pub fn fit_to_context(
+        &self,
+        render_context: &mut sauropod_prompt_templates::RenderContext,
+        output_tokens: usize,
+        truncate: bool,
+    ) -> anyhow::Result<bool> {
+        let context_size = self.underlying_model.context_size();
+        let fits = |render_context: &sauropod_prompt_templates::RenderContext| {
+            let prompt = self
+                .chat_template
+                .render(render_context)
+                .context("Rendering the chat template")?;
+            let prompt_tokens = self.underlying_model.count_tokens(&prompt)?;
+            anyhow::Ok(prompt_tokens + output_tokens <= context_size)
+        };
+
+        if fits(render_context)? {
+            return Ok(true);
+        } else if !truncate {
+            return Ok(false);
+        }
+
+        let points = render_context.truncation_points();
+        match points.last() {
+            Some(&most) if fits(&render_context.without_oldest_messages(most))? => {}
+            _ => return Ok(false),
+        }
+
+        // Dropping more messages is assumed to never lengthen the prompt.
+        let (mut low, mut high) = (0, points.len() - 1);
+        while low < high {
+            let middle = (low + high) / 2;
+            if fits(&render_context.without_oldest_messages(points[middle]))? {
+                high = middle;
+            } else {
+                low = middle + 1;
+            }
+        }
+        tracing::debug!(
+            "Dropping the {} oldest messages to fit the context",
+            points[high]
+        );
+        *render_context = render_context.without_oldest_messages(points[high]);
+        Ok(true)
+    }
+
     /// Generate responses using the underlying model.
     pub async fn generate_stream(
         self: Arc<Self>,
         input: sauropod_openai_api::CreateResponse,
         render_context: sauropod_prompt_templates::RenderContext,
+    ) -> anyhow::Result<sauropod_inference_engine_api::ResponseStream> {
+        self.generate_stream_with_options(input, render_context, GenerationOptions::default())
+            .await
+    }
+
+    /// Generate responses using the underlying model with additional options.
+    pub async fn generate_stream_with_options(
+        self: Arc<Self>,
+        input: sauropod_openai_api::CreateResponse,
+        render_context: sauropod_prompt_templates::RenderContext,
+        options: GenerationOptions,
     ) -> anyhow::Result<sauropod_inference_engine_api::ResponseStream> {
         tracing::debug!("Generating response for input: {input:#?}");
         let model = self.underlying_model.clone();
-        let response = sauropod_inference_engine_api::make_response(&input);
+        let mut response = sauropod_inference_engine_api::make_response(&input);
+        if let Some(response_id) = options.response_id {
+            response.id = response_id;
+        }
 
         let rendered_prompt = self
             .chat_template
@@ -65,37 +205,60 @@ impl Model {
             .with_context(|| {
                 format!("Failed to render chat template for context: {render_context:#?}")
             })?;
-        let sampler_properties =
-            sauropod_inference_engine_api::SamplerProperties::new(&response, &self.model_config);
+        let mut sampler_properties = sauropod_inference_engine_api::SamplerProperties::new(
+            &response,
+            &self.model_config,
+            &self.model_type,
+            &options.sampling,
+        )
+        .context("Creating the sampler properties")?;
+        if input.include.iter().flatten().any(|include| {
+            matches!(
+                include,
+                sauropod_openai_api::Includable::MessageOutputTextLogprobs
+            )
+        }) {
+            sampler_properties.top_logprobs.get_or_insert(0);
+        }
         let mut response_stream_creator = sauropod_inference_engine_api::ResponseStreamCreator::new(
-            sauropod_output_parser::get_model_parser(model.get_model_type()),
+            sauropod_output_parser::get_model_parser_with_tools(
+                self.model_type.clone(),
+                sauropod_inference_engine_api::offers_unconstrained_tool_calls(
+                    &response.response_properties,
+                ),
+            ),
             response,
         );
+        response_stream_creator.set_stop_sequences(sampler_properties.stop.clone());
+        let cancellation_token = options.cancellation_token;
         let sauropod_inference_engine_api::GenerateFromTextResponse {
             stream,
             input_token_count,
+            cached_token_count,
         } = model
             .generate_from_text(
                 sampler_properties,
                 rendered_prompt,
                 render_context.multimodal_data,
+                cancellation_token.clone(),
             )
             .await
             .context("Generating token stream")?;
 
-        response_stream_creator
-            .response
-            .usage
-            .as_mut()
-            .unwrap()
-            .input_tokens = input_token_count;
+        response_stream_creator.set_input_tokens(input_token_count, cached_token_count);
         let stream = async_stream::stream! {
+            // Dropping the response stream, e.g. when the client disconnects, stops the generation
+            let _cancel_on_drop = cancellation_token.clone().drop_guard();
             for await part in stream {
                 match part {
                     Ok(part) => {
                         for event in response_stream_creator.push_part(part) {
                             yield Ok(event);
                         }
+                        // Dropping the part stream ends the generation
+                        if response_stream_creator.is_stopped() {
+                            break;
+                        }
                     }
                     Err(e) => {
                         yield Err(e);
@@ -104,6 +267,11 @@ impl Model {
                 };
             }
 
+            if cancellation_token.is_cancelled() {
+                response_stream_creator
+                    .set_stop_reason(sauropod_inference_engine_api::StopReason::Cancelled);
+            }
+
             // None means the sender has closed the channel
             if response_stream_creator.is_empty() {
                 for event in response_stream_creator.push_text("".to_string()) {
@@ -124,16 +292,33 @@ impl Model {
         self: Arc<Self>,
         input: sauropod_openai_api::CreateResponse,
         render_context: sauropod_prompt_templates::RenderContext,
+    ) -> anyhow::Result<sauropod_openai_api::Response> {
+        self.generate_with_options(input, render_context, GenerationOptions::default())
+            .await
+    }
+
+    /// Generate responses using the underlying model with additional options.
+    pub async fn generate_with_options(
+        self: Arc<Self>,
+        input: sauropod_openai_api::CreateResponse,
+        render_context: sauropod_prompt_templates::RenderContext,
+        options: GenerationOptions,
     ) -> anyhow::Result<sauropod_openai_api::Response> {
         use tokio_stream::StreamExt as _;
 
-        let stream = self.generate_stream(input, render_context).await?;
+        let stream = self
+            .generate_stream_with_options(input, render_context, options)
+            .await?;
 
         let completed = stream
             .filter_map(|x| match x {
                 Ok(sauropod_openai_api::ResponseStreamEvent::ResponseCompletedEvent {
                     response,
                     ..
+                })
+                | Ok(sauropod_openai_api::ResponseStreamEvent::ResponseIncompleteEvent {
+                    response,
+                    ..
                 }) => Some(Ok(response)),
                 Err(e) => Some(Err(e)),
                 _ => None,
@@ -250,16 +435,25 @@ pub async fn get_model_path(
     }
 }
 
-/// Load a model.
+/// Load a model, with a draft model for speculative decoding if `draft_model_path` is set.
 pub async fn load_model(
     name: String,
     model_path: &sauropod_inference_engine_api::ModelPath,
     projector_model_path: Option<&sauropod_config::ConfigModelSource>,
+    draft_model_path: Option<&sauropod_inference_engine_api::ModelPath>,
+    settings: &ModelSettings,
 ) -> anyhow::Result<ModelPointer> {
     let projector_model_path = match projector_model_path {
         Some(source) => Some(sauropod_huggingface::download_file(source).await?),
         None => None,
     };
+    let draft_model_path = match draft_model_path {
+        Some(sauropod_inference_engine_api::ModelPath::GGUF(path)) => Some(path.as_path()),
+        Some(sauropod_inference_engine_api::ModelPath::TensorRT(_)) => {
+            anyhow::bail!("Draft models must be GGUF files");
+        }
+        None => None,
+    };
     match model_path {
         sauropod_inference_engine_api::ModelPath::TensorRT(_) => {
             anyhow::bail!("TensorRT-LLM support is not enabled in this build");
@@ -269,8 +463,181 @@ pub async fn load_model(
                 name,
                 path,
                 projector_model_path.as_deref(),
+                draft_model_path,
+                settings,
             )
             .await?,
         ) as ModelPointer),
     }
 }
+
+/// Download the LoRA adapters of a model configuration and load them for `model`.
+pub async fn load_lora_adapters(
+    model: &ModelPointer,
+    model_config: &sauropod_config::ModelConfig,
+) -> anyhow::Result<()> {
+    if let Some(name) = model_config
+        .active_lora_adapters
+        .iter()
+        .find(|name| !model_config.lora_adapters.contains_key(*name))
+    {
+        anyhow::bail!("The active LoRA adapter '{name}' isn't configured");
+    }
+
+    for (name, lora_adapter) in &model_config.lora_adapters {
+        let path = sauropod_huggingface::download_file(&lora_adapter.adapter)
+            .await
+            .with_context(|| format!("Failed to download LoRA adapter '{name}'"))?;
+        model
+            .load_lora_adapter(&lora_adapter.adapter, &path)
+            .with_context(|| format!("Failed to load LoRA adapter '{name}'"))?;
+    }
+    Ok(())
+}
+
+/// Load an embedding model.
+pub async fn load_embedding_model(
+    name: String,
+    model_path: &sauropod_inference_engine_api::ModelPath,
+) -> anyhow::Result<EmbeddingModelPointer> {
+    match model_path {
+        sauropod_inference_engine_api::ModelPath::TensorRT(_) => {
+            anyhow::bail!("TensorRT-LLM embedding models are not supported");
+        }
+        sauropod_inference_engine_api::ModelPath::GGUF(path) => Ok(Arc::new(
+            sauropod_llama_cpp::EmbeddingInferenceThread::from_file(name, path).await?,
+        )
+            as EmbeddingModelPointer),
+    }
+}
+
+/// Load a reranking model.
+pub async fn load_rerank_model(
+    name: String,
+    model_path: &sauropod_inference_engine_api::ModelPath,
+) -> anyhow::Result<RerankModelPointer> {
+    match model_path {
+        sauropod_inference_engine_api::ModelPath::TensorRT(_) => {
+            anyhow::bail!("TensorRT-LLM reranking models are not supported");
+        }
+        sauropod_inference_engine_api::ModelPath::GGUF(path) => Ok(Arc::new(
+            sauropod_llama_cpp::EmbeddingInferenceThread::reranker_from_file(name, path).await?,
+        ) as RerankModelPointer),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A model whose tokens are the words of the prompt.
+    struct WordModel {
+        context_size: usize,
+    }
+
+    #[async_trait::async_trait]
+    impl LlmModel for WordModel {
+        async fn generate_from_tokens(
+            self: Arc<Self>,
+            _sampler_properties: sauropod_inference_engine_api::SamplerProperties,
+            _tokens: sauropod_inference_engine_api::TokenSequence,
+            _cancellation_token: sauropod_inference_engine_api::CancellationToken,
+        ) -> anyhow::Result<sauropod_inference_engine_api::TokenStream> {
+            Err(anyhow::anyhow!("The word model can't generate"))
+        }
+
+        async fn generate_from_text(
+            self: Arc<Self>,
+            _sampler_properties: sauropod_inference_engine_api::SamplerProperties,
+            _text: String,
+            _multimodal_data: Vec<sauropod_prompt_templates::MultimodalData>,
+            _cancellation_token: sauropod_inference_engine_api::CancellationToken,
+        ) -> anyhow::Result<sauropod_inference_engine_api::GenerateFromTextResponse> {
+            Err(anyhow::anyhow!("The word model can't generate"))
+        }
+
+        fn count_tokens(&self, text: &str) -> anyhow::Result<usize> {
+            Ok(text.split_whitespace().count())
+        }
+
+        fn context_size(&self) -> usize {
+            self.context_size
+        }
+
+        fn get_model_chat_template(&self) -> &str {
+            "{% for message in messages %}{{ message.content }} {% endfor %}"
+        }
+
+        fn get_model_type(&self) -> sauropod_output_parser::ModelType {
+            sauropod_output_parser::ModelType::Unknown
+        }
+
+        fn supports_audio(&self) -> bool {
+            false
+        }
+
+        fn supports_vision(&self) -> bool {
+            false
+        }
+    }
+
+    fn render_context(
+        messages: &[(sauropod_prompt_templates::RenderContextRole, &str)],
+    ) -> sauropod_prompt_templates::RenderContext {
+        sauropod_prompt_templates::RenderContext {
+            messages: messages
+                .iter()
+                .map(
+                    |(role, content)| sauropod_prompt_templates::RenderContextMessage {
+                        role: *role,
+                        content: content.to_string(),
+                        tools: None,
+                        tool_calls: None,
+                    },
+                )
+                .collect(),
+            add_generation_prompt: true,
+            tools: None,
+            multimodal_data: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_fit_to_context() {
+        use sauropod_prompt_templates::RenderContextRole::{Assistant, System, User};
+
+        let model = Model::new(
+            Arc::new(WordModel { context_size: 10 }),
+            serde_json::from_value(serde_json::json!({"model": "model.gguf"})).unwrap(),
+        )
+        .unwrap();
+        let conversation = render_context(&[
+            (System, "Be brief"),
+            (User, "one two"),
+            (Assistant, "three four"),
+            (User, "five six"),
+            (Assistant, "seven eight"),
+            (User, "nine"),
+        ]);
+
+        // The whole conversation has 11 words
+        let mut untruncated = conversation.clone();
+        assert!(!model.fit_to_context(&mut untruncated, 1, false).unwrap());
+        assert_eq!(untruncated.messages.len(), 6);
+
+        let mut truncated = conversation.clone();
+        assert!(model.fit_to_context(&mut truncated, 3, true).unwrap());
+        let contents: Vec<&str> = truncated
+            .messages
+            .iter()
+            .map(|message| message.content.as_str())
+            .collect();
+        assert_eq!(
+            contents,
+            vec!["Be brief", "five six", "seven eight", "nine"]
+        );
+
+        let mut too_long = conversation;
+        assert!(!model.fit_to_context(&mut too_long, 8, true).unwrap());
+    }
+}
@@ -26,6 +26,8 @@ pub async fn auth_middleware(
     mut request: Request,
     next: Next,
 ) -> Response {
+    // The configuration may be reloaded while the request is authenticated
+    let config = state.config();
     let user_id = if let Some(auth_header) =
         request.headers().get(axum::http::header::AUTHORIZATION)
     {
@@ -42,7 +44,7 @@ pub async fn auth_middleware(
                     .into_response();
                 };
 
-                match &state.config.authentication {
+                match &config.authentication {
                     sauropod_config::AuthenticationConfig::Database => {
                         // Check the database for the user ID associated with the token
                         if let Some(user_id) =
@@ -83,7 +85,7 @@ pub async fn auth_middleware(
 
     if user_id.is_none()
         && !matches!(
-            &state.config.authentication,
+            &config.authentication,
             sauropod_config::AuthenticationConfig::None,
         )
     {
@@ -101,6 +103,49 @@ pub async fn auth_middleware(
     next.run(request).await
 }
 
+/// Middleware to only allow requests authenticated with the admin API key.
+pub async fn admin_auth_middleware(
+    axum::extract::State(state): sauropod_global_state::AxumGlobalState,
+    request: Request,
+    next: Next,
+) -> Response {
+    let config = state.config();
+    if let Err(response) = check_admin_api_key(config.admin_api_key.as_deref(), request.headers()) {
+        return response.into_response();
+    }
+
+    next.run(request).await
+}
+
+/// Check that the headers of a request carry the admin API key.
+fn check_admin_api_key(
+    admin_api_key: Option<&str>,
+    headers: &axum::http::HeaderMap,
+) -> Result<(), HttpResponse<()>> {
+    let Some(admin_api_key) = admin_api_key else {
+        return Err(HttpResponse::Unauthorized(
+            "The admin API is disabled - set admin_api_key to enable it".to_string(),
+        ));
+    };
+
+    let token = headers
+        .get(axum::http::header::AUTHORIZATION)
+        .and_then(|auth_header| auth_header.to_str().ok())
+        .and_then(|auth_str| auth_str.strip_prefix("Bearer "))
+        .map(|token| token.trim());
+    // The comparison takes the same time wherever the token differs from the key
+    let is_valid = token.is_some_and(|token| {
+        subtle::ConstantTimeEq::ct_eq(token.as_bytes(), admin_api_key.as_bytes()).into()
+    });
+    if !is_valid {
+        tracing::info!("Invalid admin API key");
+        return Err(HttpResponse::Unauthorized(
+            "Invalid admin API key".to_string(),
+        ));
+    }
+    Ok(())
+}
+
 /// An error message.
 #[derive(serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
 pub struct Error {
@@ -171,3 +216,66 @@ impl<T> From<anyhow::Result<T>> for HttpResponse<T> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(authorization: Option<&str>) -> axum::http::HeaderMap {
+        let mut headers = axum::http::HeaderMap::new();
+        if let Some(authorization) = authorization {
+            headers.insert(
+                axum::http::header::AUTHORIZATION,
+                authorization.parse().unwrap(),
+            );
+        }
+        headers
+    }
+
+    fn status(result: Result<(), HttpResponse<()>>) -> Option<axum::http::StatusCode> {
+        result
+            .err()
+            .map(|response| response.into_response().status())
+    }
+
+    #[test]
+    fn test_admin_api_key() {
+        assert_eq!(
+            status(check_admin_api_key(
+                Some("secret"),
+                &headers(Some("Bearer secret"))
+            )),
+            None
+        );
+        assert_eq!(
+            status(check_admin_api_key(Some("secret"), &headers(None))),
+            Some(axum::http::StatusCode::UNAUTHORIZED)
+        );
+        assert_eq!(
+            status(check_admin_api_key(
+                Some("secret"),
+                &headers(Some("Bearer secreT"))
+            )),
+            Some(axum::http::StatusCode::UNAUTHORIZED)
+        );
+        assert_eq!(
+            status(check_admin_api_key(
+                Some("secret"),
+                &headers(Some("secret"))
+            )),
+            Some(axum::http::StatusCode::UNAUTHORIZED)
+        );
+    }
+
+    #[test]
+    fn test_admin_api_disabled_without_key() {
+        assert_eq!(
+            status(check_admin_api_key(None, &headers(Some("Bearer secret")))),
+            Some(axum::http::StatusCode::UNAUTHORIZED)
+        );
+        assert_eq!(
+            status(check_admin_api_key(None, &headers(None))),
+            Some(axum::http::StatusCode::UNAUTHORIZED)
+        );
+    }
+}
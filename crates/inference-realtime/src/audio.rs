@@ -161,7 +161,7 @@ impl AudioBuffer {
             accumulated_offset: 0,
             vad_offset,
             vad_classifications: std::collections::VecDeque::new(),
-            vad_model: loaded_models.get_loaded_models().vad_model.clone(),
+            vad_model: loaded_models.get_loaded_models().vad_model(),
             resampled_buffer: resampling,
         }
     }
@@ -3,7 +3,7 @@ pub async fn call_speech_to_text_model(
     audio_data: Vec<f32>,
     global_state: &sauropod_global_state::GlobalState,
 ) -> anyhow::Result<String> {
-    if let Some(stt_model) = global_state.get_loaded_models().stt_model.clone() {
+    if let Some(stt_model) = global_state.get_loaded_models().stt_model() {
         match stt_model.enqueue(audio_data.clone()).await {
             Ok(text) => Ok(text),
             Err(e) => {
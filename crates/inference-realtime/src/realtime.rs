@@ -24,6 +24,8 @@ pub(crate) struct RealtimeSessionState {
     conversation: tokio::sync::Mutex<sauropod_conversation::Conversation>,
     /// The session configuration.
     pub(crate) session: tokio::sync::Mutex<RealtimeSession>,
+    /// Stops the response which is being generated.
+    current_response: std::sync::Mutex<Option<sauropod_inference_engine_api::CancellationToken>>,
 }
 
 impl crate::RealtimeFunctionality for RealtimeSessionState {
@@ -55,6 +57,7 @@ impl crate::RealtimeFunctionality for RealtimeSessionState {
             audio_buffer: tokio::sync::Mutex::new(AudioBuffer::new(&global_state)),
             global_state,
             conversation: tokio::sync::Mutex::new(sauropod_conversation::Conversation::new()),
+            current_response: std::sync::Mutex::new(None),
         })
     }
 
@@ -239,9 +242,21 @@ impl crate::RealtimeFunctionality for RealtimeSessionState {
                     })
                     .await?;
 
-                // Create response with the new conversation state
-                self.create_response(&mut conversation, socket.clone())
-                    .await?;
+                std::mem::drop(conversation);
+
+                // Create the response in its own task so that the event loop can still receive
+                // response.cancel while it's generated
+                let cloned_self = Arc::clone(self);
+                let cloned_socket = socket.clone();
+                std::mem::drop(tokio::spawn(async move {
+                    let mut conversation_state = cloned_self.conversation.lock().await;
+                    if let Err(e) = cloned_self
+                        .create_response(&mut conversation_state, cloned_socket)
+                        .await
+                    {
+                        tracing::error!("Failed to create response: {e:#?}");
+                    }
+                }));
             }
             RealtimeClientEvent::ConversationItemRetrieve { .. } => {
                 // Send this event when you want to retrieve the server's representation of a specific item in the conversation history. This is useful, for example, to inspect user audio after noise cancellation and VAD.
@@ -269,7 +284,24 @@ impl crate::RealtimeFunctionality for RealtimeSessionState {
             }
             RealtimeClientEvent::ResponseCancel { .. } => {
                 // Send this event to cancel an in-progress response. The server will respond with a response.cancelled event or an error if there is no response to cancel.
-                tracing::warn!("ResponseCancel is not implemented yet");
+                let current_response = self.current_response.lock().unwrap().take();
+                if let Some(cancellation_token) = current_response {
+                    // The response finishes with the cancelled status in its response.done event
+                    cancellation_token.cancel();
+                } else {
+                    socket
+                        .send_event(RealtimeServerEvent::Error {
+                            event_id: make_id(),
+                            error: RealtimeServerEventErrorError {
+                                message: "There is no response in progress to cancel".to_string(),
+                                code: Some("response_cancel_not_active".to_string()),
+                                r#type: "invalid_request_error".to_string(),
+                                event_id: None,
+                                param: None,
+                            },
+                        })
+                        .await?;
+                }
             }
             _ => {
                 tracing::warn!("Received unsupported client event: {:?}", client_event);
@@ -423,8 +455,17 @@ impl RealtimeSessionState {
         let render_context = sauropod_prompt_templates::RenderContext::from_create_response(
             &request,
             model.get_system_prompt(),
+            model.get_model_type(),
         )?;
-        let mut stream = model.generate_stream(request, render_context).await?;
+        let cancellation_token = sauropod_inference_engine_api::CancellationToken::new();
+        *self.current_response.lock().unwrap() = Some(cancellation_token.clone());
+        let options = sauropod_inference_engine::GenerationOptions {
+            cancellation_token,
+            ..Default::default()
+        };
+        let mut stream = model
+            .generate_stream_with_options(request, render_context, options)
+            .await?;
         let mut is_generated_response = false;
         let mut response_id = None;
         let mut last_output_index = 0;
@@ -452,6 +493,10 @@ impl RealtimeSessionState {
                 Some(Ok(sauropod_openai_api::ResponseStreamEvent::ResponseCompletedEvent {
                     response,
                     ..
+                }))
+                | Some(Ok(sauropod_openai_api::ResponseStreamEvent::ResponseIncompleteEvent {
+                    response,
+                    ..
                 })) => {
                     is_generated_response = true;
                     conversation_state
@@ -700,6 +745,8 @@ impl RealtimeSessionState {
             }
         }
 
+        self.current_response.lock().unwrap().take();
+
         if !is_generated_response {
             // TODO remove the last conversation item
             tracing::error!("No response was generated from the model");
@@ -0,0 +1,198 @@
+//! Rerank API, compatible with the Jina and Cohere rerank endpoints.
+
+use axum::response::IntoResponse as _;
+
+mod routes;
+pub use routes::*;
+mod types;
+pub use types::*;
+
+/// The maximum number of documents in one request.
+const MAX_DOCUMENTS: usize = 2048;
+
+/// Check the query and documents of a request.
+fn validate_request(request: &CreateRerankRequest) -> anyhow::Result<()> {
+    anyhow::ensure!(!request.query.is_empty(), "The query must not be empty");
+    anyhow::ensure!(
+        !request.documents.is_empty(),
+        "The documents must not be empty"
+    );
+    anyhow::ensure!(
+        request.documents.len() <= MAX_DOCUMENTS,
+        "The documents must not contain more than {MAX_DOCUMENTS} items"
+    );
+    anyhow::ensure!(
+        request
+            .documents
+            .iter()
+            .all(|document| !document.is_empty()),
+        "The documents must not contain empty items"
+    );
+    Ok(())
+}
+
+/// Order documents from the most to the least relevant, keeping at most `top_n` of them.
+fn rank_documents(
+    scores: Vec<f32>,
+    documents: Vec<String>,
+    top_n: Option<usize>,
+    return_documents: bool,
+) -> Vec<RerankResult> {
+    let mut results: Vec<RerankResult> = scores
+        .into_iter()
+        .zip(documents)
+        .enumerate()
+        .map(|(index, (relevance_score, text))| RerankResult {
+            index,
+            relevance_score,
+            document: return_documents.then_some(RerankDocument { text }),
+        })
+        .collect();
+    // The sort is stable so documents with equal scores keep their order
+    results.sort_by(|a, b| b.relevance_score.total_cmp(&a.relevance_score));
+    if let Some(top_n) = top_n {
+        results.truncate(top_n);
+    }
+    results
+}
+
+pub async fn create_rerank_impl(
+    global_state: std::sync::Arc<sauropod_global_state::GlobalState>,
+    request: CreateRerankRequest,
+) -> anyhow::Result<axum::response::Response> {
+    let Some(model) = global_state.get_rerank_model(&request.model).await else {
+        return Ok(
+            sauropod_inference_http::HttpResponse::<()>::NotFound(Some(format!(
+                "Reranking model '{}' not found",
+                request.model
+            )))
+            .into_response(),
+        );
+    };
+
+    if let Err(e) = validate_request(&request) {
+        return Ok(
+            sauropod_inference_http::HttpResponse::<()>::BadRequest(format!("Invalid input: {e}"))
+                .into_response(),
+        );
+    }
+
+    let response = model
+        .rerank(request.query, request.documents.clone())
+        .await?;
+    anyhow::ensure!(
+        response.scores.len() == request.documents.len(),
+        "The model returned {} scores for {} documents",
+        response.scores.len(),
+        request.documents.len()
+    );
+
+    Ok(axum::Json(CreateRerankResponse {
+        model: request.model,
+        object: RerankListObject::List,
+        results: rank_documents(
+            response.scores,
+            request.documents,
+            request.top_n,
+            request.return_documents,
+        ),
+        usage: RerankUsage {
+            prompt_tokens: response.input_token_count,
+            total_tokens: response.input_token_count,
+        },
+    })
+    .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn documents() -> Vec<String> {
+        vec!["a".to_string(), "b".to_string(), "c".to_string()]
+    }
+
+    #[test]
+    fn test_rank_documents() {
+        let results = rank_documents(vec![0.1, 0.9, 0.5], documents(), None, false);
+        assert_eq!(
+            results
+                .iter()
+                .map(|result| (result.index, result.relevance_score))
+                .collect::<Vec<_>>(),
+            vec![(1, 0.9), (2, 0.5), (0, 0.1)]
+        );
+        assert!(results.iter().all(|result| result.document.is_none()));
+
+        let results = rank_documents(vec![0.1, 0.9, 0.5], documents(), Some(2), true);
+        assert_eq!(
+            results,
+            vec![
+                RerankResult {
+                    index: 1,
+                    relevance_score: 0.9,
+                    document: Some(RerankDocument {
+                        text: "b".to_string()
+                    }),
+                },
+                RerankResult {
+                    index: 2,
+                    relevance_score: 0.5,
+                    document: Some(RerankDocument {
+                        text: "c".to_string()
+                    }),
+                },
+            ]
+        );
+
+        let results = rank_documents(vec![0.5, 0.5, 0.5], documents(), Some(10), false);
+        assert_eq!(
+            results
+                .iter()
+                .map(|result| result.index)
+                .collect::<Vec<_>>(),
+            vec![0, 1, 2]
+        );
+    }
+
+    #[test]
+    fn test_validate_request() {
+        let request = |value: serde_json::Value| {
+            serde_json::from_value::<CreateRerankRequest>(value).unwrap()
+        };
+
+        let valid = request(serde_json::json!({
+            "model": "rerank",
+            "query": "What is a sauropod?",
+            "documents": ["A dinosaur", "A fish"],
+        }));
+        assert!(validate_request(&valid).is_ok());
+        assert_eq!(valid.top_n, None);
+        assert!(!valid.return_documents);
+
+        assert!(
+            validate_request(&request(serde_json::json!({
+                "model": "rerank",
+                "query": "What is a sauropod?",
+                "documents": [],
+            })))
+            .is_err()
+        );
+        assert!(
+            validate_request(&request(serde_json::json!({
+                "model": "rerank",
+                "query": "",
+                "documents": ["A dinosaur"],
+            })))
+            .is_err()
+        );
+        assert!(
+            validate_request(&request(serde_json::json!({
+                "model": "rerank",
+                "query": "What is a sauropod?",
+                "documents": ["A dinosaur", ""],
+            })))
+            .is_err()
+        );
+    }
+}
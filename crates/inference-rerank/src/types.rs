@@ -0,0 +1,68 @@
+//! Rerank API types.
+
+/// A request to rank documents by their relevance to a query.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+pub struct CreateRerankRequest {
+    /// The reranking model to use.
+    pub model: String,
+    /// The query the documents are scored against.
+    pub query: String,
+    /// The documents to rank.
+    pub documents: Vec<String>,
+    /// The number of most relevant documents to return. All documents are returned if unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_n: Option<usize>,
+    /// Whether to include the text of each document in the results.
+    #[serde(default)]
+    pub return_documents: bool,
+}
+
+/// The object type of a rerank response.
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize, utoipa::ToSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum RerankListObject {
+    List,
+}
+
+/// A document returned with a rerank result.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+pub struct RerankDocument {
+    /// The text of the document.
+    pub text: String,
+}
+
+/// The relevance of one document.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+pub struct RerankResult {
+    /// The index of the document in the request.
+    pub index: usize,
+    /// The relevance score of the document, where higher is more relevant.
+    pub relevance_score: f32,
+    /// The document, if `return_documents` was set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub document: Option<RerankDocument>,
+}
+
+/// Token usage of a rerank request.
+#[derive(
+    Clone, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize, utoipa::ToSchema,
+)]
+pub struct RerankUsage {
+    /// The number of tokens in the input.
+    pub prompt_tokens: i64,
+    /// The total number of tokens used.
+    pub total_tokens: i64,
+}
+
+/// The ranked documents of a request.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+pub struct CreateRerankResponse {
+    /// The model used to rank the documents.
+    pub model: String,
+    pub object: RerankListObject,
+    /// The results ordered from the most to the least relevant document.
+    pub results: Vec<RerankResult>,
+    pub usage: RerankUsage,
+}
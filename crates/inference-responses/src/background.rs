@@ -0,0 +1,251 @@
+//! Responses generated in the background.
+
+use axum::response::sse::Event as SseEvent;
+use tokio_stream::StreamExt as _;
+
+use sauropod_openai_api::{Response, ResponseError, ResponseErrorCode, ResponseStatus};
+
+/// How often a replayed stream checks for new events of a response in progress.
+const REPLAY_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(100);
+
+/// How long cancelling a stored response waits for the generation to stop.
+const CANCEL_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Whether a response with `status` may still change.
+pub(crate) fn is_pending(status: Option<&ResponseStatus>) -> bool {
+    matches!(
+        status,
+        Some(ResponseStatus::Queued) | Some(ResponseStatus::InProgress)
+    )
+}
+
+/// Get the sequence number of a stream event.
+fn sequence_number(event: &serde_json::Value) -> anyhow::Result<i64> {
+    event
+        .get("sequence_number")
+        .and_then(serde_json::Value::as_i64)
+        .ok_or_else(|| anyhow::anyhow!("The event has no sequence number"))
+}
+
+/// Get a stored response of a user.
+pub(crate) async fn get_stored_response(
+    global_state: &sauropod_global_state::GlobalState,
+    response_id: &str,
+    user_id: sauropod_users::UserId,
+) -> anyhow::Result<Option<Response>> {
+    let row = sqlx::query!(
+        "SELECT response_output FROM response WHERE response_id = ?1 AND user_id = ?2",
+        response_id,
+        user_id
+    )
+    .fetch_optional(global_state.database())
+    .await?;
+    match row {
+        Some(row) => Ok(Some(serde_json::from_str(&row.response_output)?)),
+        None => Ok(None),
+    }
+}
+
+/// Wait for a stored response of a user to stop changing and get it.
+///
+/// Gives up waiting after `CANCEL_TIMEOUT` and returns the response as it is.
+pub(crate) async fn wait_for_stored_response(
+    global_state: &sauropod_global_state::GlobalState,
+    response_id: &str,
+    user_id: sauropod_users::UserId,
+) -> anyhow::Result<Option<Response>> {
+    let deadline = tokio::time::Instant::now() + CANCEL_TIMEOUT;
+    loop {
+        let response = get_stored_response(global_state, response_id, user_id).await?;
+        match response {
+            Some(response)
+                if is_pending(response.status.as_ref())
+                    && tokio::time::Instant::now() < deadline =>
+            {
+                tokio::time::sleep(REPLAY_POLL_INTERVAL).await;
+            }
+            response => return Ok(response),
+        }
+    }
+}
+
+/// Replace the stored output of a response.
+async fn update_stored_response(
+    global_state: &sauropod_global_state::GlobalState,
+    response: &Response,
+) -> anyhow::Result<()> {
+    let response_text = serde_json::to_string(response)?;
+    sqlx::query!(
+        "UPDATE response SET response_output = ?1 WHERE response_id = ?2",
+        response_text,
+        response.id
+    )
+    .execute(global_state.database())
+    .await?;
+    Ok(())
+}
+
+/// Store a stream event of a response so that it can be replayed.
+async fn store_response_event(
+    global_state: &sauropod_global_state::GlobalState,
+    response_id: &str,
+    event: &sauropod_openai_api::ResponseStreamEvent,
+) -> anyhow::Result<i64> {
+    let event = serde_json::to_value(event)?;
+    let sequence_number = sequence_number(&event)?;
+    let event_text = event.to_string();
+    sqlx::query!(
+        "INSERT INTO response_event (response_id, sequence_number, event) VALUES (?1, ?2, ?3)",
+        response_id,
+        sequence_number,
+        event_text
+    )
+    .execute(global_state.database())
+    .await?;
+    Ok(sequence_number)
+}
+
+/// Generate a stored response, recording its events and progress in the database.
+///
+/// `response` is the queued response which has already been stored. It fails if `event_stream`
+/// couldn't be started.
+pub(crate) async fn run_background_response(
+    global_state: std::sync::Arc<sauropod_global_state::GlobalState>,
+    event_stream: anyhow::Result<sauropod_inference_engine_api::ResponseStream>,
+    mut response: Response,
+) {
+    let mut next_sequence_number = 0;
+    let result: anyhow::Result<()> = async {
+        let mut event_stream = event_stream?;
+        while let Some(event) = event_stream.next().await {
+            let event = event?;
+            next_sequence_number =
+                store_response_event(&global_state, &response.id, &event).await? + 1;
+            match event {
+                sauropod_openai_api::ResponseStreamEvent::ResponseCreatedEvent {
+                    response: created,
+                    ..
+                }
+                | sauropod_openai_api::ResponseStreamEvent::ResponseInProgressEvent {
+                    response: created,
+                    ..
+                } => {
+                    response = created;
+                    update_stored_response(&global_state, &response).await?;
+                }
+                sauropod_openai_api::ResponseStreamEvent::ResponseCompletedEvent {
+                    response: completed,
+                    ..
+                }
+                | sauropod_openai_api::ResponseStreamEvent::ResponseIncompleteEvent {
+                    response: completed,
+                    ..
+                } => {
+                    response = completed;
+                    update_stored_response(&global_state, &response).await?;
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+    .await;
+
+    let failure = match result {
+        Err(e) => Some(format!("{e:#}")),
+        Ok(()) if is_pending(response.status.as_ref()) => {
+            Some("The response stream ended before the response was completed".to_string())
+        }
+        Ok(()) => None,
+    };
+    if let Some(message) = failure {
+        tracing::error!("Background response {} failed: {message}", response.id);
+        response.status = Some(ResponseStatus::Failed);
+        response.error = Some(ResponseError {
+            message,
+            code: ResponseErrorCode::ServerError,
+        });
+        let failed_event = sauropod_openai_api::ResponseStreamEvent::ResponseFailedEvent {
+            response: response.clone(),
+            sequence_number: next_sequence_number,
+        };
+        if let Err(e) = store_response_event(&global_state, &response.id, &failed_event).await {
+            tracing::error!(
+                "Failed to store the failure of response {}: {e:#}",
+                response.id
+            );
+        }
+        if let Err(e) = update_stored_response(&global_state, &response).await {
+            tracing::error!(
+                "Failed to store the failure of response {}: {e:#}",
+                response.id
+            );
+        }
+    }
+}
+
+/// Stream the stored events of a response after `starting_after`, following the response until it
+/// finishes.
+pub(crate) fn replay_response_events(
+    global_state: std::sync::Arc<sauropod_global_state::GlobalState>,
+    response_id: String,
+    user_id: sauropod_users::UserId,
+    starting_after: Option<i64>,
+) -> impl tokio_stream::Stream<Item = anyhow::Result<SseEvent>> {
+    async_stream::try_stream! {
+        let mut last_sequence_number = starting_after.unwrap_or(-1);
+        loop {
+            // Read the status first so that no event stored before it finished is missed
+            let Some(response) = get_stored_response(&global_state, &response_id, user_id).await? else {
+                break;
+            };
+            let events = sqlx::query!(
+                "SELECT sequence_number, event FROM response_event WHERE response_id = ?1 AND sequence_number > ?2 ORDER BY sequence_number",
+                response_id,
+                last_sequence_number
+            )
+            .fetch_all(global_state.database())
+            .await?;
+
+            for event in events {
+                last_sequence_number = event.sequence_number;
+                yield SseEvent::default().data(event.event);
+            }
+
+            if !is_pending(response.status.as_ref()) {
+                break;
+            }
+            tokio::time::sleep(REPLAY_POLL_INTERVAL).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pending_statuses() {
+        assert!(is_pending(Some(&ResponseStatus::Queued)));
+        assert!(is_pending(Some(&ResponseStatus::InProgress)));
+        assert!(!is_pending(Some(&ResponseStatus::Completed)));
+        assert!(!is_pending(Some(&ResponseStatus::Cancelled)));
+        assert!(!is_pending(Some(&ResponseStatus::Failed)));
+        assert!(!is_pending(None));
+    }
+
+    #[test]
+    fn test_event_sequence_number() {
+        let event = sauropod_openai_api::ResponseStreamEvent::ResponseTextDeltaEvent {
+            content_index: 0,
+            delta: "Hello".to_string(),
+            item_id: "item".to_string(),
+            logprobs: Vec::new(),
+            output_index: 0,
+            sequence_number: 7,
+        };
+        let value = serde_json::to_value(&event).unwrap();
+        assert_eq!(sequence_number(&value).unwrap(), 7);
+        assert!(sequence_number(&serde_json::json!({"type": "error"})).is_err());
+    }
+}
@@ -6,6 +6,7 @@ use sauropod_openai_api::{
     CreateResponse, Response, ResponseError, ResponseErrorCode, ResponseStatus,
 };
 
+mod background;
 mod routes;
 pub use routes::*;
 
@@ -145,6 +146,7 @@ async fn get_response_chain(
 pub async fn create_response_impl(
     global_state: std::sync::Arc<sauropod_global_state::GlobalState>,
     request: CreateResponse,
+    sampling: sauropod_inference_engine_api::SamplingOverrides,
     authentication: sauropod_inference_http::Authentication,
 ) -> anyhow::Result<axum::response::Response> {
     tracing::debug!("create response request: {:#?}", request);
@@ -169,6 +171,20 @@ pub async fn create_response_impl(
         );
     };
 
+    if let Err(e) = sauropod_inference_engine_api::SamplerProperties::new(
+        &sauropod_inference_engine_api::make_response(&request),
+        &model.model_config,
+        model.get_model_type(),
+        &sampling,
+    ) {
+        return Ok(
+            sauropod_inference_http::HttpResponse::<()>::BadRequest(format!(
+                "Invalid request: {e}"
+            ))
+            .into_response(),
+        );
+    }
+
     let previous_responses = if let Some(previous_response_id) =
         request.response_properties.previous_response_id.as_deref()
     {
@@ -192,37 +208,121 @@ pub async fn create_response_impl(
 
     let mut merged_request = request.clone();
     merge_responses(&mut merged_request, previous_responses);
-    let render_context = sauropod_prompt_templates::RenderContext::from_create_response(
+    let mut render_context = sauropod_prompt_templates::RenderContext::from_create_response(
         &merged_request,
         model.get_system_prompt(),
+        model.get_model_type(),
     )?;
 
+    // Room is reserved for as many output tokens as the generation may produce
+    let output_tokens = request
+        .response_properties
+        .max_output_tokens
+        .or(model.model_config.maximum_tokens)
+        .unwrap_or(sauropod_inference_engine_api::DEFAULT_MAX_OUTPUT_TOKENS)
+        .max(1) as usize;
+    let truncate = matches!(
+        request.response_properties.truncation,
+        Some(sauropod_openai_api::ResponsePropertiesTruncation::Auto)
+    );
+    if !model.fit_to_context(&mut render_context, output_tokens, truncate)? {
+        let error = if truncate {
+            "The input doesn't fit in the context of the model even after truncation"
+        } else {
+            "The input exceeds the context of the model, set `truncation` to `auto` to drop the oldest messages"
+        };
+        return Ok(
+            sauropod_inference_http::HttpResponse::<()>::BadRequest(error.to_string())
+                .into_response(),
+        );
+    }
+
     tracing::debug!("Merged request: {:#?}", merged_request);
 
+    let background = request.response_properties.background.unwrap_or(false);
+    if background && request.store == Some(false) {
+        return Ok(sauropod_inference_http::HttpResponse::<()>::BadRequest(
+            "Background responses must be stored".to_string(),
+        )
+        .into_response());
+    }
+
     let store = request.store.unwrap_or(false);
-    if request.stream.unwrap_or(false) {
+    let response_id = uuid::Uuid::new_v4().to_string();
+    let cancellation_token = sauropod_inference_engine_api::CancellationToken::new();
+    let in_flight_response = global_state.track_response(
+        response_id.clone(),
+        authentication.get_user_id(),
+        cancellation_token.clone(),
+    );
+    let options = sauropod_inference_engine::GenerationOptions {
+        response_id: Some(response_id.clone()),
+        cancellation_token,
+        sampling,
+    };
+    if background {
+        let mut queued_response = sauropod_inference_engine_api::make_response(&request);
+        queued_response.id = response_id.clone();
+        queued_response.status = Some(ResponseStatus::Queued);
+        store_response(
+            global_state.clone(),
+            request.response_properties.previous_response_id.as_deref(),
+            request.input.as_ref(),
+            &queued_response,
+            &authentication,
+        )
+        .await?;
+
+        tokio::spawn({
+            let global_state = global_state.clone();
+            let request = request.clone();
+            let queued_response = queued_response.clone();
+            async move {
+                // The response can be cancelled for as long as it's generated
+                let _in_flight_response = in_flight_response;
+                // The response is already stored, so failing to start it fails the stored response
+                let event_stream = model
+                    .generate_stream_with_options(request, render_context, options)
+                    .await;
+                background::run_background_response(global_state, event_stream, queued_response)
+                    .await;
+            }
+        });
+
+        if request.stream.unwrap_or(false) {
+            let replayed_stream = background::replay_response_events(
+                global_state,
+                response_id,
+                authentication.get_user_id(),
+                None,
+            );
+            Ok(Sse::new(replayed_stream).into_response())
+        } else {
+            Ok(axum::Json(queued_response).into_response())
+        }
+    } else if request.stream.unwrap_or(false) {
         let event_stream = model
-            .generate_stream(request.clone(), render_context)
+            .generate_stream_with_options(request.clone(), render_context, options)
             .await?;
         let mapped_stream = async_stream::stream! {
+            // The response can be cancelled for as long as it's streamed
+            let _in_flight_response = in_flight_response;
             for await event in event_stream {
                 match event {
                     Ok(sauropod_openai_api::ResponseStreamEvent::ResponseCompletedEvent {
-                        response,
-                        sequence_number,
+                        ref response,
+                        ..
+                    }
+                    | sauropod_openai_api::ResponseStreamEvent::ResponseIncompleteEvent {
+                        ref response,
+                        ..
                     }) => {
                         // Store the response if requested
                         if store {
-                            store_response(global_state.clone(),request.response_properties.previous_response_id.as_deref(), request.input.as_ref(), &response, &authentication).await?;
+                            store_response(global_state.clone(),request.response_properties.previous_response_id.as_deref(), request.input.as_ref(), response, &authentication).await?;
                         }
 
-                        yield Ok(SseEvent::default()
-                            .json_data(
-                                sauropod_openai_api::ResponseStreamEvent::ResponseCompletedEvent {
-                                    response,
-                                    sequence_number,
-                                },
-                            ).unwrap())
+                        yield Ok(SseEvent::default().json_data(event.unwrap()).unwrap())
                     }
 
                     Ok(data) => yield Ok(SseEvent::default().json_data(data).unwrap()),
@@ -232,7 +332,10 @@ pub async fn create_response_impl(
         };
         Ok(Sse::new(mapped_stream).into_response())
     } else {
-        let response = model.generate(request.clone(), render_context).await?;
+        let response = model
+            .generate_with_options(request.clone(), render_context, options)
+            .await?;
+        drop(in_flight_response);
 
         // Store the response if requested
         if store {
@@ -1,6 +1,7 @@
 use axum::extract::State;
 use axum::response::IntoResponse;
 
+use sauropod_inference_engine_api::SamplingOverrides;
 use sauropod_inference_http::UserAuthenticationExtension;
 use sauropod_openai_api::{CreateResponse, Response};
 
@@ -11,6 +12,7 @@ use sauropod_openai_api::{CreateResponse, Response};
     request_body = CreateResponse,
     responses(
         (status = 200, description = "Response created", body = Response),
+        (status = 400, description = "Invalid request", body = sauropod_inference_http::Error),
         (status = 500, description = "Error occured", body = sauropod_inference_http::Error)
     )
 )]
@@ -19,8 +21,15 @@ pub async fn create_response(
     axum::Extension(authentication): UserAuthenticationExtension,
     axum::Json(request): axum::Json<serde_json::Value>,
 ) -> axum::response::Response {
-    let request = match serde_json::from_value::<CreateResponse>(request.clone()) {
-        Ok(request) => request,
+    // Sampling parameters which aren't part of the OpenAI API are read from the same body
+    let parsed = serde_json::from_value::<CreateResponse>(request.clone()).and_then(|create| {
+        Ok((
+            create,
+            serde_json::from_value::<SamplingOverrides>(request.clone())?,
+        ))
+    });
+    let (request, sampling) = match parsed {
+        Ok(parsed) => parsed,
         Err(e) => {
             tracing::error!(
                 "Failed to parse request: {e}\n{}",
@@ -35,7 +44,7 @@ pub async fn create_response(
                 .into_response();
         }
     };
-    match crate::create_response_impl(loaded_models, request, authentication).await {
+    match crate::create_response_impl(loaded_models, request, sampling, authentication).await {
         Ok(response) => response,
         Err(e) => {
             tracing::error!("Failed to create response: {e:#?}");
@@ -50,13 +59,25 @@ pub async fn create_response(
     }
 }
 
+/// Query parameters for retrieving a response.
+#[derive(serde::Deserialize, utoipa::IntoParams)]
+pub struct GetResponseQuery {
+    /// Stream the events of the response instead of returning it.
+    ///
+    /// Only responses created in the background can be streamed.
+    stream: Option<bool>,
+    /// The sequence number of the event after which to start streaming.
+    starting_after: Option<i64>,
+}
+
 #[utoipa::path(
     get,
     path = "/v1/responses/{response_id}",
     description = "Retrieves a model response with the given ID",
     tag = "Responses",
     params(
-        ("response_id" = String, Path, description = "The ID of the response to retrieve")
+        ("response_id" = String, Path, description = "The ID of the response to retrieve"),
+        GetResponseQuery
     ),
     responses(
         (status = 200, description = "Response found", body = Response),
@@ -66,10 +87,33 @@ pub async fn create_response(
 )]
 pub async fn get_response(
     response_id: axum::extract::Path<String>,
+    axum::extract::Query(query): axum::extract::Query<GetResponseQuery>,
     axum::Extension(authentication): UserAuthenticationExtension,
     State(global_state): sauropod_global_state::AxumGlobalState,
 ) -> axum::response::Response {
     let user_id = authentication.get_user_id();
+    if query.stream.unwrap_or(false) {
+        return match crate::background::get_stored_response(&global_state, &response_id.0, user_id)
+            .await
+        {
+            Ok(Some(_)) => axum::response::Sse::new(crate::background::replay_response_events(
+                global_state.clone(),
+                response_id.0,
+                user_id,
+                query.starting_after,
+            ))
+            .into_response(),
+            Ok(None) => sauropod_inference_http::HttpResponse::<()>::NotFound(None).into_response(),
+            Err(e) => {
+                tracing::error!("Error fetching response: {e}");
+                sauropod_inference_http::HttpResponse::<()>::InternalServerError(
+                    "Error occured querying database".to_string(),
+                )
+                .into_response()
+            }
+        };
+    }
+
     let result = sqlx::query!(
         "SELECT response_output FROM response WHERE response_id = ?1 AND user_id = ?2",
         response_id.0,
@@ -144,6 +188,49 @@ pub async fn delete_response(
     response.into_response()
 }
 
+#[utoipa::path(
+    post,
+    path = "/v1/responses/{response_id}/cancel",
+    description = "Cancels a model response with the given ID which is being generated",
+    tag = "Responses",
+    params(
+        ("response_id" = String, Path, description = "The ID of the response to cancel")
+    ),
+    responses(
+        (status = 200, description = "OK, with the cancelled response if it was stored", body = Option<Response>),
+        (status = 404, description = "Not Found"),
+        (status = 500, description = "Error occured", body = sauropod_inference_http::Error)
+    )
+)]
+pub async fn cancel_response(
+    response_id: axum::extract::Path<String>,
+    axum::Extension(authentication): UserAuthenticationExtension,
+    State(global_state): sauropod_global_state::AxumGlobalState,
+) -> axum::response::Response {
+    let user_id = authentication.get_user_id();
+    if global_state.cancel_response(&response_id.0, user_id) {
+        match crate::background::wait_for_stored_response(&global_state, &response_id.0, user_id)
+            .await
+        {
+            Ok(Some(response)) => axum::Json(response).into_response(),
+            Ok(None) => ().into_response(),
+            Err(e) => {
+                tracing::error!("Error fetching response: {e}");
+                sauropod_inference_http::HttpResponse::<()>::InternalServerError(
+                    "Error occured querying database".to_string(),
+                )
+                .into_response()
+            }
+        }
+    } else {
+        sauropod_inference_http::HttpResponse::<()>::NotFound(Some(format!(
+            "No response with ID '{}' is in progress",
+            response_id.0
+        )))
+        .into_response()
+    }
+}
+
 #[utoipa::path(
     get,
     path = "/v1/models",
@@ -156,13 +243,13 @@ pub async fn delete_response(
 pub async fn get_models(
     State(loaded_models): sauropod_global_state::AxumGlobalState,
 ) -> axum::response::Response {
-    let models = loaded_models.get_all_models().await;
+    let models = loaded_models.get_model_names().await;
     let response = sauropod_openai_api::ListModelsResponse {
         data: models
-            .keys()
+            .into_iter()
             .map(|name| sauropod_openai_api::Model {
                 created: 0,
-                id: name.to_string(),
+                id: name,
                 object: sauropod_openai_api::ModelObject::Model,
                 owned_by: "".to_string(),
             })
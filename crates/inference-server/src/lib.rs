@@ -1,9 +1,17 @@
+use anyhow::Context as _;
+
+mod validate;
+pub use validate::*;
+
 /// Sauropod inference engine
 #[derive(Debug, clap::Parser)]
 #[command(version, name = "sauropod", about = env!("CARGO_PKG_DESCRIPTION"), long_about = None)]
 pub struct Cli {
+    /// A command to run instead of the server.
+    #[command(subcommand)]
+    pub command: Option<Command>,
     /// The path to the TOML config file to load.
-    #[arg(short, long, env = "SAUROPOD_CONFIG_FILE")]
+    #[arg(short, long, env = "SAUROPOD_CONFIG_FILE", global = true)]
     pub config_file: Option<std::path::PathBuf>,
     /// The path to the SQLite database file.
     #[arg(short, long, env = "SAUROPOD_DATABASE")]
@@ -24,6 +32,23 @@ pub struct Cli {
     pub trace_output: Option<String>,
 }
 
+/// Commands run instead of the server.
+#[derive(Debug, clap::Subcommand)]
+pub enum Command {
+    /// Check or describe config files.
+    #[command(subcommand)]
+    Config(ConfigCommand),
+}
+
+/// Commands for config files.
+#[derive(Debug, clap::Subcommand)]
+pub enum ConfigCommand {
+    /// Parse and validate the config file without downloading or loading any models.
+    Validate,
+    /// Print the JSON Schema of config files, for editors to validate and complete them.
+    Schema,
+}
+
 pub fn make_config_source(cli: &Cli) -> anyhow::Result<sauropod_config::ClapConfigSource> {
     let mut source = sauropod_config::ClapConfigSource::default();
     source.add_value("port".to_string(), cli.port)?;
@@ -37,3 +62,42 @@ pub fn make_config_source(cli: &Cli) -> anyhow::Result<sauropod_config::ClapConf
     }
     Ok(source)
 }
+
+/// Run a config command.
+pub fn run_config_command(cli: &Cli, command: &ConfigCommand) -> anyhow::Result<()> {
+    match command {
+        ConfigCommand::Validate => {
+            let config_file = match &cli.config_file {
+                Some(config_file) => config_file.clone(),
+                None => sauropod_config::Config::default_file_path()?,
+            };
+            let config = sauropod_config::Config::load_from_file(
+                config_file.clone(),
+                make_config_source(cli)?,
+            )
+            .with_context(|| format!("Failed to parse {}", config_file.display()))?;
+
+            let problems = validate_config(&config);
+            for problem in &problems {
+                println!("{problem}");
+            }
+            let errors = problems
+                .iter()
+                .filter(|problem| problem.severity == Severity::Error)
+                .count();
+            anyhow::ensure!(
+                errors == 0,
+                "{} has {errors} error(s)",
+                config_file.display()
+            );
+            println!("{} is valid", config_file.display());
+        }
+        ConfigCommand::Schema => {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&sauropod_config::json_schema())?
+            );
+        }
+    }
+    Ok(())
+}
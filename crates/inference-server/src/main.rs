@@ -15,6 +15,13 @@ fn create_api_router(
     // Create an API router with OpenAPI documentation
     let (router, mut spec) =
         utoipa_axum::router::OpenApiRouter::<Arc<sauropod_global_state::GlobalState>>::new()
+            .routes(utoipa_axum::routes!(
+                sauropod_inference_audio::create_transcription
+            ))
+            // Uploaded recordings are much larger than the default body limit
+            .layer(axum::extract::DefaultBodyLimit::max(
+                sauropod_inference_audio::MAX_TRANSCRIPTION_FILE_SIZE,
+            ))
             .routes(utoipa_axum::routes!(
                 sauropod_inference_realtime::get_v1_realtime
             ))
@@ -26,6 +33,18 @@ fn create_api_router(
                 sauropod_inference_responses::get_response,
                 sauropod_inference_responses::delete_response
             ))
+            .routes(utoipa_axum::routes!(
+                sauropod_inference_responses::cancel_response
+            ))
+            .routes(utoipa_axum::routes!(
+                sauropod_inference_chat_completions::create_chat_completion
+            ))
+            .routes(utoipa_axum::routes!(
+                sauropod_inference_embeddings::create_embedding
+            ))
+            .routes(utoipa_axum::routes!(
+                sauropod_inference_rerank::create_rerank
+            ))
             .routes(utoipa_axum::routes!(
                 sauropod_inference_audio::create_speech,
             ))
@@ -36,6 +55,24 @@ fn create_api_router(
                 global_state.clone(),
                 sauropod_inference_http::auth_middleware,
             ))
+            // The admin routes are authenticated with the admin API key instead
+            .merge(
+                utoipa_axum::router::OpenApiRouter::new()
+                    .routes(utoipa_axum::routes!(
+                        sauropod_inference_admin::list_admin_models
+                    ))
+                    .routes(utoipa_axum::routes!(
+                        sauropod_inference_admin::add_admin_model,
+                        sauropod_inference_admin::replace_admin_model
+                    ))
+                    .routes(utoipa_axum::routes!(
+                        sauropod_inference_admin::unload_admin_model
+                    ))
+                    .layer(axum::middleware::from_fn_with_state(
+                        global_state.clone(),
+                        sauropod_inference_http::admin_auth_middleware,
+                    )),
+            )
             // This is an unauthenticated route because it uses a pre-negotiated token
             .routes(utoipa_axum::routes!(
                 sauropod_inference_realtime::realtime_webrtc::post_v1_realtime
@@ -95,18 +132,90 @@ pub fn initialize_tracing(pretty_print: bool, verbose: bool, trace_output: Optio
         .init();
 }
 
+/// How often the config file is checked for changes.
+const CONFIG_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// When a file was last modified, or `None` if it can't be read.
+fn modified_time(path: &std::path::Path) -> Option<std::time::SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// Notify `reload` whenever the process receives SIGHUP.
+#[cfg(unix)]
+async fn notify_on_hangup(reload: Arc<tokio::sync::Notify>) {
+    let mut hangup = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            tracing::warn!("Failed to listen for SIGHUP: {e}");
+            return;
+        }
+    };
+    while hangup.recv().await.is_some() {
+        reload.notify_one();
+    }
+}
+
+/// Reload the configuration whenever the config file changes or the process receives SIGHUP.
+async fn watch_config(
+    global_state: Arc<sauropod_global_state::GlobalState>,
+    config_file: std::path::PathBuf,
+    config_source: sauropod_config::ClapConfigSource,
+) {
+    let reload = Arc::new(tokio::sync::Notify::new());
+    #[cfg(unix)]
+    tokio::spawn(notify_on_hangup(reload.clone()));
+
+    let mut last_modified = modified_time(&config_file);
+    let mut interval = tokio::time::interval(CONFIG_POLL_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                let modified = modified_time(&config_file);
+                if modified == last_modified {
+                    continue;
+                }
+                last_modified = modified;
+                tracing::info!("{} changed, reloading the configuration", config_file.display());
+            }
+            _ = reload.notified() => {
+                last_modified = modified_time(&config_file);
+                tracing::info!("Received SIGHUP, reloading the configuration");
+            }
+        }
+
+        let result = match sauropod_config::Config::load_from_file(
+            config_file.clone(),
+            config_source.clone(),
+        ) {
+            Ok(config) => global_state.reload_config(config).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            tracing::error!(
+                "Failed to reload the configuration from {}: {e:#}",
+                config_file.display()
+            );
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let config: sauropod_config::Config = {
-        let args = sauropod_inference_server::Cli::parse();
-        let config_source = sauropod_inference_server::make_config_source(&args)?;
-
-        if let Some(config_file) = args.config_file {
-            sauropod_config::Config::load_from_file(config_file, config_source)?
-        } else {
-            sauropod_config::Config::load(config_source)?
-        }
+    let args = sauropod_inference_server::Cli::parse();
+    if let Some(sauropod_inference_server::Command::Config(command)) = &args.command {
+        return sauropod_inference_server::run_config_command(&args, command);
+    }
+
+    let config_source = sauropod_inference_server::make_config_source(&args)?;
+    let config_file = match args.config_file {
+        Some(config_file) => config_file,
+        None => sauropod_config::Config::default_file_path()?,
     };
+    let config =
+        sauropod_config::Config::load_from_file(config_file.clone(), config_source.clone())?;
+    config.validate()?;
     initialize_tracing(
         config.verbose,
         config.verbose,
@@ -119,6 +228,12 @@ async fn main() -> anyhow::Result<()> {
 
     let global_state = Arc::new(sauropod_global_state::GlobalState::new(&config).await?);
 
+    tokio::spawn(watch_config(
+        global_state.clone(),
+        config_file,
+        config_source,
+    ));
+
     let (api_app, spec) = create_api_router(global_state.clone());
     let app = api_app
         .without_v07_checks()
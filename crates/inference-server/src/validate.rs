@@ -0,0 +1,360 @@
+//! Offline checks of a configuration, which run without downloading or loading any models.
+
+use sauropod_config::{ConfigModelSource, PathOrQuantization};
+
+/// The voices of the Orpheus model.
+const ORPHEUS_VOICES: &[&str] = &["tara", "leah", "jess", "leo", "dan", "mia", "zac", "zoe"];
+
+/// The only voice loaded for Kokoro models.
+const KOKORO_VOICE: &str = "af_heart";
+
+/// How serious a problem in a configuration is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    /// The server fails to start or a model fails to load.
+    Error,
+    /// The configuration works, but likely not as intended.
+    Warning,
+}
+
+/// A problem found in a configuration.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConfigProblem {
+    pub severity: Severity,
+    /// The key of the setting with the problem, for example `models.default.chat_template`.
+    pub key: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for ConfigProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        if self.key.is_empty() {
+            write!(f, "{severity}: {}", self.message)
+        } else {
+            write!(f, "{severity}: {}: {}", self.key, self.message)
+        }
+    }
+}
+
+/// Collects the problems of a configuration.
+#[derive(Default)]
+struct Problems(Vec<ConfigProblem>);
+
+impl Problems {
+    fn error(&mut self, key: impl Into<String>, message: impl Into<String>) {
+        self.0.push(ConfigProblem {
+            severity: Severity::Error,
+            key: key.into(),
+            message: message.into(),
+        });
+    }
+
+    fn warning(&mut self, key: impl Into<String>, message: impl Into<String>) {
+        self.0.push(ConfigProblem {
+            severity: Severity::Warning,
+            key: key.into(),
+            message: message.into(),
+        });
+    }
+
+    /// Check that a model source refers to a local path which exists or to a Hugging Face
+    /// repository.
+    fn check_source(&mut self, key: &str, source: &ConfigModelSource) {
+        match source {
+            ConfigModelSource::LocalPath(path) if path.is_empty() => {
+                self.error(key, "The path is empty");
+            }
+            ConfigModelSource::LocalPath(path)
+                if path.parse::<sauropod_config::HuggingfacePath>().is_ok() =>
+            {
+                self.error(
+                    key,
+                    format!(
+                        "\"{path}\" is read as a local path - use a table such as {{ repo = \"<owner>/<name>\", quantization = \"Q4_K_M\" }} for Hugging Face models"
+                    ),
+                );
+            }
+            ConfigModelSource::LocalPath(path) => {
+                if !std::path::Path::new(path).exists() {
+                    self.error(key, format!("{path} doesn't exist"));
+                }
+            }
+            ConfigModelSource::HuggingFace(repo) => {
+                let mut parts = repo.repo.split('/');
+                let valid_repo = matches!(
+                    (parts.next(), parts.next(), parts.next()),
+                    (Some(owner), Some(name), None) if !owner.is_empty() && !name.is_empty()
+                ) && !repo.repo.contains(char::is_whitespace);
+                if !valid_repo {
+                    self.error(
+                        key,
+                        format!(
+                            "\"{}\" isn't a Hugging Face repository of the form <owner>/<name>",
+                            repo.repo
+                        ),
+                    );
+                }
+                match &repo.path_or_quantization {
+                    Some(PathOrQuantization::Quantization { quantization })
+                        if quantization.is_empty() =>
+                    {
+                        self.error(key, "The quantization is empty");
+                    }
+                    Some(PathOrQuantization::FilePath { file }) if file.is_empty() => {
+                        self.error(key, "The file is empty");
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    /// Check a model source which must be a GGUF file.
+    fn check_gguf_source(&mut self, key: &str, source: &ConfigModelSource, description: &str) {
+        self.check_source(key, source);
+        let file = match source {
+            ConfigModelSource::LocalPath(path) if !std::path::Path::new(path).is_dir() => path,
+            ConfigModelSource::HuggingFace(repo) => match &repo.path_or_quantization {
+                Some(PathOrQuantization::FilePath { file }) => file,
+                _ => return,
+            },
+            _ => return,
+        };
+        if !file.is_empty() && !file.to_lowercase().ends_with(".gguf") {
+            self.error(
+                key,
+                format!("{description} must be GGUF files, but {file} isn't"),
+            );
+        }
+    }
+
+    /// Check a model source which is a directory of ONNX files.
+    fn check_onnx_source(&mut self, key: &str, source: &ConfigModelSource, description: &str) {
+        self.check_source(key, source);
+        if let ConfigModelSource::HuggingFace(repo) = source
+            && let Some(PathOrQuantization::Quantization { .. }) = &repo.path_or_quantization
+        {
+            self.error(
+                key,
+                format!("{description} are ONNX models and have no quantization"),
+            );
+        }
+    }
+
+    /// Check that a Jinja template compiles.
+    fn check_template(&mut self, key: &str, template: &str) {
+        if let Err(e) = sauropod_prompt_templates::PromptTemplate::new(template.to_string()) {
+            self.error(key, format!("Invalid Jinja template: {e}"));
+        }
+    }
+}
+
+/// Sort the entries of a map by key so that problems are reported in a stable order.
+fn sorted<V>(map: &std::collections::HashMap<String, V>) -> Vec<(&String, &V)> {
+    let mut entries: Vec<(&String, &V)> = map.iter().collect();
+    entries.sort_by_key(|(name, _)| *name);
+    entries
+}
+
+/// Check a configuration for problems which would otherwise only surface after downloading or
+/// loading models.
+pub fn validate_config(config: &sauropod_config::Config) -> Vec<ConfigProblem> {
+    let mut problems = Problems::default();
+    if let Err(e) = config.validate() {
+        problems.error("", format!("{e:#}"));
+    }
+
+    for (name, model_config) in sorted(&config.models) {
+        let key = format!("models.{name}");
+        problems.check_source(&format!("{key}.model"), &model_config.model);
+        if let Some(multimodal_projector) = &model_config.multimodal_projector {
+            problems.check_gguf_source(
+                &format!("{key}.multimodal_projector"),
+                multimodal_projector,
+                "Multimodal projectors",
+            );
+        }
+        if let Some(draft_model) = &model_config.draft_model {
+            problems.check_gguf_source(&format!("{key}.draft_model"), draft_model, "Draft models");
+        }
+        for (adapter_name, adapter) in sorted(&model_config.lora_adapters) {
+            problems.check_gguf_source(
+                &format!("{key}.lora_adapters.{adapter_name}.adapter"),
+                &adapter.adapter,
+                "LoRA adapters",
+            );
+        }
+        if let Some(system_prompt) = &model_config.system_prompt {
+            problems.check_template(&format!("{key}.system_prompt"), system_prompt);
+        }
+        if let Some(chat_template) = &model_config.chat_template {
+            problems.check_template(&format!("{key}.chat_template"), chat_template);
+        }
+    }
+
+    // Voices sharing a model source share one loaded model, which must be of a single type
+    let mut voice_types: std::collections::HashMap<&ConfigModelSource, (&str, &str)> =
+        std::collections::HashMap::new();
+    for (name, voice_config) in sorted(&config.voices) {
+        let key = format!("voices.{name}");
+        let (voice_type, model) = match voice_config {
+            sauropod_config::VoiceConfig::Kokoro { voice, model } => {
+                problems.check_onnx_source(&format!("{key}.model"), model, "Kokoro models");
+                if voice != KOKORO_VOICE {
+                    problems.warning(
+                        format!("{key}.voice"),
+                        format!(
+                            "Kokoro only loads the {KOKORO_VOICE} voice, which is used instead of {voice}"
+                        ),
+                    );
+                }
+                ("kokoro", model)
+            }
+            sauropod_config::VoiceConfig::Orpheus { voice, model } => {
+                problems.check_gguf_source(&format!("{key}.model"), model, "Orpheus models");
+                if !ORPHEUS_VOICES.contains(&voice.as_str()) {
+                    problems.warning(
+                        format!("{key}.voice"),
+                        format!(
+                            "{voice} isn't an Orpheus voice - expected one of {}",
+                            ORPHEUS_VOICES.join(", ")
+                        ),
+                    );
+                }
+                ("orpheus", model)
+            }
+        };
+        match voice_types.get(model) {
+            Some((other_type, other_name)) if *other_type != voice_type => problems.error(
+                format!("{key}.model"),
+                format!(
+                    "The model is also used by the {other_type} voice {other_name}, but a model can only be used by voices of one type"
+                ),
+            ),
+            Some(_) => {}
+            None => {
+                voice_types.insert(model, (voice_type, name));
+            }
+        }
+    }
+
+    match &config.stt_model {
+        Some(sauropod_config::SpeechToTextConfig::Parakeet { model }) => {
+            problems.check_onnx_source("stt_model.model", model, "Parakeet models");
+        }
+        Some(sauropod_config::SpeechToTextConfig::Voxtral {
+            model,
+            multimodal_projector,
+        }) => {
+            problems.check_gguf_source("stt_model.model", model, "Voxtral models");
+            problems.check_gguf_source(
+                "stt_model.multimodal_projector",
+                multimodal_projector,
+                "Voxtral projectors",
+            );
+        }
+        None => {}
+    }
+    if let Some(vad_model) = &config.vad_model {
+        problems.check_onnx_source("vad_model", vad_model, "VAD models");
+    }
+
+    problems.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_config(toml: &str) -> sauropod_config::Config {
+        config::Config::builder()
+            .add_source(config::File::from_str(toml, config::FileFormat::Toml))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap()
+    }
+
+    fn keys(problems: &[ConfigProblem]) -> Vec<(&str, Severity)> {
+        problems
+            .iter()
+            .map(|problem| (problem.key.as_str(), problem.severity))
+            .collect()
+    }
+
+    #[test]
+    fn test_validate_default_config() {
+        assert_eq!(
+            validate_config(&sauropod_config::Config::default()),
+            Vec::new()
+        );
+    }
+
+    #[test]
+    fn test_validate_models() {
+        let config = parse_config(
+            r#"
+            [models.a]
+            model = "huggingface.co/unsloth/Qwen3-8B-GGUF:Q4_K_M"
+            chat_template = "{% for message in messages %}"
+
+            [models.b]
+            model = { repo = "Qwen3-8B-GGUF", quantization = "Q4_K_M" }
+            draft_model = { repo = "unsloth/Qwen3-0.6B-GGUF", file = "model.safetensors" }
+            system_prompt = "You are {{ name }}."
+            "#,
+        );
+        let problems = validate_config(&config);
+        assert_eq!(
+            keys(&problems),
+            vec![
+                ("models.a.model", Severity::Error),
+                ("models.a.chat_template", Severity::Error),
+                ("models.b.model", Severity::Error),
+                ("models.b.draft_model", Severity::Error),
+            ],
+            "{problems:#?}"
+        );
+    }
+
+    #[test]
+    fn test_validate_voices_and_stt() {
+        let config = parse_config(
+            r#"
+            [voices.kokoro]
+            type = "kokoro"
+            voice = "bf_emma"
+            model = { repo = "unsloth/orpheus-3b-0.1-ft-GGUF", quantization = "Q4_K_M" }
+
+            [voices.orpheus]
+            type = "orpheus"
+            voice = "tara"
+
+            [voices.unknown]
+            type = "orpheus"
+            voice = "emma"
+
+            [stt_model]
+            type = "voxtral"
+            multimodal_projector = { repo = "ggml-org/Voxtral-Mini-3B-2507-GGUF", file = "mmproj.bin" }
+            "#,
+        );
+        let problems = validate_config(&config);
+        assert_eq!(
+            keys(&problems),
+            vec![
+                ("voices.kokoro.model", Severity::Error),
+                ("voices.kokoro.voice", Severity::Warning),
+                ("voices.orpheus.model", Severity::Error),
+                ("voices.unknown.voice", Severity::Warning),
+                ("voices.unknown.model", Severity::Error),
+                ("stt_model.multimodal_projector", Severity::Error),
+            ],
+            "{problems:#?}"
+        );
+    }
+}
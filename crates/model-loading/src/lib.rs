@@ -1,15 +1,277 @@
 use std::{collections::HashMap, sync::Arc};
 
 use anyhow::Context;
-use sauropod_config::ConfigModelSource;
+use sauropod_config::{ConfigModelSource, ModelKind};
 use tracing::Instrument as _;
 
+mod memory;
+
+/// How often models are checked for exceeding their idle timeout.
+const IDLE_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// A model loaded from a source, shared by the names configured with that source.
+#[derive(Clone)]
+enum LoadedPointer {
+    Chat(sauropod_inference_engine::ModelPointer),
+    Embedding(sauropod_inference_engine::EmbeddingModelPointer),
+    Rerank(sauropod_inference_engine::RerankModelPointer),
+}
+
+/// Identifies a resident model by what it's used for and where it was loaded from.
+type ResidentModelKey = (ModelKind, ConfigModelSource);
+
+/// The voices by name and the TTS models they use by source.
+type LoadedVoices = (
+    HashMap<String, Arc<sauropod_tts::ConfiguredTtsThread>>,
+    HashMap<ConfigModelSource, Arc<sauropod_tts::TtsThread>>,
+);
+
+/// A model held in memory.
+struct ResidentModel {
+    /// The loaded model.
+    pointer: LoadedPointer,
+    /// The chat models of the names using the model, created on their first request.
+    chat_models: HashMap<String, Arc<sauropod_inference_engine::Model>>,
+    /// The estimated number of bytes used by the model.
+    size: u64,
+    /// When the model was last requested.
+    last_used: std::sync::Mutex<std::time::Instant>,
+}
+
+impl ResidentModel {
+    fn new(pointer: LoadedPointer, size: u64) -> Self {
+        Self {
+            pointer,
+            chat_models: HashMap::new(),
+            size,
+            last_used: std::sync::Mutex::new(std::time::Instant::now()),
+        }
+    }
+
+    /// Record that the model was requested.
+    fn touch(&self) {
+        *self.last_used.lock().unwrap() = std::time::Instant::now();
+    }
+
+    /// When the model was last requested.
+    fn last_used(&self) -> std::time::Instant {
+        *self.last_used.lock().unwrap()
+    }
+
+    /// Whether a request holds the model, in which case unloading it wouldn't free any memory.
+    fn in_use(&self) -> bool {
+        let pointer_count = match &self.pointer {
+            LoadedPointer::Chat(pointer) => Arc::strong_count(pointer),
+            LoadedPointer::Embedding(pointer) => Arc::strong_count(pointer),
+            LoadedPointer::Rerank(pointer) => Arc::strong_count(pointer),
+        };
+        // Each chat model holds a reference to the pointer
+        pointer_count > 1 + self.chat_models.len()
+            || self
+                .chat_models
+                .values()
+                .any(|model| Arc::strong_count(model) > 1)
+    }
+}
+
+/// The state of a configured model.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ModelState {
+    /// The model is being loaded.
+    Loading,
+    /// The model is in memory.
+    Ready,
+    /// The model failed to load with an error.
+    Failed(String),
+    /// The model isn't in memory.
+    Unloaded,
+}
+
+/// The status of a configured model.
+#[derive(Clone, Debug)]
+pub struct ModelStatus {
+    /// The name of the model.
+    pub name: String,
+    /// The configuration of the model.
+    pub model_config: sauropod_config::ModelConfig,
+    /// The state of the model.
+    pub state: ModelState,
+    /// The estimated number of bytes used by the model while it's in memory.
+    ///
+    /// Names sharing a model report the same memory.
+    pub memory: Option<u64>,
+}
+
+/// An error changing the configured models.
+#[derive(Debug, thiserror::Error)]
+pub enum ModelUpdateError {
+    #[error("Model '{0}' already exists")]
+    AlreadyExists(String),
+    #[error("Model '{0}' not found")]
+    NotFound(String),
+    #[error(transparent)]
+    Failed(#[from] anyhow::Error),
+}
+
+/// The names whose configuration differs between two configurations of models.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ModelChanges {
+    /// The names which are only in the new configuration.
+    pub added: Vec<String>,
+    /// The names whose configuration changed.
+    pub replaced: Vec<String>,
+    /// The names which are only in the previous configuration.
+    pub removed: Vec<String>,
+}
+
+impl ModelChanges {
+    /// Compare the `previous` configured models with new ones.
+    pub fn new(
+        previous: &HashMap<String, sauropod_config::ModelConfig>,
+        models: &HashMap<String, sauropod_config::ModelConfig>,
+    ) -> Self {
+        let mut changes = ModelChanges::default();
+        for (name, model_config) in models {
+            match previous.get(name) {
+                None => changes.added.push(name.clone()),
+                Some(previous_config) if previous_config != model_config => {
+                    changes.replaced.push(name.clone())
+                }
+                Some(_) => {}
+            }
+        }
+        changes.removed = previous
+            .keys()
+            .filter(|name| !models.contains_key(*name))
+            .cloned()
+            .collect();
+        changes.added.sort();
+        changes.replaced.sort();
+        changes.removed.sort();
+        changes
+    }
+
+    /// Whether no names changed.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.replaced.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// The outcome of applying a new configuration of models.
+#[derive(Debug)]
+pub struct ModelsReload {
+    /// The names which changed.
+    pub changes: ModelChanges,
+    /// The new configurations, without the changes of the names which failed to be replaced.
+    pub model_configs: HashMap<String, sauropod_config::ModelConfig>,
+    /// The names which failed to load with their error.
+    pub errors: Vec<(String, ModelUpdateError)>,
+}
+
 /// The internal data of `LoadedModels`.
 struct LoadedModelsInternal {
-    /// Mapping from a model name to the loaded model.
-    model_mapping: HashMap<String, Arc<sauropod_inference_engine::Model>>,
-    /// Mapping from a model source to the loaded model pointer.
+    /// The configured models by name.
+    model_configs: HashMap<String, sauropod_config::ModelConfig>,
+    /// The models held in memory.
+    resident_models: HashMap<ResidentModelKey, ResidentModel>,
+    /// The number of bytes the resident models may use together.
+    memory_budget: Option<u64>,
+    /// The name of the model being loaded.
+    loading_model: Option<String>,
+    /// The errors of the names which failed to load.
+    load_errors: HashMap<String, String>,
+    /// The voices by name.
     tts_models: HashMap<String, Arc<sauropod_tts::ConfiguredTtsThread>>,
+    /// Mapping from a model source to the loaded TTS model, shared by the voices using it.
+    tts_sources: HashMap<ConfigModelSource, Arc<sauropod_tts::TtsThread>>,
+}
+
+impl LoadedModelsInternal {
+    /// The names configured to use a resident model.
+    fn names(&self, key: &ResidentModelKey) -> Vec<&str> {
+        let mut names: Vec<&str> = self
+            .model_configs
+            .iter()
+            .filter(|(_, model_config)| model_config.kind == key.0 && model_config.model == key.1)
+            .map(|(name, _)| name.as_str())
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// The idle timeout of a resident model, which is the longest of the names using it.
+    ///
+    /// A model is never unloaded for being idle if any of its names has no idle timeout.
+    fn idle_timeout(&self, key: &ResidentModelKey) -> Option<std::time::Duration> {
+        let mut idle_timeout = None;
+        for model_config in self
+            .model_configs
+            .values()
+            .filter(|model_config| model_config.kind == key.0 && model_config.model == key.1)
+        {
+            let seconds = model_config.idle_timeout_seconds?;
+            idle_timeout = Some(idle_timeout.map_or(seconds, |timeout: u64| timeout.max(seconds)));
+        }
+        idle_timeout.map(std::time::Duration::from_secs)
+    }
+
+    /// Unload a resident model.
+    fn unload(&mut self, key: &ResidentModelKey, reason: &str) {
+        tracing::info!("Unloading {} {reason}", self.names(key).join(", "));
+        // The inference thread exits and frees the model once its requests finish
+        self.resident_models.remove(key);
+    }
+
+    /// Unload the models which weren't requested within their idle timeout.
+    fn unload_idle_models(&mut self) {
+        let now = std::time::Instant::now();
+        let idle_models: Vec<ResidentModelKey> = self
+            .resident_models
+            .iter()
+            .filter(|(key, resident_model)| {
+                self.idle_timeout(key).is_some_and(|idle_timeout| {
+                    now.duration_since(resident_model.last_used()) >= idle_timeout
+                }) && !resident_model.in_use()
+            })
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in idle_models {
+            self.unload(&key, "after its idle timeout");
+        }
+    }
+
+    /// Unload the least recently used models until `size` more bytes fit in the memory budget,
+    /// and return whether any model was unloaded.
+    ///
+    /// Models which are in use are kept, so the budget may be exceeded.
+    fn make_room(&mut self, size: u64) -> bool {
+        let Some(memory_budget) = self.memory_budget else {
+            return false;
+        };
+        let mut unloaded = false;
+        loop {
+            let used: u64 = self.resident_models.values().map(|model| model.size).sum();
+            if used + size <= memory_budget {
+                return unloaded;
+            }
+
+            let least_recently_used = self
+                .resident_models
+                .iter()
+                .filter(|(_, resident_model)| !resident_model.in_use())
+                .min_by_key(|(_, resident_model)| resident_model.last_used())
+                .map(|(key, _)| key.clone());
+            let Some(key) = least_recently_used else {
+                tracing::warn!(
+                    "Exceeding the model memory budget of {} MiB because the loaded models are in use",
+                    memory_budget / memory::MIB
+                );
+                return unloaded;
+            };
+            self.unload(&key, "to stay within the model memory budget");
+            unloaded = true;
+        }
+    }
 }
 
 /// Loaded model state for Axum.
@@ -19,215 +281,558 @@ pub struct LoadedModels {
     ///
     /// The state needs to be copyable so it's stored as an `Arc`.
     internal: Arc<tokio::sync::RwLock<LoadedModelsInternal>>,
+    /// Held while loading a model so that a model is loaded once and within the memory budget.
+    loading: Arc<tokio::sync::Mutex<()>>,
     /// The ONNX Runtime environment.
     pub onnxruntime_env: Arc<sauropod_onnxruntime::Env>,
-    /// VAD model.
-    pub vad_model: Arc<sauropod_vad::VadThread>,
-    /// STT model.
-    pub stt_model: Option<Arc<sauropod_stt::SttThread>>,
+    /// VAD model, which is replaced when the configuration is reloaded.
+    vad_model: Arc<std::sync::RwLock<Arc<sauropod_vad::VadThread>>>,
+    /// STT model, which is replaced when the configuration is reloaded.
+    stt_model: Arc<std::sync::RwLock<Option<Arc<sauropod_stt::SttThread>>>>,
 }
 
 impl LoadedModels {
     /// Create a new `LoadedModels` instance.
     pub async fn new(config: &sauropod_config::Config) -> anyhow::Result<LoadedModels> {
-        // We might have multiple models from the same source (to create aliases) so we need to track those
-        let mut name_to_model =
-            HashMap::<String, Arc<sauropod_inference_engine::Model>>::with_capacity(2);
-
         let onnxruntime_env = Arc::new(sauropod_onnxruntime::Env::new("sauropod")?);
 
-        // Download VAD, STT, and TTS models
-        let vad_model_dir =
-            sauropod_vad::download_from_huggingface(config.vad_model.as_ref().unwrap())
-                .instrument(tracing::info_span!("download VAD model"))
-                .await?;
+        let vad_model = load_vad_model(
+            &onnxruntime_env,
+            config
+                .vad_model
+                .as_ref()
+                .context("No VAD model is configured")?,
+        )
+        .await?;
+        let stt_model = match config.stt_model.as_ref() {
+            Some(stt_model) => Some(load_stt_model(&onnxruntime_env, stt_model).await?),
+            None => None,
+        };
+        let (tts_models, tts_sources) =
+            load_voices(&onnxruntime_env, &config.voices, &HashMap::new()).await?;
 
-        // Load VAD model
-        let vad_model = sauropod_vad::make_vad_thread(&onnxruntime_env, &vad_model_dir)
-            .instrument(tracing::info_span!("load VAD model"))
-            .await?;
-        if let Err(e) = vad_model
-            .enqueue(vec![
-                0.0f32;
-                sauropod_vad::Vad::CONTEXT_FRAMES
-                    * sauropod_vad::Vad::FRAME_SIZE
-            ])
-            .instrument(tracing::info_span!("Warm up VAD"))
-            .await
+        if config.models.is_empty() {
+            tracing::warn!(
+                "No models configured - you may be missing the models section in your config file."
+            );
+        }
+        let internal = Arc::new(tokio::sync::RwLock::new(LoadedModelsInternal {
+            model_configs: config.models.clone(),
+            resident_models: HashMap::new(),
+            memory_budget: config
+                .model_memory_budget_mib
+                .map(|budget| budget * memory::MIB),
+            loading_model: None,
+            load_errors: HashMap::new(),
+            tts_models,
+            tts_sources,
+        }));
+        let loaded_models = LoadedModels {
+            internal,
+            loading: Arc::new(tokio::sync::Mutex::new(())),
+            onnxruntime_env,
+            vad_model: Arc::new(std::sync::RwLock::new(vad_model)),
+            stt_model: Arc::new(std::sync::RwLock::new(stt_model)),
+        };
+
+        // Load the models which aren't loaded on their first request
         {
-            tracing::warn!("Failed to warm up VAD model: {e:?}");
+            let _loading = loaded_models.loading.lock().await;
+            for (name, model_config) in &config.models {
+                if !model_config.lazy {
+                    loaded_models
+                        .track_load(name, loaded_models.load(name, model_config, true))
+                        .await?;
+                }
+            }
         }
 
-        // Load STT
-        let stt_model = if let Some(stt_model) = config.stt_model.as_ref() {
-            let stt_model = sauropod_stt::make_stt_thread(&onnxruntime_env, stt_model)
-                .instrument(tracing::info_span!("load STT model"))
-                .await?;
-            if let Err(e) = stt_model
-                .enqueue(vec![0.0f32; 16000])
-                .instrument(tracing::info_span!("Warm up STT"))
-                .await
+        tokio::spawn(unload_idle_models(Arc::downgrade(&loaded_models.internal)));
+        Ok(loaded_models)
+    }
+
+    /// Get the model of a configured name, loading it if it isn't in memory.
+    ///
+    /// `select` picks the model for the name from the resident model. Errors while loading are
+    /// logged and return `None`.
+    async fn get_or_load<T>(
+        &self,
+        model_name: &str,
+        kind: ModelKind,
+        select: impl Fn(&ResidentModel, &str) -> Option<T>,
+    ) -> Option<T> {
+        {
+            let internal = self.internal.read().await;
+            let model_config = internal
+                .model_configs
+                .get(model_name)
+                .filter(|model_config| model_config.kind == kind)?;
+            if let Some(resident_model) = internal
+                .resident_models
+                .get(&(kind, model_config.model.clone()))
+                && let Some(model) = select(resident_model, model_name)
             {
-                tracing::warn!("Failed to warm up STT model: {e:?}");
+                resident_model.touch();
+                return Some(model);
             }
-            Some(Arc::new(stt_model))
-        } else {
-            None
-        };
+        }
 
-        // Load LLM models
-        let mut source_to_model_pointer =
-            HashMap::<ConfigModelSource, sauropod_inference_engine::ModelPointer>::with_capacity(2);
-        if config.models.is_empty() {
-            tracing::warn!(
-                "No models configured - you may be missing the models section in your config file."
-            );
+        let _loading = self.loading.lock().await;
+        // The configuration may have been replaced while waiting
+        let model_config = self
+            .internal
+            .read()
+            .await
+            .model_configs
+            .get(model_name)
+            .filter(|model_config| model_config.kind == kind)?
+            .clone();
+        if let Err(e) = self
+            .track_load(model_name, self.load(model_name, &model_config, false))
+            .await
+        {
+            tracing::error!("Failed to load {model_name}: {e:?}");
+            return None;
         }
-        for (alias, model_config) in &config.models {
-            let pointer = get_or_create(&mut source_to_model_pointer, &model_config.model, {
-                let model_source = model_config.model.clone();
-                let alias = alias.clone();
-                let model_config = model_config.clone();
-                async move || {
-                    let model_path = sauropod_inference_engine::get_model_path(&model_source)
-                        .await
-                        .context(format!("Failed to get model path for {alias}"))?;
+        let internal = self.internal.read().await;
+        let resident_model = internal
+            .resident_models
+            .get(&(kind, model_config.model.clone()))?;
+        resident_model.touch();
+        select(resident_model, model_name)
+    }
 
-                    let llm_model = sauropod_inference_engine::load_model(
-                        alias.to_string(),
-                        &model_path,
-                        model_config.multimodal_projector.as_ref(),
-                    )
+    /// Record that `name` is loading while `load` runs, and whether it failed.
+    async fn track_load<T>(
+        &self,
+        name: &str,
+        load: impl Future<Output = anyhow::Result<T>>,
+    ) -> anyhow::Result<T> {
+        self.internal.write().await.loading_model = Some(name.to_string());
+        let result = load.await;
+
+        let mut internal = self.internal.write().await;
+        internal.loading_model = None;
+        match &result {
+            Ok(_) => {
+                internal.load_errors.remove(name);
+            }
+            Err(e) => {
+                internal
+                    .load_errors
+                    .insert(name.to_string(), format!("{e:#}"));
+            }
+        }
+        result
+    }
+
+    /// Load the model of a configured name unless it's in memory.
+    ///
+    /// Must be called while holding `loading`.
+    async fn load(
+        &self,
+        name: &str,
+        model_config: &sauropod_config::ModelConfig,
+        warm_up: bool,
+    ) -> anyhow::Result<()> {
+        let key = (model_config.kind, model_config.model.clone());
+        let resident = {
+            let internal = self.internal.read().await;
+            internal.resident_models.get(&key).map(|resident_model| {
+                (
+                    resident_model.pointer.clone(),
+                    resident_model.chat_models.contains_key(name),
+                )
+            })
+        };
+        match resident {
+            None => {
+                let resident_model = self
+                    .load_resident_model(name, model_config, warm_up)
+                    .await?;
+                self.internal
+                    .write()
                     .await
-                    .context(format!("Failed to load model for {alias}"))?;
-
-                    let temporary_model = Arc::new(sauropod_inference_engine::Model::new(
-                        llm_model.clone(),
-                        model_config.clone(),
-                    )?);
-
-                    // Warm up the model by generating a response
-                    let request = sauropod_openai_api::CreateResponse {
-                        input: Some(sauropod_openai_api::CreateResponseInput::Variant0(
-                            "Hello".to_string(),
-                        )),
-                        ..sauropod_openai_api::CreateResponse::default()
-                    };
-                    let render_context =
-                        sauropod_prompt_templates::RenderContext::from_create_response(
-                            &request, None,
-                        )?;
-                    match temporary_model
-                        .generate(request, render_context)
-                        .instrument(tracing::info_span!("Warm up model", alias = alias))
-                        .await
-                    {
-                        Ok(response) => {
-                            tracing::debug!("Warm up response was {response:#?}");
-                        }
-                        Err(e) => tracing::warn!("Failed to warm up model {alias}: {e:?}"),
-                    }
+                    .resident_models
+                    .insert(key, resident_model);
+            }
+            Some((LoadedPointer::Chat(pointer), false)) => {
+                let model = create_chat_model(name, pointer, model_config).await?;
+                if let Some(resident_model) =
+                    self.internal.write().await.resident_models.get_mut(&key)
+                {
+                    resident_model.chat_models.insert(name.to_string(), model);
+                }
+            }
+            Some(_) => {}
+        }
+        Ok(())
+    }
+
+    /// Load a new instance of a model, making room for it in the memory budget.
+    ///
+    /// Other models are only unloaded once the model has loaded, unless it fails to load while
+    /// they exceed the budget, in which case they're unloaded before trying again.
+    ///
+    /// Must be called while holding `loading`.
+    async fn load_resident_model(
+        &self,
+        name: &str,
+        model_config: &sauropod_config::ModelConfig,
+        warm_up: bool,
+    ) -> anyhow::Result<ResidentModel> {
+        let model_path = sauropod_inference_engine::get_model_path(&model_config.model)
+            .await
+            .context(format!("Failed to get model path for {name}"))?;
+        let draft_model_path = match &model_config.draft_model {
+            Some(draft_model_source) => Some(
+                sauropod_inference_engine::get_model_path(draft_model_source)
+                    .await
+                    .context(format!("Failed to get draft model path for {name}"))?,
+            ),
+            None => None,
+        };
 
-                    Ok(llm_model)
+        let size =
+            memory::estimate_model_size(model_config, &model_path, draft_model_path.as_ref()).await;
+
+        // Loading blocks on the inference engine, so it runs outside of the async runtime
+        let load_blocking = || {
+            let task = tokio::task::spawn_blocking({
+                let name = name.to_string();
+                let model_config = model_config.clone();
+                let model_path = model_path.clone();
+                let draft_model_path = draft_model_path.clone();
+                move || {
+                    tokio::runtime::Handle::current().block_on(load_pointer(
+                        &name,
+                        &model_config,
+                        &model_path,
+                        draft_model_path.as_ref(),
+                        warm_up,
+                    ))
                 }
-            })
-            .await?;
+            });
+            async move { task.await.context(format!("Failed to load {name}"))? }
+        };
+        let pointer = match load_blocking().await {
+            Ok(pointer) => pointer,
+            // The memory of the models over the budget may be what the model is missing
+            Err(e) if self.internal.write().await.make_room(size) => {
+                tracing::warn!("Failed to load {name}, trying again after unloading models: {e:#}");
+                load_blocking().await?
+            }
+            Err(e) => return Err(e),
+        };
+        tracing::info!(
+            "Loaded {name} with an estimated size of {} MiB",
+            size / memory::MIB
+        );
 
-            name_to_model.insert(
-                alias.clone(),
-                Arc::new(sauropod_inference_engine::Model::new(
-                    pointer,
-                    model_config.clone(),
-                )?),
-            );
+        let mut resident_model = ResidentModel::new(pointer.clone(), size);
+        if let LoadedPointer::Chat(pointer) = pointer {
+            let model = create_chat_model(name, pointer, model_config).await?;
+            resident_model.chat_models.insert(name.to_string(), model);
         }
+        self.internal.write().await.make_room(size);
+        Ok(resident_model)
+    }
 
-        let mut source_to_tts_pointer: HashMap<ConfigModelSource, Arc<sauropod_tts::TtsThread>> =
-            HashMap::new();
-        let mut tts_models: HashMap<String, Arc<sauropod_tts::ConfiguredTtsThread>> =
-            HashMap::new();
-        for (alias, voice_config) in &config.voices {
-            let model = match &voice_config {
-                sauropod_config::VoiceConfig::Kokoro {
-                    voice,
-                    model: model_source,
-                    ..
-                } => {
-                    get_or_create(&mut source_to_tts_pointer, model_source, async || {
-                        let model_dir = match &model_source {
-                            ConfigModelSource::HuggingFace(repo) => {
-                                sauropod_tts::kokoro::download_from_huggingface(repo).await?
-                            }
-                            ConfigModelSource::LocalPath(dir) => std::path::PathBuf::from(dir),
-                        };
-                        let model =
-                            sauropod_tts::kokoro::make_tts_thread(&onnxruntime_env, &model_dir)
-                                .await?;
-                        let mut receiver = model
-                            .enqueue("Hi.".to_string(), Some(voice.clone()))
-                            .instrument(tracing::info_span!("Warm up Kokoro TTS"))
-                            .await
-                            .context("Failed to warm up Kokoro TTS")?;
-                        while let Some(msg) = receiver.recv().await {
-                            if msg.is_err() {
-                                tracing::warn!("Error in Kokoro TTS warm up: {:?}", msg);
-                            }
-                        }
-                        Ok(model)
-                    })
-                    .await?
-                }
-                sauropod_config::VoiceConfig::Orpheus {
-                    model: model_source,
-                    ..
-                } => {
-                    get_or_create(&mut source_to_tts_pointer, model_source, async || {
-                        let model =
-                            sauropod_tts::orpheus::make_tts_thread(&onnxruntime_env, model_source)
-                                .await?;
-                        let mut receiver = model
-                            .enqueue("Hi.".to_string(), None)
-                            .instrument(tracing::info_span!("Warm up Orpheus TTS"))
-                            .await
-                            .context("Failed to warm up Orpheus TTS")?;
-                        while let Some(msg) = receiver.recv().await {
-                            if msg.is_err() {
-                                tracing::warn!("Error in Kokoro TTS warm up: {:?}", msg);
-                            }
+    /// Add a model under a new name, loading it unless it's lazy.
+    ///
+    /// The model stays configured if loading fails, and is reported as failed.
+    pub async fn add_model(
+        &self,
+        name: &str,
+        model_config: sauropod_config::ModelConfig,
+    ) -> Result<(), ModelUpdateError> {
+        let _loading = self.loading.lock().await;
+        {
+            let mut internal = self.internal.write().await;
+            if internal.model_configs.contains_key(name) {
+                return Err(ModelUpdateError::AlreadyExists(name.to_string()));
+            }
+            internal
+                .model_configs
+                .insert(name.to_string(), model_config.clone());
+        }
+
+        if !model_config.lazy {
+            self.track_load(name, self.load(name, &model_config, false))
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Replace the configuration of a name, such as to serve it from a different source.
+    ///
+    /// A new instance is loaded before the name switches to it, so requests are served without
+    /// interruption. Requests in flight finish on the previous instance, which is unloaded
+    /// afterwards unless other names use it. Other names sharing the source of the new
+    /// configuration move to the new instance.
+    pub async fn replace_model(
+        &self,
+        name: &str,
+        model_config: sauropod_config::ModelConfig,
+    ) -> Result<(), ModelUpdateError> {
+        let _loading = self.loading.lock().await;
+        let Some(previous_config) = self.internal.read().await.model_configs.get(name).cloned()
+        else {
+            return Err(ModelUpdateError::NotFound(name.to_string()));
+        };
+
+        let resident_model = if model_config.lazy {
+            None
+        } else {
+            Some(
+                self.track_load(name, self.load_resident_model(name, &model_config, false))
+                    .await?,
+            )
+        };
+
+        let mut internal = self.internal.write().await;
+        let key = (model_config.kind, model_config.model.clone());
+        let previous_key = (previous_config.kind, previous_config.model.clone());
+        internal
+            .model_configs
+            .insert(name.to_string(), model_config);
+        match resident_model {
+            Some(resident_model) => {
+                internal.resident_models.insert(key.clone(), resident_model);
+            }
+            // Settings of a lazy model apply when it's loaded again
+            None if key == previous_key => {
+                internal.resident_models.remove(&key);
+            }
+            None => {}
+        }
+        if previous_key != key && internal.names(&previous_key).is_empty() {
+            internal.unload(&previous_key, "after it was replaced");
+        }
+        Ok(())
+    }
+
+    /// Remove a configured name, unloading its model unless other names use it.
+    pub async fn remove_model(&self, name: &str) -> Result<(), ModelUpdateError> {
+        let _loading = self.loading.lock().await;
+        let mut internal = self.internal.write().await;
+        let Some(model_config) = internal.model_configs.remove(name) else {
+            return Err(ModelUpdateError::NotFound(name.to_string()));
+        };
+        internal.load_errors.remove(name);
+        let key = (model_config.kind, model_config.model);
+        if !internal.names(&key).is_empty() {
+            if let Some(resident_model) = internal.resident_models.get_mut(&key) {
+                resident_model.chat_models.remove(name);
+            }
+        } else if internal.resident_models.contains_key(&key) {
+            tracing::info!("Unloading {name} after it was removed");
+            internal.resident_models.remove(&key);
+        }
+        Ok(())
+    }
+
+    /// Apply the changes from the `previous` configured models to new ones.
+    ///
+    /// Each name is added, replaced or removed on its own, so a name which fails to load
+    /// doesn't affect the others. A name which fails to be replaced keeps its previous
+    /// configuration, and a new name which fails stays configured as failed like with
+    /// `add_model`.
+    pub async fn reload_models(
+        &self,
+        previous: &HashMap<String, sauropod_config::ModelConfig>,
+        models: &HashMap<String, sauropod_config::ModelConfig>,
+    ) -> ModelsReload {
+        let changes = ModelChanges::new(previous, models);
+        let mut model_configs = models.clone();
+        let mut errors = Vec::new();
+
+        for name in &changes.removed {
+            match self.remove_model(name).await {
+                // The model may have been replaced through the admin API
+                Ok(()) | Err(ModelUpdateError::NotFound(_)) => {}
+                Err(e) => errors.push((name.clone(), e)),
+            }
+        }
+        for name in changes.added.iter().chain(&changes.replaced) {
+            let model_config = models[name].clone();
+            // Names may also have been added or unloaded through the admin API
+            let configured = self.internal.read().await.model_configs.contains_key(name);
+            let result = if configured {
+                self.replace_model(name, model_config).await
+            } else {
+                self.add_model(name, model_config).await
+            };
+            if let Err(e) = result {
+                // The name keeps the configuration it had, and is retried by the next reload
+                if configured {
+                    match previous.get(name) {
+                        Some(previous_config) => {
+                            model_configs.insert(name.clone(), previous_config.clone())
                         }
-                        Ok(model)
-                    })
-                    .await?
+                        None => model_configs.remove(name),
+                    };
                 }
-            };
-            tts_models.insert(
-                alias.clone(),
-                sauropod_tts::ConfiguredTtsThread::new(
-                    model,
-                    voice_config.get_voice().map(|x| x.to_string()),
-                ),
-            );
+                errors.push((name.clone(), e));
+            }
         }
 
-        let internal = Arc::new(tokio::sync::RwLock::new(LoadedModelsInternal {
-            model_mapping: name_to_model,
-            tts_models,
-        }));
-        Ok(LoadedModels {
-            internal,
-            onnxruntime_env,
-            vad_model: Arc::new(vad_model),
-            stt_model,
+        ModelsReload {
+            changes,
+            model_configs,
+            errors,
+        }
+    }
+
+    /// Load the VAD, STT and voice models which differ between the `previous` configuration and
+    /// a new one, and switch to them.
+    ///
+    /// The running models are kept if any of them fails to load. Requests in flight finish on
+    /// the previous models.
+    pub async fn reload_audio_models(
+        &self,
+        previous: &sauropod_config::Config,
+        config: &sauropod_config::Config,
+    ) -> anyhow::Result<()> {
+        let existing_sources = self.internal.read().await.tts_sources.clone();
+        // Loading blocks on the inference engine, so it runs outside of the async runtime
+        let changed = tokio::task::spawn_blocking({
+            let onnxruntime_env = self.onnxruntime_env.clone();
+            let previous = previous.clone();
+            let config = config.clone();
+            move || {
+                tokio::runtime::Handle::current().block_on(load_changed_audio_models(
+                    &onnxruntime_env,
+                    &previous,
+                    &config,
+                    &existing_sources,
+                ))
+            }
         })
+        .await
+        .context("Failed to load the audio models")??;
+
+        if let Some(vad_model) = changed.vad_model {
+            *self.vad_model.write().unwrap() = vad_model;
+        }
+        if let Some(stt_model) = changed.stt_model {
+            *self.stt_model.write().unwrap() = stt_model;
+        }
+        if let Some((tts_models, tts_sources)) = changed.voices {
+            let mut internal = self.internal.write().await;
+            internal.tts_models = tts_models;
+            internal.tts_sources = tts_sources;
+        }
+        Ok(())
     }
 
-    /// Get a loaded model by name.
+    /// Unload the model of a name, which is loaded again by its next request.
+    ///
+    /// Other names using the same model are unloaded too.
+    pub async fn unload_model(&self, name: &str) -> Result<(), ModelUpdateError> {
+        let _loading = self.loading.lock().await;
+        let mut internal = self.internal.write().await;
+        let Some(model_config) = internal.model_configs.get(name) else {
+            return Err(ModelUpdateError::NotFound(name.to_string()));
+        };
+        let key = (model_config.kind, model_config.model.clone());
+        internal.load_errors.remove(name);
+        if internal.resident_models.contains_key(&key) {
+            internal.unload(&key, "on request");
+        }
+        Ok(())
+    }
+
+    /// Get the status of every configured model, ordered by name.
+    pub async fn get_model_statuses(&self) -> Vec<ModelStatus> {
+        let internal = self.internal.read().await;
+        let mut statuses: Vec<ModelStatus> = internal
+            .model_configs
+            .iter()
+            .map(|(name, model_config)| {
+                let resident_model = internal
+                    .resident_models
+                    .get(&(model_config.kind, model_config.model.clone()));
+                let state = if internal.loading_model.as_deref() == Some(name.as_str()) {
+                    ModelState::Loading
+                } else if resident_model.is_some() {
+                    ModelState::Ready
+                } else if let Some(error) = internal.load_errors.get(name) {
+                    ModelState::Failed(error.clone())
+                } else {
+                    ModelState::Unloaded
+                };
+                ModelStatus {
+                    name: name.clone(),
+                    model_config: model_config.clone(),
+                    state,
+                    memory: resident_model.map(|resident_model| resident_model.size),
+                }
+            })
+            .collect();
+        statuses.sort_by(|a, b| a.name.cmp(&b.name));
+        statuses
+    }
+
+    /// Get a chat model by name, loading it if needed.
     pub async fn get_model(
         &self,
         model_name: &str,
     ) -> Option<Arc<sauropod_inference_engine::Model>> {
+        self.get_or_load(model_name, ModelKind::Chat, |resident_model, name| {
+            resident_model.chat_models.get(name).cloned()
+        })
+        .await
+    }
+
+    /// Get an embedding model by name, loading it if needed.
+    pub async fn get_embedding_model(
+        &self,
+        model_name: &str,
+    ) -> Option<sauropod_inference_engine::EmbeddingModelPointer> {
+        self.get_or_load(
+            model_name,
+            ModelKind::Embedding,
+            |resident_model, _| match &resident_model.pointer {
+                LoadedPointer::Embedding(pointer) => Some(pointer.clone()),
+                _ => None,
+            },
+        )
+        .await
+    }
+
+    /// Get a reranking model by name, loading it if needed.
+    pub async fn get_rerank_model(
+        &self,
+        model_name: &str,
+    ) -> Option<sauropod_inference_engine::RerankModelPointer> {
+        self.get_or_load(
+            model_name,
+            ModelKind::Rerank,
+            |resident_model, _| match &resident_model.pointer {
+                LoadedPointer::Rerank(pointer) => Some(pointer.clone()),
+                _ => None,
+            },
+        )
+        .await
+    }
+
+    /// Get the names of all the configured models, whether or not they're loaded.
+    pub async fn get_model_names(&self) -> Vec<String> {
         let internal = self.internal.read().await;
-        internal.model_mapping.get(model_name).cloned()
+        let mut names: Vec<String> = internal.model_configs.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// Get the VAD model.
+    pub fn vad_model(&self) -> Arc<sauropod_vad::VadThread> {
+        self.vad_model.read().unwrap().clone()
+    }
+
+    /// Get the STT model, if one is configured.
+    pub fn stt_model(&self) -> Option<Arc<sauropod_stt::SttThread>> {
+        self.stt_model.read().unwrap().clone()
     }
 
     /// Get a loaded voice model by name.
@@ -238,16 +843,307 @@ impl LoadedModels {
         let internal = self.internal.read().await;
         internal.tts_models.get(model_name).cloned()
     }
+}
 
-    /// Get all the loaded models.
-    pub async fn get_all_models(
-        &self,
-    ) -> tokio::sync::RwLockReadGuard<'_, HashMap<String, Arc<sauropod_inference_engine::Model>>>
-    {
-        tokio::sync::RwLockReadGuard::map(self.internal.read().await, |internal| {
-            &internal.model_mapping
+/// Periodically unload the models which exceeded their idle timeout until the models are dropped.
+async fn unload_idle_models(internal: std::sync::Weak<tokio::sync::RwLock<LoadedModelsInternal>>) {
+    let mut interval = tokio::time::interval(IDLE_CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        let Some(internal) = internal.upgrade() else {
+            break;
+        };
+        internal.write().await.unload_idle_models();
+    }
+}
+
+/// Create the chat model of a name, loading the LoRA adapters it configures.
+async fn create_chat_model(
+    name: &str,
+    pointer: sauropod_inference_engine::ModelPointer,
+    model_config: &sauropod_config::ModelConfig,
+) -> anyhow::Result<Arc<sauropod_inference_engine::Model>> {
+    // Names sharing a model may configure different adapters, which are all loaded
+    sauropod_inference_engine::load_lora_adapters(&pointer, model_config)
+        .await
+        .context(format!("Failed to load LoRA adapters for {name}"))?;
+    Ok(Arc::new(sauropod_inference_engine::Model::new(
+        pointer,
+        model_config.clone(),
+    )?))
+}
+
+/// Load a model from its files.
+async fn load_pointer(
+    name: &str,
+    model_config: &sauropod_config::ModelConfig,
+    model_path: &sauropod_inference_engine_api::ModelPath,
+    draft_model_path: Option<&sauropod_inference_engine_api::ModelPath>,
+    warm_up: bool,
+) -> anyhow::Result<LoadedPointer> {
+    match model_config.kind {
+        ModelKind::Embedding => {
+            let model =
+                sauropod_inference_engine::load_embedding_model(name.to_string(), model_path)
+                    .await
+                    .context(format!("Failed to load embedding model for {name}"))?;
+
+            if warm_up
+                && let Err(e) = model
+                    .clone()
+                    .embed(vec![sauropod_inference_engine_api::EmbeddingInput::Text(
+                        "Hello".to_string(),
+                    )])
+                    .instrument(tracing::info_span!("Warm up embedding model", alias = name))
+                    .await
+            {
+                tracing::warn!("Failed to warm up embedding model {name}: {e:?}");
+            }
+            Ok(LoadedPointer::Embedding(model))
+        }
+        ModelKind::Rerank => {
+            let model = sauropod_inference_engine::load_rerank_model(name.to_string(), model_path)
+                .await
+                .context(format!("Failed to load reranking model for {name}"))?;
+
+            if warm_up
+                && let Err(e) = model
+                    .clone()
+                    .rerank("Hello".to_string(), vec!["Hello".to_string()])
+                    .instrument(tracing::info_span!("Warm up reranking model", alias = name))
+                    .await
+            {
+                tracing::warn!("Failed to warm up reranking model {name}: {e:?}");
+            }
+            Ok(LoadedPointer::Rerank(model))
+        }
+        ModelKind::Chat => {
+            let llm_model = sauropod_inference_engine::load_model(
+                name.to_string(),
+                model_path,
+                model_config.multimodal_projector.as_ref(),
+                draft_model_path,
+                &model_config.into(),
+            )
+            .await
+            .context(format!("Failed to load model for {name}"))?;
+
+            if warm_up {
+                let temporary_model = Arc::new(sauropod_inference_engine::Model::new(
+                    llm_model.clone(),
+                    model_config.clone(),
+                )?);
+
+                // Warm up the model by generating a response
+                let request = sauropod_openai_api::CreateResponse {
+                    input: Some(sauropod_openai_api::CreateResponseInput::Variant0(
+                        "Hello".to_string(),
+                    )),
+                    ..sauropod_openai_api::CreateResponse::default()
+                };
+                let render_context =
+                    sauropod_prompt_templates::RenderContext::from_create_response(
+                        &request,
+                        None,
+                        temporary_model.get_model_type(),
+                    )?;
+                match temporary_model
+                    .generate(request, render_context)
+                    .instrument(tracing::info_span!("Warm up model", alias = name))
+                    .await
+                {
+                    Ok(response) => {
+                        tracing::debug!("Warm up response was {response:#?}");
+                    }
+                    Err(e) => tracing::warn!("Failed to warm up model {name}: {e:?}"),
+                }
+            }
+
+            Ok(LoadedPointer::Chat(llm_model))
+        }
+    }
+}
+
+/// The audio models of a new configuration, which are `None` if they didn't change.
+struct ChangedAudioModels {
+    vad_model: Option<Arc<sauropod_vad::VadThread>>,
+    stt_model: Option<Option<Arc<sauropod_stt::SttThread>>>,
+    voices: Option<LoadedVoices>,
+}
+
+/// Load the VAD, STT and voice models which differ between the `previous` configuration and a
+/// new one.
+async fn load_changed_audio_models(
+    onnxruntime_env: &Arc<sauropod_onnxruntime::Env>,
+    previous: &sauropod_config::Config,
+    config: &sauropod_config::Config,
+    existing_sources: &HashMap<ConfigModelSource, Arc<sauropod_tts::TtsThread>>,
+) -> anyhow::Result<ChangedAudioModels> {
+    let vad_model = if config.vad_model != previous.vad_model {
+        let model_source = config
+            .vad_model
+            .as_ref()
+            .context("No VAD model is configured")?;
+        Some(
+            load_vad_model(onnxruntime_env, model_source)
+                .await
+                .context("Failed to load the VAD model")?,
+        )
+    } else {
+        None
+    };
+    let stt_model = if config.stt_model != previous.stt_model {
+        Some(match config.stt_model.as_ref() {
+            Some(stt_model) => Some(
+                load_stt_model(onnxruntime_env, stt_model)
+                    .await
+                    .context("Failed to load the STT model")?,
+            ),
+            None => None,
         })
+    } else {
+        None
+    };
+    let voices = if config.voices != previous.voices {
+        Some(
+            load_voices(onnxruntime_env, &config.voices, existing_sources)
+                .await
+                .context("Failed to load the voices")?,
+        )
+    } else {
+        None
+    };
+    Ok(ChangedAudioModels {
+        vad_model,
+        stt_model,
+        voices,
+    })
+}
+
+/// Download, load and warm up a VAD model.
+async fn load_vad_model(
+    onnxruntime_env: &Arc<sauropod_onnxruntime::Env>,
+    model_source: &ConfigModelSource,
+) -> anyhow::Result<Arc<sauropod_vad::VadThread>> {
+    let vad_model_dir = sauropod_vad::download_from_huggingface(model_source)
+        .instrument(tracing::info_span!("download VAD model"))
+        .await?;
+
+    let vad_model = sauropod_vad::make_vad_thread(onnxruntime_env, &vad_model_dir)
+        .instrument(tracing::info_span!("load VAD model"))
+        .await?;
+    if let Err(e) = vad_model
+        .enqueue(vec![
+            0.0f32;
+            sauropod_vad::Vad::CONTEXT_FRAMES
+                * sauropod_vad::Vad::FRAME_SIZE
+        ])
+        .instrument(tracing::info_span!("Warm up VAD"))
+        .await
+    {
+        tracing::warn!("Failed to warm up VAD model: {e:?}");
+    }
+    Ok(Arc::new(vad_model))
+}
+
+/// Load and warm up an STT model.
+async fn load_stt_model(
+    onnxruntime_env: &Arc<sauropod_onnxruntime::Env>,
+    stt_config: &sauropod_config::SpeechToTextConfig,
+) -> anyhow::Result<Arc<sauropod_stt::SttThread>> {
+    let stt_model = sauropod_stt::make_stt_thread(onnxruntime_env, stt_config)
+        .instrument(tracing::info_span!("load STT model"))
+        .await?;
+    if let Err(e) = stt_model
+        .enqueue(vec![0.0f32; 16000])
+        .instrument(tracing::info_span!("Warm up STT"))
+        .await
+    {
+        tracing::warn!("Failed to warm up STT model: {e:?}");
+    }
+    Ok(Arc::new(stt_model))
+}
+
+/// Load the TTS models of the configured voices.
+///
+/// The models in `existing_sources` are reused instead of being loaded again. Returns the
+/// voices by name and the models they use by source.
+async fn load_voices(
+    onnxruntime_env: &Arc<sauropod_onnxruntime::Env>,
+    voices: &HashMap<String, sauropod_config::VoiceConfig>,
+    existing_sources: &HashMap<ConfigModelSource, Arc<sauropod_tts::TtsThread>>,
+) -> anyhow::Result<LoadedVoices> {
+    let mut source_to_tts_pointer: HashMap<ConfigModelSource, Arc<sauropod_tts::TtsThread>> =
+        HashMap::new();
+    let mut tts_models: HashMap<String, Arc<sauropod_tts::ConfiguredTtsThread>> = HashMap::new();
+    for (alias, voice_config) in voices {
+        let model = match &voice_config {
+            sauropod_config::VoiceConfig::Kokoro {
+                voice,
+                model: model_source,
+                ..
+            } => {
+                get_or_create(&mut source_to_tts_pointer, model_source, async || {
+                    if let Some(model) = existing_sources.get(model_source) {
+                        return Ok(model.clone());
+                    }
+                    let model_dir = match &model_source {
+                        ConfigModelSource::HuggingFace(repo) => {
+                            sauropod_tts::kokoro::download_from_huggingface(repo).await?
+                        }
+                        ConfigModelSource::LocalPath(dir) => std::path::PathBuf::from(dir),
+                    };
+                    let model =
+                        sauropod_tts::kokoro::make_tts_thread(onnxruntime_env, &model_dir).await?;
+                    let mut receiver = model
+                        .enqueue("Hi.".to_string(), Some(voice.clone()))
+                        .instrument(tracing::info_span!("Warm up Kokoro TTS"))
+                        .await
+                        .context("Failed to warm up Kokoro TTS")?;
+                    while let Some(msg) = receiver.recv().await {
+                        if msg.is_err() {
+                            tracing::warn!("Error in Kokoro TTS warm up: {:?}", msg);
+                        }
+                    }
+                    Ok(model)
+                })
+                .await?
+            }
+            sauropod_config::VoiceConfig::Orpheus {
+                model: model_source,
+                ..
+            } => {
+                get_or_create(&mut source_to_tts_pointer, model_source, async || {
+                    if let Some(model) = existing_sources.get(model_source) {
+                        return Ok(model.clone());
+                    }
+                    let model =
+                        sauropod_tts::orpheus::make_tts_thread(onnxruntime_env, model_source)
+                            .await?;
+                    let mut receiver = model
+                        .enqueue("Hi.".to_string(), None)
+                        .instrument(tracing::info_span!("Warm up Orpheus TTS"))
+                        .await
+                        .context("Failed to warm up Orpheus TTS")?;
+                    while let Some(msg) = receiver.recv().await {
+                        if msg.is_err() {
+                            tracing::warn!("Error in Kokoro TTS warm up: {:?}", msg);
+                        }
+                    }
+                    Ok(model)
+                })
+                .await?
+            }
+        };
+        tts_models.insert(
+            alias.clone(),
+            sauropod_tts::ConfiguredTtsThread::new(
+                model,
+                voice_config.get_voice().map(|x| x.to_string()),
+            ),
+        );
     }
+    Ok((tts_models, source_to_tts_pointer))
 }
 
 async fn get_or_create<T>(
@@ -266,3 +1162,37 @@ where
         Ok(value)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn model_config(model: &str) -> sauropod_config::ModelConfig {
+        serde_json::from_value(serde_json::json!({ "model": model })).unwrap()
+    }
+
+    #[test]
+    fn test_model_changes() {
+        let previous = HashMap::from([
+            ("a".to_string(), model_config("a.gguf")),
+            ("b".to_string(), model_config("b.gguf")),
+            ("c".to_string(), model_config("c.gguf")),
+        ]);
+        assert!(ModelChanges::new(&previous, &previous).is_empty());
+
+        let models = HashMap::from([
+            ("a".to_string(), model_config("a.gguf")),
+            ("b".to_string(), model_config("b2.gguf")),
+            ("e".to_string(), model_config("e.gguf")),
+            ("d".to_string(), model_config("d.gguf")),
+        ]);
+        assert_eq!(
+            ModelChanges::new(&previous, &models),
+            ModelChanges {
+                added: vec!["d".to_string(), "e".to_string()],
+                replaced: vec!["b".to_string()],
+                removed: vec!["c".to_string()],
+            }
+        );
+    }
+}
@@ -0,0 +1,223 @@
+//! Estimates of the memory used by loaded models.
+
+use std::collections::HashMap;
+
+/// The number of bytes in a MiB.
+pub(crate) const MIB: u64 = 1024 * 1024;
+
+/// The shape of a model's attention layers, read from its GGUF metadata.
+#[derive(Debug, Default, PartialEq)]
+struct AttentionShape {
+    /// The number of layers.
+    block_count: Option<u64>,
+    /// The size of the hidden state.
+    embedding_length: Option<u64>,
+    /// The number of query heads.
+    head_count: Option<u64>,
+    /// The number of key and value heads, which is smaller than `head_count` with grouped-query
+    /// attention.
+    head_count_kv: Option<u64>,
+    /// The size of each key head if it isn't `embedding_length / head_count`.
+    key_length: Option<u64>,
+    /// The size of each value head if it isn't `embedding_length / head_count`.
+    value_length: Option<u64>,
+    /// The context size the model was trained with.
+    context_length: Option<u64>,
+}
+
+impl AttentionShape {
+    /// Read the shape from GGUF metadata.
+    async fn from_reader(
+        reader: impl tokio::io::AsyncRead + Unpin,
+    ) -> Result<Self, sauropod_gguf::GgufError> {
+        let mut parser = sauropod_gguf::GgufMetadataParser::new(reader).await?;
+        let mut architecture = None;
+        let mut values = HashMap::new();
+        while let Some(entry) = parser.get_next().await? {
+            let value = match entry.value {
+                sauropod_gguf::GgufValue::String(value)
+                    if entry.key == sauropod_gguf::ARCHITECTURE_KEY =>
+                {
+                    architecture = Some(value);
+                    continue;
+                }
+                sauropod_gguf::GgufValue::UInt32(value) => value as u64,
+                sauropod_gguf::GgufValue::UInt64(value) => value,
+                sauropod_gguf::GgufValue::Int32(value) if value >= 0 => value as u64,
+                _ => continue,
+            };
+            values.insert(entry.key, value);
+        }
+
+        let Some(architecture) = architecture else {
+            return Ok(Self::default());
+        };
+        let get = |key: &str| values.get(&format!("{architecture}.{key}")).copied();
+        Ok(Self {
+            block_count: get("block_count"),
+            embedding_length: get("embedding_length"),
+            head_count: get("attention.head_count"),
+            head_count_kv: get("attention.head_count_kv"),
+            key_length: get("attention.key_length"),
+            value_length: get("attention.value_length"),
+            context_length: get("context_length"),
+        })
+    }
+
+    /// The number of bytes used by the KV cache, or 0 if the metadata doesn't describe it.
+    fn kv_cache_bytes(
+        &self,
+        context_size: Option<u32>,
+        kv_cache_type: Option<sauropod_config::KvCacheType>,
+    ) -> u64 {
+        let (Some(block_count), Some(embedding_length), Some(head_count)) =
+            (self.block_count, self.embedding_length, self.head_count)
+        else {
+            return 0;
+        };
+        let Some(context_size) = context_size.map(u64::from).or(self.context_length) else {
+            return 0;
+        };
+        let head_length = embedding_length / head_count.max(1);
+        let key_length = self.key_length.unwrap_or(head_length);
+        let value_length = self.value_length.unwrap_or(head_length);
+        let head_count_kv = self.head_count_kv.unwrap_or(head_count);
+
+        let elements = block_count * context_size * head_count_kv * (key_length + value_length);
+        // The quantized types store blocks of 32 values with a 16-bit scale
+        match kv_cache_type {
+            None | Some(sauropod_config::KvCacheType::F16) => elements * 2,
+            Some(sauropod_config::KvCacheType::Q8_0) => elements * 34 / 32,
+            Some(sauropod_config::KvCacheType::Q4_0) => elements * 18 / 32,
+        }
+    }
+}
+
+/// The size of a file, or of the files directly inside a directory.
+async fn file_size(path: &std::path::Path) -> std::io::Result<u64> {
+    let metadata = tokio::fs::metadata(path).await?;
+    if !metadata.is_dir() {
+        return Ok(metadata.len());
+    }
+
+    let mut size = 0;
+    let mut entries = tokio::fs::read_dir(path).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let metadata = entry.metadata().await?;
+        if metadata.is_file() {
+            size += metadata.len();
+        }
+    }
+    Ok(size)
+}
+
+/// Estimate the bytes used by a model file, including its KV cache if `kv_cache` is set.
+async fn estimate_file(
+    model_path: &sauropod_inference_engine_api::ModelPath,
+    model_config: &sauropod_config::ModelConfig,
+    kv_cache: bool,
+) -> u64 {
+    let path = model_path.as_path();
+    let mut size = file_size(path).await.unwrap_or_else(|e| {
+        tracing::warn!("Failed to get the size of {}: {e}", path.display());
+        0
+    });
+
+    if kv_cache && let sauropod_inference_engine_api::ModelPath::GGUF(path) = model_path {
+        let shape = match tokio::fs::File::open(path).await {
+            Ok(file) => AttentionShape::from_reader(tokio::io::BufReader::new(file)).await,
+            Err(e) => Err(e.into()),
+        };
+        match shape {
+            Ok(shape) => {
+                size += shape.kv_cache_bytes(model_config.context_size, model_config.kv_cache_type)
+            }
+            Err(e) => tracing::warn!("Failed to read the metadata of {}: {e}", path.display()),
+        }
+    }
+    size
+}
+
+/// Estimate the bytes a model uses once loaded from the size of its files and its KV cache.
+pub(crate) async fn estimate_model_size(
+    model_config: &sauropod_config::ModelConfig,
+    model_path: &sauropod_inference_engine_api::ModelPath,
+    draft_model_path: Option<&sauropod_inference_engine_api::ModelPath>,
+) -> u64 {
+    // Embedding and reranking models use small contexts
+    let kv_cache = model_config.kind == sauropod_config::ModelKind::Chat;
+    let mut size = estimate_file(model_path, model_config, kv_cache).await;
+    if let Some(draft_model_path) = draft_model_path {
+        size += estimate_file(draft_model_path, model_config, kv_cache).await;
+    }
+    size
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The GGUF metadata of a Llama 3 8B model.
+    fn llama_metadata() -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend(b"GGUF");
+        bytes.extend(3u32.to_le_bytes());
+        bytes.extend(0u64.to_le_bytes());
+        bytes.extend(6u64.to_le_bytes());
+
+        let string = |bytes: &mut Vec<u8>, value: &str| {
+            bytes.extend((value.len() as u64).to_le_bytes());
+            bytes.extend(value.as_bytes());
+        };
+        string(&mut bytes, "general.architecture");
+        bytes.extend(8u32.to_le_bytes());
+        string(&mut bytes, "llama");
+        for (key, value) in [
+            ("llama.block_count", 32u32),
+            ("llama.embedding_length", 4096),
+            ("llama.attention.head_count", 32),
+            ("llama.attention.head_count_kv", 8),
+            ("llama.context_length", 8192),
+        ] {
+            string(&mut bytes, key);
+            bytes.extend(4u32.to_le_bytes());
+            bytes.extend(value.to_le_bytes());
+        }
+        bytes
+    }
+
+    #[tokio::test]
+    async fn test_attention_shape_from_metadata() {
+        let metadata = llama_metadata();
+        let shape = AttentionShape::from_reader(metadata.as_slice())
+            .await
+            .unwrap();
+        assert_eq!(
+            shape,
+            AttentionShape {
+                block_count: Some(32),
+                embedding_length: Some(4096),
+                head_count: Some(32),
+                head_count_kv: Some(8),
+                key_length: None,
+                value_length: None,
+                context_length: Some(8192),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_kv_cache_bytes() {
+        let metadata = llama_metadata();
+        let shape = AttentionShape::from_reader(metadata.as_slice())
+            .await
+            .unwrap();
+        assert_eq!(shape.kv_cache_bytes(None, None), 1024 * MIB);
+        assert_eq!(shape.kv_cache_bytes(Some(4096), None), 512 * MIB);
+        assert_eq!(
+            shape.kv_cache_bytes(None, Some(sauropod_config::KvCacheType::Q8_0)),
+            544 * MIB
+        );
+        assert_eq!(AttentionShape::default().kv_cache_bytes(None, None), 0);
+    }
+}
@@ -361,6 +361,25 @@ impl From<crate::OutputItem> for crate::Item {
     }
 }
 
+impl From<crate::LogProb> for crate::ResponseLogProb {
+    fn from(value: crate::LogProb) -> Self {
+        Self {
+            logprob: value.logprob,
+            token: value.token,
+            top_logprobs: Some(
+                value
+                    .top_logprobs
+                    .into_iter()
+                    .map(|top| crate::ResponseLogProbTopLogprobsItem {
+                        logprob: Some(top.logprob),
+                        token: Some(top.token),
+                    })
+                    .collect(),
+            ),
+        }
+    }
+}
+
 impl Default for crate::ModelResponseProperties {
     fn default() -> Self {
         Self {
@@ -0,0 +1,147 @@
+// Output parser for custom delimiters
+
+use super::{CustomFormat, Event, ModelOutputParser, ParseState};
+
+pub struct CustomParser {
+    pub(crate) format: std::sync::Arc<CustomFormat>,
+    pub(crate) parse_state: ParseState,
+}
+
+impl ModelOutputParser for CustomParser {
+    fn parse<'a>(&mut self, input: &'a str) -> Vec<Event<'a>> {
+        let mut events = Vec::with_capacity(4);
+        let mut remaining = input;
+        while !remaining.is_empty() {
+            remaining = match self.parse_state {
+                ParseState::RegularText => self.parse_regular_text(remaining, &mut events),
+                ParseState::InReasoning | ParseState::InToolCall => {
+                    self.parse_section(remaining, &mut events)
+                }
+            };
+        }
+        events
+    }
+}
+
+impl CustomParser {
+    /// Parse regular text up to the start of a section, returning the input after the start.
+    fn parse_regular_text<'a>(&mut self, input: &'a str, events: &mut Vec<Event<'a>>) -> &'a str {
+        let reasoning = self.format.reasoning.as_ref().and_then(|delimiters| {
+            input
+                .find(&delimiters.start)
+                .map(|pos| (pos, delimiters.start.len(), ParseState::InReasoning))
+        });
+        let tool_call = self.format.tool_call.as_ref().and_then(|delimiters| {
+            input
+                .find(&delimiters.start)
+                .map(|pos| (pos, delimiters.start.len(), ParseState::InToolCall))
+        });
+        let next_section = match (reasoning, tool_call) {
+            (Some(reasoning), Some(tool_call)) if tool_call.0 < reasoning.0 => Some(tool_call),
+            (Some(reasoning), _) => Some(reasoning),
+            (None, tool_call) => tool_call,
+        };
+
+        let Some((start_pos, start_len, new_state)) = next_section else {
+            events.push(Event::Text(input));
+            return "";
+        };
+        if start_pos > 0 {
+            events.push(Event::Text(&input[..start_pos]));
+        }
+        self.parse_state = new_state;
+        &input[start_pos + start_len..]
+    }
+
+    /// Parse the content of the current section up to its end, returning the input after the end.
+    fn parse_section<'a>(&mut self, input: &'a str, events: &mut Vec<Event<'a>>) -> &'a str {
+        let (delimiters, content_event, end_event): (_, fn(&'a str) -> Event<'a>, _) =
+            match self.parse_state {
+                ParseState::InReasoning => (
+                    self.format.reasoning.as_ref(),
+                    Event::Reasoning,
+                    Event::ReasoningEnd,
+                ),
+                ParseState::InToolCall => (
+                    self.format.tool_call.as_ref(),
+                    Event::ToolCall,
+                    Event::ToolCallEnd,
+                ),
+                ParseState::RegularText => unreachable!(),
+            };
+        let end = delimiters.and_then(|delimiters| {
+            input
+                .find(&delimiters.end)
+                .map(|pos| (pos, delimiters.end.len()))
+        });
+
+        let Some((end_pos, end_len)) = end else {
+            events.push(content_event(input));
+            return "";
+        };
+        if end_pos > 0 {
+            events.push(content_event(&input[..end_pos]));
+        }
+        events.push(end_event);
+        self.parse_state = ParseState::RegularText;
+        &input[end_pos + end_len..]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{CustomFormat, Delimiters, Event, ModelType, get_model_parser};
+
+    fn custom_model_type() -> ModelType {
+        ModelType::Custom(std::sync::Arc::new(CustomFormat {
+            reasoning: Some(Delimiters {
+                start: "<reasoning>".to_string(),
+                end: "</reasoning>".to_string(),
+            }),
+            tool_call: Some(Delimiters {
+                start: "<function_call>".to_string(),
+                end: "</function_call>".to_string(),
+            }),
+        }))
+    }
+
+    #[test]
+    fn custom_reasoning_and_tool_call() {
+        let mut parser = get_model_parser(custom_model_type());
+        assert_eq!(
+            parser.parse(
+                r#"<reasoning>Use the tool</reasoning>Checking.<function_call>{"name": "f", "arguments": {}}</function_call>"#
+            ),
+            vec![
+                Event::Reasoning("Use the tool"),
+                Event::ReasoningEnd,
+                Event::Text("Checking."),
+                Event::ToolCall(r#"{"name": "f", "arguments": {}}"#),
+                Event::ToolCallEnd
+            ]
+        );
+    }
+
+    #[test]
+    fn custom_streaming() {
+        let mut parser = get_model_parser(custom_model_type());
+        assert_eq!(parser.parse("<function_call>{"), vec![Event::ToolCall("{")]);
+        assert_eq!(
+            parser.parse("}</function_call>Done"),
+            vec![
+                Event::ToolCall("}"),
+                Event::ToolCallEnd,
+                Event::Text("Done")
+            ]
+        );
+    }
+
+    #[test]
+    fn custom_without_reasoning() {
+        let mut parser = get_model_parser(ModelType::Custom(Default::default()));
+        assert_eq!(
+            parser.parse("<reasoning>Text"),
+            vec![Event::Text("<reasoning>Text")]
+        );
+    }
+}
@@ -0,0 +1,72 @@
+// DeepSeek-R1 output parser implementation
+
+use super::{Event, ModelOutputParser, ParseState, Qwen3Parser};
+
+const THINK_OPEN_TAG: &str = "<think>";
+
+/// DeepSeek-R1 reasons before answering, and its chat template may already open the reasoning section.
+pub struct DeepSeekR1Parser {
+    pub(crate) inner: Qwen3Parser,
+    /// Whether any output has been parsed.
+    pub(crate) started: bool,
+}
+
+impl ModelOutputParser for DeepSeekR1Parser {
+    fn parse<'a>(&mut self, input: &'a str) -> Vec<Event<'a>> {
+        if self.started || input.is_empty() {
+            return self.inner.parse(input);
+        }
+
+        self.started = true;
+        match input.trim_start().strip_prefix(THINK_OPEN_TAG) {
+            // The model opened the reasoning section itself
+            Some(remaining) => self.inner.parse_reasoning(remaining),
+            None => self.inner.parse(input),
+        }
+    }
+}
+
+impl DeepSeekR1Parser {
+    pub(crate) fn new() -> Self {
+        Self {
+            inner: Qwen3Parser {
+                buffer: String::with_capacity(16),
+                parse_state: ParseState::InReasoning,
+            },
+            started: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{Event, ModelType, get_model_parser};
+
+    #[test]
+    fn deepseek_r1_reasoning_opened_by_template() {
+        let mut parser = get_model_parser(ModelType::DeepSeekR1);
+        assert_eq!(parser.parse("Okay"), vec![Event::Reasoning("Okay")]);
+        assert_eq!(
+            parser.parse(".</think>\n\nHi!"),
+            vec![
+                Event::Reasoning("."),
+                Event::ReasoningEnd,
+                Event::Text("\n\nHi!")
+            ]
+        );
+    }
+
+    #[test]
+    fn deepseek_r1_reasoning_opened_by_model() {
+        let mut parser = get_model_parser(ModelType::DeepSeekR1);
+        assert_eq!(parser.parse("<think>"), vec![Event::Reasoning("")]);
+        assert_eq!(
+            parser.parse("Hmm</think>Hi"),
+            vec![
+                Event::Reasoning("Hmm"),
+                Event::ReasoningEnd,
+                Event::Text("Hi")
+            ]
+        );
+    }
+}
@@ -0,0 +1,204 @@
+// Parser for the harmony response format of gpt-oss models
+
+use super::{Event, ModelOutputParser};
+
+// Tag constants
+const CHANNEL_TAG: &str = "<|channel|>";
+const MESSAGE_TAG: &str = "<|message|>";
+const RECIPIENT_PREFIX: &str = "to=";
+const FUNCTION_NAMESPACE: &str = "functions.";
+const MESSAGE_END_TAGS: [&str; 3] = ["<|end|>", "<|return|>", "<|call|>"];
+
+// Channel constants
+const ANALYSIS_CHANNEL: &str = "analysis";
+
+/// How the content of a message is emitted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum MessageKind {
+    /// Chain of thought in the analysis channel
+    Reasoning,
+    /// Text for the user in the final or commentary channel
+    Text,
+    /// Arguments of a call to a function
+    ToolCall,
+}
+
+pub(crate) enum HarmonyState {
+    /// Reading the header of a message up to `<|message|>`
+    Header,
+    /// Reading the content of a message
+    Message(MessageKind),
+}
+
+pub struct HarmonyParser {
+    /// The header of the message being read.
+    pub(crate) header: String,
+    pub(crate) state: HarmonyState,
+}
+
+impl ModelOutputParser for HarmonyParser {
+    fn parse<'a>(&mut self, input: &'a str) -> Vec<Event<'a>> {
+        let mut events = Vec::with_capacity(4);
+        let mut remaining = input;
+        while !remaining.is_empty() {
+            match self.state {
+                HarmonyState::Header => {
+                    let Some(message_pos) = remaining.find(MESSAGE_TAG) else {
+                        self.header.push_str(remaining);
+                        break;
+                    };
+                    self.header.push_str(&remaining[..message_pos]);
+                    remaining = &remaining[message_pos + MESSAGE_TAG.len()..];
+                    self.start_message(&mut events);
+                }
+                HarmonyState::Message(kind) => {
+                    let end = MESSAGE_END_TAGS
+                        .iter()
+                        .filter_map(|tag| remaining.find(tag).map(|pos| (pos, tag.len())))
+                        .min();
+                    let (content, rest) = match end {
+                        Some((end_pos, tag_len)) => {
+                            (&remaining[..end_pos], Some(&remaining[end_pos + tag_len..]))
+                        }
+                        None => (remaining, None),
+                    };
+
+                    if !content.is_empty() {
+                        events.push(match kind {
+                            MessageKind::Reasoning => Event::Reasoning(content),
+                            MessageKind::Text => Event::Text(content),
+                            MessageKind::ToolCall => Event::ToolCall(content),
+                        });
+                    }
+
+                    let Some(rest) = rest else {
+                        break;
+                    };
+                    match kind {
+                        MessageKind::Reasoning => events.push(Event::ReasoningEnd),
+                        MessageKind::ToolCall => events.push(Event::ToolCallEnd),
+                        MessageKind::Text => {}
+                    }
+                    self.state = HarmonyState::Header;
+                    self.header.clear();
+                    remaining = rest;
+                }
+            }
+        }
+        events
+    }
+}
+
+impl HarmonyParser {
+    /// Start reading the content of the message described by the buffered header.
+    fn start_message<'a>(&mut self, events: &mut Vec<Event<'a>>) {
+        let kind = if let Some(recipient) = header_recipient(&self.header) {
+            let name = recipient
+                .strip_prefix(FUNCTION_NAMESPACE)
+                .unwrap_or(recipient);
+            events.push(Event::ToolCallName(name.to_string()));
+            MessageKind::ToolCall
+        } else if header_channel(&self.header) == Some(ANALYSIS_CHANNEL) {
+            MessageKind::Reasoning
+        } else {
+            MessageKind::Text
+        };
+        self.state = HarmonyState::Message(kind);
+        self.header.clear();
+    }
+}
+
+/// Get the word starting at the beginning of `text`.
+fn header_word(text: &str) -> &str {
+    let end = text
+        .find(|c: char| c.is_whitespace() || c == '<')
+        .unwrap_or(text.len());
+    &text[..end]
+}
+
+/// Get the channel of a message from its header.
+fn header_channel(header: &str) -> Option<&str> {
+    let channel_pos = header.find(CHANNEL_TAG)?;
+    Some(header_word(&header[channel_pos + CHANNEL_TAG.len()..]))
+}
+
+/// Get the recipient of a message from its header, which can be in the role or the channel.
+fn header_recipient(header: &str) -> Option<&str> {
+    let recipient_pos = header.find(RECIPIENT_PREFIX)?;
+    let recipient = header_word(&header[recipient_pos + RECIPIENT_PREFIX.len()..]);
+    (!recipient.is_empty()).then_some(recipient)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{Event, ModelType, get_model_parser};
+
+    #[test]
+    fn harmony_reasoning_and_final() {
+        let mut parser = get_model_parser(ModelType::Harmony);
+        assert_eq!(
+            parser.parse(
+                "<|channel|>analysis<|message|>The user says hi.<|end|><|start|>assistant<|channel|>final<|message|>Hello!"
+            ),
+            vec![
+                Event::Reasoning("The user says hi."),
+                Event::ReasoningEnd,
+                Event::Text("Hello!")
+            ]
+        );
+    }
+
+    #[test]
+    fn harmony_streaming() {
+        let mut parser = get_model_parser(ModelType::Harmony);
+        assert_eq!(parser.parse("<|channel|>"), vec![]);
+        assert_eq!(parser.parse("analysis"), vec![]);
+        assert_eq!(parser.parse("<|message|>"), vec![]);
+        assert_eq!(parser.parse("Think"), vec![Event::Reasoning("Think")]);
+        assert_eq!(parser.parse("<|end|>"), vec![Event::ReasoningEnd]);
+        assert_eq!(parser.parse("<|start|>"), vec![]);
+        assert_eq!(parser.parse("assistant"), vec![]);
+        assert_eq!(parser.parse("<|channel|>"), vec![]);
+        assert_eq!(parser.parse("final"), vec![]);
+        assert_eq!(parser.parse("<|message|>"), vec![]);
+        assert_eq!(parser.parse("Done"), vec![Event::Text("Done")]);
+    }
+
+    #[test]
+    fn harmony_tool_call() {
+        let mut parser = get_model_parser(ModelType::Harmony);
+        assert_eq!(
+            parser.parse(
+                r#"<|channel|>commentary to=functions.get_weather <|constrain|>json<|message|>{"city": "Paris"}<|call|>"#
+            ),
+            vec![
+                Event::ToolCallName("get_weather".to_string()),
+                Event::ToolCall(r#"{"city": "Paris"}"#),
+                Event::ToolCallEnd
+            ]
+        );
+    }
+
+    #[test]
+    fn harmony_tool_call_recipient_in_role() {
+        let mut parser = get_model_parser(ModelType::Harmony);
+        assert_eq!(parser.parse(" to=functions.get"), vec![]);
+        assert_eq!(parser.parse("_time"), vec![]);
+        assert_eq!(
+            parser.parse("<|channel|>commentary json<|message|>{}"),
+            vec![
+                Event::ToolCallName("get_time".to_string()),
+                Event::ToolCall("{}")
+            ]
+        );
+    }
+
+    #[test]
+    fn harmony_commentary_preamble() {
+        let mut parser = get_model_parser(ModelType::Harmony);
+        assert_eq!(
+            parser.parse("<|channel|>commentary<|message|>Let me check.<|end|>"),
+            vec![Event::Text("Let me check.")]
+        );
+    }
+}
@@ -6,17 +6,91 @@ use qwen::Qwen3Parser;
 mod markdown_tool_call;
 use markdown_tool_call::MarkdownToolCallParser;
 
+mod harmony;
+use harmony::{HarmonyParser, HarmonyState};
+
+mod llama3;
+use llama3::{Llama3Parser, Llama3State};
+
+mod mistral;
+use mistral::MistralParser;
+
+mod deepseek;
+use deepseek::DeepSeekR1Parser;
+
+mod custom;
+use custom::CustomParser;
+
+/// Special tokens which identify the output format of a model.
+pub const FORMAT_TOKENS: &[&str] = &[
+    "<|channel|>",
+    "<|python_tag|>",
+    "[TOOL_CALLS]",
+    "<tool_call>",
+];
+
+/// The text opening and closing a section of the output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Delimiters {
+    /// The text opening the section.
+    pub start: String,
+    /// The text closing the section.
+    pub end: String,
+}
+
+/// An output format with custom delimiters.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CustomFormat {
+    /// The delimiters of the reasoning.
+    pub reasoning: Option<Delimiters>,
+    /// The delimiters of a tool call, which contains a JSON object with the `name` and `arguments` of the call.
+    pub tool_call: Option<Delimiters>,
+}
+
 /// The type of a model.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub enum ModelType {
     /// Qwen 3 model
     Qwen3,
     /// Models like Gemma 3 that have no reasoning and use a Markdown block for tool calls
     MarkdownToolCall,
+    /// gpt-oss models using the harmony response format
+    Harmony,
+    /// Llama 3.x models calling tools with JSON after `<|python_tag|>` or on their own
+    Llama3,
+    /// Mistral models calling tools with a `[TOOL_CALLS]` JSON array
+    Mistral,
+    /// Models using Hermes-style `<tool_call>` tags without reasoning
+    Hermes,
+    /// DeepSeek-R1 models whose output starts with reasoning
+    DeepSeekR1,
+    /// Models using custom delimiters
+    Custom(std::sync::Arc<CustomFormat>),
     /// Fallback parser for unknown models
     Unknown,
 }
 
+impl ModelType {
+    /// The text surrounding a tool call in the output of a model.
+    ///
+    /// Returns `None` if the model's tool calls aren't a JSON object with the `name` and `arguments` of the call.
+    pub fn tool_call_envelope(&self) -> Option<(&str, &str)> {
+        match self {
+            ModelType::Qwen3 | ModelType::Hermes => Some(("<tool_call>\n", "\n</tool_call>")),
+            ModelType::MarkdownToolCall => Some(("```tool_call\n", "\n```")),
+            ModelType::Llama3 => Some(("<|python_tag|>", "")),
+            ModelType::Mistral => Some(("[TOOL_CALLS][", "]")),
+            ModelType::Custom(format) => format
+                .tool_call
+                .as_ref()
+                .map(|delimiters| (delimiters.start.as_str(), delimiters.end.as_str())),
+            // Harmony addresses tool calls to the function in the message header, which the
+            // grammar of a response constrains separately, and the others don't parse tool calls
+            ModelType::Harmony | ModelType::DeepSeekR1 | ModelType::Unknown => None,
+        }
+    }
+}
+
 /// Events that can be emitted by the parser.
 #[derive(Debug, Clone, PartialEq)]
 pub enum Event<'a> {
@@ -24,6 +98,8 @@ pub enum Event<'a> {
     Reasoning(&'a str),
     /// End of a reasoning section
     ReasoningEnd,
+    /// The name of the function called by the following tool call, whose data is then the arguments
+    ToolCallName(String),
     /// A tool call with JSON data
     ToolCall(&'a str),
     /// End of a tool call
@@ -57,6 +133,16 @@ impl ModelOutputParser for NoOpParser {
 
 /// Get a model parser for the specified model type.
 pub fn get_model_parser(model_type: ModelType) -> Box<dyn ModelOutputParser> {
+    get_model_parser_with_tools(model_type, true)
+}
+
+/// Get a model parser for the specified model type and a request which may or may not offer tools.
+///
+/// Without tools, output which only looks like a tool call, e.g. JSON starting with `"name"`, is parsed as text.
+pub fn get_model_parser_with_tools(
+    model_type: ModelType,
+    tools_offered: bool,
+) -> Box<dyn ModelOutputParser> {
     match model_type {
         ModelType::Qwen3 => Box::new(Qwen3Parser {
             buffer: String::with_capacity(16),
@@ -66,14 +152,138 @@ pub fn get_model_parser(model_type: ModelType) -> Box<dyn ModelOutputParser> {
             buffer: String::with_capacity(16),
             parse_state: ParseState::RegularText,
         }),
+        ModelType::Harmony => Box::new(HarmonyParser {
+            header: String::with_capacity(16),
+            state: HarmonyState::Header,
+        }),
+        ModelType::Llama3 => Box::new(Llama3Parser {
+            matched: String::with_capacity(8),
+            // Calls made without the Python tag are only detected when the model may call a tool
+            state: if tools_offered {
+                Llama3State::Start
+            } else {
+                Llama3State::RegularText
+            },
+        }),
+        ModelType::Mistral => Box::new(MistralParser {
+            parse_state: ParseState::RegularText,
+            depth: 0,
+            in_string: false,
+            escaped: false,
+            array_open: false,
+        }),
+        ModelType::Hermes => Box::new(Qwen3Parser {
+            buffer: String::with_capacity(16),
+            parse_state: ParseState::RegularText,
+        }),
+        ModelType::DeepSeekR1 => Box::new(DeepSeekR1Parser::new()),
+        ModelType::Custom(format) => Box::new(CustomParser {
+            format,
+            parse_state: ParseState::RegularText,
+        }),
         ModelType::Unknown => Box::new(NoOpParser),
     }
 }
 
+/// Detect the output format of a model.
+///
+/// `special_tokens` are the tokens of the model's vocabulary which are in `FORMAT_TOKENS`.
+pub fn detect_model_type(
+    architecture: Option<&str>,
+    chat_template: Option<&str>,
+    special_tokens: &[String],
+) -> ModelType {
+    let architecture = architecture.unwrap_or_default();
+    let chat_template = chat_template.unwrap_or_default();
+    let has_token = |marker: &str| special_tokens.iter().any(|token| token == marker);
+
+    if architecture.starts_with("gpt-oss") || has_token("<|channel|>") {
+        return ModelType::Harmony;
+    }
+    if chat_template.contains("<｜Assistant｜>") && chat_template.contains("</think>") {
+        // Checked before the architecture as the R1 distillations are Llama and Qwen models
+        return ModelType::DeepSeekR1;
+    }
+    if architecture.starts_with("gemma") {
+        return ModelType::MarkdownToolCall;
+    }
+    if architecture.starts_with("qwen") {
+        return ModelType::Qwen3;
+    }
+
+    // The chat template shows how the model calls tools more reliably than its vocabulary,
+    // which fine-tunes like Hermes 3 inherit from their base model
+    let tool_call_formats = [
+        ("[TOOL_CALLS]", ModelType::Mistral),
+        ("<|python_tag|>", ModelType::Llama3),
+        ("<tool_call>", ModelType::Hermes),
+    ];
+    tool_call_formats
+        .iter()
+        .find(|(marker, _)| chat_template.contains(marker))
+        .or_else(|| {
+            tool_call_formats
+                .iter()
+                .find(|(marker, _)| has_token(marker))
+        })
+        .map(|(_, model_type)| model_type.clone())
+        .unwrap_or(ModelType::Unknown)
+}
+
 #[cfg(test)]
 mod test {
     use crate::Event;
 
+    #[test]
+    fn detect_model_types() {
+        use super::{ModelType, detect_model_type};
+
+        assert!(matches!(
+            detect_model_type(Some("gemma3"), None, &[]),
+            ModelType::MarkdownToolCall
+        ));
+        assert!(matches!(
+            detect_model_type(
+                Some("qwen3"),
+                Some("<tool_call>"),
+                &["<tool_call>".to_string()]
+            ),
+            ModelType::Qwen3
+        ));
+        assert!(matches!(
+            detect_model_type(Some("llama"), None, &["<|python_tag|>".to_string()]),
+            ModelType::Llama3
+        ));
+        assert!(matches!(
+            detect_model_type(Some("llama"), None, &["[TOOL_CALLS]".to_string()]),
+            ModelType::Mistral
+        ));
+        assert!(matches!(
+            detect_model_type(
+                Some("qwen2"),
+                Some("{{'<｜Assistant｜>'}}{% set content = content.split('</think>')[-1] %}"),
+                &[]
+            ),
+            ModelType::DeepSeekR1
+        ));
+        assert!(matches!(
+            detect_model_type(
+                Some("llama"),
+                Some("<tool_call>{{ tool_call }}</tool_call>"),
+                &["<|python_tag|>".to_string(), "<tool_call>".to_string()]
+            ),
+            ModelType::Hermes
+        ));
+        assert!(matches!(
+            detect_model_type(Some("gpt-oss"), None, &[]),
+            ModelType::Harmony
+        ));
+        assert!(matches!(
+            detect_model_type(Some("llama"), None, &[]),
+            ModelType::Unknown
+        ));
+    }
+
     #[test]
     fn noop_parser_emits_text() {
         let mut parser = super::get_model_parser(super::ModelType::Unknown);
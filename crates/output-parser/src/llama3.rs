@@ -0,0 +1,180 @@
+// Llama 3 output parser implementation
+
+use super::{Event, ModelOutputParser};
+
+// Tag constants
+const PYTHON_TAG: &str = "<|python_tag|>";
+const TOOL_CALL_END_TAGS: [&str; 2] = ["<|eom_id|>", "<|eot_id|>"];
+
+/// The beginnings of a JSON function call made without the Python tag.
+const JSON_TOOL_CALL_PREFIXES: [&str; 2] = [r#"{"name""#, r#"{"type""#];
+
+pub(crate) enum Llama3State {
+    /// At the start of the output, which may be a JSON function call
+    Start,
+    /// Parsing a tool call
+    InToolCall,
+    /// At the top level parsing regular text
+    RegularText,
+}
+
+pub struct Llama3Parser {
+    /// The non-whitespace output matched against `JSON_TOOL_CALL_PREFIXES`.
+    pub(crate) matched: String,
+    pub(crate) state: Llama3State,
+}
+
+impl ModelOutputParser for Llama3Parser {
+    fn parse<'a>(&mut self, input: &'a str) -> Vec<Event<'a>> {
+        match self.state {
+            Llama3State::Start => self.parse_start(input),
+            Llama3State::InToolCall => self.parse_tool_call(input),
+            Llama3State::RegularText => self.parse_regular_text(input),
+        }
+    }
+}
+
+impl Llama3Parser {
+    /// Decide whether the output starts with a JSON function call.
+    ///
+    /// The prefixes are made of fixed text so the part matched in earlier input can be emitted as a static string.
+    fn parse_start<'a>(&mut self, input: &'a str) -> Vec<Event<'a>> {
+        let mut events = Vec::new();
+        for (index, c) in input.char_indices() {
+            if c.is_whitespace() {
+                continue;
+            }
+
+            self.matched.push(c);
+            let Some(prefix) = JSON_TOOL_CALL_PREFIXES
+                .iter()
+                .find(|prefix| prefix.starts_with(self.matched.as_str()))
+            else {
+                self.matched.pop();
+                if let Some(prefix) = JSON_TOOL_CALL_PREFIXES
+                    .iter()
+                    .find(|prefix| prefix.starts_with(self.matched.as_str()))
+                    && !self.matched.is_empty()
+                {
+                    events.push(Event::Text(&prefix[..self.matched.len()]));
+                }
+                self.matched.clear();
+                self.state = Llama3State::RegularText;
+                events.extend(self.parse_regular_text(&input[index..]));
+                return events;
+            };
+
+            if prefix.len() == self.matched.len() {
+                events.push(Event::ToolCall(prefix));
+                self.matched.clear();
+                self.state = Llama3State::InToolCall;
+                let remaining = &input[index + c.len_utf8()..];
+                if !remaining.is_empty() {
+                    events.extend(self.parse_tool_call(remaining));
+                }
+                return events;
+            }
+        }
+        events
+    }
+
+    /// Parse regular text, looking for the Python tag
+    fn parse_regular_text<'a>(&mut self, input: &'a str) -> Vec<Event<'a>> {
+        let mut events = Vec::with_capacity(2);
+        if let Some(tag_pos) = input.find(PYTHON_TAG) {
+            if tag_pos > 0 {
+                events.push(Event::Text(&input[..tag_pos]));
+            }
+            self.state = Llama3State::InToolCall;
+            events.extend(self.parse_tool_call(&input[tag_pos + PYTHON_TAG.len()..]));
+        } else if !input.is_empty() {
+            events.push(Event::Text(input));
+        }
+        events
+    }
+
+    /// Parse tool call content, looking for the end of the message
+    fn parse_tool_call<'a>(&mut self, input: &'a str) -> Vec<Event<'a>> {
+        let mut events = Vec::with_capacity(3);
+        let end = TOOL_CALL_END_TAGS
+            .iter()
+            .filter_map(|tag| input.find(tag).map(|pos| (pos, tag.len())))
+            .min();
+        if let Some((end_pos, tag_len)) = end {
+            let content = &input[..end_pos];
+            if !content.is_empty() {
+                events.push(Event::ToolCall(content));
+            }
+            events.push(Event::ToolCallEnd);
+            self.state = Llama3State::RegularText;
+            let remaining = &input[end_pos + tag_len..];
+            if !remaining.is_empty() {
+                events.extend(self.parse_regular_text(remaining));
+            }
+        } else if !input.is_empty() {
+            events.push(Event::ToolCall(input));
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{Event, ModelType, get_model_parser, get_model_parser_with_tools};
+
+    #[test]
+    fn llama3_python_tag_tool_call() {
+        let mut parser = get_model_parser(ModelType::Llama3);
+        assert_eq!(
+            parser.parse(r#"<|python_tag|>{"name": "get_weather", "parameters": {}}<|eom_id|>"#),
+            vec![
+                Event::ToolCall(r#"{"name": "get_weather", "parameters": {}}"#),
+                Event::ToolCallEnd
+            ]
+        );
+    }
+
+    #[test]
+    fn llama3_json_tool_call_streaming() {
+        let mut parser = get_model_parser(ModelType::Llama3);
+        assert_eq!(parser.parse(r#"{""#), vec![]);
+        assert_eq!(parser.parse("name"), vec![]);
+        assert_eq!(
+            parser.parse(r#"": "get_time", "#),
+            vec![
+                Event::ToolCall(r#"{"name""#),
+                Event::ToolCall(r#": "get_time", "#)
+            ]
+        );
+        assert_eq!(
+            parser.parse(r#""parameters": {}}"#),
+            vec![Event::ToolCall(r#""parameters": {}}"#)]
+        );
+    }
+
+    #[test]
+    fn llama3_json_text_is_not_a_tool_call() {
+        let mut parser = get_model_parser(ModelType::Llama3);
+        assert_eq!(parser.parse(r#"{""#), vec![]);
+        assert_eq!(
+            parser.parse(r#"answer": 4}"#),
+            vec![Event::Text(r#"{""#), Event::Text(r#"answer": 4}"#)]
+        );
+    }
+
+    #[test]
+    fn llama3_json_without_tools_is_not_a_tool_call() {
+        let mut parser = get_model_parser_with_tools(ModelType::Llama3, false);
+        assert_eq!(
+            parser.parse(r#"{"name": "Ada", "age": 36}"#),
+            vec![Event::Text(r#"{"name": "Ada", "age": 36}"#)]
+        );
+    }
+
+    #[test]
+    fn llama3_text() {
+        let mut parser = get_model_parser(ModelType::Llama3);
+        assert_eq!(parser.parse("Hello"), vec![Event::Text("Hello")]);
+        assert_eq!(parser.parse(" there"), vec![Event::Text(" there")]);
+    }
+}
@@ -0,0 +1,147 @@
+// Mistral output parser implementation
+
+use super::{Event, ModelOutputParser, ParseState};
+
+// Tag constants
+const TOOL_CALLS_TAG: &str = "[TOOL_CALLS]";
+
+pub struct MistralParser {
+    pub(crate) parse_state: ParseState,
+    /// The nesting depth of the JSON in the tool call array, where 0 is the array itself.
+    pub(crate) depth: usize,
+    /// Whether the tool call is inside a JSON string.
+    pub(crate) in_string: bool,
+    /// Whether the previous character in a JSON string was an escape.
+    pub(crate) escaped: bool,
+    /// Whether the tool call array has been opened.
+    pub(crate) array_open: bool,
+}
+
+impl ModelOutputParser for MistralParser {
+    fn parse<'a>(&mut self, input: &'a str) -> Vec<Event<'a>> {
+        match self.parse_state {
+            ParseState::RegularText => self.parse_regular_text(input),
+            ParseState::InToolCall => self.parse_tool_calls(input),
+            ParseState::InReasoning => unreachable!(),
+        }
+    }
+}
+
+impl MistralParser {
+    /// Parse regular text, looking for the tool calls tag
+    pub(crate) fn parse_regular_text<'a>(&mut self, input: &'a str) -> Vec<Event<'a>> {
+        let mut events = Vec::with_capacity(2);
+        if let Some(tag_pos) = input.find(TOOL_CALLS_TAG) {
+            if tag_pos > 0 {
+                events.push(Event::Text(&input[..tag_pos]));
+            }
+            self.parse_state = ParseState::InToolCall;
+            self.depth = 0;
+            self.in_string = false;
+            self.escaped = false;
+            self.array_open = false;
+            events.extend(self.parse_tool_calls(&input[tag_pos + TOOL_CALLS_TAG.len()..]));
+        } else if !input.is_empty() {
+            events.push(Event::Text(input));
+        }
+        events
+    }
+
+    /// Parse the array of tool calls, emitting each object in it as a separate tool call
+    pub(crate) fn parse_tool_calls<'a>(&mut self, input: &'a str) -> Vec<Event<'a>> {
+        let mut events = Vec::with_capacity(3);
+        // The start of the part of the current tool call in `input`
+        let mut call_start = (self.depth > 0).then_some(0);
+        for (index, c) in input.char_indices() {
+            if self.depth == 0 {
+                match c {
+                    '[' if !self.array_open => self.array_open = true,
+                    '{' => {
+                        self.depth = 1;
+                        call_start = Some(index);
+                    }
+                    ']' => {
+                        self.parse_state = ParseState::RegularText;
+                        let remaining = &input[index + 1..];
+                        if !remaining.is_empty() {
+                            events.extend(self.parse_regular_text(remaining));
+                        }
+                        return events;
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+
+            if self.in_string {
+                if self.escaped {
+                    self.escaped = false;
+                } else if c == '\\' {
+                    self.escaped = true;
+                } else if c == '"' {
+                    self.in_string = false;
+                }
+                continue;
+            }
+
+            match c {
+                '"' => self.in_string = true,
+                '{' | '[' => self.depth += 1,
+                '}' | ']' => {
+                    self.depth -= 1;
+                    if self.depth == 0 {
+                        let start = call_start.take().unwrap_or_default();
+                        events.push(Event::ToolCall(&input[start..=index]));
+                        events.push(Event::ToolCallEnd);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if let Some(start) = call_start
+            && start < input.len()
+        {
+            events.push(Event::ToolCall(&input[start..]));
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{Event, ModelType, get_model_parser};
+
+    #[test]
+    fn mistral_tool_calls() {
+        let mut parser = get_model_parser(ModelType::Mistral);
+        assert_eq!(
+            parser.parse(
+                r#"[TOOL_CALLS][{"name": "a", "arguments": {"x": "}"}}, {"name": "b", "arguments": {}}]"#
+            ),
+            vec![
+                Event::ToolCall(r#"{"name": "a", "arguments": {"x": "}"}}"#),
+                Event::ToolCallEnd,
+                Event::ToolCall(r#"{"name": "b", "arguments": {}}"#),
+                Event::ToolCallEnd
+            ]
+        );
+    }
+
+    #[test]
+    fn mistral_tool_calls_streaming() {
+        let mut parser = get_model_parser(ModelType::Mistral);
+        assert_eq!(parser.parse("Sure. "), vec![Event::Text("Sure. ")]);
+        assert_eq!(parser.parse("[TOOL_CALLS]"), vec![]);
+        assert_eq!(parser.parse(" [{\"name"), vec![Event::ToolCall("{\"name")]);
+        assert_eq!(
+            parser.parse("\": \"a\", \"arguments\": {}}"),
+            vec![
+                Event::ToolCall("\": \"a\", \"arguments\": {}}"),
+                Event::ToolCallEnd
+            ]
+        );
+        assert_eq!(parser.parse("]"), vec![]);
+        assert_eq!(parser.parse("Done"), vec![Event::Text("Done")]);
+    }
+}
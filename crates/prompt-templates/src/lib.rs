@@ -2,12 +2,16 @@
 
 use anyhow::Context as _;
 
+/// The marker of multimodal data in the content of a message.
+const MEDIA_MARKER: &str = "<__media__>";
+
 #[derive(serde::Serialize, Clone, Copy, Debug, Eq, PartialEq)]
 #[serde(rename_all = "snake_case")]
 pub enum RenderContextRole {
     User,
     System,
     Assistant,
+    Tool,
 }
 
 #[derive(serde::Serialize, Clone, Copy, Debug, Default, Eq, PartialEq)]
@@ -30,6 +34,20 @@ pub struct Tool {
     pub parameters: serde_json::Map<String, serde_json::Value>,
 }
 
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct RenderContextFunctionCall {
+    /// The name of the function.
+    pub name: String,
+    /// The arguments of the call.
+    pub arguments: serde_json::Value,
+}
+
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct RenderContextToolCall {
+    /// The function which was called.
+    pub function: RenderContextFunctionCall,
+}
+
 #[derive(serde::Serialize, Debug, Clone)]
 pub struct RenderContextMessage {
     /// The role of the message.
@@ -39,6 +57,9 @@ pub struct RenderContextMessage {
     /// Tools that the model may use.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tools: Option<String>,
+    /// Tools called by the model in this message.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<RenderContextToolCall>>,
 }
 
 /// Multimodal data that can be used in the render context.
@@ -116,6 +137,7 @@ fn get_last_user_message(messages: &mut Vec<RenderContextMessage>) -> &mut Rende
             role: RenderContextRole::User,
             content: String::new(),
             tools: None,
+            tool_calls: None,
         });
     }
 
@@ -124,11 +146,18 @@ fn get_last_user_message(messages: &mut Vec<RenderContextMessage>) -> &mut Rende
         .expect("Messages vector should not be empty")
 }
 
+/// Parse the JSON arguments of a function call, keeping them as a string if they aren't valid JSON.
+fn function_arguments(arguments: &str) -> serde_json::Value {
+    serde_json::from_str(arguments)
+        .unwrap_or_else(|_| serde_json::Value::String(arguments.to_string()))
+}
+
 impl RenderContext {
     /// Creates a new `RenderContext` from the `CreateResponse` request.
     pub fn from_create_response(
         request: &sauropod_openai_api::CreateResponse,
         system_prompt: Option<&str>,
+        model_type: &sauropod_output_parser::ModelType,
     ) -> anyhow::Result<crate::RenderContext> {
         let function_tools = request.response_properties.tools.as_ref().map(|tools| {
             tools
@@ -216,7 +245,7 @@ impl RenderContext {
                             ..
                         } => {
                             let message = get_last_user_message(&mut messages);
-                            message.content.push_str("<__media__>");
+                            message.content.push_str(MEDIA_MARKER);
                             match MultimodalData::from_image(image_url, detail) {
                                 Ok(data) => multimodal_data.push(data),
                                 Err(e) => {
@@ -257,6 +286,7 @@ impl RenderContext {
                                 role,
                                 content: text_content.text.to_string(),
                                 tools: None,
+                                tool_calls: None,
                             })
                         }
                         sauropod_openai_api::InputContent::InputImageContent { .. } => {
@@ -278,6 +308,7 @@ impl RenderContext {
                                 role: crate::RenderContextRole::Assistant,
                                 content: text.to_string(),
                                 tools: None,
+                                tool_calls: None,
                             }),
                             sauropod_openai_api::OutputContent::RefusalContent { .. } => {
                                 result = Err(anyhow::anyhow!("RefusalContent not handled"));
@@ -286,23 +317,61 @@ impl RenderContext {
                     }
                 }
                 sauropod_openai_api::InputItem::Item(
-                    sauropod_openai_api::Item::FunctionToolCall { arguments, .. },
+                    sauropod_openai_api::Item::FunctionToolCall {
+                        arguments, name, ..
+                    },
                 ) => {
-                    // TODO support multiple model formats
+                    if let sauropod_output_parser::ModelType::Harmony = model_type {
+                        // The harmony template addresses the call to the function itself
+                        let arguments = function_arguments(arguments);
+                        messages.push(crate::RenderContextMessage {
+                            role: crate::RenderContextRole::Assistant,
+                            content: String::new(),
+                            tools: None,
+                            tool_calls: Some(vec![crate::RenderContextToolCall {
+                                function: crate::RenderContextFunctionCall {
+                                    name: name.clone(),
+                                    arguments,
+                                },
+                            }]),
+                        });
+                        return;
+                    }
+
+                    // Replay the call in the format the model's output is parsed in
+                    let (open, close) = model_type
+                        .tool_call_envelope()
+                        .unwrap_or(("```tool_call\n", "\n```"));
+                    let call = serde_json::json!({
+                        "name": name,
+                        "arguments": function_arguments(arguments),
+                    });
                     messages.push(crate::RenderContextMessage {
                         role: crate::RenderContextRole::Assistant,
-                        content: format!(r#"```tool_call{arguments}```"#),
+                        content: format!("{open}{call}{close}"),
                         tools: None,
+                        tool_calls: None,
                     });
                 }
                 sauropod_openai_api::InputItem::Item(
                     sauropod_openai_api::Item::FunctionCallOutputItemParam { output, .. },
                 ) => {
+                    if let sauropod_output_parser::ModelType::Harmony = model_type {
+                        messages.push(crate::RenderContextMessage {
+                            role: crate::RenderContextRole::Tool,
+                            content: output.clone(),
+                            tools: None,
+                            tool_calls: None,
+                        });
+                        return;
+                    }
+
                     // TODO support multiple model formats
                     messages.push(crate::RenderContextMessage {
                         role: crate::RenderContextRole::User,
                         content: format!("```tool_call_result\n{output}\n```"),
                         tools: None,
+                        tool_calls: None,
                     });
                 }
                 sauropod_openai_api::InputItem::Item(item) => {
@@ -334,6 +403,7 @@ impl RenderContext {
                     role: crate::RenderContextRole::System,
                     content: instructions.clone().unwrap_or_default(),
                     tools: function_tool_string.clone(),
+                    tool_calls: None,
                 },
             );
         }
@@ -349,6 +419,51 @@ impl RenderContext {
     }
 }
 
+impl RenderContext {
+    /// The numbers of oldest messages which can be dropped to shorten the conversation, fewest first.
+    ///
+    /// System messages and the last message are always kept, and the remaining conversation
+    /// starts with a user message so that templates requiring alternating roles can render it.
+    pub fn truncation_points(&self) -> Vec<usize> {
+        self.messages
+            .iter()
+            .filter(|message| message.role != RenderContextRole::System)
+            .enumerate()
+            .skip(1)
+            .filter(|(_, message)| message.role == RenderContextRole::User)
+            .map(|(index, _)| index)
+            .collect()
+    }
+
+    /// Copy the render context without its `count` oldest non-system messages and their
+    /// multimodal data.
+    pub fn without_oldest_messages(&self, count: usize) -> RenderContext {
+        let mut dropped = 0;
+        let mut dropped_media = 0;
+        let messages = self
+            .messages
+            .iter()
+            .filter(|message| {
+                if dropped == count || message.role == RenderContextRole::System {
+                    return true;
+                }
+                dropped += 1;
+                dropped_media += message.content.matches(MEDIA_MARKER).count();
+                false
+            })
+            .cloned()
+            .collect();
+
+        RenderContext {
+            messages,
+            add_generation_prompt: self.add_generation_prompt,
+            tools: self.tools.clone(),
+            multimodal_data: self.multimodal_data[dropped_media.min(self.multimodal_data.len())..]
+                .to_vec(),
+        }
+    }
+}
+
 /// A prompt template.
 pub struct PromptTemplate {
     template_name: &'static str,
@@ -396,3 +511,59 @@ impl PromptTemplate {
             .render(context)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(role: RenderContextRole, content: &str) -> RenderContextMessage {
+        RenderContextMessage {
+            role,
+            content: content.to_string(),
+            tools: None,
+            tool_calls: None,
+        }
+    }
+
+    #[test]
+    fn test_without_oldest_messages() {
+        let render_context = RenderContext {
+            messages: vec![
+                message(RenderContextRole::System, "You are helpful"),
+                message(RenderContextRole::User, "Describe <__media__>"),
+                message(RenderContextRole::Assistant, "A cat"),
+                message(RenderContextRole::User, "And <__media__>?"),
+                message(RenderContextRole::Assistant, "A dog"),
+                message(RenderContextRole::User, "Thanks"),
+            ],
+            add_generation_prompt: true,
+            tools: None,
+            multimodal_data: vec![
+                MultimodalData::Audio(vec![1.0]),
+                MultimodalData::Audio(vec![2.0]),
+            ],
+        };
+        assert_eq!(render_context.truncation_points(), vec![2, 4]);
+
+        let truncated = render_context.without_oldest_messages(2);
+        let contents: Vec<&str> = truncated
+            .messages
+            .iter()
+            .map(|message| message.content.as_str())
+            .collect();
+        assert_eq!(
+            contents,
+            vec!["You are helpful", "And <__media__>?", "A dog", "Thanks"]
+        );
+        assert!(matches!(
+            truncated.multimodal_data.as_slice(),
+            [MultimodalData::Audio(data)] if data == &[2.0]
+        ));
+        assert!(
+            render_context
+                .without_oldest_messages(4)
+                .multimodal_data
+                .is_empty()
+        );
+    }
+}
@@ -16,6 +16,8 @@ impl Voxtral {
             "voxtral".to_string(),
             &model_path,
             Some(projector_model_source),
+            None,
+            &Default::default(),
         )
         .await?;
 
@@ -26,11 +28,10 @@ impl Voxtral {
         let sampler_properties = sauropod_inference_engine_api::SamplerProperties {
             temperature: 0.0, // Recommended by Mistral when transcribing
             max_predict: (audio_input.len() / 400).clamp(1024, 32_000), // Allow 40 tokens per second of audio
-            min_p: None,
-            top_k: None,
-            top_p: None,
-            repetition_penalty: None,
+            ..Default::default()
         };
+        let cancellation_token = sauropod_inference_engine_api::CancellationToken::new();
+        let _cancel_on_drop = cancellation_token.clone().drop_guard();
         let sauropod_inference_engine_api::GenerateFromTextResponse { mut stream, .. } = self
             .model
             .clone()
@@ -40,12 +41,13 @@ impl Voxtral {
                 vec![sauropod_prompt_templates::MultimodalData::Audio(
                     audio_input.clone(),
                 )],
+                cancellation_token,
             )
             .await?;
         let mut text = String::with_capacity(128);
         while let Some(chunk) = stream.next().await {
             let chunk = chunk?;
-            text.push_str(&chunk);
+            text.push_str(&chunk.text);
         }
         Ok(text)
     }
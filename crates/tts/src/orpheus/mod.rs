@@ -18,8 +18,14 @@ impl Orpheus {
         model_source: &sauropod_config::ConfigModelSource,
     ) -> anyhow::Result<Self> {
         let model_path = sauropod_inference_engine::get_model_path(model_source).await?;
-        let model =
-            sauropod_inference_engine::load_model("orpheus".to_string(), &model_path, None).await?;
+        let model = sauropod_inference_engine::load_model(
+            "orpheus".to_string(),
+            &model_path,
+            None,
+            None,
+            &Default::default(),
+        )
+        .await?;
         let tokenizer = load_tokenizer(sauropod_config::HuggingfacePath {
             repo: "canopylabs/orpheus-3b-0.1-ft".to_string(),
             revision: None,
@@ -86,6 +92,8 @@ impl crate::TtsProvider for Orpheus {
         );
         tokenized.extend([128009, 128260, 128261, 128257]);
 
+        let cancellation_token = sauropod_inference_engine_api::CancellationToken::new();
+        let _cancel_on_drop = cancellation_token.clone().drop_guard();
         let mut token_stream = self
             .model
             .clone()
@@ -94,11 +102,12 @@ impl crate::TtsProvider for Orpheus {
                     max_predict: 1000,
                     temperature: 0.75,
                     top_p: Some(0.9),
-                    top_k: None,
-                    min_p: None,
                     repetition_penalty: Some(1.3),
+                    penalty_window: 64,
+                    ..Default::default()
                 },
                 tokenized,
+                cancellation_token,
             )
             .await?;
 